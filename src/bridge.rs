@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A bidirectional socket that can be bridged to a subprocess.
+///
+/// Implemented for `TcpStream` and, on Unix, for `UnixStream`.  See
+/// [`Popen::bridge`] for details.
+///
+/// [`Popen::bridge`]: struct.Popen.html#method.bridge
+pub trait BridgeSocket: Read + Write + Send + Sized + 'static {
+    /// Create an independently owned handle to the same socket.
+    fn try_clone_socket(&self) -> io::Result<Self>;
+
+    /// Shut down the read half, write half, or both halves of the socket.
+    fn shutdown_socket(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl BridgeSocket for TcpStream {
+    fn try_clone_socket(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shutdown_socket(&self, how: Shutdown) -> io::Result<()> {
+        self.shutdown(how)
+    }
+}
+
+#[cfg(unix)]
+impl BridgeSocket for UnixStream {
+    fn try_clone_socket(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn shutdown_socket(&self, how: Shutdown) -> io::Result<()> {
+        self.shutdown(how)
+    }
+}
+
+/// Amount of data transferred by [`Popen::bridge`].
+///
+/// [`Popen::bridge`]: struct.Popen.html#method.bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BridgeStats {
+    /// Number of bytes copied from the socket to the subprocess's
+    /// standard input.
+    pub to_child: u64,
+    /// Number of bytes copied from the subprocess's standard output to
+    /// the socket.
+    pub from_child: u64,
+}
+
+enum Direction {
+    ToChild,
+    FromChild,
+}
+
+// Copy everything from source to sink.  A broken pipe on the writing
+// side means the other end is no longer interested in the data, so it
+// ends the copying without an error.
+fn pump(source: &mut impl Read, sink: &mut impl Write) -> io::Result<u64> {
+    let mut buf = [0u8; 8192];
+    let mut total = 0;
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        match sink.write_all(&buf[..n]).and_then(|_| sink.flush()) {
+            Ok(()) => total += n as u64,
+            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+pub fn bridge<S: BridgeSocket>(
+    stdin: Option<File>,
+    stdout: Option<File>,
+    socket: S,
+    timeout: Option<Duration>,
+) -> io::Result<BridgeStats> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let (tx, rx) = mpsc::channel();
+    let mut running = 0;
+
+    if let Some(mut stdin) = stdin {
+        let mut socket = socket.try_clone_socket()?;
+        let tx = tx.clone();
+        running += 1;
+        thread::spawn(move || {
+            let result = pump(&mut socket, &mut stdin);
+            // Closing stdin propagates the end of input to the child.
+            drop(stdin);
+            socket.shutdown_socket(Shutdown::Read).ok();
+            tx.send((Direction::ToChild, result)).ok();
+        });
+    }
+    if let Some(mut stdout) = stdout {
+        let mut socket = socket.try_clone_socket()?;
+        let tx = tx.clone();
+        running += 1;
+        thread::spawn(move || {
+            let result = pump(&mut stdout, &mut socket);
+            socket.shutdown_socket(Shutdown::Write).ok();
+            tx.send((Direction::FromChild, result)).ok();
+        });
+    }
+    drop(tx);
+
+    let mut stats = BridgeStats::default();
+    let mut error = None;
+    while running > 0 {
+        let received = match deadline {
+            None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            Some(deadline) => {
+                let now = Instant::now();
+                let remaining = if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                rx.recv_timeout(remaining)
+            }
+        };
+        let (direction, result) = match received {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // Unblock the threads waiting on the socket.  A thread
+                // blocked reading the child's output finishes once
+                // the child closes it.
+                socket.shutdown_socket(Shutdown::Both).ok();
+                return Err(io::Error::new(ErrorKind::TimedOut, "bridge timed out"));
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => unreachable!(),
+        };
+        running -= 1;
+        match (direction, result) {
+            (Direction::ToChild, Ok(n)) => stats.to_child = n,
+            (Direction::FromChild, Ok(n)) => stats.from_child = n,
+            (_, Err(e)) => {
+                if error.is_none() {
+                    // Make sure the other direction doesn't wait for
+                    // the peer indefinitely.
+                    socket.shutdown_socket(Shutdown::Both).ok();
+                    error = Some(e);
+                }
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(stats),
+    }
+}
@@ -1,17 +1,18 @@
 #[cfg(unix)]
-mod os {
+pub(crate) mod os {
     pub const NULL_DEVICE: &str = "/dev/null";
     pub const SHELL: [&str; 2] = ["sh", "-c"];
 }
 
 #[cfg(windows)]
-mod os {
+pub(crate) mod os {
     pub const NULL_DEVICE: &str = "nul";
     pub const SHELL: [&str; 2] = ["cmd.exe", "/c"];
 }
 
-pub use self::exec::{CaptureData, Exec, NullFile};
-pub use self::os::*;
+pub use self::context::{ExecContext, Outcome, OutcomeClass};
+pub use self::coprocess::{Coprocess, CoprocessIo};
+pub use self::exec::{CaptureData, Exec, NullFile, RunLimits, RunReport};
 pub use self::pipeline::Pipeline;
 
 #[cfg(unix)]
@@ -26,11 +27,21 @@ mod exec {
     use std::fs::{File, OpenOptions};
     use std::io::{self, Read, Write};
     use std::ops::BitOr;
-    use std::path::Path;
-
-    use crate::communicate::Communicator;
+    #[cfg(unix)]
+    use std::os::unix::io::{AsRawFd, OwnedFd};
+    #[cfg(windows)]
+    use std::os::windows::io::OwnedHandle;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use crate::cmdline::ArgQuoting;
+    use crate::communicate::{Communicator, InputSource};
     use crate::os_common::ExitStatus;
-    use crate::popen::{Popen, PopenConfig, Redirection, Result as PopenResult};
+    use crate::popen::{
+        DropPolicy, ExecutableSearch, Popen, PopenConfig, Redirection, Result as PopenResult,
+        TimeoutAction,
+    };
 
     use super::os::*;
     use super::Pipeline;
@@ -121,13 +132,13 @@ mod exec {
     ///
     /// [`Popen`]: struct.Popen.html
     /// [`Popen::create`]: struct.Popen.html#method.create
-
     #[must_use]
     pub struct Exec {
         command: OsString,
         args: Vec<OsString>,
         config: PopenConfig,
-        stdin_data: Option<Vec<u8>>,
+        stdin_data: Option<Vec<FeedSource>>,
+        deterministic: bool,
     }
 
     impl Exec {
@@ -147,6 +158,7 @@ mod exec {
                 args: vec![],
                 config: PopenConfig::default(),
                 stdin_data: None,
+                deterministic: false,
             }
         }
 
@@ -194,10 +206,386 @@ mod exec {
             self
         }
 
-        fn ensure_env(&mut self) {
-            if self.config.env.is_none() {
-                self.config.env = Some(PopenConfig::current_env());
-            }
+        /// Starts the process in a new process group.
+        ///
+        /// See [`PopenConfig::setpgid`] for details.
+        ///
+        /// [`PopenConfig::setpgid`]: struct.PopenConfig.html#structfield.setpgid
+        pub fn setpgid(mut self) -> Exec {
+            self.config.setpgid = true;
+            self
+        }
+
+        /// Runs the process as a daemon that outlives this process.
+        ///
+        /// See [`PopenConfig::daemonize`] for details.
+        ///
+        /// [`PopenConfig::daemonize`]: struct.PopenConfig.html#structfield.daemonize
+        pub fn daemonize(mut self) -> Exec {
+            self.config.daemonize = true;
+            self
+        }
+
+        /// Sets the scheduling priority of the process, as a niceness.
+        ///
+        /// See [`PopenConfig::nice`] for details.
+        ///
+        /// [`PopenConfig::nice`]: struct.PopenConfig.html#structfield.nice
+        pub fn nice(mut self, nice: i32) -> Exec {
+            self.config.nice = Some(nice);
+            self
+        }
+
+        /// Sets the Windows priority class of the process.
+        ///
+        /// See [`PopenConfig::priority_class`] for details.
+        ///
+        /// [`PopenConfig::priority_class`]: struct.PopenConfig.html#structfield.priority_class
+        #[cfg(windows)]
+        pub fn priority_class(mut self, class: crate::windows::PriorityClass) -> Exec {
+            self.config.priority_class = Some(class);
+            self
+        }
+
+        /// Adds process creation flags, such as `CREATE_NO_WINDOW`.
+        ///
+        /// See [`PopenConfig::creation_flags`] for details.
+        ///
+        /// [`PopenConfig::creation_flags`]: struct.PopenConfig.html#structfield.creation_flags
+        #[cfg(windows)]
+        pub fn creation_flags(mut self, flags: u32) -> Exec {
+            self.config.creation_flags |= flags;
+            self
+        }
+
+        /// Sets how the main window of the process is shown.
+        ///
+        /// See [`PopenConfig::show_window`] for details.
+        ///
+        /// [`PopenConfig::show_window`]: struct.PopenConfig.html#structfield.show_window
+        #[cfg(windows)]
+        pub fn show_window(mut self, show: u16) -> Exec {
+            self.config.show_window = Some(show);
+            self
+        }
+
+        /// Passes the given command line to the process as it is,
+        /// instead of composing it from the arguments.
+        ///
+        /// See [`PopenConfig::raw_cmdline`] for details.
+        ///
+        /// [`PopenConfig::raw_cmdline`]: struct.PopenConfig.html#structfield.raw_cmdline
+        #[cfg(windows)]
+        pub fn raw_cmdline(mut self, cmdline: impl Into<OsString>) -> Exec {
+            self.config.raw_cmdline = Some(cmdline.into());
+            self
+        }
+
+        /// Starts the process with a restricted token.
+        ///
+        /// See [`PopenConfig::restricted_token`] for details.
+        ///
+        /// [`PopenConfig::restricted_token`]: struct.PopenConfig.html#structfield.restricted_token
+        #[cfg(windows)]
+        pub fn restricted_token(mut self) -> Exec {
+            self.config.restricted_token = true;
+            self
+        }
+
+        /// Starts the process inside the AppContainer with the given
+        /// profile name.
+        ///
+        /// See [`PopenConfig::app_container`] for details.
+        ///
+        /// [`PopenConfig::app_container`]: struct.PopenConfig.html#structfield.app_container
+        #[cfg(windows)]
+        pub fn app_container(mut self, profile: impl AsRef<OsStr>) -> Exec {
+            self.config.app_container = Some(profile.as_ref().to_owned());
+            self
+        }
+
+        /// Starts the process as another user, logging on with the given
+        /// credentials.
+        ///
+        /// See [`PopenConfig::logon`] for details.
+        ///
+        /// [`PopenConfig::logon`]: struct.PopenConfig.html#structfield.logon
+        #[cfg(windows)]
+        pub fn logon(mut self, logon: crate::windows::Logon) -> Exec {
+            self.config.logon = Some(logon);
+            self
+        }
+
+        /// Starts the process as the user of the given primary token.
+        ///
+        /// See [`PopenConfig::user_token`] for details.
+        ///
+        /// [`PopenConfig::user_token`]: struct.PopenConfig.html#structfield.user_token
+        #[cfg(windows)]
+        pub fn user_token(mut self, token: impl Into<OwnedHandle>) -> Exec {
+            self.config.user_token = Some(token.into());
+            self
+        }
+
+        /// Lets the process inherit an additional handle.
+        ///
+        /// See [`PopenConfig::inherit_handles`] for details.
+        ///
+        /// [`PopenConfig::inherit_handles`]: struct.PopenConfig.html#structfield.inherit_handles
+        #[cfg(windows)]
+        pub fn inherit_handle(mut self, handle: impl Into<OwnedHandle>) -> Exec {
+            self.config.inherit_handles.push(handle.into());
+            self
+        }
+
+        /// Runs the process in a pseudo terminal of the given size.
+        ///
+        /// See [`PopenConfig::pty`] for details.
+        ///
+        /// [`PopenConfig::pty`]: struct.PopenConfig.html#structfield.pty
+        pub fn pty(mut self, size: crate::PtySize) -> Exec {
+            self.config.pty = Some(size);
+            self
+        }
+
+        /// Isolates the process in a new namespace.
+        ///
+        /// Can be called multiple times to unshare several namespaces.
+        /// See [`PopenConfig::unshare`] for details.
+        ///
+        /// [`PopenConfig::unshare`]: struct.PopenConfig.html#structfield.unshare
+        #[cfg(target_os = "linux")]
+        pub fn unshare(mut self, namespace: crate::unix::Namespace) -> Exec {
+            self.config.unshare.push(namespace);
+            self
+        }
+
+        /// Sets the UID mappings of the new user namespace.
+        ///
+        /// See [`PopenConfig::uid_map`] for details.
+        ///
+        /// [`PopenConfig::uid_map`]: struct.PopenConfig.html#structfield.uid_map
+        #[cfg(target_os = "linux")]
+        pub fn uid_map(mut self, map: &[(u32, u32, u32)]) -> Exec {
+            self.config.uid_map = Some(map.to_vec());
+            self
+        }
+
+        /// Sets the GID mappings of the new user namespace.
+        ///
+        /// See [`PopenConfig::gid_map`] for details.
+        ///
+        /// [`PopenConfig::gid_map`]: struct.PopenConfig.html#structfield.gid_map
+        #[cfg(target_os = "linux")]
+        pub fn gid_map(mut self, map: &[(u32, u32, u32)]) -> Exec {
+            self.config.gid_map = Some(map.to_vec());
+            self
+        }
+
+        /// Starts the process in a cgroup v2.
+        ///
+        /// See [`PopenConfig::cgroup`] for details.
+        ///
+        /// [`PopenConfig::cgroup`]: struct.PopenConfig.html#structfield.cgroup
+        #[cfg(target_os = "linux")]
+        pub fn cgroup(mut self, path: impl AsRef<OsStr>) -> Exec {
+            self.config.cgroup = Some(path.as_ref().to_owned());
+            self
+        }
+
+        /// Restricts the system calls of the process with a seccomp
+        /// filter.
+        ///
+        /// See [`PopenConfig::seccomp`] for details.
+        ///
+        /// [`PopenConfig::seccomp`]: struct.PopenConfig.html#structfield.seccomp
+        #[cfg(target_os = "linux")]
+        pub fn seccomp(mut self, filter: crate::unix::SeccompFilter) -> Exec {
+            self.config.seccomp = Some(filter);
+            self
+        }
+
+        /// Restricts the filesystem access of the process with Landlock.
+        ///
+        /// See [`PopenConfig::landlock`] for details.
+        ///
+        /// [`PopenConfig::landlock`]: struct.PopenConfig.html#structfield.landlock
+        #[cfg(target_os = "linux")]
+        pub fn landlock(mut self, rules: crate::unix::Landlock) -> Exec {
+            self.config.landlock = Some(rules);
+            self
+        }
+
+        /// Adjusts the badness score of the process for the Linux OOM
+        /// killer.
+        ///
+        /// See [`PopenConfig::oom_score_adj`] for details.
+        ///
+        /// [`PopenConfig::oom_score_adj`]: struct.PopenConfig.html#structfield.oom_score_adj
+        #[cfg(target_os = "linux")]
+        pub fn oom_score_adj(mut self, adj: i32) -> Exec {
+            self.config.oom_score_adj = Some(adj);
+            self
+        }
+
+        /// Drops all capabilities of the process except `caps`.
+        ///
+        /// See [`PopenConfig::keep_capabilities`] for details.
+        ///
+        /// [`PopenConfig::keep_capabilities`]: struct.PopenConfig.html#structfield.keep_capabilities
+        #[cfg(target_os = "linux")]
+        pub fn keep_capabilities(mut self, caps: &[crate::unix::Capability]) -> Exec {
+            self.config.keep_capabilities = Some(caps.to_vec());
+            self
+        }
+
+        /// Starts the process sharing the memory of the current
+        /// process, like after `vfork()`.
+        ///
+        /// See [`PopenConfig::vfork`] for details.
+        ///
+        /// [`PopenConfig::vfork`]: struct.PopenConfig.html#structfield.vfork
+        #[cfg(target_os = "linux")]
+        pub fn vfork(mut self) -> Exec {
+            self.config.vfork = true;
+            self
+        }
+
+        /// Sets the capacity of the pipes for the standard output and
+        /// error of the process.
+        ///
+        /// See [`PopenConfig::pipe_size`] for details.
+        ///
+        /// [`PopenConfig::pipe_size`]: struct.PopenConfig.html#structfield.pipe_size
+        #[cfg(target_os = "linux")]
+        pub fn pipe_size(mut self, size: usize) -> Exec {
+            self.config.pipe_size = Some(size);
+            self
+        }
+
+        /// Restricts the program to the given OpenBSD pledge promises.
+        ///
+        /// See [`PopenConfig::pledge`] for details.
+        ///
+        /// [`PopenConfig::pledge`]: struct.PopenConfig.html#structfield.pledge
+        #[cfg(target_os = "openbsd")]
+        pub fn pledge(mut self, promises: &str) -> Exec {
+            self.config.pledge = Some(promises.to_owned());
+            self
+        }
+
+        /// Allows the child to access `path` with the given OpenBSD
+        /// `unveil()` permissions.
+        ///
+        /// See [`PopenConfig::unveil`] for details.
+        ///
+        /// [`PopenConfig::unveil`]: struct.PopenConfig.html#structfield.unveil
+        #[cfg(target_os = "openbsd")]
+        pub fn unveil(mut self, path: impl AsRef<OsStr>, permissions: &str) -> Exec {
+            self.config
+                .unveil
+                .push((path.as_ref().to_owned(), permissions.to_owned()));
+            self
+        }
+
+        /// Starts the process in a Windows job object.
+        ///
+        /// See [`PopenConfig::job`] for details.
+        ///
+        /// [`PopenConfig::job`]: struct.PopenConfig.html#structfield.job
+        #[cfg(windows)]
+        pub fn job(mut self, job: crate::windows::JobObject) -> Exec {
+            self.config.job = Some(job);
+            self
+        }
+
+        /// Restricts the process to run on the given CPUs.
+        ///
+        /// See [`PopenConfig::cpu_affinity`] for details.
+        ///
+        /// [`PopenConfig::cpu_affinity`]: struct.PopenConfig.html#structfield.cpu_affinity
+        #[cfg(any(target_os = "linux", windows))]
+        pub fn cpu_affinity(mut self, cpus: &[usize]) -> Exec {
+            self.config.cpu_affinity = Some(cpus.to_vec());
+            self
+        }
+
+        /// Starts the process suspended, so that a debugger can attach to
+        /// it before it runs.
+        ///
+        /// Only useful with [`popen`], which returns a `Popen` that can
+        /// be resumed.  See [`PopenConfig::start_suspended`] for details.
+        ///
+        /// [`popen`]: struct.Exec.html#method.popen
+        /// [`PopenConfig::start_suspended`]: struct.PopenConfig.html#structfield.start_suspended
+        pub fn start_suspended(mut self) -> Exec {
+            self.config.start_suspended = true;
+            self
+        }
+
+        /// Sets what happens to the process when the `Popen` is dropped.
+        ///
+        /// Applies to the `Popen` returned by [`popen`], or used
+        /// internally by methods such as [`capture`] while they are
+        /// interrupted by an error or a panic.  See
+        /// [`PopenConfig::drop_policy`] for details.
+        ///
+        /// [`popen`]: struct.Exec.html#method.popen
+        /// [`capture`]: struct.Exec.html#method.capture
+        /// [`PopenConfig::drop_policy`]: struct.PopenConfig.html#structfield.drop_policy
+        pub fn drop_policy(mut self, policy: DropPolicy) -> Exec {
+            self.config.drop_policy = policy;
+            self
+        }
+
+        /// Limits the time the process is allowed to run.
+        ///
+        /// Once `timeout` elapses, the process is stopped as given by
+        /// `on_timeout`, even if it is only waited for with `join` or
+        /// while its output is captured.  See [`PopenConfig::timeout`]
+        /// for details.
+        ///
+        /// [`PopenConfig::timeout`]: struct.PopenConfig.html#structfield.timeout
+        pub fn timeout(mut self, timeout: Duration, on_timeout: TimeoutAction) -> Exec {
+            self.config.timeout = Some(timeout);
+            self.config.on_timeout = on_timeout;
+            self
+        }
+
+        /// Sets how to find the program when it is given without a
+        /// directory.
+        ///
+        /// See [`PopenConfig::executable_search`] for details.
+        ///
+        /// [`PopenConfig::executable_search`]: struct.PopenConfig.html#structfield.executable_search
+        pub fn executable_search(mut self, search: ExecutableSearch) -> Exec {
+            self.config.executable_search = search;
+            self
+        }
+
+        /// Sets the rules for quoting the arguments on Windows.
+        ///
+        /// See [`PopenConfig::arg_quoting`] for details.
+        ///
+        /// [`PopenConfig::arg_quoting`]: struct.PopenConfig.html#structfield.arg_quoting
+        pub fn arg_quoting(mut self, quoting: ArgQuoting) -> Exec {
+            self.config.arg_quoting = quoting;
+            self
+        }
+
+        /// Returns the rules for quoting the arguments on Windows.
+        pub fn get_arg_quoting(&self) -> ArgQuoting {
+            self.config.arg_quoting
+        }
+
+        /// Quote the arguments of a batch file as configured on Windows.
+        ///
+        /// See [`PopenConfig::allow_batch`] for details.
+        ///
+        /// [`PopenConfig::allow_batch`]: struct.PopenConfig.html#structfield.allow_batch
+        pub fn allow_batch(mut self, allow: bool) -> Exec {
+            self.config.allow_batch = allow;
+            self
         }
 
         /// Clears the environment of the subprocess.
@@ -218,11 +606,8 @@ mod exec {
         /// the current process.  If this is undesirable, call
         /// `env_clear` first.
         pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Exec {
-            self.ensure_env();
             self.config
-                .env
-                .as_mut()
-                .unwrap()
+                .env_mut()
                 .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
             self
         }
@@ -237,29 +622,513 @@ mod exec {
         /// the current process.  If this is undesirable, call
         /// `env_clear` first.
         pub fn env_extend(mut self, vars: &[(impl AsRef<OsStr>, impl AsRef<OsStr>)]) -> Exec {
-            self.ensure_env();
             {
-                let envvec = self.config.env.as_mut().unwrap();
-                for &(ref k, ref v) in vars {
+                let envvec = self.config.env_mut();
+                for (k, v) in vars {
                     envvec.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
                 }
             }
             self
         }
 
+        /// Sets an environment variable whose value is secret.
+        ///
+        /// This is like [`env`], but also marks the variable as secret
+        /// with [`secret_env`], so that its value doesn't appear in the
+        /// command line shown for logging.
+        ///
+        /// [`env`]: struct.Exec.html#method.env
+        /// [`secret_env`]: struct.Exec.html#method.secret_env
+        pub fn env_secret(self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Exec {
+            self.secret_env(&key).env(key, value)
+        }
+
+        /// Marks an environment variable as secret.
+        ///
+        /// The value of a secret variable, such as a token or a
+        /// password, is replaced by `***` in the output of
+        /// [`to_cmdline_lossy`], and therefore in the `Debug` output of
+        /// `Exec` and `Pipeline`, in [`RunReport`], and in the command
+        /// line passed to [`ExecContext`] handlers.  The child process
+        /// still receives the real value.
+        ///
+        /// [`to_cmdline_lossy`]: struct.Exec.html#method.to_cmdline_lossy
+        /// [`RunReport`]: struct.RunReport.html
+        /// [`ExecContext`]: struct.ExecContext.html
+        pub fn secret_env(mut self, key: impl AsRef<OsStr>) -> Exec {
+            self.config.secret_env.push(key.as_ref().to_owned());
+            self
+        }
+
         /// Removes an environment variable from the child process.
         ///
-        /// Other environment variables are inherited by default.
+        /// Other environment variables are inherited by default.  On
+        /// Windows, the variable name is compared case-insensitively.
         pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Exec {
-            self.ensure_env();
-            self.config
-                .env
-                .as_mut()
-                .unwrap()
-                .retain(|&(ref k, ref _v)| k != key.as_ref());
+            self.config.remove_env(key.as_ref());
             self
         }
 
+        /// Keeps only the environment variables for which `keep` returns
+        /// true, given the name and value of each.
+        ///
+        /// The filter applies to the variables set so far, including the
+        /// inherited ones, so it can be used to strip the environment
+        /// down before adding variables, e.g. to keep only `PATH` and the
+        /// locale.
+        pub fn env_filter(mut self, keep: impl FnMut(&OsStr, &OsStr) -> bool) -> Exec {
+            self.config.filter_env(keep);
+            self
+        }
+
+        /// Makes the invocation independent of the parent's locale,
+        /// timezone, and umask.
+        ///
+        /// The locale and timezone variables (`LANG`, `LANGUAGE`, `LC_*`,
+        /// and `TZ`) are removed from the environment and replaced by
+        /// `LC_ALL=C` and `TZ=UTC`, and on Unix the umask is set to
+        /// `0o022`.  When the process is started, the environment is
+        /// passed to it sorted by variable name.  Variables set after
+        /// this call are still honored.
+        ///
+        /// Together with [`spec_hash`], this allows using the hash as a
+        /// cache key for the results of the command.
+        ///
+        /// [`spec_hash`]: struct.Exec.html#method.spec_hash
+        pub fn deterministic(mut self) -> Exec {
+            fn is_locale_var(key: &OsStr) -> bool {
+                let key = key.to_string_lossy();
+                key == "LANG" || key == "LANGUAGE" || key == "TZ" || key.starts_with("LC_")
+            }
+            {
+                let envvec = self.config.env_mut();
+                envvec.retain(|(k, _v)| !is_locale_var(k));
+                envvec.push(("LC_ALL".into(), "C".into()));
+                envvec.push(("TZ".into(), "UTC".into()));
+            }
+            #[cfg(unix)]
+            {
+                self.config.umask = Some(0o022);
+            }
+            self.deterministic = true;
+            self
+        }
+
+        /// Returns a hash of the invocation, suitable as a cache key.
+        ///
+        /// The hash covers the command and its arguments, the
+        /// environment, the working directory, the redirections of the
+        /// standard streams, the options that affect the execution of the
+        /// child, and the data given to standard input as bytes or
+        /// strings.  Files given as the working directory, as standard
+        /// streams or as mapped descriptors are identified by their device
+        /// and inode numbers on Unix, and writers, readers and `pre_exec`
+        /// hooks only by their kind.  Input given as a file or a reader is
+        /// not included.  The environment is hashed in canonical form, so
+        /// the order in which variables were set doesn't affect the hash.
+        ///
+        /// The hash is computed with a fixed algorithm (64-bit FNV-1a)
+        /// and is stable across runs and versions of Rust.
+        pub fn spec_hash(&self) -> u64 {
+            // no `..`, so that new options must be considered here
+            let PopenConfig {
+                stdin,
+                stdout,
+                stderr,
+                // Exec gives its input in stdin_data, hashed below
+                input_data: _,
+                pty,
+                #[cfg(unix)]
+                pty_raw,
+                #[cfg(unix)]
+                pty_echo,
+                // only affect how the parent handles the child
+                detached: _,
+                secret_env: _,
+                drop_policy: _,
+                executable,
+                executable_search,
+                env: _,
+                cwd,
+                #[cfg(unix)]
+                cwd_fd,
+                #[cfg(unix)]
+                setuid,
+                #[cfg(unix)]
+                setgid,
+                #[cfg(unix)]
+                chroot,
+                #[cfg(unix)]
+                rlimits,
+                #[cfg(unix)]
+                setgroups,
+                setpgid,
+                #[cfg(unix)]
+                pgid,
+                #[cfg(unix)]
+                setsid,
+                #[cfg(unix)]
+                controlling_tty,
+                #[cfg(windows)]
+                arg_quoting,
+                #[cfg(not(windows))]
+                    arg_quoting: _,
+                #[cfg(windows)]
+                allow_batch,
+                #[cfg(not(windows))]
+                    allow_batch: _,
+                #[cfg(unix)]
+                umask,
+                #[cfg(unix)]
+                pre_exec,
+                #[cfg(unix)]
+                fd_map,
+                #[cfg(unix)]
+                listen_fds,
+                #[cfg(unix)]
+                notify_socket,
+                daemonize,
+                timeout,
+                on_timeout,
+                nice,
+                #[cfg(windows)]
+                priority_class,
+                #[cfg(windows)]
+                creation_flags,
+                #[cfg(windows)]
+                show_window,
+                #[cfg(windows)]
+                raw_cmdline,
+                #[cfg(windows)]
+                restricted_token,
+                #[cfg(windows)]
+                app_container,
+                #[cfg(windows)]
+                logon,
+                #[cfg(windows)]
+                user_token,
+                #[cfg(windows)]
+                inherit_handles,
+                #[cfg(target_os = "linux")]
+                sched_policy,
+                #[cfg(target_os = "linux")]
+                unshare,
+                #[cfg(target_os = "linux")]
+                uid_map,
+                #[cfg(target_os = "linux")]
+                gid_map,
+                #[cfg(target_os = "linux")]
+                cgroup,
+                #[cfg(target_os = "linux")]
+                seccomp,
+                #[cfg(target_os = "linux")]
+                landlock,
+                #[cfg(target_os = "linux")]
+                oom_score_adj,
+                #[cfg(target_os = "linux")]
+                keep_capabilities,
+                // only change how the child is started and fed
+                #[cfg(target_os = "linux")]
+                    vfork: _,
+                #[cfg(target_os = "linux")]
+                    pipe_size: _,
+                #[cfg(target_os = "openbsd")]
+                pledge,
+                #[cfg(target_os = "openbsd")]
+                unveil,
+                #[cfg(windows)]
+                job,
+                #[cfg(any(target_os = "linux", windows))]
+                cpu_affinity,
+                // the child runs the same once resumed
+                start_suspended: _,
+                _use_default_to_construct: (),
+            } = &self.config;
+            let mut hasher = SpecHasher::new();
+            hasher.write_os(&self.command);
+            hasher.write_u64(self.args.len() as u64);
+            for arg in &self.args {
+                hasher.write_os(arg);
+            }
+            let env = self.canonical_env();
+            hasher.write_u64(env.len() as u64);
+            for (k, v) in &env {
+                hasher.write_os(k);
+                hasher.write_os(v);
+            }
+            hasher.write_opt_os(cwd.as_deref());
+            #[cfg(unix)]
+            if let Some(dir) = cwd_fd {
+                hasher.write(b"cwd-fd");
+                hasher.write_file_id(dir);
+            }
+            hasher.write_opt_os(executable.as_deref());
+            match executable_search {
+                ExecutableSearch::Inherited => (),
+                ExecutableSearch::ChildEnv => hasher.write(b"search-child-env"),
+                ExecutableSearch::Path(path) => {
+                    hasher.write(b"search-path");
+                    hasher.write_os(path);
+                }
+            }
+            hasher.write_u64(*setpgid as u64);
+            #[cfg(windows)]
+            hasher.write_u64(*arg_quoting as u64);
+            if let Some(size) = pty {
+                hasher.write(b"pty");
+                hasher.write_u64(size.rows.into());
+                hasher.write_u64(size.cols.into());
+                #[cfg(unix)]
+                {
+                    hasher.write_u64(*pty_raw as u64);
+                    hasher.write_u64(*pty_echo as u64);
+                }
+            }
+            #[cfg(windows)]
+            if let Some(cmdline) = raw_cmdline {
+                hasher.write(b"raw-cmdline");
+                hasher.write_os(cmdline);
+            }
+            // hashed only when set, like the Unix options below
+            if let Some(nice) = *nice {
+                hasher.write(b"nice");
+                hasher.write_u64(i64::from(nice) as u64);
+            }
+            #[cfg(windows)]
+            if let Some(class) = *priority_class {
+                hasher.write(b"priority-class");
+                hasher.write_u64(class.to_raw().into());
+            }
+            #[cfg(any(target_os = "linux", windows))]
+            if let Some(cpus) = cpu_affinity {
+                hasher.write(b"cpu-affinity");
+                hasher.write_u64(cpus.len() as u64);
+                for &cpu in cpus {
+                    hasher.write_u64(cpu as u64);
+                }
+            }
+            #[cfg(unix)]
+            {
+                hasher.write_opt_u64(setuid.map(u64::from));
+                hasher.write_opt_u64(setgid.map(u64::from));
+                hasher.write_opt_u64(umask.map(u64::from));
+                // Hashed only when set, to keep the hashes of existing
+                // invocations unchanged.
+                if let Some(root) = chroot {
+                    hasher.write(b"chroot");
+                    hasher.write_os(root);
+                }
+                if let Some(pgid) = *pgid {
+                    hasher.write(b"pgid");
+                    hasher.write_u64(pgid.into());
+                }
+                if *setsid {
+                    hasher.write(b"setsid");
+                }
+                if let Some(groups) = setgroups {
+                    hasher.write(b"setgroups");
+                    hasher.write_u64(groups.len() as u64);
+                    for &gid in groups {
+                        hasher.write_u64(gid.into());
+                    }
+                }
+                if !rlimits.is_empty() {
+                    hasher.write(b"rlimits");
+                    hasher.write_u64(rlimits.len() as u64);
+                    for &(resource, soft, hard) in rlimits {
+                        hasher.write_u64(resource.to_raw() as u64);
+                        hasher.write_u64(soft);
+                        hasher.write_u64(hard);
+                    }
+                }
+                if let Some(tty) = controlling_tty {
+                    hasher.write(b"controlling-tty");
+                    hasher.write_file_id(tty);
+                }
+                if !pre_exec.is_empty() {
+                    hasher.write(b"pre-exec");
+                    hasher.write_u64(pre_exec.len() as u64);
+                }
+                if !fd_map.is_empty() {
+                    hasher.write(b"fd-map");
+                    hasher.write_u64(fd_map.len() as u64);
+                    for (child_fd, fd) in fd_map {
+                        hasher.write_u64(*child_fd as u64);
+                        hasher.write_file_id(fd);
+                    }
+                }
+                if !listen_fds.is_empty() {
+                    hasher.write(b"listen-fds");
+                    hasher.write_u64(listen_fds.len() as u64);
+                    for fd in listen_fds {
+                        hasher.write_file_id(fd);
+                    }
+                }
+                if *notify_socket {
+                    hasher.write(b"notify-socket");
+                }
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(policy) = *sched_policy {
+                    let (policy, priority) = policy.to_raw();
+                    hasher.write(b"sched-policy");
+                    hasher.write_u64(policy as u64);
+                    hasher.write_u64(priority as u64);
+                }
+                if let Some(adj) = *oom_score_adj {
+                    hasher.write(b"oom-score-adj");
+                    hasher.write_u64(i64::from(adj) as u64);
+                }
+                if let Some(cgroup) = cgroup {
+                    hasher.write(b"cgroup");
+                    hasher.write_os(cgroup);
+                }
+                if !unshare.is_empty() {
+                    hasher.write(b"unshare");
+                    hasher.write_u64(unshare.len() as u64);
+                    for &namespace in unshare {
+                        hasher.write_u64(namespace.to_raw() as u64);
+                    }
+                }
+                for (tag, map) in [(b"uid-map", uid_map), (b"gid-map", gid_map)] {
+                    if let Some(map) = map {
+                        hasher.write(tag);
+                        hasher.write_u64(map.len() as u64);
+                        for &(inside, outside, count) in map {
+                            hasher.write_u64(inside.into());
+                            hasher.write_u64(outside.into());
+                            hasher.write_u64(count.into());
+                        }
+                    }
+                }
+                if let Some(filter) = seccomp {
+                    let program = filter.program();
+                    hasher.write(b"seccomp");
+                    hasher.write_u64(program.len() as u64);
+                    for insn in program {
+                        hasher.write_u64(insn.code.into());
+                        hasher.write_u64(insn.jt.into());
+                        hasher.write_u64(insn.jf.into());
+                        hasher.write_u64(insn.k.into());
+                    }
+                }
+                if let Some(rules) = landlock {
+                    hasher.write(b"landlock");
+                    for paths in [&rules.read, &rules.write] {
+                        hasher.write_u64(paths.len() as u64);
+                        for path in paths {
+                            hasher.write_os(path.as_os_str());
+                        }
+                    }
+                    hasher.write_u64(rules.best_effort as u64);
+                }
+                if let Some(caps) = keep_capabilities {
+                    hasher.write(b"keep-capabilities");
+                    hasher.write_u64(caps.len() as u64);
+                    for &cap in caps {
+                        hasher.write_u64(cap as u64);
+                    }
+                }
+            }
+            #[cfg(target_os = "openbsd")]
+            {
+                if let Some(promises) = pledge {
+                    hasher.write(b"pledge");
+                    hasher.write(promises.as_bytes());
+                }
+                if !unveil.is_empty() {
+                    hasher.write(b"unveil");
+                    hasher.write_u64(unveil.len() as u64);
+                    for (path, permissions) in unveil {
+                        hasher.write_os(path);
+                        hasher.write(permissions.as_bytes());
+                    }
+                }
+            }
+            #[cfg(windows)]
+            {
+                if *allow_batch {
+                    hasher.write(b"allow-batch");
+                }
+                if *creation_flags != 0 {
+                    hasher.write(b"creation-flags");
+                    hasher.write_u64((*creation_flags).into());
+                }
+                if let Some(show) = *show_window {
+                    hasher.write(b"show-window");
+                    hasher.write_u64(show.into());
+                }
+                if *restricted_token {
+                    hasher.write(b"restricted-token");
+                }
+                if let Some(profile) = app_container {
+                    hasher.write(b"app-container");
+                    hasher.write_os(profile);
+                }
+                // the password is left out, as the hash may be logged
+                if let Some(logon) = logon {
+                    hasher.write(b"logon");
+                    hasher.write_os(&logon.user);
+                    hasher.write_opt_os(logon.domain.as_deref());
+                    hasher.write_u64(logon.load_profile as u64);
+                }
+                if user_token.is_some() {
+                    hasher.write(b"user-token");
+                }
+                if !inherit_handles.is_empty() {
+                    hasher.write(b"inherit-handles");
+                    hasher.write_u64(inherit_handles.len() as u64);
+                }
+                if job.is_some() {
+                    hasher.write(b"job");
+                }
+            }
+            if *daemonize {
+                hasher.write(b"daemonize");
+            }
+            if let Some(timeout) = *timeout {
+                hasher.write(b"timeout");
+                hasher.write_u64(timeout.as_nanos() as u64);
+                hasher.write_u64(*on_timeout as u64);
+            }
+            hasher.write_redirection(b"stdin", stdin);
+            hasher.write_redirection(b"stdout", stdout);
+            hasher.write_redirection(b"stderr", stderr);
+            match self.stdin_data {
+                Some(ref sources) => {
+                    hasher.write_u64(sources.len() as u64);
+                    for source in sources {
+                        match source {
+                            FeedSource::Source(InputSource::Bytes(data)) => hasher.write(data),
+                            FeedSource::Source(InputSource::Shared(data)) => {
+                                hasher.write((**data).as_ref())
+                            }
+                            _ => hasher.write_u64(u64::MAX),
+                        }
+                    }
+                }
+                None => hasher.write_u64(0),
+            }
+            hasher.finish()
+        }
+
+        // The environment of the child, deduplicated in favor of the
+        // later-appearing entries and sorted by name.
+        fn canonical_env(&self) -> Vec<(OsString, OsString)> {
+            let env = match self.config.env {
+                Some(ref env) => env.clone(),
+                None => PopenConfig::current_env(),
+            };
+            let mut map = HashMap::new();
+            for (k, v) in env {
+                map.insert(k, v);
+            }
+            let mut env: Vec<_> = map.into_iter().collect();
+            env.sort();
+            env
+        }
+
         /// Specifies the current working directory of the child process.
         ///
         /// If unspecified, the current working directory is inherited
@@ -278,10 +1147,16 @@ mod exec {
         /// * a `Vec<u8>` or `&str`, which will set up a `Redirection::Pipe`
         ///   for stdin, making sure that `capture` feeds that data into the
         ///   standard input of the subprocess;
+        /// * an `Arc<[u8]>`, which is like `Vec<u8>`, but allows the same
+        ///   data to be shared among multiple commands without copying;
+        /// * a `Vec<InputSource>`, which is like `Vec<u8>`, except the data
+        ///   fed to the subprocess is the concatenation of the given
+        ///   [`InputSource`]s;
         /// * [`NullFile`], which will redirect the standard input to read from
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
+        /// [`InputSource`]: enum.InputSource.html
         /// [`NullFile`]: struct.NullFile.html
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Exec {
             match (&self.config.stdin, stdin.into()) {
@@ -291,7 +1166,7 @@ mod exec {
                 (&Redirection::Pipe, InputRedirection::AsRedirection(Redirection::Pipe)) => (),
                 (&Redirection::None, InputRedirection::FeedData(data)) => {
                     self.config.stdin = Redirection::Pipe;
-                    self.stdin_data = Some(data);
+                    self.stdin_data = Some(feed_sources(data));
                 }
                 (_, _) => panic!("stdin is already set"),
             }
@@ -305,7 +1180,7 @@ mod exec {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * [`NullFile`], which will redirect the standard output to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -325,7 +1200,7 @@ mod exec {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * [`NullFile`], which will redirect the standard error to go to
-        ///    `/dev/null`.
+        ///   `/dev/null`.
         ///
         /// [`Redirection`]: enum.Redirection.html
         /// [`NullFile`]: struct.NullFile.html
@@ -349,6 +1224,9 @@ mod exec {
         /// Starts the process, returning a `Popen` for the running process.
         pub fn popen(mut self) -> PopenResult<Popen> {
             self.check_no_stdin_data("popen");
+            if self.deterministic {
+                self.config.env = Some(self.canonical_env());
+            }
             self.args.insert(0, self.command);
             let p = Popen::create(&self.args, self.config)?;
             Ok(p)
@@ -412,7 +1290,7 @@ mod exec {
             Ok(WriteAdapter(p))
         }
 
-        fn setup_communicate(mut self) -> PopenResult<(Communicator, Popen)> {
+        pub(super) fn setup_communicate(mut self) -> PopenResult<(Communicator, Popen)> {
             let stdin_data = self.stdin_data.take();
             if let (&Redirection::None, &Redirection::None) =
                 (&self.config.stdout, &self.config.stderr)
@@ -421,7 +1299,13 @@ mod exec {
             }
             let mut p = self.popen()?;
 
-            Ok((p.communicate_start(stdin_data), p))
+            let comm = match stdin_data {
+                Some(sources) => p.communicate_start_sources(input_sources(sources)),
+                // the pseudo terminal provides stdin even without input
+                None if p.pty().is_some() => p.communicate_start(Some(vec![])),
+                None => p.communicate_start(None),
+            };
+            Ok((comm, p))
         }
 
         /// Starts the process and returns a `Communicator` handle.
@@ -460,6 +1344,126 @@ mod exec {
             })
         }
 
+        /// Starts the process, collects its output, and waits for it
+        /// to finish, giving up after `timeout`.
+        ///
+        /// This is like `capture()`, except that if the process is still
+        /// running when `timeout` elapses, whether or not it has closed
+        /// its output, it is terminated, killed if it doesn't exit within
+        /// a second after that, and waited for.  Returns the captured
+        /// data, with the output read until the process was stopped,
+        /// together with whether the timeout elapsed.
+        pub fn capture_timeout(self, timeout: Duration) -> PopenResult<(CaptureData, bool)> {
+            let deadline = Instant::now() + timeout;
+            let (comm, mut p) = self.setup_communicate()?;
+            let ((out, err), mut timed_out) = match comm.limit_time(timeout).read() {
+                Ok(capture) => (capture, false),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => (e.capture, true),
+                Err(e) => return Err(e.into()),
+            };
+            // the process may close its output before exiting
+            if !timed_out {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                timed_out = p.wait_timeout(remaining)?.is_none();
+            }
+            let exit_status = if timed_out {
+                p.terminate_with_timeout(Duration::from_secs(1))?
+            } else {
+                p.wait()?
+            };
+            let capture = CaptureData {
+                stdout: out.unwrap_or_default(),
+                stderr: err.unwrap_or_default(),
+                exit_status,
+            };
+            Ok((capture, timed_out))
+        }
+
+        /// Starts the process, collects its output, waits for it to finish,
+        /// and returns a report of the run.
+        ///
+        /// The returned [`RunReport`] contains the command line, working
+        /// directory, start time and duration, exit status, and the captured
+        /// output, making it suitable for logging.  Like `capture()`, this
+        /// sets up `stdout(Redirection::Pipe)` unless output redirections
+        /// were specified.
+        ///
+        /// `limits` bounds the amount of output collected and the time
+        /// allowed for the process to run.  If either limit is exceeded, the
+        /// process is killed, and the report's `truncated` or `timed_out`
+        /// flag is set.
+        ///
+        /// [`RunReport`]: struct.RunReport.html
+        pub fn run_report(self, limits: RunLimits) -> PopenResult<RunReport> {
+            let cmdline = self.to_cmdline_lossy();
+            let spec_hash = self.spec_hash();
+            let cwd = self.config.cwd.clone().map(PathBuf::from);
+            let started_at = SystemTime::now();
+            let start = Instant::now();
+
+            let deadline = limits.timeout.map(|timeout| start + timeout);
+            let remaining = || deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let read = |comm: &mut Communicator| match comm.read() {
+                Ok(capture) => Ok((capture, false)),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok((e.capture, true)),
+                Err(e) => Err(e),
+            };
+
+            let (mut comm, mut p) = self.setup_communicate()?;
+            if let Some(max_output) = limits.max_output {
+                comm = comm.limit_size(max_output);
+            }
+            if let Some(timeout) = remaining() {
+                comm = comm.limit_time(timeout);
+            }
+            let ((out, err), mut timed_out) = read(&mut comm)?;
+            let (stdout, stderr) = (out.unwrap_or_default(), err.unwrap_or_default());
+            let mut truncated = false;
+            if let Some(max_output) = limits.max_output {
+                if !timed_out && stdout.len() + stderr.len() >= max_output {
+                    // the output was cut short if there is more of it,
+                    // in whichever stream that is; the extra byte is
+                    // not reported
+                    comm = comm.limit_size(1);
+                    if let Some(timeout) = remaining() {
+                        comm = comm.limit_time(timeout);
+                    }
+                    let ((out, err), more_timed_out) = read(&mut comm)?;
+                    truncated =
+                        out.is_some_and(|o| !o.is_empty()) || err.is_some_and(|e| !e.is_empty());
+                    timed_out = more_timed_out && !truncated;
+                }
+            }
+            if timed_out || truncated {
+                p.kill()?;
+            }
+            // the process may close its output before exiting
+            let exit = match remaining() {
+                Some(timeout) if !timed_out && !truncated => match p.wait_timeout(timeout)? {
+                    Some(exit) => exit,
+                    None => {
+                        timed_out = true;
+                        p.kill()?;
+                        p.wait()?
+                    }
+                },
+                _ => p.wait()?,
+            };
+
+            Ok(RunReport {
+                cmdline,
+                spec_hash,
+                cwd,
+                started_at,
+                duration: start.elapsed(),
+                exit,
+                stdout,
+                stderr,
+                truncated,
+                timed_out,
+            })
+        }
+
         // used for Debug impl
         fn display_escape(s: &str) -> Cow<'_, str> {
             fn nice_char(c: char) -> bool {
@@ -483,12 +1487,16 @@ mod exec {
                 let current: Vec<_> = env::vars_os().collect();
                 let current_map: HashMap<_, _> = current.iter().map(|(x, y)| (x, y)).collect();
                 for (k, v) in cmd_env {
-                    if current_map.get(&k) == Some(&&v) {
+                    if current_map.get(k) == Some(&v) {
                         continue;
                     }
                     out.push_str(&Exec::display_escape(&k.to_string_lossy()));
                     out.push('=');
-                    out.push_str(&Exec::display_escape(&v.to_string_lossy()));
+                    if self.config.is_secret_env(k) {
+                        out.push_str("***");
+                    } else {
+                        out.push_str(&Exec::display_escape(&v.to_string_lossy()));
+                    }
                     out.push(' ');
                 }
                 let cmd_env: HashMap<_, _> = cmd_env.iter().map(|(k, v)| (k, v)).collect();
@@ -517,13 +1525,20 @@ mod exec {
         /// variant.  If a redirection to `File` is present, cloning
         /// that field will use `File::try_clone` method, which
         /// duplicates a file descriptor and can (but is not likely
-        /// to) fail.  In that scenario, `Exec::clone` panics.
+        /// to) fail.  In that scenario, `Exec::clone` panics.  It
+        /// also panics if an output is redirected to a
+        /// `Redirection::Writer`, which cannot be cloned.
+        ///
+        /// An `InputSource::Reader` in the input data cannot be
+        /// duplicated either, so it is shared by the clones, the same
+        /// way a cloned `File` shares its file position.
         fn clone(&self) -> Exec {
             Exec {
                 command: self.command.clone(),
                 args: self.args.clone(),
                 config: self.config.try_clone().unwrap(),
-                stdin_data: self.stdin_data.as_ref().cloned(),
+                stdin_data: self.stdin_data.as_ref().map(|d| clone_sources(d)),
+                deterministic: self.deterministic,
             }
         }
     }
@@ -590,6 +1605,7 @@ mod exec {
     /// [`Exec::capture`]: struct.Exec.html#method.capture
     /// [`Pipeline::capture`]: struct.Pipeline.html#method.capture
     #[derive(Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct CaptureData {
         /// Standard output as bytes.
         pub stdout: Vec<u8>,
@@ -599,35 +1615,261 @@ mod exec {
         pub exit_status: ExitStatus,
     }
 
-    impl CaptureData {
-        /// Returns the standard output as string, converted from bytes using
-        /// `String::from_utf8_lossy`.
-        pub fn stdout_str(&self) -> String {
-            String::from_utf8_lossy(&self.stdout).into_owned()
+    impl CaptureData {
+        /// Returns the standard output as string, converted from bytes using
+        /// `String::from_utf8_lossy`.
+        pub fn stdout_str(&self) -> String {
+            String::from_utf8_lossy(&self.stdout).into_owned()
+        }
+
+        /// Returns the standard error as string, converted from bytes using
+        /// `String::from_utf8_lossy`.
+        pub fn stderr_str(&self) -> String {
+            String::from_utf8_lossy(&self.stderr).into_owned()
+        }
+
+        /// True if the exit status of the process or pipeline is 0.
+        pub fn success(&self) -> bool {
+            self.exit_status.success()
+        }
+    }
+
+    /// Limits applied by [`Exec::run_report`].
+    ///
+    /// When constructing `RunLimits`, use the [`Default`] trait, such as
+    /// `RunLimits { timeout: Some(dur), ..Default::default() }`.
+    ///
+    /// [`Exec::run_report`]: struct.Exec.html#method.run_report
+    /// [`Default`]: https://doc.rust-lang.org/core/default/trait.Default.html
+    #[derive(Debug, Clone, Default)]
+    pub struct RunLimits {
+        /// Maximum number of bytes of standard output and error, taken
+        /// together, to collect.
+        pub max_output: Option<usize>,
+        /// Maximum time to wait for the process to close its output and
+        /// exit.
+        pub timeout: Option<Duration>,
+    }
+
+    /// Report of a process run by [`Exec::run_report`].
+    ///
+    /// [`Exec::run_report`]: struct.Exec.html#method.run_report
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct RunReport {
+        /// Command line, as returned by `Exec::to_cmdline_lossy`.
+        pub cmdline: String,
+        /// Hash of the invocation, as returned by `Exec::spec_hash`.
+        pub spec_hash: u64,
+        /// Working directory of the process, if specified.
+        pub cwd: Option<PathBuf>,
+        /// Time when the process was started.
+        pub started_at: SystemTime,
+        /// Time from the start of the process until it was waited for.
+        pub duration: Duration,
+        /// Exit status.
+        pub exit: ExitStatus,
+        /// Standard output as bytes.
+        pub stdout: Vec<u8>,
+        /// Standard error as bytes.
+        pub stderr: Vec<u8>,
+        /// True if the output exceeded `RunLimits::max_output`.
+        pub truncated: bool,
+        /// True if the process exceeded `RunLimits::timeout`.
+        pub timed_out: bool,
+    }
+
+    impl RunReport {
+        /// Returns the standard output as string, converted from bytes using
+        /// `String::from_utf8_lossy`.
+        pub fn stdout_str(&self) -> String {
+            String::from_utf8_lossy(&self.stdout).into_owned()
+        }
+
+        /// Returns the standard error as string, converted from bytes using
+        /// `String::from_utf8_lossy`.
+        pub fn stderr_str(&self) -> String {
+            String::from_utf8_lossy(&self.stderr).into_owned()
+        }
+
+        /// True if the process finished in time with exit status 0.
+        pub fn success(&self) -> bool {
+            !self.timed_out && self.exit.success()
+        }
+    }
+
+    // 64-bit FNV-1a, whose output doesn't depend on the platform or the
+    // Rust version.  Variable-length values are prefixed by their length
+    // so that adjacent values can't be confused.
+    struct SpecHasher(u64);
+
+    impl SpecHasher {
+        fn new() -> SpecHasher {
+            SpecHasher(0xcbf2_9ce4_8422_2325)
+        }
+
+        fn write_raw(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 ^= u64::from(b);
+                self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        fn write_u64(&mut self, n: u64) {
+            self.write_raw(&n.to_le_bytes());
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.write_u64(bytes.len() as u64);
+            self.write_raw(bytes);
+        }
+
+        #[cfg(unix)]
+        fn write_os(&mut self, s: &OsStr) {
+            use std::os::unix::ffi::OsStrExt;
+            self.write(s.as_bytes());
+        }
+
+        #[cfg(windows)]
+        fn write_os(&mut self, s: &OsStr) {
+            use std::os::windows::ffi::OsStrExt;
+            let bytes: Vec<u8> = s.encode_wide().flat_map(u16::to_le_bytes).collect();
+            self.write(&bytes);
+        }
+
+        fn write_opt_os(&mut self, s: Option<&OsStr>) {
+            match s {
+                Some(s) => {
+                    self.write_u64(1);
+                    self.write_os(s);
+                }
+                None => self.write_u64(0),
+            }
+        }
+
+        #[cfg(unix)]
+        fn write_opt_u64(&mut self, n: Option<u64>) {
+            match n {
+                Some(n) => {
+                    self.write_u64(1);
+                    self.write_u64(n);
+                }
+                None => self.write_u64(0),
+            }
+        }
+
+        // The device and inode numbers of an open file.
+        #[cfg(unix)]
+        fn write_file_id(&mut self, file: &impl AsRawFd) {
+            let (dev, ino) = crate::posix::file_id(file.as_raw_fd()).unwrap_or((0, 0));
+            self.write_u64(dev);
+            self.write_u64(ino);
+        }
+
+        // Hashed only when set.  Files are identified on Unix, writers
+        // and readers only by their kind.
+        fn write_redirection(&mut self, tag: &[u8], redirection: &Redirection) {
+            let kind: &[u8] = match redirection {
+                Redirection::None => return,
+                Redirection::Pipe => b"pipe",
+                Redirection::Merge => b"merge",
+                Redirection::File(_) | Redirection::RcFile(_) => b"file",
+                Redirection::Open(..) => b"open",
+                Redirection::Tee(_) => b"tee",
+                Redirection::Writer(_) => b"writer",
+                Redirection::Reader(_) => b"reader",
+            };
+            self.write(tag);
+            self.write(kind);
+            match redirection {
+                Redirection::Open(path, _) => self.write_os(path.as_os_str()),
+                #[cfg(unix)]
+                Redirection::File(file) | Redirection::Tee(file) => self.write_file_id(file),
+                #[cfg(unix)]
+                Redirection::RcFile(file) => self.write_file_id(&**file),
+                _ => (),
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
         }
+    }
 
-        /// Returns the standard error as string, converted from bytes using
-        /// `String::from_utf8_lossy`.
-        pub fn stderr_str(&self) -> String {
-            String::from_utf8_lossy(&self.stderr).into_owned()
+    // A reader shared by the clones of an `Exec` or a `Pipeline`.
+    #[derive(Clone)]
+    pub struct SharedReader(Arc<Mutex<Box<dyn Read + Send>>>);
+
+    impl Read for SharedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut reader = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            reader.read(buf)
         }
+    }
 
-        /// True if the exit status of the process or pipeline is 0.
-        pub fn success(&self) -> bool {
-            self.exit_status.success()
+    impl fmt::Debug for SharedReader {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("SharedReader(..)")
         }
     }
 
+    // Input data held by `Exec` and `Pipeline`, with the readers made
+    // shareable so that cloning doesn't fail.
+    #[derive(Debug)]
+    pub enum FeedSource {
+        Source(InputSource),
+        Reader(SharedReader),
+    }
+
+    pub fn feed_sources(sources: Vec<InputSource>) -> Vec<FeedSource> {
+        sources
+            .into_iter()
+            .map(|source| match source {
+                InputSource::Reader(r) => FeedSource::Reader(SharedReader(Arc::new(Mutex::new(r)))),
+                source => FeedSource::Source(source),
+            })
+            .collect()
+    }
+
+    pub fn clone_sources(sources: &[FeedSource]) -> Vec<FeedSource> {
+        sources
+            .iter()
+            .map(|source| match source {
+                FeedSource::Source(s) => FeedSource::Source(s.try_clone().unwrap()),
+                FeedSource::Reader(r) => FeedSource::Reader(r.clone()),
+            })
+            .collect()
+    }
+
+    pub fn input_sources(sources: Vec<FeedSource>) -> Vec<InputSource> {
+        sources
+            .into_iter()
+            .map(|source| match source {
+                FeedSource::Source(s) => s,
+                FeedSource::Reader(r) => InputSource::Reader(Box::new(r)),
+            })
+            .collect()
+    }
+
     #[derive(Debug)]
     pub enum InputRedirection {
         AsRedirection(Redirection),
-        FeedData(Vec<u8>),
+        FeedData(Vec<InputSource>),
     }
 
     impl From<Redirection> for InputRedirection {
         fn from(r: Redirection) -> Self {
-            if let Redirection::Merge = r {
-                panic!("Redirection::Merge is only allowed for output streams");
+            match r {
+                Redirection::Merge => {
+                    panic!("Redirection::Merge is only allowed for output streams")
+                }
+                Redirection::Tee(_) => {
+                    panic!("Redirection::Tee is only allowed for output streams")
+                }
+                Redirection::Writer(_) => {
+                    panic!("Redirection::Writer is only allowed for output streams")
+                }
+                _ => (),
             }
             InputRedirection::AsRedirection(r)
         }
@@ -639,6 +1881,20 @@ mod exec {
         }
     }
 
+    #[cfg(unix)]
+    impl From<OwnedFd> for InputRedirection {
+        fn from(fd: OwnedFd) -> Self {
+            InputRedirection::AsRedirection(Redirection::from_fd(fd))
+        }
+    }
+
+    #[cfg(windows)]
+    impl From<OwnedHandle> for InputRedirection {
+        fn from(handle: OwnedHandle) -> Self {
+            InputRedirection::AsRedirection(Redirection::from_handle(handle))
+        }
+    }
+
     /// Marker value for [`stdin`], [`stdout`], and [`stderr`] methods
     /// of [`Exec`] and [`Pipeline`].
     ///
@@ -662,13 +1918,25 @@ mod exec {
 
     impl From<Vec<u8>> for InputRedirection {
         fn from(v: Vec<u8>) -> Self {
-            InputRedirection::FeedData(v)
+            InputRedirection::FeedData(vec![InputSource::Bytes(v)])
         }
     }
 
     impl<'a> From<&'a str> for InputRedirection {
         fn from(s: &'a str) -> Self {
-            InputRedirection::FeedData(s.as_bytes().to_vec())
+            InputRedirection::FeedData(vec![InputSource::from(s)])
+        }
+    }
+
+    impl From<Arc<[u8]>> for InputRedirection {
+        fn from(data: Arc<[u8]>) -> Self {
+            InputRedirection::FeedData(vec![InputSource::from(data)])
+        }
+    }
+
+    impl From<Vec<InputSource>> for InputRedirection {
+        fn from(sources: Vec<InputSource>) -> Self {
+            InputRedirection::FeedData(sources)
         }
     }
 
@@ -683,6 +1951,9 @@ mod exec {
 
     impl From<Redirection> for OutputRedirection {
         fn from(r: Redirection) -> Self {
+            if let Redirection::Reader(_) = r {
+                panic!("Redirection::Reader is only allowed for stdin");
+            }
             OutputRedirection(r)
         }
     }
@@ -693,6 +1964,20 @@ mod exec {
         }
     }
 
+    #[cfg(unix)]
+    impl From<OwnedFd> for OutputRedirection {
+        fn from(fd: OwnedFd) -> Self {
+            OutputRedirection(Redirection::from_fd(fd))
+        }
+    }
+
+    #[cfg(windows)]
+    impl From<OwnedHandle> for OutputRedirection {
+        fn from(handle: OwnedHandle) -> Self {
+            OutputRedirection(Redirection::from_handle(handle))
+        }
+    }
+
     impl From<NullFile> for OutputRedirection {
         fn from(_nf: NullFile) -> Self {
             let null_file = OpenOptions::new().write(true).open(NULL_DEVICE).unwrap();
@@ -702,11 +1987,148 @@ mod exec {
 
     #[cfg(unix)]
     pub mod unix {
+        use std::ffi::OsStr;
+        use std::fs::File;
+        use std::io;
+        use std::os::unix::io::{OwnedFd, RawFd};
+
         use super::Exec;
 
+        /// Unix-specific extension methods for `Exec`
         pub trait ExecExt {
+            /// Set the user ID for the subprocess.
+            ///
+            /// See [`PopenConfig::setuid`] for details.
+            ///
+            /// [`PopenConfig::setuid`]: ../struct.PopenConfig.html#structfield.setuid
             fn setuid(self, uid: u32) -> Self;
+
+            /// Set the group ID for the subprocess.
+            ///
+            /// See [`PopenConfig::setgid`] for details.
+            ///
+            /// [`PopenConfig::setgid`]: ../struct.PopenConfig.html#structfield.setgid
             fn setgid(self, gid: u32) -> Self;
+
+            /// Set the supplementary group IDs for the subprocess.
+            ///
+            /// See [`PopenConfig::setgroups`] for details.
+            ///
+            /// [`PopenConfig::setgroups`]: ../struct.PopenConfig.html#structfield.setgroups
+            fn setgroups(self, groups: impl IntoIterator<Item = u32>) -> Self;
+
+            /// Change the root directory of the subprocess.
+            ///
+            /// See [`PopenConfig::chroot`] for details.
+            ///
+            /// [`PopenConfig::chroot`]: ../struct.PopenConfig.html#structfield.chroot
+            fn chroot(self, path: impl AsRef<OsStr>) -> Self;
+
+            /// Specify the working directory of the subprocess as an open
+            /// directory.
+            ///
+            /// See [`PopenConfig::cwd_fd`] for details.
+            ///
+            /// [`PopenConfig::cwd_fd`]: ../struct.PopenConfig.html#structfield.cwd_fd
+            fn cwd_fd(self, dir: impl Into<OwnedFd>) -> Self;
+
+            /// Limit the use of a resource by the subprocess.
+            ///
+            /// See [`PopenConfig::rlimits`] for details.
+            ///
+            /// [`PopenConfig::rlimits`]: ../struct.PopenConfig.html#structfield.rlimits
+            fn rlimit(self, resource: crate::unix::Rlimit, soft: u64, hard: u64) -> Self;
+
+            /// Place the subprocess in an existing process group.
+            ///
+            /// See [`PopenConfig::pgid`] for details.
+            ///
+            /// [`PopenConfig::pgid`]: ../struct.PopenConfig.html#structfield.pgid
+            fn pgid(self, pgid: u32) -> Self;
+
+            /// Set the scheduling policy of the subprocess.
+            ///
+            /// See [`PopenConfig::sched_policy`] for details.
+            ///
+            /// [`PopenConfig::sched_policy`]: ../struct.PopenConfig.html#structfield.sched_policy
+            #[cfg(target_os = "linux")]
+            fn sched_policy(self, policy: crate::unix::SchedPolicy) -> Self;
+
+            /// Run the subprocess in a new session.
+            ///
+            /// See [`PopenConfig::setsid`] for details.
+            ///
+            /// [`PopenConfig::setsid`]: ../struct.PopenConfig.html#structfield.setsid
+            fn setsid(self) -> Self;
+
+            /// Set the controlling terminal of the subprocess, which is
+            /// run in a new session.
+            ///
+            /// See [`PopenConfig::controlling_tty`] for details.
+            ///
+            /// [`PopenConfig::controlling_tty`]: ../struct.PopenConfig.html#structfield.controlling_tty
+            fn controlling_tty(self, tty: File) -> Self;
+
+            /// Set the file mode creation mask for the subprocess.
+            ///
+            /// See [`PopenConfig::umask`] for details.
+            ///
+            /// [`PopenConfig::umask`]: ../struct.PopenConfig.html#structfield.umask
+            fn umask(self, mask: u32) -> Self;
+
+            /// Add a function to run in the child process before execing
+            /// the program.
+            ///
+            /// See [`PopenConfig::pre_exec`] for details.
+            ///
+            /// # Safety
+            ///
+            /// See [`PreExec::new`].
+            ///
+            /// [`PopenConfig::pre_exec`]: ../struct.PopenConfig.html#structfield.pre_exec
+            /// [`PreExec::new`]: struct.PreExec.html#method.new
+            unsafe fn pre_exec(
+                self,
+                f: impl FnMut() -> io::Result<()> + Send + Sync + 'static,
+            ) -> Self;
+
+            /// Pass a file descriptor to the subprocess as descriptor
+            /// number `child_fd`.
+            ///
+            /// See [`PopenConfig::fd_map`] for details.
+            ///
+            /// [`PopenConfig::fd_map`]: ../struct.PopenConfig.html#structfield.fd_map
+            fn map_fd(self, child_fd: RawFd, fd: impl Into<OwnedFd>) -> Self;
+
+            /// Pass a listening socket to the subprocess with socket
+            /// activation.
+            ///
+            /// See [`PopenConfig::listen_fds`] for details.
+            ///
+            /// [`PopenConfig::listen_fds`]: ../struct.PopenConfig.html#structfield.listen_fds
+            fn listen_fd(self, fd: impl Into<OwnedFd>) -> Self;
+
+            /// Create a notification socket for the subprocess.
+            ///
+            /// See [`PopenConfig::notify_socket`] for details.
+            ///
+            /// [`PopenConfig::notify_socket`]: ../struct.PopenConfig.html#structfield.notify_socket
+            fn notify_socket(self, notify: bool) -> Self;
+
+            /// Put the pseudo terminal of the subprocess in raw mode.
+            ///
+            /// See [`PopenConfig::pty_raw`] for details.
+            ///
+            /// [`PopenConfig::pty_raw`]: ../struct.PopenConfig.html#structfield.pty_raw
+            fn pty_raw(self, raw: bool) -> Self;
+
+            /// Specify whether the pseudo terminal of the subprocess
+            /// echoes its input.
+            ///
+            /// See [`PopenConfig::pty_echo`] for details.
+            ///
+            /// [`PopenConfig::pty_echo`]: ../struct.PopenConfig.html#structfield.pty_echo
+            fn pty_echo(self, echo: bool) -> Self;
         }
 
         impl ExecExt for Exec {
@@ -719,6 +2141,86 @@ mod exec {
                 self.config.setgid = Some(gid);
                 self
             }
+
+            fn setgroups(mut self, groups: impl IntoIterator<Item = u32>) -> Exec {
+                self.config.setgroups = Some(groups.into_iter().collect());
+                self
+            }
+
+            fn chroot(mut self, path: impl AsRef<OsStr>) -> Exec {
+                self.config.chroot = Some(path.as_ref().to_owned());
+                self
+            }
+
+            fn cwd_fd(mut self, dir: impl Into<OwnedFd>) -> Exec {
+                self.config.cwd_fd = Some(dir.into());
+                self
+            }
+
+            fn rlimit(mut self, resource: crate::unix::Rlimit, soft: u64, hard: u64) -> Exec {
+                self.config.rlimits.push((resource, soft, hard));
+                self
+            }
+
+            fn pgid(mut self, pgid: u32) -> Exec {
+                self.config.pgid = Some(pgid);
+                self
+            }
+
+            #[cfg(target_os = "linux")]
+            fn sched_policy(mut self, policy: crate::unix::SchedPolicy) -> Exec {
+                self.config.sched_policy = Some(policy);
+                self
+            }
+
+            fn setsid(mut self) -> Exec {
+                self.config.setsid = true;
+                self
+            }
+
+            fn controlling_tty(mut self, tty: File) -> Exec {
+                self.config.setsid = true;
+                self.config.controlling_tty = Some(tty);
+                self
+            }
+
+            fn umask(mut self, mask: u32) -> Exec {
+                self.config.umask = Some(mask);
+                self
+            }
+
+            unsafe fn pre_exec(
+                mut self,
+                f: impl FnMut() -> io::Result<()> + Send + Sync + 'static,
+            ) -> Exec {
+                self.config.pre_exec.push(crate::unix::PreExec::new(f));
+                self
+            }
+
+            fn map_fd(mut self, child_fd: RawFd, fd: impl Into<OwnedFd>) -> Exec {
+                self.config.fd_map.push((child_fd, fd.into()));
+                self
+            }
+
+            fn listen_fd(mut self, fd: impl Into<OwnedFd>) -> Exec {
+                self.config.listen_fds.push(fd.into());
+                self
+            }
+
+            fn notify_socket(mut self, notify: bool) -> Exec {
+                self.config.notify_socket = notify;
+                self
+            }
+
+            fn pty_raw(mut self, raw: bool) -> Exec {
+                self.config.pty_raw = raw;
+                self
+            }
+
+            fn pty_echo(mut self, echo: bool) -> Exec {
+                self.config.pty_echo = echo;
+                self
+            }
         }
     }
 }
@@ -734,7 +2236,10 @@ mod pipeline {
     use crate::os_common::ExitStatus;
     use crate::popen::{Popen, Redirection, Result as PopenResult};
 
-    use super::exec::{CaptureData, Exec, InputRedirection, OutputRedirection};
+    use super::exec::{
+        clone_sources, feed_sources, input_sources, CaptureData, Exec, FeedSource,
+        InputRedirection, OutputRedirection,
+    };
 
     /// A builder for multiple [`Popen`] instances connected via
     /// pipes.
@@ -778,14 +2283,13 @@ mod pipeline {
     /// [`Popen`]: struct.Popen.html
     /// [`Exec`]: struct.Exec.html
     /// [`Pipeline`]: struct.Pipeline.html
-
     #[must_use]
     pub struct Pipeline {
         cmds: Vec<Exec>,
         stdin: Redirection,
         stdout: Redirection,
         stderr_file: Option<File>,
-        stdin_data: Option<Vec<u8>>,
+        stdin_data: Option<Vec<FeedSource>>,
     }
 
     impl Pipeline {
@@ -860,8 +2364,10 @@ mod pipeline {
         /// * a `Vec<u8>` or `&str`, which will set up a `Redirection::Pipe`
         ///   for stdin, making sure that `capture` feeds that data into the
         ///   standard input of the subprocess.
+        /// * a `Vec<InputSource>`, which feeds the concatenation of the
+        ///   sources to the standard input.
         /// * `NullFile`, which will redirect the standard input to read from
-        ///    /dev/null.
+        ///   /dev/null.
         ///
         /// [`Redirection`]: enum.Redirection.html
         pub fn stdin(mut self, stdin: impl Into<InputRedirection>) -> Pipeline {
@@ -869,7 +2375,7 @@ mod pipeline {
                 InputRedirection::AsRedirection(r) => self.stdin = r,
                 InputRedirection::FeedData(data) => {
                     self.stdin = Redirection::Pipe;
-                    self.stdin_data = Some(data);
+                    self.stdin_data = Some(feed_sources(data));
                 }
             };
             self
@@ -883,7 +2389,7 @@ mod pipeline {
         /// * a [`Redirection`];
         /// * a `File`, which is a shorthand for `Redirection::File(file)`;
         /// * `NullFile`, which will redirect the standard output to write to
-        ///    /dev/null.
+        ///   /dev/null.
         ///
         /// [`Redirection`]: enum.Redirection.html
         pub fn stdout(mut self, stdout: impl Into<OutputRedirection>) -> Pipeline {
@@ -1017,7 +2523,7 @@ mod pipeline {
                 v[0].stdin.take(),
                 v[vlen - 1].stdout.take(),
                 Some(err_read),
-                stdin_data,
+                stdin_data.map(input_sources),
             );
             Ok((comm, v))
         }
@@ -1081,7 +2587,7 @@ mod pipeline {
                 stdin: self.stdin.try_clone().unwrap(),
                 stdout: self.stdout.try_clone().unwrap(),
                 stderr_file: self.stderr_file.as_ref().map(|f| f.try_clone().unwrap()),
-                stdin_data: self.stdin_data.clone(),
+                stdin_data: self.stdin_data.as_ref().map(|d| clone_sources(d)),
             }
         }
     }
@@ -1154,3 +2660,492 @@ mod pipeline {
         }
     }
 }
+
+mod context {
+    use std::fmt;
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use crate::os_common::ExitStatus;
+    use crate::popen::{Popen, PopenError, Result as PopenResult};
+
+    use super::exec::{CaptureData, Exec};
+
+    /// Class of the outcome of running a command, used to select the
+    /// handlers registered with [`ExecContext::on`].
+    ///
+    /// [`ExecContext::on`]: struct.ExecContext.html#method.on
+    #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+    pub enum OutcomeClass {
+        /// The command exited with status 0.
+        Success,
+        /// The command exited with a non-zero status.
+        NonzeroExit,
+        /// The command was killed by a signal or otherwise terminated
+        /// abnormally.
+        Signaled,
+        /// The command didn't finish in the allotted time.
+        Timeout,
+        /// The command could not be started.
+        SpawnError,
+    }
+
+    /// Outcome of running a command, passed to the handlers registered
+    /// with [`ExecContext::on`].
+    ///
+    /// [`ExecContext::on`]: struct.ExecContext.html#method.on
+    #[derive(Debug)]
+    pub enum Outcome<'a> {
+        /// The command exited with status 0.
+        Success,
+        /// The command exited with a non-zero status.
+        NonzeroExit(ExitStatus),
+        /// The command was killed by a signal or otherwise terminated
+        /// abnormally.
+        Signaled(ExitStatus),
+        /// The command didn't finish in the allotted time and was killed.
+        Timeout(Duration),
+        /// The command could not be started.
+        SpawnError(&'a PopenError),
+    }
+
+    impl Outcome<'_> {
+        fn from_status(status: ExitStatus) -> Outcome<'static> {
+            match status {
+                ExitStatus::Exited(0) => Outcome::Success,
+                ExitStatus::Exited(_) => Outcome::NonzeroExit(status),
+                _ => Outcome::Signaled(status),
+            }
+        }
+
+        /// Returns the class of the outcome.
+        pub fn class(&self) -> OutcomeClass {
+            match *self {
+                Outcome::Success => OutcomeClass::Success,
+                Outcome::NonzeroExit(_) => OutcomeClass::NonzeroExit,
+                Outcome::Signaled(_) => OutcomeClass::Signaled,
+                Outcome::Timeout(_) => OutcomeClass::Timeout,
+                Outcome::SpawnError(_) => OutcomeClass::SpawnError,
+            }
+        }
+    }
+
+    type Handler = Box<dyn Fn(&str, &Outcome<'_>) + Send + Sync>;
+
+    /// Context for running commands with centrally attached outcome
+    /// handlers.
+    ///
+    /// Handlers are registered once for a class of outcomes, and invoked
+    /// for every command run through the context whose outcome belongs to
+    /// that class.  Each handler receives the command line, as returned by
+    /// [`Exec::to_cmdline_lossy`], and the outcome.  For example:
+    ///
+    /// ```no_run
+    /// # use subprocess::*;
+    /// # fn dummy() -> Result<()> {
+    /// let ctx = ExecContext::new()
+    ///     .on(OutcomeClass::Signaled, |cmd, outcome| {
+    ///         eprintln!("{} crashed: {:?}", cmd, outcome)
+    ///     })
+    ///     .on(OutcomeClass::NonzeroExit, |cmd, outcome| {
+    ///         eprintln!("{} failed: {:?}", cmd, outcome)
+    ///     });
+    /// ctx.join(Exec::cmd("make"))?;
+    /// let listing = ctx.capture(Exec::cmd("ls"))?.stdout_str();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Exec::to_cmdline_lossy`]: struct.Exec.html#method.to_cmdline_lossy
+    #[derive(Default)]
+    pub struct ExecContext {
+        handlers: Vec<(OutcomeClass, Handler)>,
+        timeout: Option<Duration>,
+    }
+
+    impl ExecContext {
+        /// Create a context without handlers.
+        pub fn new() -> ExecContext {
+            ExecContext::default()
+        }
+
+        /// Register a handler invoked for outcomes of the given class.
+        ///
+        /// Multiple handlers can be registered for the same class, in which
+        /// case they are invoked in the order of registration.
+        pub fn on(
+            mut self,
+            class: OutcomeClass,
+            handler: impl Fn(&str, &Outcome<'_>) + Send + Sync + 'static,
+        ) -> ExecContext {
+            self.handlers.push((class, Box::new(handler)));
+            self
+        }
+
+        /// Limit the time a command run with [`capture`] may take.
+        ///
+        /// If the command doesn't finish in time, it is killed, the
+        /// `Timeout` handlers are invoked, and an error of kind
+        /// `ErrorKind::TimedOut` is returned.
+        ///
+        /// [`capture`]: struct.ExecContext.html#method.capture
+        pub fn timeout(mut self, timeout: Duration) -> ExecContext {
+            self.timeout = Some(timeout);
+            self
+        }
+
+        fn notify(&self, cmdline: &str, outcome: &Outcome<'_>) {
+            let class = outcome.class();
+            for (handler_class, handler) in &self.handlers {
+                if *handler_class == class {
+                    handler(cmdline, outcome);
+                }
+            }
+        }
+
+        fn notify_result<T>(&self, cmdline: &str, result: &PopenResult<T>) {
+            if let Err(ref e) = *result {
+                self.notify(cmdline, &Outcome::SpawnError(e));
+            }
+        }
+
+        fn kill_timed_out<T>(
+            &self,
+            cmdline: &str,
+            mut p: Popen,
+            timeout: Duration,
+            error: io::Error,
+        ) -> PopenResult<T> {
+            p.kill()?;
+            p.wait()?;
+            self.notify(cmdline, &Outcome::Timeout(timeout));
+            Err(error.into())
+        }
+
+        /// Run the command like [`Exec::join`], invoking the handlers for
+        /// its outcome.
+        ///
+        /// [`Exec::join`]: struct.Exec.html#method.join
+        pub fn join(&self, exec: Exec) -> PopenResult<ExitStatus> {
+            let cmdline = exec.to_cmdline_lossy();
+            let p = exec.popen();
+            self.notify_result(&cmdline, &p);
+            let status = p?.wait()?;
+            self.notify(&cmdline, &Outcome::from_status(status));
+            Ok(status)
+        }
+
+        /// Run the command like [`Exec::join`], but wait for no longer
+        /// than `timeout`.
+        ///
+        /// If the command doesn't finish in time, it is killed, the
+        /// `Timeout` handlers are invoked, and `Ok(None)` is returned.
+        ///
+        /// [`Exec::join`]: struct.Exec.html#method.join
+        pub fn join_timeout(
+            &self,
+            exec: Exec,
+            timeout: Duration,
+        ) -> PopenResult<Option<ExitStatus>> {
+            let cmdline = exec.to_cmdline_lossy();
+            let p = exec.popen();
+            self.notify_result(&cmdline, &p);
+            let mut p = p?;
+            match p.wait_timeout(timeout)? {
+                Some(status) => {
+                    self.notify(&cmdline, &Outcome::from_status(status));
+                    Ok(Some(status))
+                }
+                None => {
+                    p.kill()?;
+                    p.wait()?;
+                    self.notify(&cmdline, &Outcome::Timeout(timeout));
+                    Ok(None)
+                }
+            }
+        }
+
+        /// Run the command like [`Exec::capture`], invoking the handlers for
+        /// its outcome.
+        ///
+        /// If the context has a [`timeout`], it bounds both reading the
+        /// output and waiting for the command to exit.
+        ///
+        /// [`Exec::capture`]: struct.Exec.html#method.capture
+        /// [`timeout`]: struct.ExecContext.html#method.timeout
+        pub fn capture(&self, exec: Exec) -> PopenResult<CaptureData> {
+            let deadline = self
+                .timeout
+                .map(|timeout| (Instant::now() + timeout, timeout));
+            let cmdline = exec.to_cmdline_lossy();
+            let started = exec.setup_communicate();
+            self.notify_result(&cmdline, &started);
+            let (mut comm, mut p) = started?;
+            if let Some((_, timeout)) = deadline {
+                comm = comm.limit_time(timeout);
+            }
+            let (maybe_out, maybe_err) = match (comm.read(), deadline) {
+                (Ok(capture), _) => capture,
+                (Err(e), Some((_, timeout))) if e.kind() == io::ErrorKind::TimedOut => {
+                    return self.kill_timed_out(&cmdline, p, timeout, e.error);
+                }
+                (Err(e), _) => return Err(e.into()),
+            };
+            // the process may close its output before exiting
+            let exit_status = match deadline {
+                Some((deadline, timeout)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match p.wait_timeout(remaining)? {
+                        Some(exit_status) => exit_status,
+                        None => {
+                            let error = io::Error::new(io::ErrorKind::TimedOut, "timeout");
+                            return self.kill_timed_out(&cmdline, p, timeout, error);
+                        }
+                    }
+                }
+                None => p.wait()?,
+            };
+            let capture = CaptureData {
+                stdout: maybe_out.unwrap_or_else(Vec::new),
+                stderr: maybe_err.unwrap_or_else(Vec::new),
+                exit_status,
+            };
+            self.notify(&cmdline, &Outcome::from_status(capture.exit_status));
+            Ok(capture)
+        }
+    }
+
+    impl fmt::Debug for ExecContext {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let classes: Vec<_> = self.handlers.iter().map(|(class, _)| class).collect();
+            f.debug_struct("ExecContext")
+                .field("handlers", &classes)
+                .field("timeout", &self.timeout)
+                .finish()
+        }
+    }
+}
+
+mod coprocess {
+    use std::fmt;
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+
+    use crate::popen::{Popen, Redirection, Result as PopenResult};
+
+    use super::exec::Exec;
+
+    /// A long-lived subprocess that is restarted when it dies.
+    ///
+    /// `Coprocess` keeps a child process, such as a formatter or a
+    /// language server, running across requests.  Requests are made
+    /// with [`call`] or [`call_with`], which write to the standard
+    /// input of the child and read from its standard output.  If the
+    /// child has died, it is transparently started again, and a
+    /// request that failed because of the child dying is retried once
+    /// with the new child.
+    ///
+    /// The child is started lazily, on the first request.  The
+    /// handler registered with [`on_start`] runs after every start,
+    /// and can be used to initialize the child.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use subprocess::*;
+    /// # fn dummy() -> Result<()> {
+    /// let mut coproc = Coprocess::new(Exec::cmd("cat"));
+    /// assert_eq!(coproc.call(b"hello\n")?, b"hello\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`call`]: struct.Coprocess.html#method.call
+    /// [`call_with`]: struct.Coprocess.html#method.call_with
+    /// [`on_start`]: struct.Coprocess.html#method.on_start
+    pub struct Coprocess {
+        exec: Exec,
+        // Declared before `child` so that stdin is closed before
+        // dropping the Popen waits for the child to exit.
+        io: Option<(File, BufReader<File>)>,
+        child: Option<Popen>,
+        on_start: Option<Box<dyn FnMut(&mut CoprocessIo<'_>) -> io::Result<()> + Send>>,
+        restarts: u64,
+    }
+
+    /// Standard streams of the child of a [`Coprocess`].
+    ///
+    /// [`Coprocess`]: struct.Coprocess.html
+    #[derive(Debug)]
+    pub struct CoprocessIo<'a> {
+        /// Standard input of the child.
+        pub stdin: &'a mut File,
+        /// Standard output of the child.
+        pub stdout: &'a mut BufReader<File>,
+    }
+
+    impl CoprocessIo<'_> {
+        /// Write `data` to the standard input of the child and flush it.
+        pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+            self.stdin.write_all(data)?;
+            self.stdin.flush()
+        }
+
+        /// Read a line from the standard output of the child.
+        ///
+        /// The returned line includes the terminating newline, if any.
+        /// Reaching end-of-file before reading anything is reported as
+        /// an error of kind `UnexpectedEof`.
+        pub fn receive_line(&mut self) -> io::Result<Vec<u8>> {
+            let mut line = vec![];
+            if self.stdout.read_until(b'\n', &mut line)? == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "coprocess closed its output",
+                ));
+            }
+            Ok(line)
+        }
+    }
+
+    impl Coprocess {
+        /// Create a `Coprocess` that runs `exec`.
+        ///
+        /// The standard input and output of `exec` are redirected to
+        /// pipes.  `exec` is cloned for each start of the child, so it
+        /// must not feed data to the standard input or redirect it to
+        /// a `File`.
+        pub fn new(exec: Exec) -> Coprocess {
+            Coprocess {
+                exec: exec.stdin(Redirection::Pipe).stdout(Redirection::Pipe),
+                io: None,
+                child: None,
+                on_start: None,
+                restarts: 0,
+            }
+        }
+
+        /// Register a handler invoked after each start of the child.
+        ///
+        /// The handler can send initialization commands to the child
+        /// and read its responses.  If it fails, the start is
+        /// considered failed.
+        pub fn on_start(
+            mut self,
+            handler: impl FnMut(&mut CoprocessIo<'_>) -> io::Result<()> + Send + 'static,
+        ) -> Coprocess {
+            self.on_start = Some(Box::new(handler));
+            self
+        }
+
+        /// Send `request` to the child and return a line of its response.
+        ///
+        /// This is a shorthand for [`call_with`] that sends the request
+        /// and receives a line with [`CoprocessIo::receive_line`].
+        ///
+        /// [`call_with`]: struct.Coprocess.html#method.call_with
+        /// [`CoprocessIo::receive_line`]: struct.CoprocessIo.html#method.receive_line
+        pub fn call(&mut self, request: &[u8]) -> PopenResult<Vec<u8>> {
+            self.call_with(|io| {
+                io.send(request)?;
+                io.receive_line()
+            })
+        }
+
+        /// Make a request with the given function.
+        ///
+        /// `f` communicates with the child through its standard streams.
+        /// The child is started first if it is not running.  If `f`
+        /// fails and the child turns out to have died, the child is
+        /// started again and `f` is retried once.  Errors that occur
+        /// while the child is still running are returned without
+        /// retrying.
+        pub fn call_with<R>(
+            &mut self,
+            mut f: impl FnMut(&mut CoprocessIo<'_>) -> io::Result<R>,
+        ) -> PopenResult<R> {
+            self.ensure_running()?;
+            match self.with_io(&mut f) {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    if !self.child_died(&e) {
+                        return Err(e.into());
+                    }
+                }
+            }
+            self.stop();
+            self.restarts += 1;
+            self.ensure_running()?;
+            Ok(self.with_io(&mut f)?)
+        }
+
+        /// Return the number of times the child was restarted after it
+        /// died during a request.
+        pub fn restarts(&self) -> u64 {
+            self.restarts
+        }
+
+        /// Return the `Popen` of the running child, if any.
+        pub fn child(&mut self) -> Option<&mut Popen> {
+            self.child.as_mut()
+        }
+
+        fn with_io<R>(
+            &mut self,
+            f: &mut impl FnMut(&mut CoprocessIo<'_>) -> io::Result<R>,
+        ) -> io::Result<R> {
+            let (stdin, stdout) = self.io.as_mut().unwrap();
+            f(&mut CoprocessIo { stdin, stdout })
+        }
+
+        fn child_died(&mut self, err: &io::Error) -> bool {
+            match err.kind() {
+                ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof => true,
+                _ => self.child.as_mut().unwrap().poll().is_some(),
+            }
+        }
+
+        fn ensure_running(&mut self) -> PopenResult<()> {
+            if let Some(ref mut child) = self.child {
+                if child.poll().is_none() {
+                    return Ok(());
+                }
+                self.stop();
+            }
+            let mut child = self.exec.clone().popen()?;
+            let stdin = child.stdin.take().unwrap();
+            let stdout = BufReader::new(child.stdout.take().unwrap());
+            self.child = Some(child);
+            self.io = Some((stdin, stdout));
+            if let Some(ref mut on_start) = self.on_start {
+                let (stdin, stdout) = self.io.as_mut().unwrap();
+                if let Err(e) = on_start(&mut CoprocessIo { stdin, stdout }) {
+                    self.stop();
+                    return Err(e.into());
+                }
+            }
+            Ok(())
+        }
+
+        // Close the streams of the child and make sure it is gone.
+        fn stop(&mut self) {
+            self.io = None;
+            if let Some(mut child) = self.child.take() {
+                if child.poll().is_none() {
+                    child.kill().ok();
+                }
+                child.wait().ok();
+            }
+        }
+    }
+
+    impl fmt::Debug for Coprocess {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Coprocess")
+                .field("exec", &self.exec)
+                .field("child", &self.child)
+                .field("restarts", &self.restarts)
+                .finish()
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// Control groups (cgroup v2) in which subprocesses can be started, see
+// PopenConfig::cgroup.
+
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A cgroup v2 directory, for limiting the resources of subprocesses.
+///
+/// A subprocess is placed in the cgroup by passing its path to
+/// [`PopenConfig::cgroup`].  The limits are written to the control
+/// files of the cgroup, which requires the corresponding controllers
+/// to be enabled in the `cgroup.subtree_control` of its parent.
+///
+/// [`PopenConfig::cgroup`]: ../struct.PopenConfig.html#structfield.cgroup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Refer to an existing cgroup directory.
+    pub fn open(path: impl Into<PathBuf>) -> Cgroup {
+        Cgroup { path: path.into() }
+    }
+
+    /// Create the cgroup directory, unless it already exists.
+    ///
+    /// The parent directory must be a cgroup, typically under
+    /// `/sys/fs/cgroup`, which the calling process has permission to
+    /// modify.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Cgroup> {
+        let path = path.into();
+        match fs::create_dir(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+            result => result?,
+        }
+        Ok(Cgroup { path })
+    }
+
+    /// Return the path of the cgroup directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Limit the memory used by the processes in the cgroup.
+    ///
+    /// Writes `memory.max`.  `None` removes the limit.
+    pub fn set_memory_max(&self, bytes: Option<u64>) -> io::Result<()> {
+        match bytes {
+            Some(bytes) => self.write("memory.max", &bytes.to_string()),
+            None => self.write("memory.max", "max"),
+        }
+    }
+
+    /// Limit the CPU time used by the processes in the cgroup to
+    /// `quota` in each `period`.
+    ///
+    /// Writes `cpu.max`, with a resolution of one microsecond.  For
+    /// example, a quota of 50ms per period of 100ms allows half of one
+    /// CPU.  `None` removes the limit.
+    pub fn set_cpu_max(&self, quota: Option<Duration>, period: Duration) -> io::Result<()> {
+        let period = period.as_micros();
+        let value = match quota {
+            Some(quota) => format!("{} {}", quota.as_micros(), period),
+            None => format!("max {}", period),
+        };
+        self.write("cpu.max", &value)
+    }
+
+    /// Remove the cgroup directory.
+    ///
+    /// This fails while processes remain in the cgroup.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_dir(&self.path)
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(self.path.join(file))?;
+        file.write_all(value.as_bytes())
+    }
+}
+
+impl AsRef<OsStr> for Cgroup {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+impl AsRef<Path> for Cgroup {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
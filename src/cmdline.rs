@@ -0,0 +1,189 @@
+use std::io;
+
+/// Rules for quoting arguments when composing a Windows command line.
+///
+/// Windows passes the command line to the new process as a single
+/// string, leaving it to the program to split it into arguments.  Most
+/// programs use the rules of `CommandLineToArgvW` and the MSVC runtime,
+/// but some, most notably `cmd.exe` and the batch files it runs, use
+/// different ones.  The quoting is selected with
+/// [`PopenConfig::arg_quoting`], and is ignored on other platforms.
+///
+/// [`PopenConfig::arg_quoting`]: struct.PopenConfig.html#structfield.arg_quoting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum ArgQuoting {
+    /// Quoting understood by `CommandLineToArgvW` and the MSVC runtime.
+    ///
+    /// Arguments containing whitespace or double quotes are enclosed in
+    /// double quotes, and double quotes inside them are escaped with a
+    /// backslash.  This is the default.
+    #[default]
+    Standard,
+
+    /// Quoting understood by `cmd.exe`, for running batch files.
+    ///
+    /// In addition to whitespace, arguments containing characters
+    /// special to `cmd.exe`, such as `&`, `|`, or `%`, are enclosed in
+    /// double quotes.  Double quotes inside them are doubled, and `%` is
+    /// escaped so that it doesn't expand variables.  Arguments
+    /// containing newlines can't be passed and are rejected.  Unless
+    /// `PopenConfig::allow_batch` is set, these rules are used for batch
+    /// files whatever the configured quoting.
+    Cmd,
+
+    /// Quoting for programs that don't treat backslash as an escape.
+    ///
+    /// Every argument is enclosed in double quotes, and double quotes
+    /// inside it are doubled, as expected by programs using the legacy
+    /// parsing rule where `""` inside a quoted argument stands for a
+    /// literal double quote.
+    MsvcLegacy,
+}
+
+impl ArgQuoting {
+    /// Compose a command line from `args` using these quoting rules.
+    ///
+    /// This returns the command line that would be passed to
+    /// `CreateProcess` on Windows.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if an argument contains
+    /// a NUL character, or a character that can't be passed with these
+    /// rules.
+    pub fn join(self, args: &[impl AsRef<str>]) -> io::Result<String> {
+        let args: Vec<Vec<u16>> = args
+            .iter()
+            .map(|arg| arg.as_ref().encode_utf16().collect())
+            .collect();
+        let cmdline = assemble(args.iter().map(Vec::as_slice), self)?;
+        Ok(String::from_utf16(&cmdline).unwrap())
+    }
+}
+
+fn is(c: u16, ascii: char) -> bool {
+    c == ascii as u16
+}
+
+// Join the UTF-16 encoded arguments into a command line.
+pub(crate) fn assemble<'a>(
+    args: impl IntoIterator<Item = &'a [u16]>,
+    quoting: ArgQuoting,
+) -> io::Result<Vec<u16>> {
+    let mut cmdline = vec![];
+    for (i, arg) in args.into_iter().enumerate() {
+        if i != 0 {
+            cmdline.push(' ' as u16);
+        }
+        if arg.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "argument contains a NUL character",
+            ));
+        }
+        match quoting {
+            ArgQuoting::Standard => append_quoted(arg, &mut cmdline),
+            ArgQuoting::Cmd => append_quoted_cmd(arg, &mut cmdline)?,
+            ArgQuoting::MsvcLegacy => append_quoted_doubling(arg, &mut cmdline),
+        }
+    }
+    Ok(cmdline)
+}
+
+// Translated from ArgvQuote at http://tinyurl.com/zmgtnls
+fn append_quoted(arg: &[u16], cmdline: &mut Vec<u16>) {
+    if !arg.is_empty()
+        && !arg
+            .iter()
+            .any(|&c| is(c, ' ') || is(c, '\t') || is(c, '\n') || is(c, '\x0b') || is(c, '"'))
+    {
+        cmdline.extend(arg);
+        return;
+    }
+    cmdline.push('"' as u16);
+
+    let mut i = 0;
+    while i < arg.len() {
+        let mut num_backslashes = 0;
+        while i < arg.len() && is(arg[i], '\\') {
+            i += 1;
+            num_backslashes += 1;
+        }
+
+        if i == arg.len() {
+            for _ in 0..num_backslashes * 2 {
+                cmdline.push('\\' as u16);
+            }
+            break;
+        } else if is(arg[i], '"') {
+            for _ in 0..num_backslashes * 2 + 1 {
+                cmdline.push('\\' as u16);
+            }
+            cmdline.push(arg[i]);
+        } else {
+            for _ in 0..num_backslashes {
+                cmdline.push('\\' as u16);
+            }
+            cmdline.push(arg[i]);
+        }
+        i += 1;
+    }
+    cmdline.push('"' as u16);
+}
+
+fn append_quoted_cmd(arg: &[u16], cmdline: &mut Vec<u16>) -> io::Result<()> {
+    if arg.iter().any(|&c| is(c, '\r') || is(c, '\n')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "newlines can't be passed to cmd.exe",
+        ));
+    }
+    let needs_quotes = arg.is_empty()
+        || arg.iter().any(|&c| {
+            " \t\x0b\"&|<>^()%!,;="
+                .encode_utf16()
+                .any(|special| c == special)
+        });
+    if !needs_quotes {
+        cmdline.extend(arg);
+        return Ok(());
+    }
+    cmdline.push('"' as u16);
+    let mut trailing_backslashes = 0;
+    for &c in arg {
+        if is(c, '"') {
+            cmdline.extend("\"\"".encode_utf16());
+        } else if is(c, '%') {
+            // Expands to "%" followed by the empty substring of the
+            // (always defined) %cd% variable, which cmd.exe doesn't
+            // expand any further.
+            cmdline.extend("%%cd:~,%".encode_utf16());
+        } else {
+            cmdline.push(c);
+        }
+        trailing_backslashes = if is(c, '\\') {
+            trailing_backslashes + 1
+        } else {
+            0
+        };
+    }
+    // Double the trailing backslashes so that programs started by the
+    // batch file don't read the closing quote as escaped.
+    for _ in 0..trailing_backslashes {
+        cmdline.push('\\' as u16);
+    }
+    cmdline.push('"' as u16);
+    Ok(())
+}
+
+fn append_quoted_doubling(arg: &[u16], cmdline: &mut Vec<u16>) {
+    cmdline.push('"' as u16);
+    for &c in arg {
+        if is(c, '"') {
+            cmdline.push(c);
+        }
+        cmdline.push(c);
+    }
+    cmdline.push('"' as u16);
+}
@@ -1,15 +1,23 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, ErrorKind};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[cfg(unix)]
 mod raw {
+    use super::{CommunicateEvent as Event, InputSource, Progress, Stream, Tracer};
     use crate::posix;
     use std::cmp::min;
+    use std::collections::VecDeque;
     use std::fs::File;
     use std::io::{self, Read, Write};
+    use std::mem;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+    use std::thread;
     use std::time::{Duration, Instant};
 
     fn as_pollfd<'a>(f: Option<&'a File>, for_read: bool) -> posix::PollFd<'a> {
@@ -63,13 +71,236 @@ mod raw {
         ))
     }
 
+    type Chunk = io::Result<Vec<u8>>;
+
+    // Read a file or reader source in chunks and send them to the sink,
+    // until end-of-file, an error, or the receiver going away.
+    fn read_chunks(mut source: InputSource, sink: SyncSender<Chunk>) {
+        const READ_SIZE: usize = 65536;
+
+        loop {
+            let mut chunk = vec![0; READ_SIZE];
+            let n = match source.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = sink.send(Err(e));
+                    break;
+                }
+            };
+            chunk.truncate(n);
+            if sink.send(Ok(chunk)).is_err() {
+                break;
+            }
+        }
+    }
+
+    // The remaining chunks of a file or reader source, returned to the
+    // caller by take_input().
+    struct ChunkReader {
+        chunks: Receiver<Chunk>,
+        chunk: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.pos == self.chunk.len() {
+                self.chunk = match self.chunks.recv() {
+                    Ok(chunk) => chunk?,
+                    Err(_) => return Ok(0),
+                };
+                self.pos = 0;
+            }
+            let n = min(buf.len(), self.chunk.len() - self.pos);
+            buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    // Input data pending to be written to the subprocess, taken from the
+    // sources in order.  In-memory sources are written directly, while files
+    // and readers are read by a helper thread, so that a read blocking on
+    // the source cannot hold up the communication past its deadline.
+    #[derive(Debug)]
+    struct InputFeed {
+        sources: VecDeque<InputSource>,
+        // the in-memory source being written
+        current: Option<InputSource>,
+        // chunks of the file or reader source being written, the last one
+        // received is in `buf`
+        chunks: Option<Receiver<Chunk>>,
+        buf: Vec<u8>,
+        pos: usize,
+    }
+
+    impl InputFeed {
+        fn new(sources: Vec<InputSource>) -> InputFeed {
+            InputFeed {
+                sources: sources.into(),
+                current: None,
+                chunks: None,
+                buf: Vec::new(),
+                pos: 0,
+            }
+        }
+
+        fn start(&mut self, source: InputSource) {
+            self.pos = 0;
+            self.buf.clear();
+            if source.as_slice().is_some() {
+                self.current = Some(source);
+                return;
+            }
+            let (tx, rx) = mpsc::sync_channel(1);
+            thread::spawn(move || read_chunks(source, tx));
+            self.chunks = Some(rx);
+        }
+
+        // Return the data that hasn't been written yet, refilling it from the
+        // next source if needed.  An empty slice means that all sources have
+        // been exhausted.  Fails with `TimedOut` if a file or reader source
+        // doesn't provide data before the deadline.
+        fn pending(&mut self, deadline: Option<Instant>) -> io::Result<&[u8]> {
+            loop {
+                let len = match (&self.current, &self.chunks) {
+                    (Some(current), _) => current.as_slice().unwrap().len(),
+                    (None, Some(_)) => self.buf.len(),
+                    (None, None) => match self.sources.pop_front() {
+                        Some(source) => {
+                            self.start(source);
+                            continue;
+                        }
+                        None => return Ok(&[]),
+                    },
+                };
+                if self.pos < len {
+                    break;
+                }
+                self.pos = 0;
+                if self.current.take().is_some() {
+                    continue;
+                }
+                self.buf.clear();
+                let chunks = self.chunks.as_ref().unwrap();
+                let received = match deadline {
+                    Some(deadline) => {
+                        chunks.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => chunks.recv().map_err(RecvTimeoutError::from),
+                };
+                match received {
+                    Ok(Ok(chunk)) => self.buf = chunk,
+                    Ok(Err(e)) => {
+                        self.chunks = None;
+                        return Err(e);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                    }
+                    // the source is exhausted
+                    Err(RecvTimeoutError::Disconnected) => self.chunks = None,
+                }
+            }
+            let data = match self.current {
+                Some(ref current) => current.as_slice().unwrap(),
+                None => &self.buf,
+            };
+            Ok(&data[self.pos..])
+        }
+
+        fn advance(&mut self, n: usize) {
+            self.pos += n;
+        }
+
+        fn is_exhausted(&self) -> bool {
+            let current_done = match self.current {
+                None => true,
+                Some(ref current) => self.pos == current.as_slice().unwrap().len(),
+            };
+            current_done && self.chunks.is_none() && self.sources.is_empty()
+        }
+
+        // Convert the data not yet written back into a list of sources.
+        fn into_sources(self) -> Vec<InputSource> {
+            let mut sources = Vec::with_capacity(self.sources.len() + 2);
+            if let Some(current) = self.current {
+                let data = current.as_slice().unwrap();
+                if self.pos < data.len() {
+                    sources.push(InputSource::Bytes(data[self.pos..].to_vec()));
+                }
+            }
+            if let Some(chunks) = self.chunks {
+                sources.push(InputSource::Reader(Box::new(ChunkReader {
+                    chunks,
+                    chunk: self.buf,
+                    pos: self.pos,
+                })));
+            }
+            sources.extend(self.sources);
+            sources
+        }
+    }
+
     #[derive(Debug)]
     pub struct RawCommunicator {
         stdin: Option<File>,
         stdout: Option<File>,
         stderr: Option<File>,
-        input_data: Vec<u8>,
-        input_pos: usize,
+        input: InputFeed,
+        out_eof: bool,
+        err_eof: bool,
+        // stdin was closed by a read_step() that reported written data
+        stdin_eof_pending: bool,
+        input_written: u64,
+        // how much to read from stdout and stderr at a time
+        read_sizes: [usize; 2],
+        pub tracer: Tracer,
+    }
+
+    fn raw_fd(f: Option<&File>) -> Option<RawFd> {
+        f.map(File::as_raw_fd)
+    }
+
+    // Note: chunk size for writing must be smaller than the pipe buffer
+    // size.  A large enough write to a pipe deadlocks despite polling.
+    const WRITE_SIZE: usize = 4096;
+
+    // Read up to the capacity of a pipe at a time, so that a subprocess
+    // filling a large pipe is drained in one read.
+    const MIN_READ_SIZE: usize = 4096;
+
+    #[cfg(target_os = "linux")]
+    fn read_size(f: Option<&File>) -> usize {
+        f.and_then(|f| posix::pipe_size(f.as_raw_fd()).ok())
+            .map_or(MIN_READ_SIZE, |size| size.max(MIN_READ_SIZE))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read_size(_f: Option<&File>) -> usize {
+        MIN_READ_SIZE
+    }
+
+    // Write the next chunk of input, closing stdin once the input is
+    // exhausted.
+    fn write_input(
+        stdin: &mut Option<File>,
+        input: &mut InputFeed,
+        deadline: Option<Instant>,
+    ) -> io::Result<usize> {
+        let data = input.pending(deadline)?;
+        let chunk = &data[..min(WRITE_SIZE, data.len())];
+        let n = stdin.as_ref().unwrap().write(chunk)?;
+        input.advance(n);
+        if input.is_exhausted() {
+            // close stdin when done writing, so the child receives EOF
+            stdin.take();
+            // deallocate the input data, we don't need it any more
+            *input = InputFeed::new(Vec::new());
+        }
+        Ok(n)
     }
 
     impl RawCommunicator {
@@ -77,37 +308,60 @@ mod raw {
             stdin: Option<File>,
             stdout: Option<File>,
             stderr: Option<File>,
-            input_data: Option<Vec<u8>>,
+            input_data: Option<Vec<InputSource>>,
         ) -> RawCommunicator {
-            let input_data = input_data.unwrap_or_else(Vec::new);
+            let read_sizes = [read_size(stdout.as_ref()), read_size(stderr.as_ref())];
             RawCommunicator {
                 stdin,
                 stdout,
                 stderr,
-                input_data,
-                input_pos: 0,
+                input: InputFeed::new(input_data.unwrap_or_default()),
+                out_eof: false,
+                err_eof: false,
+                stdin_eof_pending: false,
+                input_written: 0,
+                read_sizes,
+                tracer: Tracer::default(),
             }
         }
 
+        pub fn take_input(&mut self) -> (Option<File>, Vec<InputSource>) {
+            let input = mem::replace(&mut self.input, InputFeed::new(Vec::new()));
+            (self.stdin.take(), input.into_sources())
+        }
+
         fn do_read(
             source_ref: &mut Option<&File>,
             dest: &mut Vec<u8>,
+            read_size: usize,
             size_limit: Option<usize>,
             total_read: usize,
         ) -> io::Result<()> {
-            let mut buf = &mut [0u8; 4096][..];
+            let mut size = read_size;
             if let Some(size_limit) = size_limit {
                 if total_read >= size_limit {
                     return Ok(());
                 }
-                if size_limit - total_read < buf.len() {
-                    buf = &mut buf[0..size_limit - total_read];
-                }
+                size = min(size, size_limit - total_read);
             }
-            let n = source_ref.unwrap().read(buf)?;
-            if n != 0 {
-                dest.extend_from_slice(&buf[..n]);
-            } else {
+            // read directly into the spare capacity of dest, which
+            // unlike resizing it doesn't zero-fill up to a whole pipe
+            // buffer on each read
+            dest.reserve(size);
+            let fd = source_ref.unwrap().as_raw_fd();
+            let buf = dest.spare_capacity_mut().as_mut_ptr().cast();
+            let n = match unsafe { libc::read(fd, buf, size) } {
+                -1 => match io::Error::last_os_error() {
+                    // the master side of a pseudo terminal fails with EIO
+                    // once the slave side is closed
+                    e if e.raw_os_error() == Some(libc::EIO) => 0,
+                    e => return Err(e),
+                },
+                n => n as usize,
+            };
+            // the kernel has initialized the n bytes it read
+            unsafe { dest.set_len(dest.len() + n) };
+            if n == 0 {
                 *source_ref = None;
             }
             Ok(())
@@ -120,12 +374,8 @@ mod raw {
             outvec: &mut Vec<u8>,
             errvec: &mut Vec<u8>,
         ) -> io::Result<()> {
-            // Note: chunk size for writing must be smaller than the pipe buffer
-            // size.  A large enough write to a pipe deadlocks despite polling.
-            const WRITE_SIZE: usize = 4096;
-
-            let mut stdout_ref = self.stdout.as_ref();
-            let mut stderr_ref = self.stderr.as_ref();
+            let mut stdout_ref = self.stdout.as_ref().filter(|_| !self.out_eof);
+            let mut stderr_ref = self.stderr.as_ref().filter(|_| !self.err_eof);
 
             loop {
                 if let Some(size_limit) = size_limit {
@@ -139,44 +389,133 @@ mod raw {
                     break;
                 }
 
+                let fds = [
+                    raw_fd(self.stdin.as_ref()),
+                    raw_fd(stdout_ref),
+                    raw_fd(stderr_ref),
+                ];
                 let (in_ready, out_ready, err_ready) =
                     maybe_poll(self.stdin.as_ref(), stdout_ref, stderr_ref, deadline)?;
+                let ready = [in_ready, out_ready, err_ready];
                 if !in_ready && !out_ready && !err_ready {
+                    self.tracer.trace(fds, ready, [0, 0, 0]);
                     return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
                 }
+                let (mut written, out_before, err_before) = (0, outvec.len(), errvec.len());
                 if in_ready {
-                    let input = &self.input_data[self.input_pos..];
-                    let chunk = &input[..min(WRITE_SIZE, input.len())];
-                    let n = self.stdin.as_ref().unwrap().write(chunk)?;
-                    self.input_pos += n;
-                    if self.input_pos == self.input_data.len() {
-                        // close stdin when done writing, so the child receives EOF
-                        self.stdin.take();
-                        // deallocate the input data, we don't need it any more
-                        self.input_data = Vec::new();
-                    }
+                    written = write_input(&mut self.stdin, &mut self.input, deadline)?;
+                    self.input_written += written as u64;
                 }
                 if out_ready {
                     RawCommunicator::do_read(
                         &mut stdout_ref,
                         outvec,
+                        self.read_sizes[0],
                         size_limit,
                         outvec.len() + errvec.len(),
                     )?;
+                    self.out_eof = stdout_ref.is_none();
                 }
                 if err_ready {
                     RawCommunicator::do_read(
                         &mut stderr_ref,
                         errvec,
+                        self.read_sizes[1],
                         size_limit,
                         outvec.len() + errvec.len(),
                     )?;
+                    self.err_eof = stderr_ref.is_none();
                 }
+                let bytes = [
+                    written,
+                    outvec.len() - out_before,
+                    errvec.len() - err_before,
+                ];
+                self.tracer.trace(fds, ready, bytes);
             }
 
             Ok(())
         }
 
+        pub fn progress(&self) -> Progress {
+            Progress {
+                input_written: self.input_written,
+                stdin_closed: self.stdin.is_none(),
+                stdout_eof: self.stdout.is_none() || self.out_eof,
+                stderr_eof: self.stderr.is_none() || self.err_eof,
+            }
+        }
+
+        pub fn read_step(&mut self, deadline: Option<Instant>) -> io::Result<Option<Event>> {
+            if self.stdin_eof_pending {
+                self.stdin_eof_pending = false;
+                return Ok(Some(Event::Eof(Stream::Stdin)));
+            }
+            let mut stdout_ref = self.stdout.as_ref().filter(|_| !self.out_eof);
+            let mut stderr_ref = self.stderr.as_ref().filter(|_| !self.err_eof);
+            if let (None, None, None) = (self.stdin.as_ref(), stdout_ref, stderr_ref) {
+                return Ok(None);
+            }
+
+            let fds = [
+                raw_fd(self.stdin.as_ref()),
+                raw_fd(stdout_ref),
+                raw_fd(stderr_ref),
+            ];
+            let (in_ready, out_ready, err_ready) =
+                maybe_poll(self.stdin.as_ref(), stdout_ref, stderr_ref, deadline)?;
+            // Handle a single ready stream, the others will be reported
+            // ready again by the next poll.
+            let event = if in_ready {
+                match write_input(&mut self.stdin, &mut self.input, deadline) {
+                    // the input source didn't provide data in time
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Event::Idle,
+                    result => {
+                        let n = result?;
+                        self.input_written += n as u64;
+                        let closed = self.stdin.is_none();
+                        if n == 0 && closed {
+                            Event::Eof(Stream::Stdin)
+                        } else {
+                            self.stdin_eof_pending = closed;
+                            Event::WroteInput(n)
+                        }
+                    }
+                }
+            } else if out_ready {
+                let mut data = vec![];
+                RawCommunicator::do_read(&mut stdout_ref, &mut data, self.read_sizes[0], None, 0)?;
+                data.shrink_to_fit();
+                self.out_eof = stdout_ref.is_none();
+                if self.out_eof {
+                    Event::Eof(Stream::Stdout)
+                } else {
+                    Event::Stdout(data)
+                }
+            } else if err_ready {
+                let mut data = vec![];
+                RawCommunicator::do_read(&mut stderr_ref, &mut data, self.read_sizes[1], None, 0)?;
+                data.shrink_to_fit();
+                self.err_eof = stderr_ref.is_none();
+                if self.err_eof {
+                    Event::Eof(Stream::Stderr)
+                } else {
+                    Event::Stderr(data)
+                }
+            } else {
+                Event::Idle
+            };
+            let bytes = match event {
+                Event::WroteInput(n) => [n, 0, 0],
+                Event::Stdout(ref data) => [0, data.len(), 0],
+                Event::Stderr(ref data) => [0, 0, data.len()],
+                _ => [0, 0, 0],
+            };
+            self.tracer
+                .trace(fds, [in_ready, out_ready, err_ready], bytes);
+            Ok(Some(event))
+        }
+
         pub fn read(
             &mut self,
             deadline: Option<Instant>,
@@ -199,9 +538,12 @@ mod raw {
 
 #[cfg(windows)]
 mod raw {
+    use super::{CommunicateEvent as Event, InputSource, Progress, Stream, Tracer};
     use std::fs::File;
     use std::io::{self, Read, Write};
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+    use std::sync::Arc;
     use std::thread;
     use std::time::Instant;
 
@@ -246,6 +588,35 @@ mod raw {
         }
     }
 
+    fn write_sources(
+        stdin: &mut File,
+        sources: Vec<InputSource>,
+        written: &AtomicU64,
+    ) -> io::Result<()> {
+        let mut buf = vec![];
+        for mut source in sources {
+            if let Some(data) = source.as_slice() {
+                for chunk in data.chunks(4096) {
+                    stdin.write_all(chunk)?;
+                    written.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+                continue;
+            }
+            buf.resize(4096, 0);
+            loop {
+                let n = match source.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                stdin.write_all(&buf[..n])?;
+                written.fetch_add(n as u64, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
     fn spawn_with_arg<T: Send + 'static>(f: impl FnOnce(T) + Send + 'static, arg: T) {
         thread::spawn(move || f(arg));
     }
@@ -253,9 +624,29 @@ mod raw {
     #[derive(Debug)]
     pub struct RawCommunicator {
         rx: mpsc::Receiver<Message>,
+        tx: SyncSender<Message>,
+        // The standard input and the data to feed it.  The helper thread
+        // writing it is started on first read, so that the input can
+        // still be taken out by take_input().
+        pending_input: Option<(File, Vec<InputSource>)>,
         helper_set: u8,
         requested_streams: u8,
         leftover: Option<(StreamIdent, Vec<u8>)>,
+        input_written: Arc<AtomicU64>,
+        pub tracer: Tracer,
+    }
+
+    // Readiness and bytes moved for tracing the receipt of a message
+    fn trace_args(ident: StreamIdent, len: usize) -> ([bool; 3], [usize; 3]) {
+        let (mut ready, mut bytes) = ([false; 3], [0; 3]);
+        let idx = match ident {
+            StreamIdent::In => 0,
+            StreamIdent::Out => 1,
+            StreamIdent::Err => 2,
+        };
+        ready[idx] = true;
+        bytes[idx] = len;
+        (ready, bytes)
     }
 
     struct Timeout;
@@ -265,7 +656,7 @@ mod raw {
             stdin: Option<File>,
             stdout: Option<File>,
             stderr: Option<File>,
-            input_data: Option<Vec<u8>>,
+            input_data: Option<Vec<InputSource>>,
         ) -> RawCommunicator {
             let mut helper_set = 0u8;
             let mut requested_streams = 0u8;
@@ -280,29 +671,56 @@ mod raw {
                 requested_streams |= StreamIdent::Err as u8;
                 |tx| read_and_transmit(stderr, StreamIdent::Err, tx)
             });
-            let write_stdin = stdin.map(|mut stdin| {
+            let pending_input = stdin.map(|stdin| {
                 let input_data = input_data.expect("must provide input to redirected stdin");
                 helper_set |= StreamIdent::In as u8;
-                move |tx: SyncSender<_>| match stdin.write_all(&input_data) {
-                    Ok(()) => drop(tx.send((StreamIdent::In, Payload::EOF))),
-                    Err(e) => drop(tx.send((StreamIdent::In, Payload::Err(e)))),
-                }
+                (stdin, input_data)
             });
 
             let (tx, rx) = mpsc::sync_channel(0);
 
             read_stdout.map(|f| spawn_with_arg(f, tx.clone()));
             read_stderr.map(|f| spawn_with_arg(f, tx.clone()));
-            write_stdin.map(|f| spawn_with_arg(f, tx.clone()));
 
             RawCommunicator {
                 rx,
+                tx,
+                pending_input,
                 helper_set,
                 requested_streams,
                 leftover: None,
+                input_written: Arc::new(AtomicU64::new(0)),
+                tracer: Tracer::default(),
             }
         }
 
+        pub fn take_input(&mut self) -> (Option<File>, Vec<InputSource>) {
+            match self.pending_input.take() {
+                Some((stdin, input_data)) => {
+                    self.helper_set &= !(StreamIdent::In as u8);
+                    (Some(stdin), input_data)
+                }
+                // the helper thread already owns the input
+                None => (None, Vec::new()),
+            }
+        }
+
+        fn start_input(&mut self) {
+            let (mut stdin, input_data) = match self.pending_input.take() {
+                Some(pending) => pending,
+                None => return,
+            };
+            let input_written = Arc::clone(&self.input_written);
+            spawn_with_arg(
+                move |tx: SyncSender<_>| match write_sources(&mut stdin, input_data, &input_written)
+                {
+                    Ok(()) => drop(tx.send((StreamIdent::In, Payload::EOF))),
+                    Err(e) => drop(tx.send((StreamIdent::In, Payload::Err(e)))),
+                },
+                self.tx.clone(),
+            );
+        }
+
         fn recv_until(&self, deadline: Option<Instant>) -> Result<Message, Timeout> {
             if let Some(deadline) = deadline {
                 match self
@@ -353,6 +771,7 @@ mod raw {
                     return true;
                 };
 
+            self.start_input();
             if let Some((ident, data)) = self.leftover.take() {
                 if !grow_result(ident, &data, &mut self.leftover) {
                     return Ok(());
@@ -362,11 +781,15 @@ mod raw {
             while self.helper_set != 0 {
                 match self.recv_until(deadline) {
                     Ok((ident, Payload::EOF)) => {
+                        let (ready, bytes) = trace_args(ident, 0);
+                        self.tracer.trace(ready, bytes);
                         self.helper_set &= !(ident as u8);
                         continue;
                     }
                     Ok((ident, Payload::Data(data))) => {
                         assert!(data.len() != 0);
+                        let (ready, bytes) = trace_args(ident, data.len());
+                        self.tracer.trace(ready, bytes);
                         if !grow_result(ident, &data, &mut self.leftover) {
                             break;
                         }
@@ -375,6 +798,7 @@ mod raw {
                         return Err(e);
                     }
                     Err(Timeout) => {
+                        self.tracer.trace([false; 3], [0; 3]);
                         return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
                     }
                 }
@@ -382,6 +806,61 @@ mod raw {
             Ok(())
         }
 
+        pub fn progress(&self) -> Progress {
+            let done = |ident: StreamIdent| {
+                let has_leftover = match self.leftover {
+                    Some((leftover_ident, _)) => leftover_ident as u8 == ident as u8,
+                    None => false,
+                };
+                self.helper_set & ident as u8 == 0 && !has_leftover
+            };
+            Progress {
+                input_written: self.input_written.load(Ordering::Relaxed),
+                stdin_closed: done(StreamIdent::In),
+                stdout_eof: done(StreamIdent::Out),
+                stderr_eof: done(StreamIdent::Err),
+            }
+        }
+
+        pub fn read_step(&mut self, deadline: Option<Instant>) -> io::Result<Option<Event>> {
+            fn data_event(ident: StreamIdent, data: Vec<u8>) -> Event {
+                match ident {
+                    StreamIdent::Out => Event::Stdout(data),
+                    StreamIdent::Err => Event::Stderr(data),
+                    StreamIdent::In => unreachable!(),
+                }
+            }
+
+            self.start_input();
+            if let Some((ident, data)) = self.leftover.take() {
+                return Ok(Some(data_event(ident, data)));
+            }
+            if self.helper_set == 0 {
+                return Ok(None);
+            }
+            let message = self.recv_until(deadline);
+            let (ready, bytes) = match message {
+                Ok((ident, Payload::Data(ref data))) => trace_args(ident, data.len()),
+                Ok((ident, _)) => trace_args(ident, 0),
+                Err(Timeout) => ([false; 3], [0; 3]),
+            };
+            self.tracer.trace(ready, bytes);
+            let event = match message {
+                Ok((ident, Payload::EOF)) => {
+                    self.helper_set &= !(ident as u8);
+                    Event::Eof(match ident {
+                        StreamIdent::In => Stream::Stdin,
+                        StreamIdent::Out => Stream::Stdout,
+                        StreamIdent::Err => Stream::Stderr,
+                    })
+                }
+                Ok((ident, Payload::Data(data))) => data_event(ident, data),
+                Ok((_ident, Payload::Err(e))) => return Err(e),
+                Err(Timeout) => Event::Idle,
+            };
+            Ok(Some(event))
+        }
+
         pub fn read(
             &mut self,
             deadline: Option<Instant>,
@@ -416,6 +895,226 @@ mod raw {
 
 use raw::RawCommunicator;
 
+/// Information about an iteration of the communication loop, passed to the
+/// hook installed with [`Communicator::trace_hook`].
+///
+/// Only available with the `poll-trace` feature.
+///
+/// [`Communicator::trace_hook`]: struct.Communicator.html#method.trace_hook
+#[cfg(feature = "poll-trace")]
+#[derive(Debug, Clone)]
+pub struct PollTrace {
+    /// State of the standard input, output, and error, in that order.
+    pub streams: [StreamTrace; 3],
+}
+
+/// State of a single stream in an iteration of the communication loop.
+///
+/// Only available with the `poll-trace` feature.
+#[cfg(feature = "poll-trace")]
+#[derive(Debug, Clone)]
+pub struct StreamTrace {
+    /// File descriptor that was polled, or None if the stream was not
+    /// polled, e.g. because it's not redirected or already finished.
+    #[cfg(unix)]
+    pub fd: Option<std::os::unix::io::RawFd>,
+    /// Whether the stream was ready for reading or writing.  On Windows, a
+    /// stream is ready when its helper thread has reported progress.
+    pub ready: bool,
+    /// Number of bytes written to or read from the stream.
+    pub bytes: usize,
+}
+
+// Calls the hook installed with Communicator::trace_hook() in each
+// iteration of the communication loop.  Without the poll-trace feature,
+// tracing compiles to nothing.
+#[derive(Default)]
+pub struct Tracer {
+    #[cfg(feature = "poll-trace")]
+    hook: Option<Box<dyn FnMut(&PollTrace) + Send>>,
+}
+
+impl Tracer {
+    #[cfg(unix)]
+    #[inline]
+    fn trace(
+        &mut self,
+        _fds: [Option<std::os::unix::io::RawFd>; 3],
+        _ready: [bool; 3],
+        _bytes: [usize; 3],
+    ) {
+        #[cfg(feature = "poll-trace")]
+        {
+            if let Some(ref mut hook) = self.hook {
+                let stream = |i: usize| StreamTrace {
+                    fd: _fds[i],
+                    ready: _ready[i],
+                    bytes: _bytes[i],
+                };
+                hook(&PollTrace {
+                    streams: [stream(0), stream(1), stream(2)],
+                });
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    #[inline]
+    fn trace(&mut self, _ready: [bool; 3], _bytes: [usize; 3]) {
+        #[cfg(feature = "poll-trace")]
+        {
+            if let Some(ref mut hook) = self.hook {
+                let stream = |i: usize| StreamTrace {
+                    ready: _ready[i],
+                    bytes: _bytes[i],
+                };
+                hook(&PollTrace {
+                    streams: [stream(0), stream(1), stream(2)],
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "poll-trace")]
+        {
+            if self.hook.is_some() {
+                return f.write_str("Tracer(hook)");
+            }
+        }
+        f.write_str("Tracer")
+    }
+}
+
+/// A source of data fed to the standard input of the subprocess.
+///
+/// A list of `InputSource` values can be passed to
+/// [`Popen::communicate_start_sources`] or to [`Exec::stdin`], in which case
+/// the sources are written to the subprocess one after the other, like with
+/// `cat a b | cmd`.  Files and readers are read incrementally during
+/// communication, so large inputs don't need to be loaded into memory first.
+/// They are read on a helper thread, so a reader that blocks doesn't hold up
+/// a communication limited by [`Communicator::limit_time`].
+///
+/// [`Popen::communicate_start_sources`]: struct.Popen.html#method.communicate_start_sources
+/// [`Exec::stdin`]: struct.Exec.html#method.stdin
+/// [`Communicator::limit_time`]: struct.Communicator.html#method.limit_time
+pub enum InputSource {
+    /// Data provided in memory.
+    Bytes(Vec<u8>),
+    /// Data provided in memory and shared with other owners, such as
+    /// `Arc<[u8]>`.
+    ///
+    /// This allows feeding the same data to multiple subprocesses
+    /// without copying it for each of them.
+    Shared(Arc<dyn AsRef<[u8]> + Send + Sync>),
+    /// Contents of an open file, read until end-of-file.
+    File(File),
+    /// Data produced by an arbitrary reader, read until end-of-file.
+    Reader(Box<dyn Read + Send>),
+}
+
+impl InputSource {
+    /// Clone the underlying `InputSource`, or return an error.
+    ///
+    /// Fails for the `File` variant if `File::try_clone` fails, and always
+    /// fails for the `Reader` variant because readers cannot be cloned.
+    pub fn try_clone(&self) -> io::Result<InputSource> {
+        Ok(match *self {
+            InputSource::Bytes(ref data) => InputSource::Bytes(data.clone()),
+            InputSource::Shared(ref data) => InputSource::Shared(Arc::clone(data)),
+            InputSource::File(ref f) => InputSource::File(f.try_clone()?),
+            InputSource::Reader(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "cannot clone InputSource::Reader",
+                ))
+            }
+        })
+    }
+}
+
+impl fmt::Debug for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InputSource::Bytes(ref data) => write!(f, "Bytes({} bytes)", data.len()),
+            InputSource::Shared(ref data) => {
+                write!(f, "Shared({} bytes)", (**data).as_ref().len())
+            }
+            InputSource::File(ref file) => f.debug_tuple("File").field(file).finish(),
+            InputSource::Reader(_) => f.write_str("Reader(..)"),
+        }
+    }
+}
+
+impl InputSource {
+    // The data of the in-memory variants, None for File and Reader.
+    fn as_slice(&self) -> Option<&[u8]> {
+        match *self {
+            InputSource::Bytes(ref data) => Some(data),
+            InputSource::Shared(ref data) => Some((**data).as_ref()),
+            InputSource::File(_) | InputSource::Reader(_) => None,
+        }
+    }
+}
+
+impl Read for InputSource {
+    // Only used for reading the File and Reader variants, in-memory data
+    // is consumed directly.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            InputSource::Bytes(_) | InputSource::Shared(_) => unreachable!(),
+            InputSource::File(ref mut f) => f.read(buf),
+            InputSource::Reader(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+impl From<Vec<u8>> for InputSource {
+    fn from(data: Vec<u8>) -> InputSource {
+        InputSource::Bytes(data)
+    }
+}
+
+impl From<&[u8]> for InputSource {
+    fn from(data: &[u8]) -> InputSource {
+        InputSource::Bytes(data.to_vec())
+    }
+}
+
+impl From<&str> for InputSource {
+    fn from(s: &str) -> InputSource {
+        InputSource::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for InputSource {
+    fn from(s: String) -> InputSource {
+        InputSource::Bytes(s.into_bytes())
+    }
+}
+
+impl From<Arc<[u8]>> for InputSource {
+    fn from(data: Arc<[u8]>) -> InputSource {
+        InputSource::Shared(Arc::new(data))
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for InputSource {
+    fn from(data: bytes::Bytes) -> InputSource {
+        InputSource::Shared(Arc::new(data))
+    }
+}
+
+impl From<File> for InputSource {
+    fn from(f: File) -> InputSource {
+        InputSource::File(f)
+    }
+}
+
 /// Unattended data exchange with the subprocess.
 ///
 /// When a subprocess both expects input and provides output, care must be
@@ -439,7 +1138,7 @@ impl Communicator {
         stdin: Option<File>,
         stdout: Option<File>,
         stderr: Option<File>,
-        input_data: Option<Vec<u8>>,
+        input_data: Option<Vec<InputSource>>,
     ) -> Communicator {
         Communicator {
             inner: RawCommunicator::new(stdin, stdout, stderr, input_data),
@@ -489,13 +1188,12 @@ impl Communicator {
     /// # Errors
     ///
     /// * `Err(CommunicateError)` if a system call fails.  In case of timeout,
-    /// the underlying error kind will be `ErrorKind::TimedOut`.
+    ///   the underlying error kind will be `ErrorKind::TimedOut`.
     ///
     /// Regardless of the nature of the error, the content prior to the error
     /// can be retrieved using the [`capture`] attribute of the error.
     ///
     /// [`capture`]: struct.CommunicateError.html#structfield.capture
-
     pub fn read(&mut self) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), CommunicateError> {
         let deadline = self.time_limit.map(|timeout| Instant::now() + timeout);
         match self.inner.read(deadline, self.size_limit) {
@@ -527,6 +1225,390 @@ impl Communicator {
         self.time_limit = Some(time);
         self
     }
+
+    /// Install a hook called in each iteration of the communication loop.
+    ///
+    /// The hook receives the streams that were polled, their readiness, and
+    /// the number of bytes moved in the iteration.  It is meant for
+    /// diagnosing stuck communication, and is only available with the
+    /// `poll-trace` feature.
+    #[cfg(feature = "poll-trace")]
+    pub fn trace_hook(mut self, hook: impl FnMut(&PollTrace) + Send + 'static) -> Communicator {
+        self.inner.tracer.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Return the state of communication with the subprocess.
+    ///
+    /// This is useful for resuming communication after `read()` returns an
+    /// error due to timeout: it shows how much input was written and which
+    /// streams are finished, so the caller can decide how to proceed.
+    pub fn progress(&self) -> Progress {
+        self.inner.progress()
+    }
+
+    /// Perform a single step of communication and report what happened.
+    ///
+    /// This waits for one of the standard streams to become ready, and then
+    /// either writes a chunk of input data or reads a chunk of output,
+    /// returning the corresponding [`CommunicateEvent`].  It allows building
+    /// custom communication loops, e.g. ones that interleave communication
+    /// with other work, while keeping the deadlock avoidance of `read()`.
+    ///
+    /// If `limit_time` has been called, the method will wait for no more
+    /// than the specified duration, and return `CommunicateEvent::Idle` if
+    /// no stream became ready in that time.  The size limit is not used.
+    ///
+    /// Returns `Ok(None)` once the input has been written and all output
+    /// streams have reached end-of-file.
+    ///
+    /// [`CommunicateEvent`]: enum.CommunicateEvent.html
+    pub fn read_step(&mut self) -> io::Result<Option<CommunicateEvent>> {
+        let deadline = self.time_limit.map(|timeout| Instant::now() + timeout);
+        self.inner.read_step(deadline)
+    }
+
+    /// Return an iterator over chunks of the subprocess's output.
+    ///
+    /// Each iteration performs a `read()`, subject to the limits set by
+    /// `limit_size` and `limit_time`, and yields the obtained data as
+    /// `(Stream, Vec<u8>)` pairs, one for each stream that provided data.
+    /// Iteration ends when all streams have reached end-of-file.
+    ///
+    /// Errors are yielded as `Err` items.  A timeout doesn't end the
+    /// iteration, so the caller can decide whether to keep waiting, while
+    /// other errors do.
+    pub fn iter_chunks(&mut self) -> Chunks<'_> {
+        Chunks {
+            comm: self,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Convert the communicator into a reader of the subprocess's standard
+    /// output.
+    ///
+    /// The returned [`StdoutReader`] implements `Read` and `BufRead`, so
+    /// the output can be passed directly to a parser without collecting it
+    /// in memory first.  Input data is still fed to the subprocess while
+    /// reading, and the standard error, if redirected to a pipe, is
+    /// collected and available through [`StdoutReader::stderr`].
+    ///
+    /// If `limit_time` has been called, it limits the time spent in each
+    /// read from the subprocess.  The size limit is not used by the reader.
+    ///
+    /// [`StdoutReader`]: struct.StdoutReader.html
+    /// [`StdoutReader::stderr`]: struct.StdoutReader.html#method.stderr
+    pub fn stdout_reader(self) -> StdoutReader {
+        StdoutReader {
+            comm: self,
+            buf: Vec::new(),
+            pos: 0,
+            stderr: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Split the communicator into independent writer and reader halves.
+    ///
+    /// The [`CommunicateWriter`] owns the subprocess's standard input along
+    /// with any input data not yet written, and the [`CommunicateReader`]
+    /// reads the standard output and error.  Both halves are `Send`, so
+    /// feeding and draining the subprocess can happen on different threads.
+    ///
+    /// The split is meant to be done before the first call to `read()`.  On
+    /// Windows, once reading has started, the input is owned by the helper
+    /// thread and the writer half will not have access to it.
+    ///
+    /// [`CommunicateWriter`]: struct.CommunicateWriter.html
+    /// [`CommunicateReader`]: struct.CommunicateReader.html
+    pub fn split(mut self) -> (CommunicateWriter, CommunicateReader) {
+        let (stdin, input) = self.inner.take_input();
+        let writer = CommunicateWriter {
+            stdin,
+            input: input.into(),
+        };
+        (writer, CommunicateReader { inner: self })
+    }
+}
+
+/// The writing half of a [`Communicator`], created by [`Communicator::split`].
+///
+/// The input data passed to the communicator is written to the subprocess
+/// by `finish()`, or before any data written using the `Write` trait.
+/// Dropping the writer closes the subprocess's standard input without
+/// writing the data that remains queued.
+///
+/// [`Communicator`]: struct.Communicator.html
+/// [`Communicator::split`]: struct.Communicator.html#method.split
+#[derive(Debug)]
+pub struct CommunicateWriter {
+    stdin: Option<File>,
+    input: VecDeque<InputSource>,
+}
+
+impl CommunicateWriter {
+    fn write_queued(&mut self) -> io::Result<()> {
+        while let Some(mut source) = self.input.pop_front() {
+            let stdin = self.stdin_ref()?;
+            match source.as_slice() {
+                Some(data) => stdin.write_all(data)?,
+                None => {
+                    io::copy(&mut source, stdin)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stdin_ref(&mut self) -> io::Result<&mut File> {
+        self.stdin.as_mut().ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::BrokenPipe,
+                "standard input is not available for writing",
+            )
+        })
+    }
+
+    /// Write the queued input data and close the standard input, so that
+    /// the subprocess receives end-of-file.
+    pub fn finish(mut self) -> io::Result<()> {
+        let result = self.write_queued();
+        self.stdin.take();
+        result
+    }
+}
+
+impl Write for CommunicateWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_queued()?;
+        self.stdin_ref()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_queued()?;
+        match self.stdin {
+            Some(ref mut stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The reading half of a [`Communicator`], created by [`Communicator::split`].
+///
+/// It reads the subprocess's standard output and error like the
+/// communicator, but never writes to the standard input.
+///
+/// [`Communicator`]: struct.Communicator.html
+/// [`Communicator::split`]: struct.Communicator.html#method.split
+#[must_use]
+#[derive(Debug)]
+pub struct CommunicateReader {
+    inner: Communicator,
+}
+
+impl CommunicateReader {
+    /// Read the subprocess's output and error contents.
+    ///
+    /// Behaves like [`Communicator::read`].
+    ///
+    /// [`Communicator::read`]: struct.Communicator.html#method.read
+    pub fn read(&mut self) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), CommunicateError> {
+        self.inner.read()
+    }
+
+    /// Return the subprocess's output and error contents as strings.
+    ///
+    /// Behaves like [`Communicator::read_string`].
+    ///
+    /// [`Communicator::read_string`]: struct.Communicator.html#method.read_string
+    pub fn read_string(&mut self) -> Result<(Option<String>, Option<String>), CommunicateError> {
+        self.inner.read_string()
+    }
+
+    /// Limit the amount of data the next `read()` will read from the
+    /// subprocess.
+    pub fn limit_size(mut self, size: usize) -> CommunicateReader {
+        self.inner = self.inner.limit_size(size);
+        self
+    }
+
+    /// Limit the amount of time the next `read()` will spend reading from the
+    /// subprocess.
+    pub fn limit_time(mut self, time: Duration) -> CommunicateReader {
+        self.inner = self.inner.limit_time(time);
+        self
+    }
+}
+
+/// Standard stream of the subprocess.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Stream {
+    /// The standard input.
+    Stdin,
+    /// The standard output.
+    Stdout,
+    /// The standard error.
+    Stderr,
+}
+
+/// State of communication reported by [`Communicator::progress`].
+///
+/// [`Communicator::progress`]: struct.Communicator.html#method.progress
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Progress {
+    /// Number of input bytes written to the standard input so far.
+    pub input_written: u64,
+    /// Whether the standard input has been closed, either because all input
+    /// was written or because it was not redirected to a pipe.
+    pub stdin_closed: bool,
+    /// Whether no more data can be read from the standard output, either
+    /// because it reached end-of-file or because it was not redirected to
+    /// a pipe.
+    pub stdout_eof: bool,
+    /// Whether no more data can be read from the standard error, either
+    /// because it reached end-of-file or because it was not redirected to
+    /// a pipe.
+    pub stderr_eof: bool,
+}
+
+/// Event reported by [`Communicator::read_step`].
+///
+/// [`Communicator::read_step`]: struct.Communicator.html#method.read_step
+#[derive(Debug)]
+pub enum CommunicateEvent {
+    /// The given number of input bytes was written to the standard input.
+    ///
+    /// On Windows the input is written by a helper thread and this event
+    /// is not reported, only `Eof(Stream::Stdin)` once the input has been
+    /// written.
+    WroteInput(usize),
+    /// A chunk of data was read from the standard output.
+    Stdout(Vec<u8>),
+    /// A chunk of data was read from the standard error.
+    Stderr(Vec<u8>),
+    /// The standard input was closed after writing all input, or the
+    /// standard output or error reached end-of-file.
+    Eof(Stream),
+    /// Nothing happened before the time limit expired.
+    Idle,
+}
+
+/// Iterator over chunks of subprocess output, created by
+/// [`Communicator::iter_chunks`].
+///
+/// [`Communicator::iter_chunks`]: struct.Communicator.html#method.iter_chunks
+#[derive(Debug)]
+pub struct Chunks<'a> {
+    comm: &'a mut Communicator,
+    pending: VecDeque<io::Result<(Stream, Vec<u8>)>>,
+    done: bool,
+}
+
+impl Iterator for Chunks<'_> {
+    type Item = io::Result<(Stream, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.done {
+            let (out, err) = match self.comm.read() {
+                Ok(capture) => capture,
+                Err(e) => {
+                    if e.kind() != ErrorKind::TimedOut {
+                        self.done = true;
+                    }
+                    let (out, err) = e.capture;
+                    self.push_chunks(out, err);
+                    self.pending.push_back(Err(e.error));
+                    break;
+                }
+            };
+            if !self.push_chunks(out, err) {
+                self.done = true;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl Chunks<'_> {
+    // Queue the non-empty chunks, returning whether there were any.
+    fn push_chunks(&mut self, out: Option<Vec<u8>>, err: Option<Vec<u8>>) -> bool {
+        let before = self.pending.len();
+        for (stream, data) in [(Stream::Stdout, out), (Stream::Stderr, err)] {
+            match data {
+                Some(data) if !data.is_empty() => self.pending.push_back(Ok((stream, data))),
+                _ => (),
+            }
+        }
+        self.pending.len() != before
+    }
+}
+
+/// Reader of the subprocess's standard output, created by
+/// [`Communicator::stdout_reader`].
+///
+/// Reading from the subprocess's standard output also writes the input data
+/// and reads the standard error as needed to avoid deadlock.  If the standard
+/// output was not redirected to a pipe, the reader is at end-of-file from the
+/// start.
+///
+/// [`Communicator::stdout_reader`]: struct.Communicator.html#method.stdout_reader
+#[derive(Debug)]
+pub struct StdoutReader {
+    comm: Communicator,
+    buf: Vec<u8>,
+    pos: usize,
+    stderr: Vec<u8>,
+    eof: bool,
+}
+
+impl StdoutReader {
+    /// Return the standard error content collected so far.
+    ///
+    /// The standard error is read alongside the standard output, so the
+    /// entire content is only available after the reader has reached
+    /// end-of-file.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+}
+
+impl BufRead for StdoutReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        const READ_SIZE: usize = 65536;
+
+        while self.pos == self.buf.len() && !self.eof {
+            let deadline = self.comm.time_limit.map(|timeout| Instant::now() + timeout);
+            let (err, (out, err_data)) = self.comm.inner.read(deadline, Some(READ_SIZE));
+            let out = out.unwrap_or_default();
+            let err_data = err_data.unwrap_or_default();
+            if err.is_none() && out.is_empty() && err_data.is_empty() {
+                self.eof = true;
+            }
+            self.stderr.extend_from_slice(&err_data);
+            self.buf = out;
+            self.pos = 0;
+            if let Some(err) = err {
+                return Err(err);
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = std::cmp::min(self.pos + amt, self.buf.len());
+    }
+}
+
+impl Read for StdoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = std::cmp::min(available.len(), buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
 }
 
 /// Like String::from_utf8_lossy(), but takes `Vec<u8>` and reuses its storage if
@@ -542,7 +1624,7 @@ pub fn communicate(
     stdin: Option<File>,
     stdout: Option<File>,
     stderr: Option<File>,
-    input_data: Option<Vec<u8>>,
+    input_data: Option<Vec<InputSource>>,
 ) -> Communicator {
     if stdin.is_some() {
         input_data
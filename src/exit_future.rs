@@ -0,0 +1,172 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, Result};
+
+// State shared between the future and the code notified of the exit.
+#[derive(Debug, Default)]
+struct Shared {
+    exited: bool,
+    waker: Option<Waker>,
+}
+
+fn notify(shared: &Mutex<Shared>) {
+    let waker = {
+        let mut shared = shared.lock().unwrap();
+        shared.exited = true;
+        shared.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+#[cfg(unix)]
+mod watch {
+    use super::{notify, Shared};
+    use crate::popen::Popen;
+    use crate::posix;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[derive(Debug)]
+    pub struct Watcher;
+
+    impl Watcher {
+        // Start a thread that sleeps until the child exits.  It uses the
+        // child's exit fd if there is one, and waitid() with WNOWAIT
+        // otherwise, so that the exit status is left for the Popen.
+        pub fn start(popen: &Popen, shared: Arc<Mutex<Shared>>) -> io::Result<Watcher> {
+            let pid = popen.pid().unwrap();
+            let exit_fd = match popen.exit_fd() {
+                Some(exit_fd) => Some(exit_fd.try_clone()?),
+                None => None,
+            };
+            thread::Builder::new()
+                .name("subprocess-exit-watch".into())
+                .spawn(move || {
+                    match exit_fd {
+                        Some(exit_fd) => {
+                            let mut fds = [posix::PollFd::new(Some(&exit_fd), posix::POLLIN)];
+                            posix::poll(&mut fds, None).ok();
+                        }
+                        None => {
+                            posix::wait_exit_nowait(Some(pid)).ok();
+                        }
+                    }
+                    notify(&shared);
+                })?;
+            Ok(Watcher)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod watch {
+    use super::{notify, Shared};
+    use crate::popen::Popen;
+    use crate::win32;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    // Registration of a thread pool wait on the process handle.
+    #[derive(Debug)]
+    pub struct Watcher {
+        wait: win32::RegisteredWait,
+        context: *const Mutex<Shared>,
+    }
+
+    unsafe impl Send for Watcher {}
+
+    unsafe extern "system" fn callback(context: win32::PVOID, _timed_out: win32::BOOLEAN) {
+        notify(&*(context as *const Mutex<Shared>));
+    }
+
+    impl Watcher {
+        pub fn start(popen: &Popen, shared: Arc<Mutex<Shared>>) -> io::Result<Watcher> {
+            let context = Arc::into_raw(shared);
+            match win32::RegisterWaitForSingleObject(
+                popen.process_handle().unwrap(),
+                Some(callback),
+                context as win32::PVOID,
+            ) {
+                Ok(wait) => Ok(Watcher { wait, context }),
+                Err(e) => {
+                    drop(unsafe { Arc::from_raw(context) });
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            // Unregistering waits for a running callback to complete,
+            // after which the context is no longer referenced.
+            if self.wait.unregister().is_ok() {
+                drop(unsafe { Arc::from_raw(self.context) });
+            }
+        }
+    }
+}
+
+/// Future resolving to the exit status of a subprocess.
+///
+/// Returned by [`Popen::wait_async`].
+///
+/// [`Popen::wait_async`]: struct.Popen.html#method.wait_async
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ExitFuture<'a> {
+    popen: &'a mut Popen,
+    shared: Arc<Mutex<Shared>>,
+    watcher: Option<watch::Watcher>,
+}
+
+impl ExitFuture<'_> {
+    pub(crate) fn new(popen: &mut Popen) -> ExitFuture<'_> {
+        ExitFuture {
+            popen,
+            shared: Arc::default(),
+            watcher: None,
+        }
+    }
+
+    fn try_status(&mut self) -> Result<Option<ExitStatus>> {
+        self.popen.wait_timeout(Default::default())
+    }
+}
+
+impl Future for ExitFuture<'_> {
+    type Output = Result<ExitStatus>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<ExitStatus>> {
+        let this = self.get_mut();
+        if let Some(exit_status) = this.try_status()? {
+            return Poll::Ready(Ok(exit_status));
+        }
+        let exited = {
+            let mut shared = this.shared.lock().unwrap();
+            shared.waker = Some(cx.waker().clone());
+            shared.exited
+        };
+        if this.watcher.is_none() {
+            let watcher = watch::Watcher::start(this.popen, Arc::clone(&this.shared))?;
+            this.watcher = Some(watcher);
+        } else if exited {
+            // The watcher has seen the child exit, so waiting for it
+            // won't block for long.
+            return Poll::Ready(this.popen.wait());
+        }
+        // Check again in case the child exited before the waker was
+        // registered.
+        match this.try_status()? {
+            Some(exit_status) => Poll::Ready(Ok(exit_status)),
+            None => Poll::Pending,
+        }
+    }
+}
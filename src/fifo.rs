@@ -0,0 +1,184 @@
+// Named pipes for subprocesses that take a file name rather than a
+// standard stream.
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::popen::Popen;
+use crate::posix;
+
+/// A named pipe (FIFO) for passing data to or from a subprocess.
+///
+/// Some programs only read their input from, or write their output to,
+/// a file given by name.  A FIFO gives them a name for a pipe, which the
+/// parent opens at the other end, without staging the data in a
+/// temporary file.
+///
+/// Opening one end of a FIFO normally blocks until the other end is
+/// opened, which deadlocks if the parent opens its end before starting
+/// the subprocess, or if the subprocess exits without opening the FIFO.
+/// [`open_read`] and [`open_write`] open the parent's end once the
+/// subprocess has opened its own, and return if it exits first.
+/// [`open_pair`] opens both ends in the parent, in an order that
+/// doesn't block, for example to make one of them a redirection of
+/// the subprocess.
+///
+/// The FIFO is removed when the `Fifo` is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use subprocess::*;
+/// # use subprocess::unix::Fifo;
+/// # use std::io::Read;
+/// # fn dummy() -> Result<()> {
+/// let fifo = Fifo::temp()?;
+/// let mut p = Exec::cmd("tar").arg("cf").arg(fifo.path()).arg("src").popen()?;
+/// let mut archive = vec![];
+/// fifo.open_read(&mut p)?.read_to_end(&mut archive)?;
+/// p.wait()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`open_read`]: #method.open_read
+/// [`open_write`]: #method.open_write
+/// [`open_pair`]: #method.open_pair
+#[derive(Debug)]
+pub struct Fifo {
+    path: PathBuf,
+    // the private directory created by temp()
+    temp_dir: Option<PathBuf>,
+}
+
+impl Fifo {
+    /// Create a FIFO at `path`, readable and writable only by the
+    /// current user.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Fifo> {
+        let path = path.as_ref().to_owned();
+        posix::mkfifo(path.as_os_str(), 0o600)?;
+        Ok(Fifo {
+            path,
+            temp_dir: None,
+        })
+    }
+
+    /// Create a FIFO in a new directory inside the temporary directory,
+    /// accessible only by the current user.
+    ///
+    /// The directory is removed together with the FIFO.
+    pub fn temp() -> io::Result<Fifo> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp_dir = std::env::temp_dir().join(format!(
+            "subprocess-fifo-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        DirBuilder::new().mode(0o700).create(&temp_dir)?;
+        match Fifo::create(temp_dir.join("fifo")) {
+            Ok(mut fifo) => {
+                fifo.temp_dir = Some(temp_dir);
+                Ok(fifo)
+            }
+            Err(e) => {
+                fs::remove_dir(&temp_dir).ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Return the path of the FIFO, to pass to the subprocess.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open both ends of the FIFO, returning the read end and the write
+    /// end.
+    ///
+    /// The read end is opened first, without blocking, so that opening
+    /// the write end doesn't block either.  Both are then in blocking
+    /// mode.  Since the write end is open from the start, reading
+    /// doesn't report end-of-file until it is closed, for example once
+    /// it is given to a subprocess as a redirection and the subprocess
+    /// exits.
+    pub fn open_pair(&self) -> io::Result<(File, File)> {
+        let read = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?;
+        let write = OpenOptions::new().write(true).open(&self.path)?;
+        posix::set_nonblocking(read.as_raw_fd(), false)?;
+        Ok((read, write))
+    }
+
+    /// Open the FIFO for reading what `child` writes to it.
+    ///
+    /// This waits for `child` to open the FIFO for writing.  If `child`
+    /// exits without opening it, the returned file is at end-of-file.
+    pub fn open_read(&self, child: &mut Popen) -> io::Result<File> {
+        self.open_end(false, child)
+    }
+
+    /// Open the FIFO for writing what `child` reads from it.
+    ///
+    /// This waits for `child` to open the FIFO for reading.  If `child`
+    /// exits without opening it, writing to the returned file fails
+    /// with `BrokenPipe`.
+    pub fn open_write(&self, child: &mut Popen) -> io::Result<File> {
+        self.open_end(true, child)
+    }
+
+    fn open_end(&self, write: bool, child: &mut Popen) -> io::Result<File> {
+        // open() blocks until the child opens the other end, so it is
+        // done in a thread while watching the child
+        let (sender, receiver) = mpsc::channel();
+        let path = self.path.clone();
+        thread::Builder::new()
+            .name("subprocess-fifo".into())
+            .spawn(move || {
+                let opened = OpenOptions::new().read(!write).write(write).open(path);
+                let _ = sender.send(opened);
+            })?;
+        loop {
+            match receiver.recv_timeout(Duration::from_millis(10)) {
+                Ok(opened) => return opened,
+                Err(RecvTimeoutError::Timeout) if child.poll().is_some() => break,
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("the thread always sends"),
+            }
+        }
+        // The child has exited without opening the FIFO.  Opening the
+        // other end without blocking releases the thread, once it is
+        // waiting at this end, which is needed for the write end.
+        loop {
+            let other = OpenOptions::new()
+                .read(write)
+                .write(!write)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path);
+            match other {
+                Ok(_other) => return receiver.recv().unwrap(),
+                Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+        if let Some(ref temp_dir) = self.temp_dir {
+            fs::remove_dir(temp_dir).ok();
+        }
+    }
+}
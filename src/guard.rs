@@ -0,0 +1,71 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use crate::popen::Popen;
+
+/// Guard that stops a subprocess when it goes out of scope.
+///
+/// `ChildGuard` wraps a [`Popen`] and dereferences to it.  When the
+/// guard is dropped, including while unwinding from a panic, the
+/// subprocess is terminated and waited for, and killed if it doesn't
+/// exit within the grace period, as with
+/// [`Popen::terminate_with_timeout`].  This makes it easy to ensure
+/// that subprocesses started by tests and short-lived tools don't
+/// outlive them.
+///
+/// [`Popen::scoped`] provides the same guarantee for a closure.
+///
+/// [`Popen`]: struct.Popen.html
+/// [`Popen::terminate_with_timeout`]: struct.Popen.html#method.terminate_with_timeout
+/// [`Popen::scoped`]: struct.Popen.html#method.scoped
+#[derive(Debug)]
+pub struct ChildGuard {
+    popen: Option<Popen>,
+    grace: Duration,
+}
+
+impl ChildGuard {
+    /// Grace period used by `ChildGuard::new`.
+    pub const DEFAULT_GRACE: Duration = Duration::from_secs(1);
+
+    /// Guard `popen`, giving it `DEFAULT_GRACE` to exit once terminated.
+    pub fn new(popen: Popen) -> ChildGuard {
+        ChildGuard::with_grace(popen, ChildGuard::DEFAULT_GRACE)
+    }
+
+    /// Guard `popen`, giving it `grace` to exit once terminated.
+    pub fn with_grace(popen: Popen, grace: Duration) -> ChildGuard {
+        ChildGuard {
+            popen: Some(popen),
+            grace,
+        }
+    }
+
+    /// Release the `Popen` from the guard, so that it is no longer
+    /// stopped when the guard goes out of scope.
+    pub fn into_inner(mut self) -> Popen {
+        self.popen.take().unwrap()
+    }
+}
+
+impl Deref for ChildGuard {
+    type Target = Popen;
+
+    fn deref(&self) -> &Popen {
+        self.popen.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for ChildGuard {
+    fn deref_mut(&mut self) -> &mut Popen {
+        self.popen.as_mut().unwrap()
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if let Some(ref mut popen) = self.popen {
+            popen.terminate_with_timeout(self.grace).ok();
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(unix)]
+mod os {
+    use crate::popen::Popen;
+    use crate::posix;
+    use std::cmp::min;
+    #[cfg(target_os = "linux")]
+    use std::fs::File;
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    pub enum Inner {
+        #[cfg(target_os = "linux")]
+        Pidfd(File),
+        // Without a pidfd, the process is identified by its PID and,
+        // where available, its start time, and polled.
+        Pid {
+            pid: u32,
+            start_time: Option<u64>,
+        },
+    }
+
+    pub fn clone_from(popen: &Popen, fallback: bool) -> io::Result<Option<Inner>> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(pidfd) = popen.exit_fd() {
+                return Ok(Some(Inner::Pidfd(pidfd.try_clone()?)));
+            }
+        }
+        if !fallback {
+            #[cfg(not(target_os = "linux"))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "process handles require pidfd support",
+            ));
+            #[cfg(target_os = "linux")]
+            return Ok(None);
+        }
+        let pid = match popen.pid() {
+            Some(pid) => pid,
+            None => return Ok(None),
+        };
+        let start_time = posix::process_state(pid)?.and_then(|state| state.start_time);
+        Ok(Some(Inner::Pid { pid, start_time }))
+    }
+
+    // Whether the process identified by the PID and start time is
+    // still running.
+    fn is_running(pid: u32, start_time: Option<u64>) -> io::Result<bool> {
+        Ok(match posix::process_state(pid)? {
+            Some(state) => {
+                state.running && (start_time.is_none() || state.start_time == start_time)
+            }
+            None => false,
+        })
+    }
+
+    pub fn wait_timeout(inner: &Inner, timeout: Option<Duration>) -> io::Result<bool> {
+        match *inner {
+            #[cfg(target_os = "linux")]
+            Inner::Pidfd(ref pidfd) => {
+                let mut fds = [posix::PollFd::new(Some(pidfd), posix::POLLIN)];
+                Ok(posix::poll(&mut fds, timeout)? > 0)
+            }
+            Inner::Pid { pid, start_time } => {
+                let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                // double delay at every iteration, maxing at 100ms
+                let mut delay = Duration::from_millis(1);
+                loop {
+                    if !is_running(pid, start_time)? {
+                        return Ok(true);
+                    }
+                    let mut sleep = delay;
+                    if let Some(deadline) = deadline {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Ok(false);
+                        }
+                        sleep = min(sleep, deadline - now);
+                    }
+                    thread::sleep(sleep);
+                    delay = min(delay * 2, Duration::from_millis(100));
+                }
+            }
+        }
+    }
+
+    pub fn send_signal(inner: &Inner, signal: i32) -> io::Result<()> {
+        let result = match *inner {
+            #[cfg(target_os = "linux")]
+            Inner::Pidfd(ref pidfd) => posix::pidfd_send_signal(pidfd, signal),
+            Inner::Pid { pid, start_time } => {
+                if !is_running(pid, start_time)? {
+                    return Ok(());
+                }
+                posix::kill(pid, signal)
+            }
+        };
+        match result {
+            // the process has exited
+            Err(ref e) if e.raw_os_error() == Some(posix::ESRCH) => Ok(()),
+            result => result,
+        }
+    }
+
+    pub fn terminate(inner: &Inner) -> io::Result<()> {
+        send_signal(inner, posix::SIGTERM)
+    }
+
+    pub fn kill(inner: &Inner) -> io::Result<()> {
+        send_signal(inner, posix::SIGKILL)
+    }
+}
+
+#[cfg(windows)]
+mod os {
+    use crate::popen::Popen;
+    use crate::win32;
+    use std::io;
+    use std::time::Duration;
+
+    pub type Inner = win32::Handle;
+
+    pub fn clone_from(popen: &Popen, _fallback: bool) -> io::Result<Option<Inner>> {
+        popen
+            .process_handle()
+            .map(win32::DuplicateProcessHandle)
+            .transpose()
+    }
+
+    pub fn wait_timeout(handle: &Inner, timeout: Option<Duration>) -> io::Result<bool> {
+        match win32::WaitForSingleObject(handle, timeout)? {
+            win32::WaitEvent::TIMEOUT => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn terminate(handle: &Inner) -> io::Result<()> {
+        match win32::TerminateProcess(handle, 1) {
+            // the process has exited
+            Err(ref e) if e.raw_os_error() == Some(win32::ERROR_ACCESS_DENIED as i32) => {
+                if win32::GetExitCodeProcess(handle)? == win32::STILL_ACTIVE {
+                    return Err(io::Error::from_raw_os_error(
+                        win32::ERROR_ACCESS_DENIED as i32,
+                    ));
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    pub fn kill(handle: &Inner) -> io::Result<()> {
+        terminate(handle)
+    }
+}
+
+/// A handle that can be used to wait for a process and to kill it.
+///
+/// Obtained with [`Popen::handle`] or [`Popen::try_clone_handle`], the
+/// handle refers to the process independently of the `Popen`, and can
+/// be sent to another thread.  Cloning the handle is cheap, and the
+/// clones can wait for and kill the process from multiple threads at
+/// the same time.  Unlike `Popen`, the handle doesn't collect the exit
+/// status of the process, so waiting on it doesn't interfere with the
+/// `Popen` waiting for the process.
+///
+/// On Linux, the handle is a pidfd, and on Windows a process handle
+/// duplicated with `DuplicateHandle`.  Since the handle refers to the
+/// process itself rather than its PID, it can't accidentally refer to
+/// an unrelated process after the PID is reused.  The underlying
+/// pidfd, returned by `ProcessHandle::pidfd`, or process handle can be
+/// passed to another process, e.g. over a Unix domain socket, and
+/// turned back into a handle there.
+///
+/// On other Unix systems, and on Linux kernels older than 5.3, handles
+/// returned by `Popen::handle` refer to the process by its PID and
+/// start time, and poll for its exit.
+///
+/// [`Popen::handle`]: struct.Popen.html#method.handle
+/// [`Popen::try_clone_handle`]: struct.Popen.html#method.try_clone_handle
+#[derive(Clone)]
+pub struct ProcessHandle {
+    pid: u32,
+    inner: Arc<os::Inner>,
+}
+
+impl ProcessHandle {
+    pub(crate) fn from_popen(popen: &crate::Popen, fallback: bool) -> io::Result<ProcessHandle> {
+        let not_running = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the process has already been waited for",
+            )
+        };
+        let pid = popen.pid().ok_or_else(not_running)?;
+        let inner = os::clone_from(popen, fallback)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no handle to the process is available",
+            )
+        })?;
+        Ok(ProcessHandle {
+            pid,
+            inner: Arc::new(inner),
+        })
+    }
+
+    // Create a handle that polls the process by its PID, as on systems
+    // without pidfd support.
+    #[cfg(all(test, unix))]
+    pub(crate) fn pid_based_for_test(popen: &crate::Popen) -> ProcessHandle {
+        let pid = popen.pid().unwrap();
+        let start_time = crate::posix::process_state(pid)
+            .unwrap()
+            .unwrap()
+            .start_time;
+        ProcessHandle {
+            pid,
+            inner: Arc::new(os::Inner::Pid { pid, start_time }),
+        }
+    }
+
+    /// Return the PID of the process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Wait for the process to exit.
+    pub fn wait(&self) -> io::Result<()> {
+        os::wait_timeout(&self.inner, None)?;
+        Ok(())
+    }
+
+    /// Wait for the process to exit, timing out after the specified
+    /// duration.
+    ///
+    /// Returns `true` if the process has exited, and `false` if the
+    /// timeout has elapsed.
+    pub fn wait_timeout(&self, dur: Duration) -> io::Result<bool> {
+        os::wait_timeout(&self.inner, Some(dur))
+    }
+
+    /// Check whether the process has exited, without blocking.
+    pub fn has_exited(&self) -> io::Result<bool> {
+        os::wait_timeout(&self.inner, Some(Duration::from_secs(0)))
+    }
+
+    /// Terminate the process.
+    ///
+    /// See [`Popen::terminate`] for details.  Does nothing if the
+    /// process has already exited.
+    ///
+    /// [`Popen::terminate`]: struct.Popen.html#method.terminate
+    pub fn terminate(&self) -> io::Result<()> {
+        os::terminate(&self.inner)
+    }
+
+    /// Kill the process.
+    ///
+    /// See [`Popen::kill`] for details.  Does nothing if the process
+    /// has already exited.
+    ///
+    /// [`Popen::kill`]: struct.Popen.html#method.kill
+    pub fn kill(&self) -> io::Result<()> {
+        os::kill(&self.inner)
+    }
+}
+
+impl fmt::Debug for ProcessHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessHandle")
+            .field("pid", &self.pid)
+            .finish()
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::ProcessHandle;
+    use std::io;
+
+    impl ProcessHandle {
+        /// Send the specified signal to the process.
+        ///
+        /// Does nothing if the process has already exited.
+        pub fn send_signal(&self, signal: i32) -> io::Result<()> {
+            super::os::send_signal(&self.inner, signal)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::os::Inner;
+    use super::ProcessHandle;
+    use crate::posix;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsFd, BorrowedFd};
+    use std::sync::Arc;
+
+    impl ProcessHandle {
+        /// Create a handle from a pidfd, such as one received from
+        /// another process.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error of kind `InvalidInput` if `pidfd` is not a
+        /// pidfd, or refers to a process that has been waited for.
+        pub fn from_pidfd(pidfd: File) -> io::Result<ProcessHandle> {
+            let pid = posix::pidfd_pid(&pidfd)?;
+            Ok(ProcessHandle {
+                pid,
+                inner: Arc::new(Inner::Pidfd(pidfd)),
+            })
+        }
+
+        /// Return the pidfd backing the handle.
+        ///
+        /// Returns `None` if the handle was returned by `Popen::handle`
+        /// on a kernel without pidfd support, in which case it refers to
+        /// the process by its PID.
+        pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+            match *self.inner {
+                Inner::Pidfd(ref pidfd) => Some(pidfd.as_fd()),
+                Inner::Pid { .. } => None,
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::ProcessHandle;
+    use crate::win32;
+    use std::os::windows::io::{
+        AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, IntoRawHandle, RawHandle,
+    };
+    use std::sync::Arc;
+
+    impl AsHandle for ProcessHandle {
+        fn as_handle(&self) -> BorrowedHandle<'_> {
+            self.inner.as_handle()
+        }
+    }
+
+    impl AsRawHandle for ProcessHandle {
+        fn as_raw_handle(&self) -> RawHandle {
+            self.inner.as_raw_handle()
+        }
+    }
+
+    /// If the handle has been cloned, a duplicate of the process handle
+    /// is returned.
+    ///
+    /// # Panics
+    ///
+    /// If duplicating the process handle fails.
+    impl IntoRawHandle for ProcessHandle {
+        fn into_raw_handle(self) -> RawHandle {
+            let handle = match Arc::try_unwrap(self.inner) {
+                Ok(handle) => handle,
+                Err(handle) => win32::DuplicateProcessHandle(&handle)
+                    .expect("failed to duplicate process handle"),
+            };
+            let raw = handle.as_raw_handle();
+            std::mem::forget(handle);
+            raw
+        }
+    }
+
+    impl FromRawHandle for ProcessHandle {
+        /// Create a handle from a raw process handle, which must have
+        /// the `SYNCHRONIZE`, `PROCESS_TERMINATE`, and
+        /// `PROCESS_QUERY_LIMITED_INFORMATION` access rights.
+        unsafe fn from_raw_handle(handle: RawHandle) -> ProcessHandle {
+            let inner = win32::Handle::from_raw_handle(handle);
+            let pid = win32::GetProcessId(&inner);
+            ProcessHandle {
+                pid,
+                inner: Arc::new(inner),
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Global hooks notified of the subprocesses started and reaped by Popen,
+// so that they can be logged, metered or vetoed in one place.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::os_common::{ExitStatus, ResourceUsage};
+use crate::popen::PopenConfig;
+
+type SpawnHook = dyn Fn(&SpawnInfo) -> io::Result<()> + Send + Sync;
+type ExitHook = dyn Fn(&ExitInfo) + Send + Sync;
+
+static SPAWN_HOOK: Mutex<Option<Arc<SpawnHook>>> = Mutex::new(None);
+static EXIT_HOOK: Mutex<Option<Arc<ExitHook>>> = Mutex::new(None);
+
+/// A subprocess about to be started, passed to the hook installed with
+/// [`set_spawn_hook`].
+///
+/// [`set_spawn_hook`]: fn.set_spawn_hook.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpawnInfo {
+    /// The command and its arguments.  The command is the program that
+    /// will be executed, as found in the `PATH` or set by
+    /// [`PopenConfig::executable`], or as specified if it can't be
+    /// found.
+    ///
+    /// [`PopenConfig::executable`]: struct.PopenConfig.html#structfield.executable
+    pub argv: Vec<OsString>,
+    /// The environment of the subprocess, which is that of the current
+    /// process unless [`PopenConfig::env`] is set.  The values of the
+    /// variables listed in [`PopenConfig::secret_env`] are replaced by
+    /// `***`.
+    ///
+    /// [`PopenConfig::env`]: struct.PopenConfig.html#structfield.env
+    /// [`PopenConfig::secret_env`]: struct.PopenConfig.html#structfield.secret_env
+    pub env: Vec<(OsString, OsString)>,
+    /// The directory the subprocess starts in, or `None` if it inherits
+    /// a current directory that can't be determined, or starts in the
+    /// directory given by [`PopenConfig::cwd_fd`].
+    ///
+    /// [`PopenConfig::cwd_fd`]: struct.PopenConfig.html#structfield.cwd_fd
+    pub cwd: Option<PathBuf>,
+}
+
+/// A subprocess that has exited, passed to the hook installed with
+/// [`set_exit_hook`].
+///
+/// [`set_exit_hook`]: fn.set_exit_hook.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExitInfo {
+    /// The PID the subprocess had.
+    pub pid: u32,
+    /// The command and its arguments, as passed to the spawn hook.
+    pub argv: Vec<OsString>,
+    /// The exit status of the subprocess.
+    pub exit_status: ExitStatus,
+    /// Time from the start of the subprocess until it was found to have
+    /// exited, as returned by [`Popen::runtime`].
+    ///
+    /// [`Popen::runtime`]: struct.Popen.html#method.runtime
+    pub runtime: Duration,
+    /// Resources used by the subprocess, as returned by
+    /// [`Popen::resource_usage`].
+    ///
+    /// [`Popen::resource_usage`]: struct.Popen.html#method.resource_usage
+    pub resource_usage: Option<ResourceUsage>,
+}
+
+/// Install a hook called before each subprocess is started.
+///
+/// The hook is called by `Popen::create` (including through `Exec` and
+/// `Pipeline`) with the command, environment and directory of the
+/// subprocess, once the configuration has been validated.  Returning an
+/// error vetoes the launch: the subprocess is not started, and
+/// `Popen::create` fails with `PopenError::IoError` containing the
+/// error.
+///
+/// The hook replaces any previously installed one, and is called from
+/// whichever thread starts the subprocess.  Subprocesses started from
+/// within the hook are also passed to it.
+pub fn set_spawn_hook(hook: impl Fn(&SpawnInfo) -> io::Result<()> + Send + Sync + 'static) {
+    *SPAWN_HOOK.lock().unwrap() = Some(Arc::new(hook));
+}
+
+/// Install a hook called when a subprocess is found to have exited.
+///
+/// The hook is called once for each subprocess started while it was
+/// installed, when its `Popen` first observes the exit, e.g. in `wait`,
+/// `poll` or when the `Popen` is dropped.  Subprocesses that are never
+/// waited for, such as detached ones, are not reported, and neither are
+/// processes adopted with `Popen::from_pid`.
+///
+/// The hook replaces any previously installed one, and is called from
+/// whichever thread observes the exit.
+pub fn set_exit_hook(hook: impl Fn(&ExitInfo) + Send + Sync + 'static) {
+    *EXIT_HOOK.lock().unwrap() = Some(Arc::new(hook));
+}
+
+/// Remove the hooks installed with [`set_spawn_hook`] and
+/// [`set_exit_hook`].
+///
+/// [`set_spawn_hook`]: fn.set_spawn_hook.html
+/// [`set_exit_hook`]: fn.set_exit_hook.html
+pub fn clear_hooks() {
+    *SPAWN_HOOK.lock().unwrap() = None;
+    *EXIT_HOOK.lock().unwrap() = None;
+}
+
+// Call the spawn hook for a subprocess about to be started.  Returns
+// the argv to keep for the exit hook, if one is installed.
+pub(crate) fn spawning(
+    argv: &[OsString],
+    config: &PopenConfig,
+) -> io::Result<Option<Vec<OsString>>> {
+    // call the hooks without holding the lock, so that they can start
+    // subprocesses of their own
+    let spawn_hook = SPAWN_HOOK.lock().unwrap().clone();
+    let has_exit_hook = EXIT_HOOK.lock().unwrap().is_some();
+    if spawn_hook.is_none() && !has_exit_hook {
+        return Ok(None);
+    }
+    let mut argv = argv.to_vec();
+    argv[0] = crate::popen::resolve_program(&argv[0], config);
+    if let Some(hook) = spawn_hook {
+        let env = match config.env {
+            Some(ref env) => env.clone(),
+            None => std::env::vars_os().collect(),
+        };
+        let info = SpawnInfo {
+            argv: argv.clone(),
+            env: env
+                .into_iter()
+                .map(|(k, v)| {
+                    if config.is_secret_env(&k) {
+                        (k, OsString::from("***"))
+                    } else {
+                        (k, v)
+                    }
+                })
+                .collect(),
+            cwd: match config.cwd {
+                Some(ref cwd) => Some(PathBuf::from(cwd)),
+                #[cfg(unix)]
+                None if config.cwd_fd.is_some() => None,
+                None => std::env::current_dir().ok(),
+            },
+        };
+        hook(&info)?;
+    }
+    Ok(Some(argv).filter(|_| has_exit_hook))
+}
+
+// Call the exit hook for a subprocess started while it was installed.
+pub(crate) fn exited(
+    pid: u32,
+    argv: Vec<OsString>,
+    exit_status: ExitStatus,
+    runtime: Duration,
+    resource_usage: Option<ResourceUsage>,
+) {
+    let exit_hook = EXIT_HOOK.lock().unwrap().clone();
+    if let Some(hook) = exit_hook {
+        hook(&ExitInfo {
+            pid,
+            argv,
+            exit_status,
+            runtime,
+            resource_usage,
+        });
+    }
+}
@@ -0,0 +1,109 @@
+// Windows job objects in which subprocesses can be started, see
+// PopenConfig::job.
+
+use std::io;
+use std::sync::Arc;
+
+use crate::win32::{self, Handle};
+
+/// A Windows job object, for managing a subprocess together with its
+/// descendants.
+///
+/// A subprocess is placed in the job by passing it to
+/// [`PopenConfig::job`], before it starts running.  The processes it
+/// creates are placed in the same job, so that [`terminate`] and the
+/// limits of the job apply to the whole process tree.  Cloning a
+/// `JobObject` refers to the same job.
+///
+/// [`PopenConfig::job`]: ../struct.PopenConfig.html#structfield.job
+/// [`terminate`]: #method.terminate
+#[derive(Debug, Clone)]
+pub struct JobObject {
+    handle: Arc<Handle>,
+}
+
+impl JobObject {
+    /// Create a new anonymous job object without limits.
+    pub fn new() -> io::Result<JobObject> {
+        Ok(JobObject {
+            handle: Arc::new(win32::CreateJobObject()?),
+        })
+    }
+
+    /// Terminate all processes in the job with the given exit code.
+    pub fn terminate(&self, exit_code: u32) -> io::Result<()> {
+        win32::TerminateJobObject(&self.handle, exit_code)
+    }
+
+    /// Whether to terminate the processes in the job when the last
+    /// handle to the job is closed.
+    ///
+    /// With this set, the process tree doesn't outlive the job, even if
+    /// the parent process exits without cleaning up.
+    pub fn set_kill_on_close(&self, kill: bool) -> io::Result<()> {
+        self.update_limits(|info| {
+            set_flag(info, win32::JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, kill);
+        })
+    }
+
+    /// Limit the memory committed by all processes in the job, in
+    /// bytes.  `None` removes the limit.
+    pub fn set_memory_limit(&self, bytes: Option<usize>) -> io::Result<()> {
+        self.update_limits(|info| {
+            set_flag(info, win32::JOB_OBJECT_LIMIT_JOB_MEMORY, bytes.is_some());
+            info.JobMemoryLimit = bytes.unwrap_or(0);
+        })
+    }
+
+    /// Limit the number of processes running in the job at the same
+    /// time.  `None` removes the limit.
+    pub fn set_process_limit(&self, count: Option<u32>) -> io::Result<()> {
+        self.update_limits(|info| {
+            set_flag(
+                info,
+                win32::JOB_OBJECT_LIMIT_ACTIVE_PROCESS,
+                count.is_some(),
+            );
+            info.BasicLimitInformation.ActiveProcessLimit = count.unwrap_or(0);
+        })
+    }
+
+    /// Limit the CPU time used by the processes in the job to the
+    /// given percentage of all CPUs, between 1 and 100.  `None` removes
+    /// the limit.
+    pub fn set_cpu_rate(&self, percent: Option<u32>) -> io::Result<()> {
+        if let Some(percent) = percent {
+            if percent == 0 || percent > 100 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "CPU rate must be between 1 and 100 percent",
+                ));
+            }
+        }
+        // the rate is in 1/100 of a percent
+        win32::SetJobCpuRate(&self.handle, percent.map(|p| p * 100))
+    }
+
+    pub(crate) fn assign(&self, process: &Handle) -> io::Result<()> {
+        win32::AssignProcessToJobObject(&self.handle, process)
+    }
+
+    // The limits are set all at once, so read the current ones to
+    // change only one of them.
+    fn update_limits(
+        &self,
+        update: impl FnOnce(&mut win32::JOBOBJECT_EXTENDED_LIMIT_INFORMATION),
+    ) -> io::Result<()> {
+        let mut info = win32::QueryJobLimits(&self.handle)?;
+        update(&mut info);
+        win32::SetJobLimits(&self.handle, &info)
+    }
+}
+
+fn set_flag(info: &mut win32::JOBOBJECT_EXTENDED_LIMIT_INFORMATION, flag: u32, on: bool) {
+    if on {
+        info.BasicLimitInformation.LimitFlags |= flag;
+    } else {
+        info.BasicLimitInformation.LimitFlags &= !flag;
+    }
+}
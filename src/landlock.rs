@@ -0,0 +1,184 @@
+// Landlock filesystem restrictions applied by the child before exec,
+// see PopenConfig::landlock.
+
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+// Constants and structures from linux/landlock.h.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+// all rights of ABI version 1, from EXECUTE to MAKE_SYM
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+// rights which can be granted on a regular file rather than a directory
+const ACCESS_FILE: u64 =
+    ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Landlock rules limiting the filesystem access of a subprocess.
+///
+/// The rules are applied by passing them to [`PopenConfig::landlock`].
+/// Once applied, the subprocess can only read the paths given to
+/// [`allow_read`] and [`allow_write`] and the files beneath them, and
+/// only write those given to [`allow_write`].
+///
+/// Landlock requires Linux 5.13 or later with Landlock enabled.
+/// [`abi_version`] can be used to check for support, and
+/// [`best_effort`] to run the subprocess unrestricted where it is not
+/// supported.
+///
+/// [`PopenConfig::landlock`]: ../struct.PopenConfig.html#structfield.landlock
+/// [`allow_read`]: #method.allow_read
+/// [`allow_write`]: #method.allow_write
+/// [`abi_version`]: #method.abi_version
+/// [`best_effort`]: #method.best_effort
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Landlock {
+    pub(crate) read: Vec<PathBuf>,
+    pub(crate) write: Vec<PathBuf>,
+    pub(crate) best_effort: bool,
+}
+
+impl Landlock {
+    /// Create rules allowing no filesystem access at all.
+    pub fn new() -> Landlock {
+        Landlock::default()
+    }
+
+    /// Allow reading and executing files beneath `path`.
+    pub fn allow_read(mut self, path: impl Into<PathBuf>) -> Landlock {
+        self.read.push(path.into());
+        self
+    }
+
+    /// Allow reading, writing, creating and removing files beneath
+    /// `path`.
+    pub fn allow_write(mut self, path: impl Into<PathBuf>) -> Landlock {
+        self.write.push(path.into());
+        self
+    }
+
+    /// Whether to start the subprocess without restrictions if the
+    /// kernel doesn't support Landlock.
+    ///
+    /// By default, starting the subprocess fails in that case.
+    pub fn best_effort(mut self, best_effort: bool) -> Landlock {
+        self.best_effort = best_effort;
+        self
+    }
+
+    /// Return the Landlock ABI version supported by the running kernel,
+    /// or `None` if Landlock is not supported or disabled.
+    pub fn abi_version() -> Option<u32> {
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                ptr::null::<RulesetAttr>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if version > 0 {
+            Some(version as u32)
+        } else {
+            None
+        }
+    }
+
+    // Create the ruleset in the parent, so that the child only has to
+    // restrict itself with it.  Returns None if Landlock is unsupported
+    // and best effort was requested.
+    pub(crate) fn create_ruleset(&self) -> io::Result<Option<OwnedFd>> {
+        let abi = match Landlock::abi_version() {
+            Some(abi) => abi,
+            None if self.best_effort => return Ok(None),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Landlock is not supported by the kernel",
+                ))
+            }
+        };
+        // handle every right the kernel knows about, so that anything
+        // not granted below is denied
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        for path in &self.read {
+            add_rule(&ruleset, path, ACCESS_READ & handled)?;
+        }
+        for path in &self.write {
+            add_rule(&ruleset, path, handled)?;
+        }
+        Ok(Some(ruleset))
+    }
+}
+
+fn add_rule(ruleset: &OwnedFd, path: &Path, mut access: u64) -> io::Result<()> {
+    // O_PATH doesn't require permission to read the path
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(path)?;
+    if !file.metadata()?.is_dir() {
+        access &= ACCESS_FILE;
+    }
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: file.as_raw_fd(),
+    };
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0u32,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
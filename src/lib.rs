@@ -75,26 +75,112 @@
 #![warn(missing_debug_implementations, rust_2018_idioms, missing_docs)]
 #![allow(clippy::type_complexity, clippy::single_match)]
 
+mod bridge;
 mod builder;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod cmdline;
 mod communicate;
+#[cfg(feature = "async")]
+mod exit_future;
+#[cfg(unix)]
+mod fifo;
+mod guard;
+mod handle;
+mod hooks;
+#[cfg(windows)]
+mod job;
+#[cfg(target_os = "linux")]
+mod landlock;
+#[cfg(unix)]
+mod notify;
 mod popen;
+mod pty;
+mod pump;
+mod registry;
+#[cfg(target_os = "linux")]
+mod seccomp;
+mod split;
+mod watchdog;
 
 #[cfg(unix)]
 mod posix;
+#[cfg(unix)]
+mod reaper;
+
+#[cfg(all(unix, feature = "sigchld"))]
+mod sigchld;
 
 #[cfg(windows)]
 mod win32;
 
 mod os_common;
 
-pub use self::builder::{CaptureData, Exec, NullFile, Pipeline};
-pub use self::communicate::{CommunicateError, Communicator};
-pub use self::os_common::ExitStatus;
-pub use self::popen::{make_pipe, Popen, PopenConfig, PopenError, Redirection, Result};
+pub use self::bridge::{BridgeSocket, BridgeStats};
+pub use self::builder::{
+    CaptureData, Coprocess, CoprocessIo, Exec, ExecContext, NullFile, Outcome, OutcomeClass,
+    Pipeline, RunLimits, RunReport,
+};
+pub use self::cmdline::ArgQuoting;
+pub use self::communicate::{
+    Chunks, CommunicateError, CommunicateEvent, CommunicateReader, CommunicateWriter, Communicator,
+    InputSource, Progress, StdoutReader, Stream,
+};
+#[cfg(feature = "poll-trace")]
+pub use self::communicate::{PollTrace, StreamTrace};
+#[cfg(feature = "async")]
+pub use self::exit_future::ExitFuture;
+pub use self::guard::ChildGuard;
+pub use self::handle::ProcessHandle;
+pub use self::hooks::{clear_hooks, set_exit_hook, set_spawn_hook, ExitInfo, SpawnInfo};
+pub use self::os_common::{ExitStatus, ResourceUsage, Signal, UsageSample};
+pub use self::popen::{
+    make_pipe, ChildSetupStep, DropPolicy, ExecFailure, ExecutableSearch, Popen, PopenConfig,
+    PopenConfigBuilder, PopenError, Redirection, Result, StopAction, TimeoutAction,
+};
+pub use self::pty::{Pty, PtySize};
+pub use self::registry::{enable_registry, shutdown_all};
+pub use self::split::{ChildHandle, ChildStreams};
 
 /// Subprocess extensions for Unix platforms.
 pub mod unix {
+    #[cfg(unix)]
+    pub use super::builder::unix::*;
+    #[cfg(target_os = "linux")]
+    pub use super::cgroup::Cgroup;
+    #[cfg(unix)]
+    pub use super::fifo::Fifo;
+    #[cfg(target_os = "linux")]
+    pub use super::landlock::Landlock;
+    #[cfg(unix)]
+    pub use super::notify::{Notifications, NotifyMessage};
     pub use super::popen::os_ext::*;
+    #[cfg(target_os = "linux")]
+    pub use super::popen::{Capability, Namespace, SchedPolicy};
+    #[cfg(unix)]
+    pub use super::popen::{PreExec, Rlimit};
+    #[cfg(all(unix, feature = "sigwinch"))]
+    pub use super::pty::ResizeRelay;
+    #[cfg(unix)]
+    pub use super::reaper::start_init_reaper;
+    #[cfg(target_os = "linux")]
+    pub use super::reaper::{set_child_subreaper, start_subreaper};
+    #[cfg(target_os = "linux")]
+    pub use super::seccomp::{BpfInstruction, SeccompAction, SeccompFilter};
+    #[cfg(all(unix, feature = "sigchld"))]
+    pub use super::sigchld::enable_sigchld_wakeup;
+}
+
+/// Subprocess extensions for Windows platforms.
+pub mod windows {
+    #[cfg(windows)]
+    pub use super::job::JobObject;
+    #[cfg(windows)]
+    pub use super::popen::{socket_handle, Logon, PriorityClass};
+    #[cfg(windows)]
+    pub use super::win32::{
+        CREATE_NEW_CONSOLE, CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW, DETACHED_PROCESS,
+    };
 }
 
 #[cfg(test)]
@@ -0,0 +1,166 @@
+// Readiness notifications sent by subprocesses with sd_notify(), see
+// PopenConfig::notify_socket.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A message sent by a subprocess over its `NOTIFY_SOCKET`.
+///
+/// Each message corresponds to one `NAME=value` assignment of the
+/// `sd_notify()` protocol.  A datagram with several assignments
+/// produces several messages, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NotifyMessage {
+    /// `READY=1`: the service has finished starting up.
+    Ready,
+    /// `RELOADING=1`: the service is reloading its configuration.
+    Reloading,
+    /// `STOPPING=1`: the service is shutting down.
+    Stopping,
+    /// `STATUS=...`: a description of the state of the service.
+    Status(String),
+    /// `WATCHDOG=1`: a keep-alive ping from the service.
+    Watchdog,
+    /// `ERRNO=...`: the service failed with the given `errno` value.
+    Errno(i32),
+    /// `MAINPID=...`: the PID of the main process of the service, when
+    /// it is not the subprocess itself.
+    MainPid(u32),
+    /// Any other assignment, given as its name and value.
+    Other(String, String),
+}
+
+impl NotifyMessage {
+    fn parse(line: &str) -> Option<NotifyMessage> {
+        let (name, value) = line.split_once('=')?;
+        let other = || NotifyMessage::Other(name.to_owned(), value.to_owned());
+        Some(match (name, value) {
+            ("READY", "1") => NotifyMessage::Ready,
+            ("RELOADING", "1") => NotifyMessage::Reloading,
+            ("STOPPING", "1") => NotifyMessage::Stopping,
+            ("STATUS", status) => NotifyMessage::Status(status.to_owned()),
+            ("WATCHDOG", "1") => NotifyMessage::Watchdog,
+            ("ERRNO", errno) => errno.parse().map_or_else(|_| other(), NotifyMessage::Errno),
+            ("MAINPID", pid) => pid.parse().map_or_else(|_| other(), NotifyMessage::MainPid),
+            _ => other(),
+        })
+    }
+}
+
+/// Notifications sent by a subprocess started with
+/// [`PopenConfig::notify_socket`].
+///
+/// Obtained with [`Popen::notifications`].  A background thread
+/// receives the messages from the socket and queues them until they are
+/// read.  Dropping the `Notifications`, normally together with the
+/// `Popen`, stops the thread and removes the socket.
+///
+/// [`PopenConfig::notify_socket`]: ../struct.PopenConfig.html#structfield.notify_socket
+/// [`Popen::notifications`]: ../struct.Popen.html#method.notifications
+#[derive(Debug)]
+pub struct Notifications {
+    messages: Mutex<mpsc::Receiver<NotifyMessage>>,
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    waker: UnixDatagram,
+}
+
+impl Notifications {
+    // Create the socket in the temporary directory, and start the
+    // thread receiving from it.
+    pub(crate) fn open() -> io::Result<Notifications> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "subprocess-notify-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let socket = UnixDatagram::bind(&path)?;
+        let waker = UnixDatagram::unbound()?;
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let spawned = thread::Builder::new()
+            .name("subprocess-notify".into())
+            .spawn(move || {
+                // the largest datagram sd_notify() sends
+                let mut buf = vec![0u8; 65536];
+                loop {
+                    let n = match socket.recv(&mut buf) {
+                        Ok(n) => n,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    };
+                    if thread_stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let text = String::from_utf8_lossy(&buf[..n]);
+                    for message in text.lines().filter_map(NotifyMessage::parse) {
+                        if sender.send(message).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            fs::remove_file(&path).ok();
+            return Err(e);
+        }
+        Ok(Notifications {
+            messages: Mutex::new(receiver),
+            path,
+            stop,
+            waker,
+        })
+    }
+
+    // The path passed to the child in NOTIFY_SOCKET.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the next queued message, if any, without waiting.
+    pub fn try_recv(&self) -> Option<NotifyMessage> {
+        self.messages.lock().unwrap().try_recv().ok()
+    }
+
+    /// Return the next message, waiting for up to `timeout` for one to
+    /// arrive.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<NotifyMessage> {
+        self.messages.lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    /// Wait for up to `timeout` for the subprocess to report that it is
+    /// ready, discarding the messages that precede `READY=1`.
+    ///
+    /// Returns false on timeout, which is also what happens if the
+    /// subprocess exits without reporting readiness.
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.recv_timeout(remaining) {
+                Some(NotifyMessage::Ready) => return true,
+                Some(_) => (),
+                None => return false,
+            }
+        }
+    }
+}
+
+impl Drop for Notifications {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // wake up the thread, which then sees the stop flag
+        self.waker.send_to(&[], &self.path).ok();
+        fs::remove_file(&self.path).ok();
+    }
+}
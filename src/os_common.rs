@@ -1,6 +1,13 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::process;
+use std::time::Duration;
+
 /// Exit status of a process.
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExitStatus {
     /// The process exited with the specified exit code.
     ///
@@ -10,8 +17,13 @@ pub enum ExitStatus {
 
     /// The process exited due to a signal with the specified number.
     ///
+    /// Whether the process also dumped core is reported by
+    /// [`Popen::core_dumped`].
+    ///
     /// This variant is never created on Windows, where signals of
     /// Unix kind do not exist.
+    ///
+    /// [`Popen::core_dumped`]: struct.Popen.html#method.core_dumped
     Signaled(u8),
 
     /// The process exit status cannot be described by the preceding
@@ -40,12 +52,241 @@ impl ExitStatus {
     /// You can pass the concrete `libc` signal numbers to this function, such as
     /// `status.is_killed_by(libc::SIGABRT)`.
     pub fn is_killed_by<T: Eq + From<u8>>(self, signum: T) -> bool {
-        if let ExitStatus::Signaled(n) = self {
+        if let Some(n) = self.signal() {
             let n: T = n.into();
             return n == signum;
         }
         false
     }
+
+    /// The number of the signal that killed the subprocess, if any.
+    pub fn signal(self) -> Option<u8> {
+        match self {
+            ExitStatus::Signaled(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// The name of the signal that killed the subprocess, such as
+    /// `"SIGSEGV"`.
+    ///
+    /// Returns `None` if the subprocess wasn't killed by a signal, or
+    /// if the signal has no well-known name.
+    pub fn signal_name(self) -> Option<&'static str> {
+        self.signal().and_then(signal_name)
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExitStatus::Exited(code) => write!(f, "exited with status {}", code),
+            ExitStatus::Signaled(n) => match self.signal_name() {
+                Some(name) => write!(f, "killed by {}", name),
+                None => write!(f, "killed by signal {}", n),
+            },
+            ExitStatus::Other(status) => write!(f, "unrecognized wait status {}", status),
+            ExitStatus::Undetermined => write!(f, "exit status undetermined"),
+        }
+    }
+}
+
+/// Convert the exit status of a process started with
+/// `std::process::Command`.
+impl From<process::ExitStatus> for ExitStatus {
+    #[cfg(unix)]
+    fn from(status: process::ExitStatus) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        crate::posix::decode_exit_status(status.into_raw())
+    }
+
+    #[cfg(windows)]
+    fn from(status: process::ExitStatus) -> ExitStatus {
+        match status.code() {
+            Some(code) => ExitStatus::Exited(code as u32),
+            None => ExitStatus::Undetermined,
+        }
+    }
+}
+
+/// Convert to the exit status used by `std::process`, where possible.
+///
+/// Fails with an error of kind `InvalidInput` for `Undetermined`, for
+/// exit codes that don't fit in a wait status on Unix, and for the
+/// variants that only exist on Unix on Windows.
+impl TryFrom<ExitStatus> for process::ExitStatus {
+    type Error = io::Error;
+
+    #[cfg(unix)]
+    fn try_from(status: ExitStatus) -> io::Result<process::ExitStatus> {
+        use std::os::unix::process::ExitStatusExt;
+        let raw = match status {
+            ExitStatus::Exited(code) if code <= 0xff => (code as i32) << 8,
+            ExitStatus::Signaled(n) => n as i32,
+            ExitStatus::Other(raw) => raw,
+            _ => return Err(not_convertible(status)),
+        };
+        Ok(process::ExitStatus::from_raw(raw))
+    }
+
+    #[cfg(windows)]
+    fn try_from(status: ExitStatus) -> io::Result<process::ExitStatus> {
+        use std::os::windows::process::ExitStatusExt;
+        match status {
+            ExitStatus::Exited(code) => Ok(process::ExitStatus::from_raw(code)),
+            _ => Err(not_convertible(status)),
+        }
+    }
+}
+
+fn not_convertible(status: ExitStatus) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{:?} has no std::process::ExitStatus equivalent", status),
+    )
+}
+
+#[cfg(unix)]
+fn signal_name(signum: u8) -> Option<&'static str> {
+    let name = match signum as i32 {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGCHLD => "SIGCHLD",
+        libc::SIGCONT => "SIGCONT",
+        libc::SIGSTOP => "SIGSTOP",
+        libc::SIGTSTP => "SIGTSTP",
+        libc::SIGTTIN => "SIGTTIN",
+        libc::SIGTTOU => "SIGTTOU",
+        libc::SIGURG => "SIGURG",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGVTALRM => "SIGVTALRM",
+        libc::SIGPROF => "SIGPROF",
+        libc::SIGWINCH => "SIGWINCH",
+        libc::SIGIO => "SIGIO",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(windows)]
+fn signal_name(_signum: u8) -> Option<&'static str> {
+    None
+}
+
+/// Resources used by a process, as reported when it is reaped.
+///
+/// Obtained with [`Popen::resource_usage`].  On Unix, the values come
+/// from the `rusage` returned by `wait4()`, and on Windows from
+/// `GetProcessTimes` and `GetProcessMemoryInfo`.
+///
+/// [`Popen::resource_usage`]: struct.Popen.html#method.resource_usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// Peak resident set size (working set on Windows), in bytes.
+    pub max_rss: u64,
+    /// Number of page faults serviced without I/O.  On Windows, this
+    /// is the total number of page faults.
+    pub minor_faults: u64,
+    /// Number of page faults that required I/O.  Always 0 on Windows,
+    /// which doesn't make the distinction.
+    pub major_faults: u64,
+}
+
+/// Snapshot of the resources used by a running process.
+///
+/// Obtained with [`Popen::sample_usage`].
+///
+/// [`Popen::sample_usage`]: struct.Popen.html#method.sample_usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UsageSample {
+    /// Current resident set size (working set on Windows), in bytes.
+    pub rss: u64,
+    /// CPU time spent in user mode so far.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode so far.
+    pub system_time: Duration,
+}
+
+/// A signal that can be sent to a process with [`Popen::send_signal`].
+///
+/// On Unix-like systems, each variant corresponds to a signal number.
+/// Windows has no signals, so `Interrupt` and `Break` are delivered as
+/// console control events, and the remaining variants terminate the
+/// process.
+///
+/// [`Popen::send_signal`]: struct.Popen.html#method.send_signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Signal {
+    /// Interrupt from the keyboard: `SIGINT` on Unix, `CTRL_C_EVENT`
+    /// on Windows.
+    Interrupt,
+
+    /// Quit from the keyboard: `SIGQUIT` on Unix, `CTRL_BREAK_EVENT`
+    /// on Windows.
+    Break,
+
+    /// Hangup of the controlling terminal: `SIGHUP` on Unix.
+    Hangup,
+
+    /// Request to terminate: `SIGTERM` on Unix.
+    Terminate,
+
+    /// Unconditional termination: `SIGKILL` on Unix.
+    Kill,
+
+    /// Signal with the specified number, not covered by the other
+    /// variants.
+    #[cfg(unix)]
+    Other(i32),
+}
+
+#[cfg(unix)]
+impl Signal {
+    /// Return the signal number.
+    pub fn number(self) -> i32 {
+        match self {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Break => libc::SIGQUIT,
+            Signal::Hangup => libc::SIGHUP,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Other(signum) => signum,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<i32> for Signal {
+    fn from(signum: i32) -> Signal {
+        match signum {
+            libc::SIGINT => Signal::Interrupt,
+            libc::SIGQUIT => Signal::Break,
+            libc::SIGHUP => Signal::Hangup,
+            libc::SIGTERM => Signal::Terminate,
+            libc::SIGKILL => Signal::Kill,
+            _ => Signal::Other(signum),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
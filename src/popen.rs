@@ -3,14 +3,43 @@ use std::env;
 use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::fs::File;
-use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::result;
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crate::communicate;
-use crate::os_common::{ExitStatus, StandardStream};
+#[cfg(unix)]
+use std::cell::UnsafeCell;
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, BorrowedFd, OwnedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, BorrowedHandle, OwnedHandle, OwnedSocket};
+#[cfg(unix)]
+use std::sync::Arc;
+
+use crate::bridge::{self, BridgeSocket, BridgeStats};
+use crate::cmdline::ArgQuoting;
+use crate::communicate::{self, InputSource};
+#[cfg(feature = "async")]
+use crate::exit_future::ExitFuture;
+use crate::guard::ChildGuard;
+use crate::handle::ProcessHandle;
+#[cfg(windows)]
+use crate::job::JobObject;
+#[cfg(target_os = "linux")]
+use crate::landlock::Landlock;
+#[cfg(unix)]
+use crate::notify::Notifications;
+use crate::os_common::{ExitStatus, ResourceUsage, Signal, StandardStream, UsageSample};
+use crate::pty::{Pty, PtySize};
+use crate::pump;
+#[cfg(target_os = "linux")]
+use crate::seccomp::{BpfInstruction, SeccompFilter};
+use crate::split::{ChildHandle, ChildStreams};
+use crate::watchdog::Watchdog;
 
 use self::ChildState::*;
 
@@ -65,7 +94,35 @@ pub struct Popen {
     pub stderr: Option<File>,
 
     child_state: ChildState,
+    // Set for processes adopted with from_pid or from_raw_handle.
+    adopted: bool,
+    // Set when a stream is taken with one of the take_* methods.
+    streams_taken: bool,
+    input_data: Option<Vec<u8>>,
     detached: bool,
+    stop_action: Option<StopAction>,
+    drop_policy: DropPolicy,
+    resource_usage: Option<ResourceUsage>,
+    core_dumped: bool,
+    start_time: Instant,
+    // Time from the start until the child was found to have finished.
+    runtime: Option<Duration>,
+    // ID of the process group led by the child, if one was created.
+    pgid: Option<u32>,
+    pty: Option<Pty>,
+    #[cfg(unix)]
+    notifications: Option<Notifications>,
+    // Set while a child started suspended hasn't been resumed.
+    #[cfg(unix)]
+    pending_exec: Option<os::PendingExec>,
+    // The argv reported to the exit hook, if one was installed when the
+    // child was started.
+    hook_argv: Option<Vec<OsString>>,
+    // Threads copying the output to Redirection::Writer sinks, joined
+    // by wait().
+    pumps: Vec<JoinHandle<()>>,
+    // Armed by PopenConfig::timeout.
+    watchdog: Option<Watchdog>,
 }
 
 #[derive(Debug)]
@@ -94,14 +151,15 @@ enum ChildState {
 ///
 /// This ensures that fields added later do not break existing code.
 ///
-/// An alternative to using `PopenConfig` directly is creating
-/// processes using [`Exec`], a builder for `Popen`.
+/// The configuration can also be created with [`PopenConfig::builder`],
+/// which validates it before it is used.  An alternative to using
+/// `PopenConfig` directly is creating processes using [`Exec`], a
+/// builder for `Popen`.
 ///
 /// [`Popen::create`]: struct.Popen.html#method.create
+/// [`PopenConfig::builder`]: struct.PopenConfig.html#method.builder
 /// [`Exec`]: struct.Exec.html
 /// [`Default`]: https://doc.rust-lang.org/core/default/trait.Default.html
-
-#[derive(Debug)]
 pub struct PopenConfig {
     /// How to configure the executed program's standard input.
     pub stdin: Redirection,
@@ -109,6 +167,60 @@ pub struct PopenConfig {
     pub stdout: Redirection,
     /// How to configure the executed program's standard error.
     pub stderr: Redirection,
+    /// Data to feed to the executed program's standard input.
+    ///
+    /// The data is written by [`Popen::communicate_start`], and the
+    /// methods built on it such as [`Popen::communicate`], when they are
+    /// called without input data of their own.  It requires `stdin` to
+    /// be `Redirection::Pipe`.
+    ///
+    /// [`Popen::communicate_start`]: struct.Popen.html#method.communicate_start
+    /// [`Popen::communicate`]: struct.Popen.html#method.communicate
+    pub input_data: Option<Vec<u8>>,
+
+    /// Run the subprocess in a pseudo terminal of the given size.
+    ///
+    /// If specified, the subprocess is attached to a new pseudo
+    /// terminal instead of pipes, so that interactive programs, and
+    /// ones which only emit colors or progress on a terminal, behave as
+    /// on a console.  The `stdin` and `stdout` of the `Popen` are
+    /// connected to the terminal, and the terminal is available through
+    /// [`Popen::pty`], for example to resize it.  `stdin` and `stdout`
+    /// can be left as they are or set to `Redirection::Pipe`, which
+    /// makes no difference; other redirections and `daemonize` can't be
+    /// combined with a pseudo terminal.  The standard error of the
+    /// subprocess also goes to the terminal.
+    ///
+    /// On Unix, the terminal is allocated with `openpty()`, and the
+    /// child starts a new session with `setsid()` and makes the terminal
+    /// its controlling terminal, so it can't be combined with
+    /// `controlling_tty` or `pgid`.  Its attributes are set by
+    /// `pty_raw` and `pty_echo`.
+    ///
+    /// On Windows, the terminal is a pseudo console created by
+    /// `CreatePseudoConsole`.  It can't be combined with `logon`.
+    ///
+    /// [`Popen::pty`]: struct.Popen.html#method.pty
+    pub pty: Option<PtySize>,
+
+    /// Put the pseudo terminal of the subprocess in raw mode.
+    ///
+    /// If set together with `pty`, the terminal is set up with
+    /// `cfmakeraw()`, which passes the input to the subprocess byte by
+    /// byte, without echoing it, processing special characters or
+    /// converting line endings.  The default is the canonical mode of
+    /// a new terminal, with line editing.
+    #[cfg(unix)]
+    pub pty_raw: bool,
+
+    /// Whether the pseudo terminal of the subprocess echoes its input.
+    ///
+    /// Defaults to true.  Clearing it turns off `ECHO` and related
+    /// flags of the terminal given by `pty`, for example to send
+    /// passwords or to keep the input out of the captured output.
+    #[cfg(unix)]
+    pub pty_echo: bool,
+
     /// Whether the `Popen` instance is initially detached.
     pub detached: bool,
 
@@ -119,8 +231,27 @@ pub struct PopenConfig {
     /// to the subprocess, which will see that as `argv[0]`.  On some
     /// Unix systems, `ps` will show the string passed as `argv[0]`,
     /// even though `executable` is actually running.
+    ///
+    /// On Windows, the program, whether given by `executable` or by
+    /// `argv[0]`, is located before the subprocess is started and passed
+    /// to `CreateProcess` apart from the command line, so that a path
+    /// containing spaces can't be mistaken for a shorter one.
     pub executable: Option<OsString>,
 
+    /// How to find the program when it is given without a directory.
+    ///
+    /// By default, the `PATH` of the current process is searched by the
+    /// child, and a missing program is reported as the `io::Error` of
+    /// the failed exec.  Alternatively, the program can be looked up in
+    /// the `PATH` of the configured `env`, or in a given list of
+    /// directories, before the child is started, reporting a missing
+    /// program with [`PopenError::ExecutableNotFound`].  See
+    /// [`ExecutableSearch`] for details.
+    ///
+    /// [`PopenError::ExecutableNotFound`]: enum.PopenError.html#variant.ExecutableNotFound
+    /// [`ExecutableSearch`]: enum.ExecutableSearch.html
+    pub executable_search: ExecutableSearch,
+
     /// Environment variables to pass to the subprocess.
     ///
     /// If this is None, environment variables are inherited from the calling
@@ -130,14 +261,49 @@ pub struct PopenConfig {
     /// variable appearing later in the vector.
     pub env: Option<Vec<(OsString, OsString)>>,
 
+    /// Names of environment variables whose values are secret.
+    ///
+    /// The value of a secret variable, such as a token or a password,
+    /// is replaced by `***` in the `Debug` output of `PopenConfig`, of
+    /// [`Exec`] and [`Pipeline`], and wherever the command line is
+    /// shown for logging.  The subprocess still receives the real
+    /// value.
+    ///
+    /// [`Exec`]: struct.Exec.html
+    /// [`Pipeline`]: struct.Pipeline.html
+    pub secret_env: Vec<OsString>,
+
     /// Initial current working directory of the subprocess.
     ///
-    /// None means inherit the working directory from the parent.
+    /// None means inherit the working directory from the parent.  On
+    /// Windows, directories too long for `CreateProcess` are passed in
+    /// the `\\?\` form, as is an `executable` longer than `MAX_PATH`.
+    ///
+    /// If the directory can't be entered, `Popen::create` fails with
+    /// [`PopenError::ChildSetupFailed`] for the `Chdir` step on Unix,
+    /// and with [`PopenError::ExecFailed`] on Windows.  Versions before
+    /// 0.3 reported it as `PopenError::IoError`.
+    ///
+    /// [`PopenError::ChildSetupFailed`]: enum.PopenError.html#variant.ChildSetupFailed
+    /// [`PopenError::ExecFailed`]: enum.PopenError.html#variant.ExecFailed
     pub cwd: Option<OsString>,
 
+    /// Initial current working directory of the subprocess, given as
+    /// an open directory.
+    ///
+    /// If specified, the child changes to the directory with `fchdir()`
+    /// instead of looking up a path, so that it starts in the very
+    /// directory that was opened, even if it has been renamed or
+    /// replaced since.  This can't be combined with `cwd` or `chroot`.
+    #[cfg(unix)]
+    pub cwd_fd: Option<OwnedFd>,
+
     /// Set user ID for the subprocess.
     ///
     /// If specified, calls `setuid()` before execing the child process.
+    /// This is done after setting the groups with `setgroups` and
+    /// `setgid`, which requires privileges that are given up by
+    /// `setuid`.
     #[cfg(unix)]
     pub setuid: Option<u32>,
 
@@ -149,14 +315,539 @@ pub struct PopenConfig {
     #[cfg(unix)]
     pub setgid: Option<u32>,
 
+    /// Change the root directory of the subprocess.
+    ///
+    /// If specified, calls `chroot()` before execing the child process,
+    /// confining it to the given directory, which normally requires
+    /// root privileges.  This is done before changing the current
+    /// directory, so `cwd` is interpreted inside the new root, and
+    /// defaults to the new root.  The program is also looked up inside
+    /// the new root.  It is done before `setuid`, so the subprocess can
+    /// run unprivileged in the new root.
+    #[cfg(unix)]
+    pub chroot: Option<OsString>,
+
+    /// Resource limits of the subprocess.
+    ///
+    /// Each entry gives a resource with its soft and hard limit, which
+    /// are set with `setrlimit()` before execing the child process, and
+    /// before `setuid`, so that a privileged parent can raise the hard
+    /// limits.  `u64::MAX` stands for no limit (`RLIM_INFINITY`).  By
+    /// default, the limits of the parent are inherited.
+    #[cfg(unix)]
+    pub rlimits: Vec<(Rlimit, u64, u64)>,
+
+    /// Set the supplementary group IDs of the subprocess.
+    ///
+    /// If specified, calls `setgroups()` before execing the child
+    /// process.  A process running as root that switches to another
+    /// user with `setuid` should normally also set the groups, or it
+    /// keeps the supplementary groups of root.  The groups of a user,
+    /// as set by `initgroups()`, are obtained with [`user_groups`].
+    ///
+    /// [`user_groups`]: unix/fn.user_groups.html
+    #[cfg(unix)]
+    pub setgroups: Option<Vec<u32>>,
+
     /// Make the subprocess belong to a new process group.
     ///
-    /// If specified, calls `setpgid(0, 0)` before execing the child process.
+    /// If specified, calls `setpgid(0, 0)` before execing the child
+    /// process on Unix, and starts the process with
+    /// `CREATE_NEW_PROCESS_GROUP` on Windows.  The whole group can then
+    /// be signaled with [`Popen::kill_group`] and
+    /// [`Popen::terminate_group`].
     ///
     /// Not to be confused with similarly named `setgid`.
-    #[cfg(unix)]
+    ///
+    /// [`Popen::kill_group`]: struct.Popen.html#method.kill_group
+    /// [`Popen::terminate_group`]: struct.Popen.html#method.terminate_group
     pub setpgid: bool,
 
+    /// Place the subprocess in an existing process group.
+    ///
+    /// If specified, calls `setpgid(0, pgid)` before execing the child
+    /// process, so that subprocesses started at different times can be
+    /// signaled together.  The group is typically that of an earlier
+    /// subprocess started with `setpgid`, as returned by
+    /// [`Popen::pgid`], and must belong to the same session.  The group
+    /// can then be signaled with [`Popen::kill_group`] and
+    /// [`Popen::terminate_group`] of either subprocess.
+    ///
+    /// Can't be combined with `setpgid` or `setsid`, which create a new
+    /// group.
+    ///
+    /// [`Popen::pgid`]: struct.Popen.html#method.pgid
+    /// [`Popen::kill_group`]: struct.Popen.html#method.kill_group
+    /// [`Popen::terminate_group`]: struct.Popen.html#method.terminate_group
+    #[cfg(unix)]
+    pub pgid: Option<u32>,
+
+    /// Make the subprocess the leader of a new session.
+    ///
+    /// If specified, calls `setsid()` before execing the child process,
+    /// detaching it from the controlling terminal of the parent.  The
+    /// subprocess also becomes the leader of a new process group, as
+    /// with `setpgid`.
+    #[cfg(unix)]
+    pub setsid: bool,
+
+    /// Make the terminal the controlling terminal of the subprocess.
+    ///
+    /// If specified, the terminal, typically the slave side of a
+    /// pseudo-terminal, is acquired with the `TIOCSCTTY` ioctl in the
+    /// new session of the subprocess, so that it receives job control
+    /// signals and can open `/dev/tty`.  Requires `setsid`.  The
+    /// terminal is not redirected to the standard streams, which can be
+    /// done with `Redirection::File`.
+    #[cfg(unix)]
+    pub controlling_tty: Option<File>,
+
+    /// Rules for quoting the arguments when composing the command line.
+    ///
+    /// Only used on Windows, where the arguments are passed to the
+    /// subprocess as a single string.  See [`ArgQuoting`] for the
+    /// available rules.
+    ///
+    /// [`ArgQuoting`]: enum.ArgQuoting.html
+    pub arg_quoting: ArgQuoting,
+
+    /// Whether to pass the arguments of a batch file as configured.
+    ///
+    /// Only used on Windows, where a `.bat` or `.cmd` program is run by
+    /// `cmd.exe`, which parses the command line with rules of its own.
+    /// Arguments quoted for other programs can break out of their
+    /// quotes and run commands, so by default the arguments of a batch
+    /// file are quoted with `ArgQuoting::Cmd` regardless of
+    /// `arg_quoting`, and an argument that can't be passed safely is
+    /// refused with [`PopenError::UnsafeBatchArgument`].  Setting this
+    /// flag uses `arg_quoting` for batch files as for other programs.
+    ///
+    /// [`PopenError::UnsafeBatchArgument`]: enum.PopenError.html#variant.UnsafeBatchArgument
+    pub allow_batch: bool,
+
+    /// Set the file mode creation mask of the subprocess.
+    ///
+    /// If specified, calls `umask()` before execing the child process,
+    /// so that the permissions of files it creates don't depend on the
+    /// umask of the parent.
+    #[cfg(unix)]
+    pub umask: Option<u32>,
+
+    /// Functions to run in the child process before execing the program.
+    ///
+    /// Like `std::os::unix::process::CommandExt::pre_exec`, for setup
+    /// this crate doesn't provide, such as an `ioctl` on an inherited
+    /// device or a `prctl` flag.  The functions run in order, after the
+    /// other setup, including `setuid` and `umask`, but before
+    /// `landlock` and `seccomp` restrict the process.  See
+    /// [`PreExec::new`] for what they may do.
+    ///
+    /// [`PreExec::new`]: unix/struct.PreExec.html#method.new
+    #[cfg(unix)]
+    pub pre_exec: Vec<PreExec>,
+
+    /// Additional file descriptors passed to the subprocess, each as
+    /// the given descriptor number in the child.
+    ///
+    /// This can be used to pass descriptors beyond the standard
+    /// streams, such as a control socket expected on descriptor 3 or a
+    /// pre-opened log file.  The descriptors are installed with
+    /// `dup2()` just before the program is executed, after
+    /// `pre_exec` and `landlock`, without `FD_CLOEXEC`, so that the
+    /// program inherits them.  Descriptors can be given in any order
+    /// and may be moved around, e.g. swapping 3 and 4; the original
+    /// descriptors are not inherited, unless mapped to their own
+    /// number.  The standard streams can't be mapped this way, and
+    /// each child descriptor can only be given once.
+    #[cfg(unix)]
+    pub fd_map: Vec<(RawFd, OwnedFd)>,
+
+    /// Listening sockets passed to the subprocess with the socket
+    /// activation protocol of systemd.
+    ///
+    /// The sockets are passed as descriptors 3 and up, in order, and the
+    /// environment variables `LISTEN_FDS` and `LISTEN_PID` are set to
+    /// their number and to the PID of the subprocess, as expected by
+    /// `sd_listen_fds()`.  This allows a supervisor to bind the ports of
+    /// the services it starts.  `LISTEN_FDNAMES` is removed from the
+    /// environment.  `fd_map` can't also map the descriptors used for
+    /// the sockets.
+    #[cfg(unix)]
+    pub listen_fds: Vec<OwnedFd>,
+
+    /// Create a notification socket for the subprocess, as systemd
+    /// does for services of `Type=notify`.
+    ///
+    /// The `NOTIFY_SOCKET` environment variable of the subprocess is set
+    /// to the path of a datagram socket created in the temporary
+    /// directory, over which `sd_notify()` reports readiness and status
+    /// changes.  The messages can be read with [`Popen::notifications`],
+    /// for example to wait until a service is ready before using it.
+    ///
+    /// [`Popen::notifications`]: struct.Popen.html#method.notifications
+    #[cfg(unix)]
+    pub notify_socket: bool,
+
+    /// Run the subprocess as a daemon that outlives this process.
+    ///
+    /// On Unix, the child starts a new session with `setsid()` and
+    /// forks again, so that the program runs in a grandchild that is
+    /// not a session leader and is re-parented to init once the
+    /// intermediate process exits.  On Windows, the process is started
+    /// with `DETACHED_PROCESS` and `CREATE_NEW_PROCESS_GROUP`, without
+    /// a console.  On both, the standard streams that are not
+    /// redirected are connected to the null device instead of being
+    /// inherited, and the `Popen` is detached.
+    ///
+    /// On Unix, the daemon is not a child of this process, so its exit
+    /// status can't be obtained: waiting for it returns
+    /// `ExitStatus::Undetermined` without waiting.  [`Popen::pid`]
+    /// returns the PID of the daemon, which can still be signaled.
+    ///
+    /// [`Popen::pid`]: struct.Popen.html#method.pid
+    pub daemonize: bool,
+
+    /// What to do with the subprocess when the `Popen` is dropped while
+    /// it is still running.
+    ///
+    /// By default, dropping waits for the subprocess to finish.  This
+    /// doesn't apply if the `Popen` is detached.  See [`DropPolicy`]
+    /// for the alternatives.
+    ///
+    /// [`DropPolicy`]: enum.DropPolicy.html
+    pub drop_policy: DropPolicy,
+
+    /// Maximum time the subprocess is allowed to run.
+    ///
+    /// If specified, a watchdog thread started together with the
+    /// subprocess stops it as given by `on_timeout` once the time,
+    /// measured on the monotonic clock, has elapsed.  The timeout is
+    /// enforced whether or not the `Popen` is waited for, so that it
+    /// also ends a subprocess whose output is read to the end, and
+    /// whether it was reached is reported by [`Popen::timed_out`].
+    /// Only the subprocess itself is stopped, not the processes it has
+    /// started, which may keep its output open.
+    ///
+    /// [`Popen::timed_out`]: struct.Popen.html#method.timed_out
+    pub timeout: Option<Duration>,
+
+    /// How the subprocess is stopped when `timeout` elapses.
+    pub on_timeout: TimeoutAction,
+
+    /// Set the scheduling priority of the subprocess, as a niceness.
+    ///
+    /// Higher values give the subprocess a lower priority, so that
+    /// background work doesn't compete with the interactive parent.
+    /// On Unix-like systems, calls `setpriority()` with the value,
+    /// which ranges from -20 to 19, before execing the child process;
+    /// the value is absolute, not an increment, and lowering it below
+    /// that of the parent requires privileges.  On Windows, the process
+    /// is started with the closest priority class: 15 and above give
+    /// `IDLE_PRIORITY_CLASS`, 5 to 14 `BELOW_NORMAL_PRIORITY_CLASS`,
+    /// -5 to -14 `ABOVE_NORMAL_PRIORITY_CLASS`, and -15 and below
+    /// `HIGH_PRIORITY_CLASS`.
+    pub nice: Option<i32>,
+
+    /// Start the subprocess with the given Windows priority class.
+    ///
+    /// This gives direct access to the priority classes, including
+    /// `REALTIME_PRIORITY_CLASS` which can't be expressed with `nice`.
+    /// It can't be combined with `nice`.
+    #[cfg(windows)]
+    pub priority_class: Option<PriorityClass>,
+
+    /// Additional process creation flags, such as
+    /// [`CREATE_NO_WINDOW`] or [`CREATE_NEW_CONSOLE`].
+    ///
+    /// The flags are passed to `CreateProcess` together with the ones
+    /// implied by the other options.  A GUI application can start
+    /// console programs with `CREATE_NO_WINDOW` so that no console
+    /// window flashes.
+    ///
+    /// [`CREATE_NO_WINDOW`]: windows/constant.CREATE_NO_WINDOW.html
+    /// [`CREATE_NEW_CONSOLE`]: windows/constant.CREATE_NEW_CONSOLE.html
+    #[cfg(windows)]
+    pub creation_flags: u32,
+
+    /// How the main window of the subprocess is shown, as a `SW_*`
+    /// value such as `SW_HIDE` (0).
+    ///
+    /// If specified, sets `wShowWindow` in the `STARTUPINFO` of the
+    /// subprocess, which applies to the first window it shows, or to
+    /// the console window created with `CREATE_NEW_CONSOLE`.
+    #[cfg(windows)]
+    pub show_window: Option<u16>,
+
+    /// Command line to pass to the subprocess as it is.
+    ///
+    /// If specified, it is passed to `CreateProcess` instead of the
+    /// command line composed from `argv`, for programs that parse their
+    /// command line in ways no quoting rules describe, such as
+    /// `msiexec` or `cmd /c` with a command of its own.  The program is
+    /// still given by `executable` or by `argv[0]`, and the rest of
+    /// `argv` is unused.  Neither `arg_quoting` nor the quoting of
+    /// batch files described under `allow_batch` applies to it.
+    #[cfg(windows)]
+    pub raw_cmdline: Option<OsString>,
+
+    /// Start the subprocess with a restricted token.
+    ///
+    /// If true, the subprocess runs with a token created from the one
+    /// of the current process by `CreateRestrictedToken`, with all
+    /// privileges removed except `SeChangeNotifyPrivilege`.
+    #[cfg(windows)]
+    pub restricted_token: bool,
+
+    /// Start the subprocess inside the AppContainer with the given
+    /// profile name.
+    ///
+    /// The profile is created if it doesn't exist.  The subprocess then
+    /// runs isolated from the files, registry keys and network of the
+    /// user, except those granted to the AppContainer.  It can still
+    /// use the standard streams redirected by the parent.
+    #[cfg(windows)]
+    pub app_container: Option<OsString>,
+
+    /// Start the subprocess as another user, logging on with the given
+    /// credentials.
+    ///
+    /// If specified, the subprocess is created by
+    /// `CreateProcessWithLogonW`.  Unless `env` is specified, the
+    /// subprocess gets the environment of the user rather than that of
+    /// the current process.  It can't be combined with `user_token`,
+    /// `restricted_token` or `app_container`.
+    #[cfg(windows)]
+    pub logon: Option<Logon>,
+
+    /// Start the subprocess as the user of the given primary token.
+    ///
+    /// If specified, the subprocess is created by
+    /// `CreateProcessAsUserW`, which typically requires the calling
+    /// process to run as a service.  Unless `env` is specified, the
+    /// subprocess gets the environment of the user rather than that of
+    /// the current process.  It can't be combined with `logon` or
+    /// `restricted_token`.
+    #[cfg(windows)]
+    pub user_token: Option<OwnedHandle>,
+
+    /// Additional handles inherited by the subprocess.
+    ///
+    /// The subprocess only inherits its standard streams and these
+    /// handles, which are passed with `PROC_THREAD_ATTRIBUTE_HANDLE_LIST`
+    /// and keep their values in the subprocess.  Other inheritable
+    /// handles of the current process, such as those created for a
+    /// subprocess spawned concurrently from another thread, are not
+    /// inherited.  The handles are only inheritable while the
+    /// subprocess is being created.  Sockets are converted to handles with
+    /// [`windows::socket_handle`].  It can't be combined with `logon`.
+    ///
+    /// [`windows::socket_handle`]: windows/fn.socket_handle.html
+    #[cfg(windows)]
+    pub inherit_handles: Vec<OwnedHandle>,
+
+    /// Set the scheduling policy of the subprocess.
+    ///
+    /// If specified, calls `sched_setscheduler()` before execing the
+    /// child process, and before `setuid`, so that privileged real-time
+    /// policies can be given to an unprivileged subprocess.
+    #[cfg(target_os = "linux")]
+    pub sched_policy: Option<SchedPolicy>,
+
+    /// Namespaces in which to isolate the subprocess.
+    ///
+    /// If not empty, calls `unshare()` with the namespaces before
+    /// execing the child process, and before the other setup, such as
+    /// `chroot` and `setuid`, which then applies inside the namespaces.
+    /// A new mount namespace has its mounts made private, so that
+    /// mounting in it doesn't affect the parent.
+    ///
+    /// As with `unshare(1)` without `--fork`, a new PID namespace
+    /// applies to the children of the subprocess, the first of which
+    /// becomes PID 1, and not to the subprocess itself.
+    #[cfg(target_os = "linux")]
+    pub unshare: Vec<Namespace>,
+
+    /// UID mappings of a new user namespace, as `(inside, outside,
+    /// count)` ranges.
+    ///
+    /// Written to `/proc/self/uid_map` after creating the namespace.
+    /// An unprivileged process can only map its own UID, typically with
+    /// `[(0, uid, 1)]` to run as root inside the namespace.  Requires
+    /// `Namespace::User` in `unshare`.
+    #[cfg(target_os = "linux")]
+    pub uid_map: Option<Vec<(u32, u32, u32)>>,
+
+    /// GID mappings of a new user namespace, as `(inside, outside,
+    /// count)` ranges.
+    ///
+    /// Written to `/proc/self/gid_map` after creating the namespace,
+    /// after denying `setgroups()` in the namespace, which is required
+    /// for unprivileged processes.  Requires `Namespace::User` in
+    /// `unshare`.
+    #[cfg(target_os = "linux")]
+    pub gid_map: Option<Vec<(u32, u32, u32)>>,
+
+    /// Start the subprocess in a cgroup v2, given by the path of its
+    /// directory.
+    ///
+    /// If specified, the child moves itself to the cgroup by writing to
+    /// its `cgroup.procs` before execing the program, so the program
+    /// runs under the limits of the cgroup from the start.  A
+    /// [`Cgroup`] can be used to create the cgroup and set its limits.
+    ///
+    /// [`Cgroup`]: unix/struct.Cgroup.html
+    #[cfg(target_os = "linux")]
+    pub cgroup: Option<OsString>,
+
+    /// Seccomp filter restricting the system calls of the subprocess.
+    ///
+    /// If specified, the child sets `PR_SET_NO_NEW_PRIVS` and installs
+    /// the filter with `seccomp()` as the last step before execing the
+    /// program, so the filter must allow `execve`.  The filter is
+    /// inherited by the children of the subprocess and can't be
+    /// removed.
+    #[cfg(target_os = "linux")]
+    pub seccomp: Option<SeccompFilter>,
+
+    /// Landlock rules restricting the filesystem access of the
+    /// subprocess.
+    ///
+    /// If specified, the Landlock ruleset is created when the
+    /// subprocess is started, and the child sets `PR_SET_NO_NEW_PRIVS`
+    /// and restricts itself with it before execing the program.  The
+    /// restrictions are inherited by the children of the subprocess.
+    #[cfg(target_os = "linux")]
+    pub landlock: Option<Landlock>,
+
+    /// Adjust the badness score of the subprocess for the Linux OOM
+    /// killer.
+    ///
+    /// If specified, the value, from -1000 to 1000, is written to
+    /// `/proc/self/oom_score_adj` before execing the child process.
+    /// Positive values make the subprocess more likely to be killed
+    /// when the system runs out of memory, so that a sacrificial worker
+    /// is killed before the parent.  Lowering the value below that of
+    /// the parent requires privileges.
+    #[cfg(target_os = "linux")]
+    pub oom_score_adj: Option<i32>,
+
+    /// Capabilities to keep in the bounding set of the subprocess.
+    ///
+    /// If specified, all other capabilities are dropped from the
+    /// capability bounding set and the inheritable set, and the
+    /// ambient set is cleared, before changing the user or group and
+    /// execing the program.  The program then can't gain the dropped
+    /// capabilities, even when it runs as root or is set-user-ID.
+    /// Dropping from the bounding set requires `CAP_SETPCAP`.
+    #[cfg(target_os = "linux")]
+    pub keep_capabilities: Option<Vec<Capability>>,
+
+    /// Start the subprocess with `clone(CLONE_VM | CLONE_VFORK)`
+    /// instead of `fork()`.
+    ///
+    /// The child then shares the memory of the current process until
+    /// it executes the program, like after `vfork()`, instead of
+    /// getting a copy of its page tables.  This avoids the cost of the
+    /// copy when the current process is large, and the risk of `fork()`
+    /// failing under strict memory overcommit.  The thread starting the
+    /// subprocess is suspended until the program is executed or the
+    /// setup of the child fails.
+    ///
+    /// So that the child can't disturb the current process, this can't
+    /// be combined with `pre_exec`, which runs arbitrary code,
+    /// `daemonize` and `start_suspended`, which would keep the thread
+    /// suspended, `setuid`, `setgid` and `setgroups`, which the C
+    /// library applies to all the threads of a process, or a new user
+    /// namespace, which the kernel refuses to a process sharing its
+    /// memory.  The remaining setup is done with system calls on data
+    /// prepared in advance, without allocating or taking locks.
+    /// Subprocesses `posix_spawn()` can start are started that way
+    /// regardless, see [`Popen::create`].
+    ///
+    /// [`Popen::create`]: struct.Popen.html#method.create
+    #[cfg(target_os = "linux")]
+    pub vfork: bool,
+
+    /// Capacity of the pipes created for the standard output and error
+    /// of the subprocess, in bytes.
+    ///
+    /// If specified, the pipes created for `Redirection::Pipe` are
+    /// resized with `F_SETPIPE_SZ`, by default from 64 KiB, so that a
+    /// subprocess producing output faster than it is read blocks less
+    /// often.  The size is capped at `/proc/sys/fs/pipe-max-size`,
+    /// normally 1 MiB, and rounded up by the kernel to a power of two
+    /// number of pages; the capacity in effect is returned by
+    /// [`Popen::pipe_size`], and `communicate` reads up to that much at
+    /// a time.  Resizing is best-effort: if the user has exceeded the
+    /// kernel's limit on pipe buffers of unprivileged users
+    /// (`/proc/sys/fs/pipe-user-pages-soft`), the pipes keep their size.
+    ///
+    /// [`Popen::pipe_size`]: struct.Popen.html#method.pipe_size
+    #[cfg(target_os = "linux")]
+    pub pipe_size: Option<usize>,
+
+    /// Pledge promises of the program, such as `"stdio rpath"`.
+    ///
+    /// If specified, the child calls `pledge()` with these as the
+    /// `execpromises` right before execing the program, which then
+    /// starts restricted to them.  See `pledge(2)` for the promises.
+    #[cfg(target_os = "openbsd")]
+    pub pledge: Option<String>,
+
+    /// Paths visible to the child, with their `unveil()` permissions
+    /// such as `"rx"`.
+    ///
+    /// If not empty, the child calls `unveil()` for each path and then
+    /// locks the list before execing the program.  OpenBSD discards the
+    /// unveiled paths on exec, so this restricts the programs the child
+    /// is able to execute, and the program has to `unveil()` its own
+    /// paths; combine with `pledge` to restrict the program itself.
+    #[cfg(target_os = "openbsd")]
+    pub unveil: Vec<(OsString, String)>,
+
+    /// Start the subprocess in a Windows job object.
+    ///
+    /// If specified, the subprocess is created suspended and assigned
+    /// to the job before it runs, so it and all of its descendants are
+    /// subject to the limits of the job, and are terminated together by
+    /// [`JobObject::terminate`] or when the job is closed with kill on
+    /// close set.
+    ///
+    /// [`JobObject::terminate`]: windows/struct.JobObject.html#method.terminate
+    #[cfg(windows)]
+    pub job: Option<JobObject>,
+
+    /// Restrict the subprocess to run on the given CPUs.
+    ///
+    /// The CPUs are numbered from 0.  On Linux, calls
+    /// `sched_setaffinity()` before execing the child process.  On
+    /// Windows, the process is created suspended and resumed after
+    /// setting its affinity with `SetProcessAffinityMask`, which only
+    /// supports the CPUs of the first processor group.  The subprocess
+    /// fails to start if none of the CPUs is available.
+    #[cfg(any(target_os = "linux", windows))]
+    pub cpu_affinity: Option<Vec<usize>>,
+
+    /// Start the subprocess suspended, before it runs any code of the
+    /// program.
+    ///
+    /// This allows a debugger, tracer or profiler to attach to the
+    /// subprocess before it starts.  The subprocess runs once resumed
+    /// with [`Popen::resume`].
+    ///
+    /// On Unix-like systems, the child stops itself with `SIGSTOP`
+    /// just before executing the program, after all the other setup is
+    /// done except installing the `seccomp` filter, and
+    /// `Popen::create` returns once it has stopped.  Failure to install
+    /// the filter or to execute the program is then reported by
+    /// `Popen::resume` instead of `Popen::create`.  On Windows, the
+    /// process is created with `CREATE_SUSPENDED`.
+    ///
+    /// Can't be combined with `daemonize`.
+    ///
+    /// [`Popen::resume`]: struct.Popen.html#method.resume
+    pub start_suspended: bool,
+
     // Add this field to force construction using ..Default::default() for
     // backward compatibility.  Unfortunately we can't mark this non-public
     // because then ..Default::default() wouldn't work either.
@@ -164,6 +855,125 @@ pub struct PopenConfig {
     pub _use_default_to_construct: (),
 }
 
+impl fmt::Debug for PopenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the values of secret environment variables are not shown
+        let env = self.env.as_ref().map(|env| {
+            env.iter()
+                .map(|(k, v)| {
+                    if self.is_secret_env(k) {
+                        (k.as_os_str(), OsStr::new("***"))
+                    } else {
+                        (k.as_os_str(), v.as_os_str())
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut d = f.debug_struct("PopenConfig");
+        d.field("stdin", &self.stdin);
+        d.field("stdout", &self.stdout);
+        d.field("stderr", &self.stderr);
+        d.field("input_data", &self.input_data);
+        d.field("pty", &self.pty);
+        #[cfg(unix)]
+        d.field("pty_raw", &self.pty_raw);
+        #[cfg(unix)]
+        d.field("pty_echo", &self.pty_echo);
+        d.field("detached", &self.detached);
+        d.field("executable", &self.executable);
+        d.field("executable_search", &self.executable_search);
+        d.field("env", &env);
+        d.field("secret_env", &self.secret_env);
+        d.field("cwd", &self.cwd);
+        #[cfg(unix)]
+        d.field("cwd_fd", &self.cwd_fd);
+        #[cfg(unix)]
+        d.field("setuid", &self.setuid);
+        #[cfg(unix)]
+        d.field("setgid", &self.setgid);
+        #[cfg(unix)]
+        d.field("chroot", &self.chroot);
+        #[cfg(unix)]
+        d.field("rlimits", &self.rlimits);
+        #[cfg(unix)]
+        d.field("setgroups", &self.setgroups);
+        d.field("setpgid", &self.setpgid);
+        #[cfg(unix)]
+        d.field("pgid", &self.pgid);
+        #[cfg(unix)]
+        d.field("setsid", &self.setsid);
+        #[cfg(unix)]
+        d.field("controlling_tty", &self.controlling_tty);
+        d.field("arg_quoting", &self.arg_quoting);
+        d.field("allow_batch", &self.allow_batch);
+        #[cfg(unix)]
+        d.field("umask", &self.umask);
+        #[cfg(unix)]
+        d.field("pre_exec", &self.pre_exec);
+        #[cfg(unix)]
+        d.field("fd_map", &self.fd_map);
+        #[cfg(unix)]
+        d.field("listen_fds", &self.listen_fds);
+        #[cfg(unix)]
+        d.field("notify_socket", &self.notify_socket);
+        d.field("daemonize", &self.daemonize);
+        d.field("drop_policy", &self.drop_policy);
+        d.field("timeout", &self.timeout);
+        d.field("on_timeout", &self.on_timeout);
+        d.field("nice", &self.nice);
+        #[cfg(windows)]
+        d.field("priority_class", &self.priority_class);
+        #[cfg(windows)]
+        d.field("creation_flags", &self.creation_flags);
+        #[cfg(windows)]
+        d.field("show_window", &self.show_window);
+        #[cfg(windows)]
+        d.field("raw_cmdline", &self.raw_cmdline);
+        #[cfg(windows)]
+        d.field("restricted_token", &self.restricted_token);
+        #[cfg(windows)]
+        d.field("app_container", &self.app_container);
+        #[cfg(windows)]
+        d.field("logon", &self.logon);
+        #[cfg(windows)]
+        d.field("user_token", &self.user_token);
+        #[cfg(windows)]
+        d.field("inherit_handles", &self.inherit_handles);
+        #[cfg(target_os = "linux")]
+        d.field("sched_policy", &self.sched_policy);
+        #[cfg(target_os = "linux")]
+        d.field("unshare", &self.unshare);
+        #[cfg(target_os = "linux")]
+        d.field("uid_map", &self.uid_map);
+        #[cfg(target_os = "linux")]
+        d.field("gid_map", &self.gid_map);
+        #[cfg(target_os = "linux")]
+        d.field("cgroup", &self.cgroup);
+        #[cfg(target_os = "linux")]
+        d.field("seccomp", &self.seccomp);
+        #[cfg(target_os = "linux")]
+        d.field("landlock", &self.landlock);
+        #[cfg(target_os = "linux")]
+        d.field("oom_score_adj", &self.oom_score_adj);
+        #[cfg(target_os = "linux")]
+        d.field("keep_capabilities", &self.keep_capabilities);
+        #[cfg(target_os = "linux")]
+        d.field("vfork", &self.vfork);
+        #[cfg(target_os = "linux")]
+        d.field("pipe_size", &self.pipe_size);
+        #[cfg(target_os = "openbsd")]
+        d.field("pledge", &self.pledge);
+        #[cfg(target_os = "openbsd")]
+        d.field("unveil", &self.unveil);
+        #[cfg(windows)]
+        d.field("job", &self.job);
+        #[cfg(any(target_os = "linux", windows))]
+        d.field("cpu_affinity", &self.cpu_affinity);
+        d.field("start_suspended", &self.start_suspended);
+        d.finish()
+    }
+}
+
 impl PopenConfig {
     /// Clone the underlying [`PopenConfig`], or return an error.
     ///
@@ -179,16 +989,121 @@ impl PopenConfig {
             stdin: self.stdin.try_clone()?,
             stdout: self.stdout.try_clone()?,
             stderr: self.stderr.try_clone()?,
+            input_data: self.input_data.clone(),
+            pty: self.pty,
+            #[cfg(unix)]
+            pty_raw: self.pty_raw,
+            #[cfg(unix)]
+            pty_echo: self.pty_echo,
             detached: self.detached,
             executable: self.executable.as_ref().cloned(),
+            executable_search: self.executable_search.clone(),
             env: self.env.clone(),
+            secret_env: self.secret_env.clone(),
             cwd: self.cwd.clone(),
             #[cfg(unix)]
+            cwd_fd: self.cwd_fd.as_ref().map(OwnedFd::try_clone).transpose()?,
+            #[cfg(unix)]
             setuid: self.setuid,
             #[cfg(unix)]
             setgid: self.setgid,
             #[cfg(unix)]
+            chroot: self.chroot.clone(),
+            #[cfg(unix)]
+            rlimits: self.rlimits.clone(),
+            #[cfg(unix)]
+            setgroups: self.setgroups.clone(),
             setpgid: self.setpgid,
+            #[cfg(unix)]
+            pgid: self.pgid,
+            #[cfg(unix)]
+            setsid: self.setsid,
+            #[cfg(unix)]
+            controlling_tty: match self.controlling_tty {
+                Some(ref tty) => Some(tty.try_clone()?),
+                None => None,
+            },
+            arg_quoting: self.arg_quoting,
+            allow_batch: self.allow_batch,
+            #[cfg(unix)]
+            umask: self.umask,
+            #[cfg(unix)]
+            pre_exec: self.pre_exec.clone(),
+            #[cfg(unix)]
+            fd_map: self
+                .fd_map
+                .iter()
+                .map(|(child_fd, fd)| Ok((*child_fd, fd.try_clone()?)))
+                .collect::<io::Result<_>>()?,
+            #[cfg(unix)]
+            listen_fds: self
+                .listen_fds
+                .iter()
+                .map(OwnedFd::try_clone)
+                .collect::<io::Result<_>>()?,
+            #[cfg(unix)]
+            notify_socket: self.notify_socket,
+            daemonize: self.daemonize,
+            drop_policy: self.drop_policy,
+            timeout: self.timeout,
+            on_timeout: self.on_timeout,
+            nice: self.nice,
+            #[cfg(windows)]
+            priority_class: self.priority_class,
+            #[cfg(windows)]
+            creation_flags: self.creation_flags,
+            #[cfg(windows)]
+            show_window: self.show_window,
+            #[cfg(windows)]
+            raw_cmdline: self.raw_cmdline.clone(),
+            #[cfg(windows)]
+            restricted_token: self.restricted_token,
+            #[cfg(windows)]
+            app_container: self.app_container.clone(),
+            #[cfg(windows)]
+            logon: self.logon.clone(),
+            #[cfg(windows)]
+            user_token: match self.user_token {
+                Some(ref token) => Some(token.try_clone()?),
+                None => None,
+            },
+            #[cfg(windows)]
+            inherit_handles: self
+                .inherit_handles
+                .iter()
+                .map(OwnedHandle::try_clone)
+                .collect::<io::Result<_>>()?,
+            #[cfg(target_os = "linux")]
+            sched_policy: self.sched_policy,
+            #[cfg(target_os = "linux")]
+            unshare: self.unshare.clone(),
+            #[cfg(target_os = "linux")]
+            uid_map: self.uid_map.clone(),
+            #[cfg(target_os = "linux")]
+            gid_map: self.gid_map.clone(),
+            #[cfg(target_os = "linux")]
+            cgroup: self.cgroup.clone(),
+            #[cfg(target_os = "linux")]
+            seccomp: self.seccomp.clone(),
+            #[cfg(target_os = "linux")]
+            landlock: self.landlock.clone(),
+            #[cfg(target_os = "linux")]
+            oom_score_adj: self.oom_score_adj,
+            #[cfg(target_os = "linux")]
+            keep_capabilities: self.keep_capabilities.clone(),
+            #[cfg(target_os = "linux")]
+            vfork: self.vfork,
+            #[cfg(target_os = "linux")]
+            pipe_size: self.pipe_size,
+            #[cfg(target_os = "openbsd")]
+            pledge: self.pledge.clone(),
+            #[cfg(target_os = "openbsd")]
+            unveil: self.unveil.clone(),
+            #[cfg(windows)]
+            job: self.job.clone(),
+            #[cfg(any(target_os = "linux", windows))]
+            cpu_affinity: self.cpu_affinity.clone(),
+            start_suspended: self.start_suspended,
             _use_default_to_construct: (),
         })
     }
@@ -200,474 +1115,3063 @@ impl PopenConfig {
     pub fn current_env() -> Vec<(OsString, OsString)> {
         env::vars_os().collect()
     }
-}
 
-impl Default for PopenConfig {
-    fn default() -> PopenConfig {
-        PopenConfig {
-            stdin: Redirection::None,
-            stdout: Redirection::None,
-            stderr: Redirection::None,
-            detached: false,
-            executable: None,
-            env: None,
-            cwd: None,
-            #[cfg(unix)]
-            setuid: None,
-            #[cfg(unix)]
-            setgid: None,
-            #[cfg(unix)]
-            setpgid: false,
-            _use_default_to_construct: (),
-        }
+    // Return the environment of the subprocess for modification,
+    // starting from the inherited one if none is specified.
+    pub(crate) fn env_mut(&mut self) -> &mut Vec<(OsString, OsString)> {
+        self.env.get_or_insert_with(PopenConfig::current_env)
     }
-}
 
-/// Instruction what to do with a stream in the child process.
-///
-/// `Redirection` values are used for the `stdin`, `stdout`, and
-/// `stderr` field of the `PopenConfig` struct.  They tell
-/// `Popen::create` how to set up the standard streams in the child
-/// process and the corresponding fields of the `Popen` struct in the
-/// parent.
+    // Whether the value of the variable must not be shown.
+    pub(crate) fn is_secret_env(&self, key: &OsStr) -> bool {
+        self.secret_env.iter().any(|secret| env_key_eq(secret, key))
+    }
 
-#[derive(Debug)]
-pub enum Redirection {
-    /// Do nothing with the stream.
-    ///
-    /// The stream is typically inherited from the parent.  The field
-    /// in `Popen` corresponding to the stream will be `None`.
-    None,
+    // Remove the variable, including all its earlier definitions.
+    pub(crate) fn remove_env(&mut self, key: &OsStr) {
+        self.env_mut().retain(|(k, _)| !env_key_eq(k, key));
+    }
 
-    /// Redirect the stream to a pipe.
+    // Keep only the variables for which `keep` returns true, considering
+    // only the last definition of each.
+    pub(crate) fn filter_env(&mut self, mut keep: impl FnMut(&OsStr, &OsStr) -> bool) {
+        let env = self.env_mut();
+        let mut filtered: Vec<(OsString, OsString)> = vec![];
+        for (k, v) in env.drain(..).rev() {
+            if !filtered.iter().any(|(seen, _)| env_key_eq(seen, &k)) {
+                filtered.push((k, v));
+            }
+        }
+        filtered.retain(|(k, v)| keep(k, v));
+        filtered.reverse();
+        *env = filtered;
+    }
+
+    /// Create a builder for `PopenConfig`.
     ///
-    /// This variant requests that a stream be redirected to a
-    /// unidirectional pipe.  One end of the pipe is passed to the
-    /// child process and configured as one of its standard streams,
-    /// and the other end is available to the parent for communicating
-    /// with the child.
+    /// The builder starts out with the default configuration.  For
+    /// example:
     ///
-    /// The field with `Popen` corresponding to the stream will be
-    /// `Some(file)`, `File` being the parent's end of the pipe.
-    Pipe,
+    /// ```
+    /// # use subprocess::*;
+    /// # fn dummy() -> Result<()> {
+    /// let config = PopenConfig::builder()
+    ///     .stdout(Redirection::Pipe)
+    ///     .stderr(Redirection::Merge)
+    ///     .build()?;
+    /// let p = Popen::create(&["ls"], config)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder() -> PopenConfigBuilder {
+        PopenConfigBuilder {
+            config: PopenConfig::default(),
+        }
+    }
 
-    /// Merge the stream to the other output stream.
+    /// Check the configuration for inconsistencies.
     ///
-    /// This variant is only valid when configuring redirection of
-    /// standard output and standard error.  Using
-    /// `Redirection::Merge` for `PopenConfig::stderr` requests the
-    /// child's stderr to refer to the same underlying file as the
-    /// child's stdout (which may or may not itself be redirected),
-    /// equivalent to the `2>&1` operator of the Bourne shell.
-    /// Analogously, using `Redirection::Merge` for
-    /// `PopenConfig::stdout` is equivalent to `1>&2` in the shell.
+    /// This is done by `Popen::create` and by
+    /// [`PopenConfigBuilder::build`] before spawning the process.
     ///
-    /// Specifying `Redirection::Merge` for `PopenConfig::stdin` or
-    /// specifying it for both `stdout` and `stderr` is invalid and
-    /// will cause `Popen::create` to return
-    /// `Err(PopenError::LogicError)`.
+    /// # Errors
     ///
-    /// The field in `Popen` corresponding to the stream will be
-    /// `None`.
-    Merge,
-
-    /// Redirect the stream to the specified open `File`.
+    /// Returns `Err(PopenError::LogicError)` if any of these is set:
     ///
-    /// This does not create a pipe, it simply spawns the child so
-    /// that the specified stream sees that file.  The child can read
-    /// from or write to the provided file on its own, without any
+    /// * `Redirection::Merge`, `Tee` or `Writer` for `stdin`
+    /// * `input_data` without piping `stdin`
+    /// * `Redirection::Reader` for `stdout` or `stderr`
+    /// * `Redirection::Merge` for both `stdout` and `stderr`
+    /// * `daemonize` with `start_suspended`
+    /// * `pty` with `daemonize`, `controlling_tty`, `pgid`, or
+    ///   redirections other than piping `stdin` and `stdout`
+    /// * `controlling_tty` without `setsid`
+    /// * `pgid` with `setpgid` or `setsid`
+    /// * `cwd_fd` with `cwd` or `chroot`
+    /// * `fd_map` mapping a standard stream, the same descriptor twice,
+    ///   or a descriptor of `listen_fds`
+    /// * `uid_map` or `gid_map` without a new user namespace
+    /// * `vfork` with `pre_exec`, `daemonize`, `start_suspended`,
+    ///   `setuid`, `setgid`, `setgroups` or a new user namespace
+    /// * `nice` with `priority_class`
+    /// * more than one of `logon`, `user_token` and `restricted_token`
+    /// * `logon` with `app_container`, `inherit_handles` or `pty`
+    ///
+    /// [`PopenConfigBuilder::build`]: struct.PopenConfigBuilder.html#method.build
+    pub fn validate(&self) -> Result<()> {
+        if let Redirection::Merge = self.stdin {
+            return Err(PopenError::LogicError(
+                "Redirection::Merge not valid for stdin",
+            ));
+        }
+        if let Redirection::Tee(_) = self.stdin {
+            return Err(PopenError::LogicError(
+                "Redirection::Tee not valid for stdin",
+            ));
+        }
+        if let Redirection::Writer(_) = self.stdin {
+            return Err(PopenError::LogicError(
+                "Redirection::Writer not valid for stdin",
+            ));
+        }
+        if self.input_data.is_some() && !matches!(self.stdin, Redirection::Pipe) {
+            return Err(PopenError::LogicError(
+                "input_data requires stdin to be Redirection::Pipe",
+            ));
+        }
+        if let (Redirection::Reader(_), _) | (_, Redirection::Reader(_)) =
+            (&self.stdout, &self.stderr)
+        {
+            return Err(PopenError::LogicError(
+                "Redirection::Reader only valid for stdin",
+            ));
+        }
+        if let (Redirection::Merge, Redirection::Merge) = (&self.stdout, &self.stderr) {
+            return Err(PopenError::LogicError(
+                "Redirection::Merge not valid for both stdout and stderr",
+            ));
+        }
+        if self.daemonize && self.start_suspended {
+            return Err(PopenError::LogicError(
+                "daemonize can't be combined with start_suspended",
+            ));
+        }
+        // the pseudo terminal provides all the standard streams
+        let piped = |r: &Redirection| matches!(r, Redirection::None | Redirection::Pipe);
+        let redirected = !piped(&self.stdin)
+            || !piped(&self.stdout)
+            || !matches!(self.stderr, Redirection::None);
+        if self.pty.is_some() && (redirected || self.daemonize) {
+            return Err(PopenError::LogicError(
+                "pty can't be combined with daemonize or redirecting the standard streams",
+            ));
+        }
+        #[cfg(unix)]
+        {
+            if self.controlling_tty.is_some() && !self.setsid {
+                return Err(PopenError::LogicError("controlling_tty requires setsid"));
+            }
+            if self.pgid.is_some() && (self.setpgid || self.setsid) {
+                return Err(PopenError::LogicError(
+                    "pgid can't be combined with setpgid or setsid",
+                ));
+            }
+            if self.pty.is_some() && (self.controlling_tty.is_some() || self.pgid.is_some()) {
+                return Err(PopenError::LogicError(
+                    "pty can't be combined with controlling_tty or pgid",
+                ));
+            }
+            if self.cwd_fd.is_some() && (self.cwd.is_some() || self.chroot.is_some()) {
+                return Err(PopenError::LogicError(
+                    "cwd_fd can't be combined with cwd or chroot",
+                ));
+            }
+            for (i, &(child_fd, _)) in self.fd_map.iter().enumerate() {
+                if child_fd <= 2 {
+                    return Err(PopenError::LogicError(
+                        "fd_map can't replace the standard streams",
+                    ));
+                }
+                if self.fd_map[..i].iter().any(|&(fd, _)| fd == child_fd) {
+                    return Err(PopenError::LogicError(
+                        "fd_map contains the same descriptor twice",
+                    ));
+                }
+                if (child_fd as usize) < 3 + self.listen_fds.len() {
+                    return Err(PopenError::LogicError(
+                        "fd_map can't replace the descriptors of listen_fds",
+                    ));
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if (self.uid_map.is_some() || self.gid_map.is_some())
+                && !self.unshare.contains(&Namespace::User)
+            {
+                return Err(PopenError::LogicError(
+                    "uid_map and gid_map require unsharing the user namespace",
+                ));
+            }
+            if self.vfork
+                && (!self.pre_exec.is_empty()
+                    || self.daemonize
+                    || self.start_suspended
+                    || self.setuid.is_some()
+                    || self.setgid.is_some()
+                    || self.setgroups.is_some()
+                    || self.unshare.contains(&Namespace::User))
+            {
+                return Err(PopenError::LogicError(
+                    "vfork can't be combined with pre_exec, daemonize, start_suspended, \
+                     setuid, setgid, setgroups or a new user namespace",
+                ));
+            }
+        }
+        #[cfg(windows)]
+        {
+            if self.nice.is_some() && self.priority_class.is_some() {
+                return Err(PopenError::LogicError(
+                    "nice can't be combined with priority_class",
+                ));
+            }
+            let users = self.logon.is_some() as u8
+                + self.user_token.is_some() as u8
+                + self.restricted_token as u8;
+            if users > 1 {
+                return Err(PopenError::LogicError(
+                    "only one of logon, user_token and restricted_token can be set",
+                ));
+            }
+            if self.logon.is_some() && self.app_container.is_some() {
+                return Err(PopenError::LogicError(
+                    "logon can't be combined with app_container",
+                ));
+            }
+            if self.logon.is_some() && !self.inherit_handles.is_empty() {
+                return Err(PopenError::LogicError(
+                    "logon can't be combined with inherit_handles",
+                ));
+            }
+            if self.logon.is_some() && self.pty.is_some() {
+                return Err(PopenError::LogicError("logon can't be combined with pty"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compare environment variable names, which are case-insensitive on
+// Windows.
+pub(crate) fn env_key_eq(a: &OsStr, b: &OsStr) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        fn upper(c: u16) -> u16 {
+            if c < 128 {
+                (c as u8).to_ascii_uppercase() as u16
+            } else {
+                c
+            }
+        }
+        a.encode_wide().map(upper).eq(b.encode_wide().map(upper))
+    }
+    #[cfg(not(windows))]
+    {
+        a == b
+    }
+}
+
+/// Builder for [`PopenConfig`], created by [`PopenConfig::builder`].
+///
+/// Each method sets the `PopenConfig` field of the same name.  Fields
+/// not set keep their default values.
+///
+/// [`PopenConfig`]: struct.PopenConfig.html
+/// [`PopenConfig::builder`]: struct.PopenConfig.html#method.builder
+#[must_use]
+#[derive(Debug)]
+pub struct PopenConfigBuilder {
+    config: PopenConfig,
+}
+
+impl PopenConfigBuilder {
+    /// Specify how to set up the standard input of the subprocess.
+    pub fn stdin(mut self, stdin: Redirection) -> PopenConfigBuilder {
+        self.config.stdin = stdin;
+        self
+    }
+
+    /// Specify how to set up the standard output of the subprocess.
+    pub fn stdout(mut self, stdout: Redirection) -> PopenConfigBuilder {
+        self.config.stdout = stdout;
+        self
+    }
+
+    /// Specify how to set up the standard error of the subprocess.
+    pub fn stderr(mut self, stderr: Redirection) -> PopenConfigBuilder {
+        self.config.stderr = stderr;
+        self
+    }
+
+    /// Specify data to feed to the standard input of the subprocess.
+    ///
+    /// This requires `stdin` to be set to `Redirection::Pipe`.
+    pub fn input_data(mut self, data: impl Into<Vec<u8>>) -> PopenConfigBuilder {
+        self.config.input_data = Some(data.into());
+        self
+    }
+
+    /// Run the subprocess in a pseudo terminal of the given size.
+    pub fn pty(mut self, size: PtySize) -> PopenConfigBuilder {
+        self.config.pty = Some(size);
+        self
+    }
+
+    /// Put the pseudo terminal of the subprocess in raw mode.
+    #[cfg(unix)]
+    pub fn pty_raw(mut self, raw: bool) -> PopenConfigBuilder {
+        self.config.pty_raw = raw;
+        self
+    }
+
+    /// Specify whether the pseudo terminal of the subprocess echoes its
+    /// input.
+    #[cfg(unix)]
+    pub fn pty_echo(mut self, echo: bool) -> PopenConfigBuilder {
+        self.config.pty_echo = echo;
+        self
+    }
+
+    /// Specify whether the `Popen` instance is initially detached.
+    pub fn detached(mut self, detached: bool) -> PopenConfigBuilder {
+        self.config.detached = detached;
+        self
+    }
+
+    /// Specify the executable to run instead of `argv[0]`.
+    pub fn executable(mut self, executable: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.executable = Some(executable.as_ref().to_owned());
+        self
+    }
+
+    /// Specify how to find the program when it is given without a
+    /// directory.
+    pub fn executable_search(mut self, search: ExecutableSearch) -> PopenConfigBuilder {
+        self.config.executable_search = search;
+        self
+    }
+
+    /// Specify the environment of the subprocess, replacing the
+    /// inherited one.
+    pub fn env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> PopenConfigBuilder
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.config.env = Some(
+            vars.into_iter()
+                .map(|(k, v)| (k.as_ref().to_owned(), v.as_ref().to_owned()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Clear the environment of the subprocess, so that it doesn't
+    /// inherit the environment of this process.
+    pub fn env_clear(mut self) -> PopenConfigBuilder {
+        self.config.env = Some(vec![]);
+        self
+    }
+
+    /// Set an environment variable in the subprocess.
+    ///
+    /// Other variables are inherited, unless the environment was
+    /// specified with `env` or cleared with `env_clear`.
+    pub fn env_set(
+        mut self,
+        key: impl AsRef<OsStr>,
+        value: impl AsRef<OsStr>,
+    ) -> PopenConfigBuilder {
+        self.config
+            .env_mut()
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Mark an environment variable as secret, so that its value isn't
+    /// shown for logging.
+    pub fn secret_env(mut self, key: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.secret_env.push(key.as_ref().to_owned());
+        self
+    }
+
+    /// Remove an environment variable from the subprocess.
+    ///
+    /// On Windows, the variable name is compared case-insensitively.
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.remove_env(key.as_ref());
+        self
+    }
+
+    /// Keep only the environment variables for which `keep` returns
+    /// true, given the name and value of each.
+    ///
+    /// The filter applies to the variables set so far, including the
+    /// inherited ones.
+    pub fn env_filter(mut self, keep: impl FnMut(&OsStr, &OsStr) -> bool) -> PopenConfigBuilder {
+        self.config.filter_env(keep);
+        self
+    }
+
+    /// Specify the initial current working directory of the subprocess.
+    pub fn cwd(mut self, cwd: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.cwd = Some(cwd.as_ref().to_owned());
+        self
+    }
+
+    /// Specify the initial current working directory of the subprocess
+    /// as an open directory.
+    #[cfg(unix)]
+    pub fn cwd_fd(mut self, dir: impl Into<OwnedFd>) -> PopenConfigBuilder {
+        self.config.cwd_fd = Some(dir.into());
+        self
+    }
+
+    /// Set the user ID for the subprocess.
+    #[cfg(unix)]
+    pub fn setuid(mut self, uid: u32) -> PopenConfigBuilder {
+        self.config.setuid = Some(uid);
+        self
+    }
+
+    /// Set the group ID for the subprocess.
+    #[cfg(unix)]
+    pub fn setgid(mut self, gid: u32) -> PopenConfigBuilder {
+        self.config.setgid = Some(gid);
+        self
+    }
+
+    /// Change the root directory of the subprocess.
+    #[cfg(unix)]
+    pub fn chroot(mut self, path: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.chroot = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Limit the use of a resource by the subprocess.
+    ///
+    /// Can be called multiple times to limit several resources.
+    #[cfg(unix)]
+    pub fn rlimit(mut self, resource: Rlimit, soft: u64, hard: u64) -> PopenConfigBuilder {
+        self.config.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Set the supplementary group IDs for the subprocess.
+    #[cfg(unix)]
+    pub fn setgroups(mut self, groups: impl IntoIterator<Item = u32>) -> PopenConfigBuilder {
+        self.config.setgroups = Some(groups.into_iter().collect());
+        self
+    }
+
+    /// Set the file mode creation mask for the subprocess.
+    #[cfg(unix)]
+    pub fn umask(mut self, mask: u32) -> PopenConfigBuilder {
+        self.config.umask = Some(mask);
+        self
+    }
+
+    /// Add a function to run in the child process before execing the
+    /// program.
+    ///
+    /// # Safety
+    ///
+    /// See [`PreExec::new`].
+    ///
+    /// [`PreExec::new`]: unix/struct.PreExec.html#method.new
+    #[cfg(unix)]
+    pub unsafe fn pre_exec(
+        mut self,
+        f: impl FnMut() -> io::Result<()> + Send + Sync + 'static,
+    ) -> PopenConfigBuilder {
+        self.config.pre_exec.push(PreExec::new(f));
+        self
+    }
+
+    /// Pass a file descriptor to the subprocess as descriptor number
+    /// `child_fd`.
+    #[cfg(unix)]
+    pub fn map_fd(mut self, child_fd: RawFd, fd: impl Into<OwnedFd>) -> PopenConfigBuilder {
+        self.config.fd_map.push((child_fd, fd.into()));
+        self
+    }
+
+    /// Pass a listening socket to the subprocess with socket activation.
+    #[cfg(unix)]
+    pub fn listen_fd(mut self, fd: impl Into<OwnedFd>) -> PopenConfigBuilder {
+        self.config.listen_fds.push(fd.into());
+        self
+    }
+
+    /// Create a notification socket for the subprocess.
+    #[cfg(unix)]
+    pub fn notify_socket(mut self, notify: bool) -> PopenConfigBuilder {
+        self.config.notify_socket = notify;
+        self
+    }
+
+    /// Specify whether the subprocess belongs to a new process group.
+    pub fn setpgid(mut self, setpgid: bool) -> PopenConfigBuilder {
+        self.config.setpgid = setpgid;
+        self
+    }
+
+    /// Place the subprocess in an existing process group.
+    #[cfg(unix)]
+    pub fn pgid(mut self, pgid: u32) -> PopenConfigBuilder {
+        self.config.pgid = Some(pgid);
+        self
+    }
+
+    /// Specify whether the subprocess starts a new session.
+    #[cfg(unix)]
+    pub fn setsid(mut self, setsid: bool) -> PopenConfigBuilder {
+        self.config.setsid = setsid;
+        self
+    }
+
+    /// Set the controlling terminal of the subprocess.
+    #[cfg(unix)]
+    pub fn controlling_tty(mut self, tty: File) -> PopenConfigBuilder {
+        self.config.controlling_tty = Some(tty);
+        self
+    }
+
+    /// Set the rules for quoting the arguments on Windows.
+    pub fn arg_quoting(mut self, quoting: ArgQuoting) -> PopenConfigBuilder {
+        self.config.arg_quoting = quoting;
+        self
+    }
+
+    /// Specify whether the arguments of a batch file are quoted as
+    /// configured on Windows.
+    pub fn allow_batch(mut self, allow: bool) -> PopenConfigBuilder {
+        self.config.allow_batch = allow;
+        self
+    }
+
+    /// Specify whether the subprocess is run as a daemon.
+    pub fn daemonize(mut self, daemonize: bool) -> PopenConfigBuilder {
+        self.config.daemonize = daemonize;
+        self
+    }
+
+    /// Specify what happens to the subprocess when the `Popen` is dropped.
+    pub fn drop_policy(mut self, policy: DropPolicy) -> PopenConfigBuilder {
+        self.config.drop_policy = policy;
+        self
+    }
+
+    /// Specify the maximum time the subprocess is allowed to run, and
+    /// how it is stopped once that time elapses.
+    pub fn timeout(mut self, timeout: Duration, on_timeout: TimeoutAction) -> PopenConfigBuilder {
+        self.config.timeout = Some(timeout);
+        self.config.on_timeout = on_timeout;
+        self
+    }
+
+    /// Set the scheduling priority of the subprocess, as a niceness.
+    pub fn nice(mut self, nice: i32) -> PopenConfigBuilder {
+        self.config.nice = Some(nice);
+        self
+    }
+
+    /// Set the Windows priority class of the subprocess.
+    #[cfg(windows)]
+    pub fn priority_class(mut self, class: PriorityClass) -> PopenConfigBuilder {
+        self.config.priority_class = Some(class);
+        self
+    }
+
+    /// Add process creation flags.
+    #[cfg(windows)]
+    pub fn creation_flags(mut self, flags: u32) -> PopenConfigBuilder {
+        self.config.creation_flags |= flags;
+        self
+    }
+
+    /// Set how the main window of the subprocess is shown.
+    #[cfg(windows)]
+    pub fn show_window(mut self, show: u16) -> PopenConfigBuilder {
+        self.config.show_window = Some(show);
+        self
+    }
+
+    /// Specify the command line passed to the subprocess as it is.
+    #[cfg(windows)]
+    pub fn raw_cmdline(mut self, cmdline: impl Into<OsString>) -> PopenConfigBuilder {
+        self.config.raw_cmdline = Some(cmdline.into());
+        self
+    }
+
+    /// Start the subprocess with a restricted token.
+    #[cfg(windows)]
+    pub fn restricted_token(mut self, restricted: bool) -> PopenConfigBuilder {
+        self.config.restricted_token = restricted;
+        self
+    }
+
+    /// Start the subprocess inside an AppContainer.
+    #[cfg(windows)]
+    pub fn app_container(mut self, profile: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.app_container = Some(profile.as_ref().to_owned());
+        self
+    }
+
+    /// Start the subprocess as another user, logging on with the given
+    /// credentials.
+    #[cfg(windows)]
+    pub fn logon(mut self, logon: Logon) -> PopenConfigBuilder {
+        self.config.logon = Some(logon);
+        self
+    }
+
+    /// Start the subprocess as the user of the given primary token.
+    #[cfg(windows)]
+    pub fn user_token(mut self, token: impl Into<OwnedHandle>) -> PopenConfigBuilder {
+        self.config.user_token = Some(token.into());
+        self
+    }
+
+    /// Let the subprocess inherit an additional handle.
+    #[cfg(windows)]
+    pub fn inherit_handle(mut self, handle: impl Into<OwnedHandle>) -> PopenConfigBuilder {
+        self.config.inherit_handles.push(handle.into());
+        self
+    }
+
+    /// Set the scheduling policy of the subprocess.
+    #[cfg(target_os = "linux")]
+    pub fn sched_policy(mut self, policy: SchedPolicy) -> PopenConfigBuilder {
+        self.config.sched_policy = Some(policy);
+        self
+    }
+
+    /// Isolate the subprocess in a new namespace.
+    ///
+    /// Can be called multiple times to unshare several namespaces.
+    #[cfg(target_os = "linux")]
+    pub fn unshare(mut self, namespace: Namespace) -> PopenConfigBuilder {
+        self.config.unshare.push(namespace);
+        self
+    }
+
+    /// Set the UID mappings of the new user namespace.
+    #[cfg(target_os = "linux")]
+    pub fn uid_map(mut self, map: &[(u32, u32, u32)]) -> PopenConfigBuilder {
+        self.config.uid_map = Some(map.to_vec());
+        self
+    }
+
+    /// Set the GID mappings of the new user namespace.
+    #[cfg(target_os = "linux")]
+    pub fn gid_map(mut self, map: &[(u32, u32, u32)]) -> PopenConfigBuilder {
+        self.config.gid_map = Some(map.to_vec());
+        self
+    }
+
+    /// Start the subprocess in a cgroup v2.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup(mut self, path: impl AsRef<OsStr>) -> PopenConfigBuilder {
+        self.config.cgroup = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Restrict the system calls of the subprocess with a seccomp filter.
+    #[cfg(target_os = "linux")]
+    pub fn seccomp(mut self, filter: SeccompFilter) -> PopenConfigBuilder {
+        self.config.seccomp = Some(filter);
+        self
+    }
+
+    /// Restrict the filesystem access of the subprocess with Landlock.
+    #[cfg(target_os = "linux")]
+    pub fn landlock(mut self, rules: Landlock) -> PopenConfigBuilder {
+        self.config.landlock = Some(rules);
+        self
+    }
+
+    /// Adjust the badness score of the subprocess for the OOM killer.
+    #[cfg(target_os = "linux")]
+    pub fn oom_score_adj(mut self, adj: i32) -> PopenConfigBuilder {
+        self.config.oom_score_adj = Some(adj);
+        self
+    }
+
+    /// Drop all capabilities of the subprocess except `caps`.
+    #[cfg(target_os = "linux")]
+    pub fn keep_capabilities(mut self, caps: &[Capability]) -> PopenConfigBuilder {
+        self.config.keep_capabilities = Some(caps.to_vec());
+        self
+    }
+
+    /// Start the subprocess sharing the memory of the current process,
+    /// like after `vfork()`.
+    #[cfg(target_os = "linux")]
+    pub fn vfork(mut self, vfork: bool) -> PopenConfigBuilder {
+        self.config.vfork = vfork;
+        self
+    }
+
+    /// Set the capacity of the pipes for the standard output and error
+    /// of the subprocess.
+    #[cfg(target_os = "linux")]
+    pub fn pipe_size(mut self, size: usize) -> PopenConfigBuilder {
+        self.config.pipe_size = Some(size);
+        self
+    }
+
+    /// Restrict the program to the given pledge promises.
+    #[cfg(target_os = "openbsd")]
+    pub fn pledge(mut self, promises: &str) -> PopenConfigBuilder {
+        self.config.pledge = Some(promises.to_owned());
+        self
+    }
+
+    /// Allow the child to access `path` with the given `unveil()`
+    /// permissions.
+    #[cfg(target_os = "openbsd")]
+    pub fn unveil(mut self, path: impl AsRef<OsStr>, permissions: &str) -> PopenConfigBuilder {
+        self.config
+            .unveil
+            .push((path.as_ref().to_owned(), permissions.to_owned()));
+        self
+    }
+
+    /// Start the subprocess in a Windows job object.
+    #[cfg(windows)]
+    pub fn job(mut self, job: JobObject) -> PopenConfigBuilder {
+        self.config.job = Some(job);
+        self
+    }
+
+    /// Restrict the subprocess to run on the given CPUs.
+    #[cfg(any(target_os = "linux", windows))]
+    pub fn cpu_affinity(mut self, cpus: &[usize]) -> PopenConfigBuilder {
+        self.config.cpu_affinity = Some(cpus.to_vec());
+        self
+    }
+
+    /// Specify whether the subprocess starts suspended.
+    pub fn start_suspended(mut self, start_suspended: bool) -> PopenConfigBuilder {
+        self.config.start_suspended = start_suspended;
+        self
+    }
+
+    /// Validate the configuration and return it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error reported by [`PopenConfig::validate`] if the
+    /// configuration is inconsistent.
+    ///
+    /// [`PopenConfig::validate`]: struct.PopenConfig.html#method.validate
+    pub fn build(self) -> Result<PopenConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl Default for PopenConfig {
+    fn default() -> PopenConfig {
+        PopenConfig {
+            stdin: Redirection::None,
+            stdout: Redirection::None,
+            stderr: Redirection::None,
+            input_data: None,
+            pty: None,
+            #[cfg(unix)]
+            pty_raw: false,
+            #[cfg(unix)]
+            pty_echo: true,
+            detached: false,
+            executable: None,
+            executable_search: ExecutableSearch::Inherited,
+            env: None,
+            secret_env: vec![],
+            cwd: None,
+            #[cfg(unix)]
+            cwd_fd: None,
+            #[cfg(unix)]
+            setuid: None,
+            #[cfg(unix)]
+            setgid: None,
+            #[cfg(unix)]
+            chroot: None,
+            #[cfg(unix)]
+            rlimits: vec![],
+            #[cfg(unix)]
+            setgroups: None,
+            setpgid: false,
+            #[cfg(unix)]
+            pgid: None,
+            #[cfg(unix)]
+            setsid: false,
+            #[cfg(unix)]
+            controlling_tty: None,
+            arg_quoting: ArgQuoting::Standard,
+            allow_batch: false,
+            #[cfg(unix)]
+            umask: None,
+            #[cfg(unix)]
+            pre_exec: vec![],
+            #[cfg(unix)]
+            fd_map: vec![],
+            #[cfg(unix)]
+            listen_fds: vec![],
+            #[cfg(unix)]
+            notify_socket: false,
+            daemonize: false,
+            drop_policy: DropPolicy::Wait,
+            timeout: None,
+            on_timeout: TimeoutAction::Terminate,
+            nice: None,
+            #[cfg(windows)]
+            priority_class: None,
+            #[cfg(windows)]
+            creation_flags: 0,
+            #[cfg(windows)]
+            show_window: None,
+            #[cfg(windows)]
+            raw_cmdline: None,
+            #[cfg(windows)]
+            restricted_token: false,
+            #[cfg(windows)]
+            app_container: None,
+            #[cfg(windows)]
+            logon: None,
+            #[cfg(windows)]
+            user_token: None,
+            #[cfg(windows)]
+            inherit_handles: vec![],
+            #[cfg(target_os = "linux")]
+            sched_policy: None,
+            #[cfg(target_os = "linux")]
+            unshare: vec![],
+            #[cfg(target_os = "linux")]
+            uid_map: None,
+            #[cfg(target_os = "linux")]
+            gid_map: None,
+            #[cfg(target_os = "linux")]
+            cgroup: None,
+            #[cfg(target_os = "linux")]
+            seccomp: None,
+            #[cfg(target_os = "linux")]
+            landlock: None,
+            #[cfg(target_os = "linux")]
+            oom_score_adj: None,
+            #[cfg(target_os = "linux")]
+            keep_capabilities: None,
+            #[cfg(target_os = "linux")]
+            vfork: false,
+            #[cfg(target_os = "linux")]
+            pipe_size: None,
+            #[cfg(target_os = "openbsd")]
+            pledge: None,
+            #[cfg(target_os = "openbsd")]
+            unveil: vec![],
+            #[cfg(windows)]
+            job: None,
+            #[cfg(any(target_os = "linux", windows))]
+            cpu_affinity: None,
+            start_suspended: false,
+            _use_default_to_construct: (),
+        }
+    }
+}
+
+/// Instruction what to do with a stream in the child process.
+///
+/// `Redirection` values are used for the `stdin`, `stdout`, and
+/// `stderr` field of the `PopenConfig` struct.  They tell
+/// `Popen::create` how to set up the standard streams in the child
+/// process and the corresponding fields of the `Popen` struct in the
+/// parent.
+///
+/// More ways of redirecting a stream may be added in the future, so a
+/// `match` on a `Redirection` must include a wildcard arm.
+#[non_exhaustive]
+pub enum Redirection {
+    /// Do nothing with the stream.
+    ///
+    /// The stream is typically inherited from the parent.  The field
+    /// in `Popen` corresponding to the stream will be `None`.
+    None,
+
+    /// Redirect the stream to a pipe.
+    ///
+    /// This variant requests that a stream be redirected to a
+    /// unidirectional pipe.  One end of the pipe is passed to the
+    /// child process and configured as one of its standard streams,
+    /// and the other end is available to the parent for communicating
+    /// with the child.
+    ///
+    /// The field with `Popen` corresponding to the stream will be
+    /// `Some(file)`, `File` being the parent's end of the pipe.
+    Pipe,
+
+    /// Merge the stream to the other output stream.
+    ///
+    /// This variant is only valid when configuring redirection of
+    /// standard output and standard error.  Using
+    /// `Redirection::Merge` for `PopenConfig::stderr` requests the
+    /// child's stderr to refer to the same underlying file as the
+    /// child's stdout (which may or may not itself be redirected),
+    /// equivalent to the `2>&1` operator of the Bourne shell.
+    /// Analogously, using `Redirection::Merge` for
+    /// `PopenConfig::stdout` is equivalent to `1>&2` in the shell.
+    ///
+    /// Specifying `Redirection::Merge` for `PopenConfig::stdin` or
+    /// specifying it for both `stdout` and `stderr` is invalid and
+    /// will cause `Popen::create` to return
+    /// `Err(PopenError::LogicError)`.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    Merge,
+
+    /// Redirect the stream to the specified open `File`.
+    ///
+    /// This does not create a pipe, it simply spawns the child so
+    /// that the specified stream sees that file.  The child can read
+    /// from or write to the provided file on its own, without any
     /// intervention by the parent.
     ///
-    /// The field in `Popen` corresponding to the stream will be
-    /// `None`.
-    File(File),
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    File(File),
+
+    /// Like `File`, but the file is specified as `Rc`.
+    ///
+    /// This allows the same file to be used in multiple redirections.
+    RcFile(Rc<File>),
+
+    /// Redirect the stream to the file at the given path, opened with
+    /// the given options when the subprocess is started.
+    ///
+    /// This spares opening the file in advance, and allows choosing
+    /// how it is opened, for example to append to a log file, which is
+    /// what [`append`] does, to refuse overwriting an existing file
+    /// with `create_new`, or to set the permissions of a new file with
+    /// `OpenOptionsExt::mode` on Unix.  A relative path is relative to
+    /// the current directory of the parent, not to `cwd`.  Failure to
+    /// open the file makes `Popen::create` return
+    /// `PopenError::IoError`.
+    ///
+    /// The field in `Popen` corresponding to the stream will be
+    /// `None`.
+    ///
+    /// [`append`]: #method.append
+    Open(PathBuf, OpenOptions),
+
+    /// Redirect the stream to a pipe, like `Pipe`, and also copy the
+    /// output to the specified `File` as it is produced.
+    ///
+    /// This is useful to show the output of a command live, or to log
+    /// it, while still capturing it, for example with
+    /// [`Exec::capture`].  A background thread reads what the child
+    /// writes and copies it both to the file and to the pipe read by
+    /// the parent, so the output is only copied as fast as the parent
+    /// reads it.  [`tee_stdout`] and [`tee_stderr`] copy the output to
+    /// the standard streams of the current process.
+    ///
+    /// This variant is only valid when configuring redirection of
+    /// standard output and standard error.  The field in `Popen`
+    /// corresponding to the stream will be `Some(file)`, `File` being
+    /// the end of the pipe read by the parent.
+    ///
+    /// [`Exec::capture`]: struct.Exec.html#method.capture
+    /// [`tee_stdout`]: #method.tee_stdout
+    /// [`tee_stderr`]: #method.tee_stderr
+    Tee(File),
+
+    /// Redirect the stream to a pipe, and copy the output to the
+    /// specified writer.
+    ///
+    /// This allows the output to be passed to a compressor, a socket
+    /// wrapper or a logger without going through a file.  A background
+    /// thread reads what the child writes and copies it to the writer,
+    /// then flushes and drops the writer once the child, and any
+    /// process it has passed the stream to, has closed it.
+    /// [`Popen::wait`] waits for the copy to finish, so that all the
+    /// output has been written once it returns.  If writing fails, the
+    /// copy stops, and the child's further writes fail as if the pipe
+    /// had been closed.
+    ///
+    /// This variant is only valid when configuring redirection of
+    /// standard output and standard error.  The field in `Popen`
+    /// corresponding to the stream will be `None`.
+    ///
+    /// [`Popen::wait`]: struct.Popen.html#method.wait
+    Writer(Box<dyn Write + Send>),
+
+    /// Redirect the stream to a pipe, and feed it the data produced by
+    /// the specified reader.
+    ///
+    /// This allows the child to consume data generated by the parent
+    /// as it is needed, without storing it in memory or in a temporary
+    /// file first.  A background thread reads from the reader and
+    /// writes the data to the pipe, as fast as the child consumes it,
+    /// and closes the pipe when the reader reaches end-of-file.  The
+    /// copy stops if the child closes the stream or exits, or if
+    /// reading fails, which the child sees as end-of-file.
+    ///
+    /// This variant is only valid when configuring redirection of
+    /// standard input.  The field in `Popen` corresponding to the
+    /// stream will be `None`.
+    Reader(Box<dyn Read + Send>),
+}
+
+impl Redirection {
+    /// Clone the underlying `Redirection`, or return an error.
+    ///
+    /// Can fail in `File` and `Tee` variants, and always fails in the
+    /// `Writer` and `Reader` variants because writers and readers
+    /// cannot be cloned.
+    pub fn try_clone(&self) -> io::Result<Redirection> {
+        Ok(match *self {
+            Redirection::None => Redirection::None,
+            Redirection::Pipe => Redirection::Pipe,
+            Redirection::Merge => Redirection::Merge,
+            Redirection::File(ref f) => Redirection::File(f.try_clone()?),
+            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(f)),
+            Redirection::Open(ref path, ref options) => {
+                Redirection::Open(path.clone(), options.clone())
+            }
+            Redirection::Tee(ref f) => Redirection::Tee(f.try_clone()?),
+            Redirection::Writer(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot clone Redirection::Writer",
+                ))
+            }
+            Redirection::Reader(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot clone Redirection::Reader",
+                ))
+            }
+        })
+    }
+
+    /// Redirect the stream to the file at `path`, opened with `options`.
+    ///
+    /// The result is a `Redirection::Open`.
+    pub fn open(path: impl AsRef<Path>, options: &OpenOptions) -> Redirection {
+        Redirection::Open(path.as_ref().to_owned(), options.clone())
+    }
+
+    /// Append the output to the file at `path`, creating it if it
+    /// doesn't exist, like the `>>` operator of the shell.
+    ///
+    /// The result is a `Redirection::Open`.
+    pub fn append(path: impl AsRef<Path>) -> Redirection {
+        Redirection::open(path, OpenOptions::new().append(true).create(true))
+    }
+
+    /// Capture the stream and also copy it to the standard output of
+    /// the current process.
+    ///
+    /// The result is a `Redirection::Tee` with a duplicate of the
+    /// standard output.
+    pub fn tee_stdout() -> io::Result<Redirection> {
+        #[cfg(unix)]
+        let stdout = io::stdout().as_fd().try_clone_to_owned()?;
+        #[cfg(windows)]
+        let stdout = io::stdout().as_handle().try_clone_to_owned()?;
+        Ok(Redirection::Tee(File::from(stdout)))
+    }
+
+    /// Capture the stream and also copy it to the standard error of
+    /// the current process.
+    ///
+    /// The result is a `Redirection::Tee` with a duplicate of the
+    /// standard error.
+    pub fn tee_stderr() -> io::Result<Redirection> {
+        #[cfg(unix)]
+        let stderr = io::stderr().as_fd().try_clone_to_owned()?;
+        #[cfg(windows)]
+        let stderr = io::stderr().as_handle().try_clone_to_owned()?;
+        Ok(Redirection::Tee(File::from(stderr)))
+    }
+
+    /// Redirect the stream to the specified file descriptor.
+    ///
+    /// This accepts anything that owns a file descriptor, such as a
+    /// `UnixStream` or a `TcpStream`, and takes over the ownership, so
+    /// the descriptor is closed exactly once without resorting to raw
+    /// descriptors.  The result is a `Redirection::File`.
+    #[cfg(unix)]
+    pub fn from_fd(fd: impl Into<OwnedFd>) -> Redirection {
+        Redirection::File(File::from(fd.into()))
+    }
+
+    /// Redirect the stream to the specified handle.
+    ///
+    /// This accepts anything that owns a handle, such as a `File` or a
+    /// `ChildStdout` from `std::process`, and takes over the ownership,
+    /// so the handle is closed exactly once without resorting to raw
+    /// handles.  The result is a `Redirection::File`.  Sockets are
+    /// passed with [`from_socket`] instead.
+    ///
+    /// [`from_socket`]: #method.from_socket
+    #[cfg(windows)]
+    pub fn from_handle(handle: impl Into<OwnedHandle>) -> Redirection {
+        Redirection::File(File::from(handle.into()))
+    }
+
+    /// Redirect the stream to the specified socket.
+    ///
+    /// This accepts anything that owns a socket, such as a `TcpStream`
+    /// or a `UdpSocket`, for example to start an inetd-style server
+    /// with a connected socket as its `stdin` and `stdout`.  The socket
+    /// is converted to a handle with [`windows::socket_handle`], which
+    /// can fail.  The result is a `Redirection::File`.
+    ///
+    /// Sockets created by the standard library are overlapped, which
+    /// programs performing ordinary synchronous I/O on their standard
+    /// streams handle, but programs checking the type of the handle
+    /// may not treat it as a file or a pipe.
+    ///
+    /// [`windows::socket_handle`]: windows/fn.socket_handle.html
+    #[cfg(windows)]
+    pub fn from_socket(socket: impl Into<OwnedSocket>) -> io::Result<Redirection> {
+        Ok(Redirection::from_handle(socket_handle(socket)?))
+    }
+}
+
+impl fmt::Debug for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Redirection::None => f.write_str("None"),
+            Redirection::Pipe => f.write_str("Pipe"),
+            Redirection::Merge => f.write_str("Merge"),
+            Redirection::File(ref file) => f.debug_tuple("File").field(file).finish(),
+            Redirection::RcFile(ref file) => f.debug_tuple("RcFile").field(file).finish(),
+            Redirection::Open(ref path, ref options) => {
+                f.debug_tuple("Open").field(path).field(options).finish()
+            }
+            Redirection::Tee(ref file) => f.debug_tuple("Tee").field(file).finish(),
+            Redirection::Writer(_) => f.write_str("Writer(..)"),
+            Redirection::Reader(_) => f.write_str("Reader(..)"),
+        }
+    }
+}
+
+impl From<File> for Redirection {
+    fn from(f: File) -> Redirection {
+        Redirection::File(f)
+    }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for Redirection {
+    fn from(fd: OwnedFd) -> Redirection {
+        Redirection::from_fd(fd)
+    }
+}
+
+#[cfg(windows)]
+impl From<OwnedHandle> for Redirection {
+    fn from(handle: OwnedHandle) -> Redirection {
+        Redirection::from_handle(handle)
+    }
+}
+
+impl Popen {
+    /// Execute an external program in a new process.
+    ///
+    /// `argv` is a slice containing the program followed by its
+    /// arguments, such as `&["ps", "x"]`. `config` specifies details
+    /// how to create and interface to the process.
+    ///
+    /// For example, this launches the `cargo update` command:
+    ///
+    /// ```no_run
+    /// # use subprocess::*;
+    /// # fn dummy() -> Result<()> {
+    /// Popen::create(&["cargo", "update"], PopenConfig::default())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// On Linux and macOS, the process is started with `posix_spawn()`
+    /// when everything `config` requires of the child can be done by
+    /// it, which is the case for redirections, `cwd`, `cwd_fd`,
+    /// `setpgid`, `pgid` and `fd_map`.  This avoids copying the address
+    /// space of the current process, which makes starting programs from
+    /// large processes considerably faster.  Otherwise, and on other
+    /// Unix systems, the process is started with `fork()` and `exec()`.
+    ///
+    /// # Errors
+    ///
+    /// If the external program cannot be executed for any reason, an
+    /// error is returned.  The most typical reason for execution to
+    /// fail is that the program is missing on the `PATH`, but other
+    /// errors are also possible.  Failing to execute the program is
+    /// reported as `PopenError::ExecFailed`, which names the program
+    /// and tells the kind of failure.  Note that this is distinct from the
+    /// program running and then exiting with a failure code - this
+    /// can be detected by calling the `wait` method to obtain its
+    /// exit status.
+    pub fn create(argv: &[impl AsRef<OsStr>], mut config: PopenConfig) -> Result<Popen> {
+        if argv.is_empty() {
+            return Err(PopenError::LogicError("argv must not be empty"));
+        }
+        config.validate()?;
+        if let Some(search_path) = config.executable_search.search_path(config.env.as_deref()) {
+            let name = match config.executable {
+                Some(ref executable) => executable.clone(),
+                None => argv[0].as_ref().to_owned(),
+            };
+            let cwd = config.cwd.as_deref();
+            #[cfg(unix)]
+            let root = config.chroot.as_deref();
+            #[cfg(windows)]
+            let root = None;
+            config.executable = Some(find_executable(&name, &search_path, cwd, root)?.into());
+        }
+        if config.daemonize {
+            redirect_to_null(&mut config)?;
+            config.detached = true;
+        }
+        let argv: Vec<OsString> = argv.iter().map(|p| p.as_ref().to_owned()).collect();
+        let hook_argv = crate::hooks::spawning(&argv, &config)?;
+        let mut inst = Popen {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            child_state: ChildState::Preparing,
+            adopted: false,
+            streams_taken: false,
+            input_data: config.input_data.take(),
+            detached: config.detached,
+            stop_action: None,
+            drop_policy: config.drop_policy,
+            resource_usage: None,
+            core_dumped: false,
+            start_time: Instant::now(),
+            runtime: None,
+            pgid: None,
+            pty: None,
+            #[cfg(unix)]
+            notifications: None,
+            #[cfg(unix)]
+            pending_exec: None,
+            hook_argv,
+            pumps: vec![],
+            watchdog: None,
+        };
+        #[cfg(unix)]
+        let (new_group, joined_group) = (
+            config.setpgid || config.setsid || config.pty.is_some(),
+            config.pgid,
+        );
+        #[cfg(windows)]
+        let (new_group, joined_group) = (config.setpgid, None);
+        let timeout = config.timeout.map(|timeout| (timeout, config.on_timeout));
+        inst.os_start(argv, config)?;
+        if let Some((timeout, action)) = timeout {
+            // nothing to watch if a daemon has already been detached
+            if inst.pid().is_some() {
+                match inst
+                    .handle()
+                    .and_then(|h| Watchdog::arm(h, timeout, action))
+                {
+                    Ok(watchdog) => inst.watchdog = Some(watchdog),
+                    Err(e) => {
+                        // don't leave the child running without its limit
+                        inst.kill().ok();
+                        inst.wait().ok();
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        if new_group {
+            inst.pgid = inst.pid();
+        } else if joined_group.is_some() {
+            inst.pgid = joined_group;
+        }
+        crate::registry::register(&inst);
+        Ok(inst)
+    }
+
+    /// Adopt an existing process with the specified PID.
+    ///
+    /// The returned `Popen` can be used to wait for the process and to
+    /// terminate or kill it like one started with `create`, but has no
+    /// streams.  It is detached, so dropping it doesn't wait for the
+    /// process, and `start_time` and `elapsed` are measured from the
+    /// adoption.
+    ///
+    /// Only the parent of a process can obtain its exit status.  If the
+    /// process is a child of the current process, e.g. one started by
+    /// other means, waiting reaps it and returns its exit status as
+    /// usual.  Otherwise, waiting merely observes the exit, which is
+    /// reported as `ExitStatus::Undetermined`.  On Linux, this uses a
+    /// pidfd; elsewhere the process is polled with `kill(pid, 0)`, which
+    /// keeps succeeding while the process is a zombie not yet reaped by
+    /// its parent, and can't tell if the PID has been reused.
+    #[cfg(unix)]
+    pub fn from_pid(pid: u32) -> Popen {
+        Popen::adopt(os::adopted_state(pid))
+    }
+
+    /// Adopt an existing process given its handle.
+    ///
+    /// The returned `Popen` takes ownership of the handle, which must
+    /// have the `SYNCHRONIZE`, `PROCESS_TERMINATE`, and
+    /// `PROCESS_QUERY_LIMITED_INFORMATION` access rights.  It can be used
+    /// to wait for the process, including obtaining its exit code, and to
+    /// terminate it like one started with `create`, but has no streams.
+    /// It is detached, so dropping it doesn't wait for the process, and
+    /// `start_time` and `elapsed` are measured from the adoption.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid process handle, not owned by anything
+    /// else.
+    #[cfg(windows)]
+    pub unsafe fn from_raw_handle(handle: std::os::windows::io::RawHandle) -> Popen {
+        Popen::adopt(os::adopted_state(handle))
+    }
+
+    fn adopt(child_state: ChildState) -> Popen {
+        Popen {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            child_state,
+            adopted: true,
+            streams_taken: false,
+            input_data: None,
+            detached: true,
+            stop_action: None,
+            drop_policy: DropPolicy::default(),
+            resource_usage: None,
+            core_dumped: false,
+            start_time: Instant::now(),
+            runtime: None,
+            pgid: None,
+            pty: None,
+            #[cfg(unix)]
+            notifications: None,
+            #[cfg(unix)]
+            pending_exec: None,
+            hook_argv: None,
+            pumps: vec![],
+            watchdog: None,
+        }
+    }
+
+    /// Run `f` with a subprocess that is stopped when `f` returns.
+    ///
+    /// The subprocess is started as with [`create`] and passed to `f`.
+    /// When `f` returns or panics, the subprocess is terminated and
+    /// waited for, unless it has already exited, as described for
+    /// [`ChildGuard`].  Returns the value returned by `f`.
+    ///
+    /// [`create`]: struct.Popen.html#method.create
+    /// [`ChildGuard`]: struct.ChildGuard.html
+    pub fn scoped<R>(
+        argv: &[impl AsRef<OsStr>],
+        config: PopenConfig,
+        f: impl FnOnce(&mut Popen) -> R,
+    ) -> Result<R> {
+        let mut guard = ChildGuard::new(Popen::create(argv, config)?);
+        Ok(f(&mut guard))
+    }
+
+    // Create the pipes requested by stdin, stdout, and stderr from
+    // the PopenConfig used to construct us, and return the Files to
+    // be given to the child process.
+    //
+    // For Redirection::Pipe, this stores the parent end of the pipe
+    // to the appropriate self.std* field, and returns the child end
+    // of the pipe.
+    //
+    // For Redirection::File, this transfers the ownership of the File
+    // to the corresponding child.
+    fn setup_streams(
+        &mut self,
+        stdin: Redirection,
+        stdout: Redirection,
+        stderr: Redirection,
+    ) -> Result<(Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>)> {
+        fn prepare_pipe(
+            parent_writes: bool,
+            parent_ref: &mut Option<File>,
+            child_ref: &mut Option<Rc<File>>,
+        ) -> Result<()> {
+            // Store the parent's end of the pipe into the given
+            // reference, and store the child end.  The pipe is created
+            // non-inheritable, so that neither end is leaked to a
+            // process started concurrently by another thread; the child
+            // end is only passed to the child when it is spawned.
+            let (read, write) = os::make_pipe()?;
+            let (parent_end, child_end) = if parent_writes {
+                (write, read)
+            } else {
+                (read, write)
+            };
+            os::set_inheritable(&child_end, true)?;
+            *parent_ref = Some(parent_end);
+            *child_ref = Some(Rc::new(child_end));
+            Ok(())
+        }
+        fn prepare_tee(
+            target: File,
+            parent_ref: &mut Option<File>,
+            child_ref: &mut Option<Rc<File>>,
+        ) -> Result<()> {
+            // The child writes to one pipe, which a thread copies to the
+            // target and to a second pipe, whose read end is stored into
+            // the given reference.
+            let mut source = None;
+            prepare_pipe(false, &mut source, child_ref)?;
+            let (read, write) = os::make_pipe()?;
+            pump::tee(source.unwrap(), target, write)?;
+            *parent_ref = Some(read);
+            Ok(())
+        }
+        fn prepare_writer(
+            sink: Box<dyn Write + Send>,
+            pumps: &mut Vec<JoinHandle<()>>,
+            child_ref: &mut Option<Rc<File>>,
+        ) -> Result<()> {
+            // The child writes to a pipe, which a thread copies to the
+            // sink.
+            let mut source = None;
+            prepare_pipe(false, &mut source, child_ref)?;
+            pumps.push(pump::to_writer(source.unwrap(), sink)?);
+            Ok(())
+        }
+        fn prepare_reader(
+            source: Box<dyn Read + Send>,
+            child_ref: &mut Option<Rc<File>>,
+        ) -> Result<()> {
+            // A thread copies the source to the pipe the child reads.
+            let mut dest = None;
+            prepare_pipe(true, &mut dest, child_ref)?;
+            pump::from_reader(source, dest.unwrap())?;
+            Ok(())
+        }
+        fn prepare_file(file: File, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
+            // Make the File inheritable and store it for use in the child.
+            os::set_inheritable(&file, true)?;
+            *child_ref = Some(Rc::new(file));
+            Ok(())
+        }
+        fn prepare_rc_file(file: Rc<File>, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
+            // Like prepare_file, but for Rc<File>
+            os::set_inheritable(&file, true)?;
+            *child_ref = Some(file);
+            Ok(())
+        }
+        fn reuse_stream(
+            dest: &mut Option<Rc<File>>,
+            src: &mut Option<Rc<File>>,
+            src_id: StandardStream,
+        ) -> io::Result<()> {
+            // For Redirection::Merge, make stdout and stderr refer to
+            // the same File.  If the file is unavailable, use the
+            // appropriate system output stream.
+            if src.is_none() {
+                *src = Some(get_standard_stream(src_id)?);
+            }
+            *dest = Some(Rc::clone(src.as_ref().unwrap()));
+            Ok(())
+        }
+
+        enum MergeKind {
+            ErrToOut, // 2>&1
+            OutToErr, // 1>&2
+            None,
+        }
+        let mut merge: MergeKind = MergeKind::None;
+
+        let (mut child_stdin, mut child_stdout, mut child_stderr) = (None, None, None);
+
+        match stdin {
+            Redirection::Pipe => prepare_pipe(true, &mut self.stdin, &mut child_stdin)?,
+            Redirection::File(file) => prepare_file(file, &mut child_stdin)?,
+            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdin)?,
+            Redirection::Open(path, options) => {
+                prepare_file(options.open(path)?, &mut child_stdin)?
+            }
+            Redirection::Reader(source) => prepare_reader(source, &mut child_stdin)?,
+            Redirection::Merge | Redirection::Tee(_) | Redirection::Writer(_) => {
+                unreachable!("checked by PopenConfig::validate")
+            }
+            Redirection::None => (),
+        };
+        match stdout {
+            Redirection::Pipe => prepare_pipe(false, &mut self.stdout, &mut child_stdout)?,
+            Redirection::File(file) => prepare_file(file, &mut child_stdout)?,
+            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdout)?,
+            Redirection::Open(path, options) => {
+                prepare_file(options.open(path)?, &mut child_stdout)?
+            }
+            Redirection::Tee(target) => prepare_tee(target, &mut self.stdout, &mut child_stdout)?,
+            Redirection::Writer(sink) => prepare_writer(sink, &mut self.pumps, &mut child_stdout)?,
+            Redirection::Reader(_) => unreachable!("checked by PopenConfig::validate"),
+            Redirection::Merge => merge = MergeKind::OutToErr,
+            Redirection::None => (),
+        };
+        match stderr {
+            Redirection::Pipe => prepare_pipe(false, &mut self.stderr, &mut child_stderr)?,
+            Redirection::File(file) => prepare_file(file, &mut child_stderr)?,
+            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stderr)?,
+            Redirection::Open(path, options) => {
+                prepare_file(options.open(path)?, &mut child_stderr)?
+            }
+            Redirection::Tee(target) => prepare_tee(target, &mut self.stderr, &mut child_stderr)?,
+            Redirection::Writer(sink) => prepare_writer(sink, &mut self.pumps, &mut child_stderr)?,
+            Redirection::Reader(_) => unreachable!("checked by PopenConfig::validate"),
+            Redirection::Merge => merge = MergeKind::ErrToOut,
+            Redirection::None => (),
+        };
+
+        // Handle Redirection::Merge after creating the output child
+        // streams.  Merge by cloning the child stream, or the
+        // appropriate standard stream if we don't have a child stream
+        // requested using Redirection::Pipe or Redirection::File.  In
+        // other words, 2>&1 (ErrToOut) is implemented by making
+        // child_stderr point to a dup of child_stdout, or of the OS's
+        // stdout stream.
+        match merge {
+            MergeKind::ErrToOut => {
+                reuse_stream(&mut child_stderr, &mut child_stdout, StandardStream::Output)?
+            }
+            MergeKind::OutToErr => {
+                reuse_stream(&mut child_stdout, &mut child_stderr, StandardStream::Error)?
+            }
+            MergeKind::None => (),
+        }
+
+        Ok((child_stdin, child_stdout, child_stderr))
+    }
+
+    /// Return the resources used by the subprocess, if it has been
+    /// reaped.
+    ///
+    /// The usage is collected when the subprocess is found to have
+    /// finished by one of `wait`, `wait_timeout`, or `poll`, and
+    /// includes CPU time, peak memory use, and page faults.  Returns
+    /// `None` while the subprocess is running, or if it was reaped by
+    /// someone else, such as foreign code calling `waitpid()`.
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.resource_usage
+    }
+
+    /// Return whether the subprocess was killed by a signal and dumped
+    /// core.
+    ///
+    /// Like [`resource_usage`], this is known once the subprocess is
+    /// found to have finished by one of `wait`, `wait_timeout`, or
+    /// `poll`, and is false before that, if the subprocess was reaped
+    /// by someone else, and always on Windows.
+    ///
+    /// [`resource_usage`]: struct.Popen.html#method.resource_usage
+    pub fn core_dumped(&self) -> bool {
+        self.core_dumped
+    }
+
+    /// Return the capacity of the pipes connected to the standard
+    /// output and error of the subprocess, in bytes.
+    ///
+    /// Each is `None` if the stream isn't a pipe held by the `Popen`,
+    /// for example because it was redirected elsewhere or already
+    /// taken.  See [`PopenConfig::pipe_size`] for changing the
+    /// capacity.
+    ///
+    /// [`PopenConfig::pipe_size`]: struct.PopenConfig.html#structfield.pipe_size
+    #[cfg(target_os = "linux")]
+    pub fn pipe_size(&self) -> (Option<usize>, Option<usize>) {
+        use std::os::unix::io::AsRawFd;
+        let size = |f: &Option<File>| {
+            f.as_ref()
+                .and_then(|f| crate::posix::pipe_size(f.as_raw_fd()).ok())
+        };
+        (size(&self.stdout), size(&self.stderr))
+    }
+
+    /// Return the time at which the subprocess was started.
+    pub fn start_time(&self) -> Instant {
+        self.start_time
+    }
+
+    /// Return the time elapsed since the subprocess was started.
+    ///
+    /// Once the subprocess is known to have finished, this stops
+    /// advancing and is equal to [`runtime`].
+    ///
+    /// [`runtime`]: struct.Popen.html#method.runtime
+    pub fn elapsed(&self) -> Duration {
+        self.runtime.unwrap_or_else(|| self.start_time.elapsed())
+    }
+
+    /// Return the total running time of the subprocess, if it has
+    /// finished.
+    ///
+    /// The running time is measured from the start of the subprocess
+    /// until it was found to have finished by one of `wait`,
+    /// `wait_timeout`, or `poll`, so it is accurate to within the
+    /// polling interval.  Returns `None` as long as `exit_status`
+    /// does.
+    pub fn runtime(&self) -> Option<Duration> {
+        self.runtime
+    }
+
+    /// Return whether the subprocess reached the timeout given by
+    /// [`PopenConfig::timeout`], and was stopped because of it.
+    ///
+    /// [`PopenConfig::timeout`]: struct.PopenConfig.html#structfield.timeout
+    pub fn timed_out(&self) -> bool {
+        self.watchdog.as_ref().is_some_and(Watchdog::fired)
+    }
+
+    // Record that the child was found to have finished, and report it
+    // to the exit hook.  The resource usage, if any, must already be
+    // set.
+    fn set_finished(&mut self, exit_status: ExitStatus) {
+        let pid = self.pid();
+        let runtime = self.start_time.elapsed();
+        self.child_state = Finished(exit_status);
+        self.runtime = Some(runtime);
+        if let (Some(pid), Some(argv)) = (pid, self.hook_argv.take()) {
+            crate::hooks::exited(pid, argv, exit_status, runtime, self.resource_usage);
+        }
+    }
+
+    /// Sample the current memory and CPU use of the running subprocess.
+    ///
+    /// Unlike [`resource_usage`], which is only available once the
+    /// subprocess has finished, this can be called periodically to
+    /// watch the subprocess, e.g. to enforce a memory limit.  The values
+    /// are obtained from `/proc` on Linux, `proc_pidinfo` on macOS, and
+    /// `GetProcessMemoryInfo` and `GetProcessTimes` on Windows.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the subprocess is
+    /// known to have finished, and an error on Unix systems other than
+    /// Linux and macOS.
+    ///
+    /// [`resource_usage`]: struct.Popen.html#method.resource_usage
+    pub fn sample_usage(&self) -> io::Result<UsageSample> {
+        match self.child_state {
+            Preparing => panic!("child_state == Preparing"),
+            Running { .. } => os::sample_usage(self),
+            Finished(..) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the process has finished",
+            )),
+        }
+    }
+
+    /// Set what happens to the subprocess when the `Popen` is dropped.
+    ///
+    /// This overrides the policy given by [`PopenConfig::drop_policy`].
+    /// It has no effect if the `Popen` is detached.
+    ///
+    /// [`PopenConfig::drop_policy`]: struct.PopenConfig.html#structfield.drop_policy
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
+    }
+
+    /// Mark the process as detached.
+    ///
+    /// This method has no effect on the OS level, it simply tells
+    /// `Popen` not to wait for the subprocess to finish when going
+    /// out of scope.  If the child process has already finished, or
+    /// if it is guaranteed to finish before `Popen` goes out of
+    /// scope, calling `detach` has no effect.
+    pub fn detach(&mut self) {
+        self.detached = true;
+    }
+
+    /// Return the PID of the subprocess, if it is known to be still running.
+    ///
+    /// Note that this method won't actually *check* whether the child
+    /// process is still running, it will only return the information
+    /// last set using one of `create`, `wait`, `wait_timeout`, or
+    /// `poll`.  For a newly created `Popen`, `pid()` always returns
+    /// `Some`.
+    pub fn pid(&self) -> Option<u32> {
+        match self.child_state {
+            Running { pid, .. } => Some(pid),
+            _ => None,
+        }
+    }
+
+    /// Return the pseudo terminal of the subprocess, if it was started
+    /// with [`PopenConfig::pty`].
+    ///
+    /// [`PopenConfig::pty`]: struct.PopenConfig.html#structfield.pty
+    pub fn pty(&self) -> Option<&Pty> {
+        self.pty.as_ref()
+    }
+
+    /// Return the notifications sent by the subprocess, if it was
+    /// started with [`PopenConfig::notify_socket`].
+    ///
+    /// [`PopenConfig::notify_socket`]: struct.PopenConfig.html#structfield.notify_socket
+    #[cfg(unix)]
+    pub fn notifications(&self) -> Option<&Notifications> {
+        self.notifications.as_ref()
+    }
+
+    /// Return the exit status of the subprocess, if it is known to have finished.
+    ///
+    /// Note that this method won't actually *check* whether the child
+    /// process has finished, it only returns the previously available
+    /// information.  To check or wait for the process to finish, call
+    /// `wait`, `wait_timeout`, or `poll`.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        match self.child_state {
+            Finished(exit_status) => Some(exit_status),
+            _ => None,
+        }
+    }
+
+    /// Take ownership of the parent's end of the standard input pipe.
+    ///
+    /// Returns `None` if `stdin` was not redirected to a pipe, or was
+    /// already taken.  Unlike taking `self.stdin` directly, taking a
+    /// stream marks the `Popen` so that a later attempt to communicate with the
+    /// subprocess, which would no longer see the stream, panics instead
+    /// of silently skipping it.  The `Popen` can still be used to wait
+    /// for the subprocess and to terminate it.
+    pub fn take_stdin(&mut self) -> Option<File> {
+        let stream = self.stdin.take();
+        self.streams_taken |= stream.is_some();
+        stream
+    }
+
+    /// Take ownership of the parent's end of the standard output pipe.
+    ///
+    /// See [`take_stdin`] for details.
+    ///
+    /// [`take_stdin`]: struct.Popen.html#method.take_stdin
+    pub fn take_stdout(&mut self) -> Option<File> {
+        let stream = self.stdout.take();
+        self.streams_taken |= stream.is_some();
+        stream
+    }
+
+    /// Take ownership of the parent's end of the standard error pipe.
+    ///
+    /// See [`take_stdin`] for details.
+    ///
+    /// [`take_stdin`]: struct.Popen.html#method.take_stdin
+    pub fn take_stderr(&mut self) -> Option<File> {
+        let stream = self.stderr.take();
+        self.streams_taken |= stream.is_some();
+        stream
+    }
+
+    // The streams to communicate over, which must not have been taken.
+    fn communicate_streams(&mut self) -> (Option<File>, Option<File>, Option<File>) {
+        assert!(
+            !self.streams_taken,
+            "cannot communicate after a stream was taken with take_stdin, take_stdout, or take_stderr"
+        );
+        (self.stdin.take(), self.stdout.take(), self.stderr.take())
+    }
+
+    /// Prepare to communicate with the subprocess.
+    ///
+    /// Communicating refers to unattended data exchange with the subprocess.
+    /// During communication the given `input_data` is written to the
+    /// subprocess's standard input which is then closed, while simultaneously
+    /// its standard output and error streams are read until end-of-file is
+    /// reached.  If `input_data` is `None`, the `input_data` given in the
+    /// `PopenConfig`, if any, is written instead.
+    ///
+    /// The difference between this and simply writing input data to
+    /// `self.stdin` and then reading output from `self.stdout` and
+    /// `self.stderr` is that the reading and the writing are performed
+    /// simultaneously.  A naive implementation that writes and then reads has
+    /// an issue when the subprocess responds to part of the input by
+    /// providing output.  The output must be read for the subprocess to
+    /// accept further input, but the parent process is still blocked on
+    /// writing the rest of the input daata.  Since neither process can
+    /// proceed, a deadlock occurs.  This is why a correct implementation must
+    /// write and read at the same time.
+    ///
+    /// This method does not perform the actual communication, it just sets it
+    /// up and returns a [`Communicator`].  Call the [`read`] or
+    /// [`read_string`] method on the `Communicator` to exchange data with the
+    /// subprocess.
+    ///
+    /// Compared to `communicate()` and `communicate_bytes()`, the
+    /// `Communicator` provides more control, such as timeout, read size
+    /// limit, and the ability to retrieve captured output in case of read
+    /// error.
+    ///
+    /// [`Communicator`]: struct.Communicator.html
+    /// [`read`]: struct.Communicator.html#method.read
+    /// # Panics
+    ///
+    /// If a stream was taken with [`take_stdin`], [`take_stdout`], or
+    /// [`take_stderr`].
+    ///
+    /// [`read_string`]: struct.Communicator.html#method.read_string
+    /// [`take_stdin`]: struct.Popen.html#method.take_stdin
+    /// [`take_stdout`]: struct.Popen.html#method.take_stdout
+    /// [`take_stderr`]: struct.Popen.html#method.take_stderr
+    pub fn communicate_start(&mut self, input_data: Option<Vec<u8>>) -> Communicator {
+        let input_data = input_data.or_else(|| self.input_data.take());
+        let (stdin, stdout, stderr) = self.communicate_streams();
+        communicate::communicate(
+            stdin,
+            stdout,
+            stderr,
+            input_data.map(|data| vec![InputSource::Bytes(data)]),
+        )
+    }
+
+    /// Prepare to communicate with the subprocess, feeding it input from
+    /// multiple sources.
+    ///
+    /// This is like [`communicate_start`], except the input is composed of
+    /// the provided `sources`, which are written to the subprocess one after
+    /// the other, like the shell's `cat a b | cmd`.  Files and readers among
+    /// the sources are read incrementally during communication, avoiding the
+    /// need to concatenate large inputs in memory beforehand.
+    ///
+    /// # Panics
+    ///
+    /// If `stdin` was not redirected to a pipe, or if a stream was taken
+    /// as with `communicate_start`.
+    ///
+    /// [`communicate_start`]: struct.Popen.html#method.communicate_start
+    pub fn communicate_start_sources(
+        &mut self,
+        sources: impl IntoIterator<Item = InputSource>,
+    ) -> Communicator {
+        let (stdin, stdout, stderr) = self.communicate_streams();
+        communicate::communicate(stdin, stdout, stderr, Some(sources.into_iter().collect()))
+    }
+
+    /// Feed the subprocess with input data and capture its output.
+    ///
+    /// This will write the provided `input_data` to the subprocess's standard
+    /// input, and simultaneously read its standard output and error.  The
+    /// output and error contents are returned as a pair of `Option<Vec<u8>>`.
+    /// The `None` options correspond to streams not specified as
+    /// `Redirection::Pipe` when creating the subprocess.
+    ///
+    /// This implementation reads and writes simultaneously, avoiding deadlock
+    /// in case the subprocess starts writing output before reading the whole
+    /// input - see [`communicate_start()`] for details.
+    ///
+    /// Note that this method does not wait for the subprocess to finish, only
+    /// to close its output/error streams.  It is rare but possible for the
+    /// program to continue running after having closed the streams, in which
+    /// case `Popen::Drop` will wait for it to finish.  If such a wait is
+    /// undesirable, it can be prevented by waiting explicitly using `wait()`,
+    /// by detaching the process using `detach()`, or by terminating it with
+    /// `terminate()`.
+    ///
+    /// For additional control over communication, such as timeout and size
+    /// limit, call [`communicate_start()`].
+    ///
+    /// # Panics
+    ///
+    /// If `input_data` is provided and `stdin` was not redirected to a pipe.
+    /// Also, if `input_data` is not provided and `stdin` was redirected to a
+    /// pipe, or if a stream was taken as with `communicate_start()`.
+    ///
+    /// # Errors
+    ///
+    /// * `Err(::std::io::Error)` if a system call fails
+    ///
+    /// [`communicate_start()`]: struct.Popen.html#method.communicate_start
+    pub fn communicate_bytes(
+        &mut self,
+        input_data: Option<&[u8]>,
+    ) -> io::Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        self.communicate_start(input_data.map(|i| i.to_vec()))
+            .read()
+            .map_err(|e| e.error)
+    }
+
+    /// Feed the subprocess with data and capture its output as string.
+    ///
+    /// This is a convenience method equivalent to [`communicate_bytes`], but
+    /// with input as `&str` and output as `String`.  Invalid UTF-8 sequences,
+    /// if found, are replaced with the the `U+FFFD` Unicode replacement
+    /// character.
+    ///
+    /// # Panics
+    ///
+    /// The same as with `communicate_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// * `Err(::std::io::Error)` if a system call fails
+    ///
+    /// [`communicate_bytes`]: struct.Popen.html#method.communicate_bytes
+    pub fn communicate(
+        &mut self,
+        input_data: Option<&str>,
+    ) -> io::Result<(Option<String>, Option<String>)> {
+        self.communicate_start(input_data.map(|s| s.as_bytes().to_vec()))
+            .read_string()
+            .map_err(|e| e.error)
+    }
+
+    /// Check whether the process is still running, without blocking or errors.
+    ///
+    /// This checks whether the process is still running and if it
+    /// is still running, `None` is returned, otherwise
+    /// `Some(exit_status)`.  This method is guaranteed not to block
+    /// and is exactly equivalent to
+    /// `wait_timeout(Duration::from_secs(0)).unwrap_or(None)`.
+    pub fn poll(&mut self) -> Option<ExitStatus> {
+        self.wait_timeout(Duration::from_secs(0)).unwrap_or(None)
+    }
 
-    /// Like `File`, but the file is specified as `Rc`.
+    /// Check whether the process has finished, without reaping it.
     ///
-    /// This allows the same file to be used in multiple redirections.
-    RcFile(Rc<File>),
-}
+    /// Unlike [`poll`], this doesn't collect the exit status of a
+    /// finished process, which remains available to whoever waits for
+    /// it, e.g. another component that calls `waitpid()` on the PID.
+    /// Returns `None` if the process is still running.
+    ///
+    /// On Unix-like systems, this uses `waitid()` with `WNOWAIT`.  On
+    /// Windows, where waiting never consumes the exit status, it uses
+    /// `GetExitCodeProcess`.
+    ///
+    /// [`poll`]: struct.Popen.html#method.poll
+    pub fn peek_status(&self) -> io::Result<Option<ExitStatus>> {
+        match self.child_state {
+            Preparing => panic!("child_state == Preparing"),
+            Running { .. } => os::peek_status(self),
+            Finished(exit_status) => Ok(Some(exit_status)),
+        }
+    }
 
-impl Redirection {
-    /// Clone the underlying `Redirection`, or return an error.
+    /// Wait for the process to finish, and return its exit status.
     ///
-    /// Can fail in `File` variant.
-    pub fn try_clone(&self) -> io::Result<Redirection> {
-        Ok(match *self {
-            Redirection::None => Redirection::None,
-            Redirection::Pipe => Redirection::Pipe,
-            Redirection::Merge => Redirection::Merge,
-            Redirection::File(ref f) => Redirection::File(f.try_clone()?),
-            Redirection::RcFile(ref f) => Redirection::RcFile(Rc::clone(&f)),
-        })
+    /// If the process has already finished, it will exit immediately,
+    /// returning the exit status.  Calling `wait` after that will
+    /// return the cached exit status without executing any system
+    /// calls.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if a system call fails in an unpredicted way.
+    /// This should not happen in normal usage.
+    ///
+    /// If an output stream is redirected with `Redirection::Writer`,
+    /// this also waits for its output to be copied to the writer,
+    /// which lasts until the processes the stream was passed to have
+    /// closed it.
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        let exit_status = self.os_wait()?;
+        for pump in self.pumps.drain(..) {
+            let _ = pump.join();
+        }
+        Ok(exit_status)
     }
-}
 
-impl Popen {
-    /// Execute an external program in a new process.
+    /// Wait for the process to finish, timing out after the specified duration.
     ///
-    /// `argv` is a slice containing the program followed by its
-    /// arguments, such as `&["ps", "x"]`. `config` specifies details
-    /// how to create and interface to the process.
+    /// This function behaves like `wait()`, except that the caller
+    /// will be blocked for roughly no longer than `dur`.  It returns
+    /// `Ok(None)` if the timeout is known to have elapsed.
     ///
-    /// For example, this launches the `cargo update` command:
+    /// On Unix-like systems, timeout is implemented by calling
+    /// `waitpid(..., WNOHANG)` in a loop with adaptive sleep
+    /// intervals between iterations.
+    pub fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>> {
+        self.os_wait_timeout(dur)
+    }
+
+    /// Wait for the process to finish asynchronously.
     ///
-    /// ```no_run
-    /// # use subprocess::*;
-    /// # fn dummy() -> Result<()> {
-    /// Popen::create(&["cargo", "update"], PopenConfig::default())?;
-    /// # Ok(())
-    /// # }
-    /// ```
+    /// Returns a future that resolves to the exit status of the
+    /// process, allowing async code to wait for the exit alongside
+    /// other events.  The future doesn't depend on any particular
+    /// runtime: once polled, it is woken when the process exits, as
+    /// observed through the pidfd on Linux, a kqueue on macOS and
+    /// FreeBSD, `waitid` on other Unix systems, and
+    /// `RegisterWaitForSingleObject` on Windows.  On Unix this takes a
+    /// helper thread which lives until the process exits.
+    ///
+    /// This method is available with the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn wait_async(&mut self) -> ExitFuture<'_> {
+        ExitFuture::new(self)
+    }
+
+    /// Wait for any of the given processes to finish.
+    ///
+    /// Returns the index of a finished process in `children` along
+    /// with its exit status, or `Ok(None)` if `timeout` elapses first.
+    /// A process that has already finished, including one whose exit
+    /// status was previously obtained, is reported immediately, so it
+    /// should be removed from `children` before calling this again.
+    ///
+    /// On Linux, this sleeps until one of the processes exits using
+    /// their pidfds, and on Windows using `WaitForMultipleObjects`.
+    /// Elsewhere, the processes are polled in a loop with adaptive
+    /// sleep intervals, unless SIGCHLD wakeup is enabled.
     ///
     /// # Errors
     ///
-    /// If the external program cannot be executed for any reason, an
-    /// error is returned.  The most typical reason for execution to
-    /// fail is that the program is missing on the `PATH`, but other
-    /// errors are also possible.  Note that this is distinct from the
-    /// program running and then exiting with a failure code - this
-    /// can be detected by calling the `wait` method to obtain its
-    /// exit status.
-    pub fn create(argv: &[impl AsRef<OsStr>], config: PopenConfig) -> Result<Popen> {
-        if argv.is_empty() {
-            return Err(PopenError::LogicError("argv must not be empty"));
+    /// Returns `LogicError` if `children` is empty, or an `Err` if a
+    /// system call fails in an unpredicted way.
+    pub fn wait_any(
+        children: &mut [&mut Popen],
+        timeout: Option<Duration>,
+    ) -> Result<Option<(usize, ExitStatus)>> {
+        if children.is_empty() {
+            return Err(PopenError::LogicError("no processes to wait for"));
         }
-        let argv: Vec<OsString> = argv.iter().map(|p| p.as_ref().to_owned()).collect();
-        let mut inst = Popen {
-            stdin: None,
-            stdout: None,
-            stderr: None,
-            child_state: ChildState::Preparing,
-            detached: config.detached,
-        };
-        inst.os_start(argv, config)?;
-        Ok(inst)
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        os::wait_any(children, deadline)
     }
 
-    // Create the pipes requested by stdin, stdout, and stderr from
-    // the PopenConfig used to construct us, and return the Files to
-    // be given to the child process.
-    //
-    // For Redirection::Pipe, this stores the parent end of the pipe
-    // to the appropriate self.std* field, and returns the child end
-    // of the pipe.
-    //
-    // For Redirection::File, this transfers the ownership of the File
-    // to the corresponding child.
-    fn setup_streams(
-        &mut self,
-        stdin: Redirection,
-        stdout: Redirection,
-        stderr: Redirection,
-    ) -> Result<(Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>)> {
-        fn prepare_pipe(
-            parent_writes: bool,
-            parent_ref: &mut Option<File>,
-            child_ref: &mut Option<Rc<File>>,
-        ) -> Result<()> {
-            // Store the parent's end of the pipe into the given
-            // reference, and store the child end.
-            let (read, write) = os::make_pipe()?;
-            let (parent_end, child_end) = if parent_writes {
-                (write, read)
-            } else {
-                (read, write)
-            };
-            os::set_inheritable(&parent_end, false)?;
-            *parent_ref = Some(parent_end);
-            *child_ref = Some(Rc::new(child_end));
-            Ok(())
-        }
-        fn prepare_file(file: File, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
-            // Make the File inheritable and store it for use in the child.
-            os::set_inheritable(&file, true)?;
-            *child_ref = Some(Rc::new(file));
-            Ok(())
+    /// Terminate the subprocess.
+    ///
+    /// On Unix-like systems, this sends the `SIGTERM` signal to the
+    /// child process, which can be caught by the child in order to
+    /// perform cleanup before exiting.  On Windows, it is equivalent
+    /// to `kill()`.
+    pub fn terminate(&mut self) -> io::Result<()> {
+        self.os_terminate()
+    }
+
+    /// Kill the subprocess.
+    ///
+    /// On Unix-like systems, this sends the `SIGKILL` signal to the
+    /// child process, which cannot be caught.
+    ///
+    /// On Windows, it invokes [`TerminateProcess`] on the process
+    /// handle with equivalent semantics.
+    ///
+    /// [`TerminateProcess`]: https://msdn.microsoft.com/en-us/library/windows/desktop/ms686714(v=vs.85).aspx
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.os_kill()
+    }
+
+    /// Suspend the subprocess.
+    ///
+    /// The subprocess stops running until resumed with [`resume`].
+    /// This can be used to pause an expensive subprocess without
+    /// losing its work, e.g. while the system is under load.
+    ///
+    /// On Unix-like systems, this sends the `SIGSTOP` signal, which
+    /// cannot be caught.  On Windows, it suspends all threads of the
+    /// process with `NtSuspendProcess`.
+    ///
+    /// If the subprocess is known to have finished, this does nothing.
+    ///
+    /// [`resume`]: struct.Popen.html#method.resume
+    pub fn suspend(&mut self) -> io::Result<()> {
+        match self.child_state {
+            Preparing => panic!("child_state == Preparing"),
+            Running { .. } => os::suspend(self),
+            Finished(..) => Ok(()),
         }
-        fn prepare_rc_file(file: Rc<File>, child_ref: &mut Option<Rc<File>>) -> io::Result<()> {
-            // Like prepare_file, but for Rc<File>
-            os::set_inheritable(&file, true)?;
-            *child_ref = Some(file);
-            Ok(())
+    }
+
+    /// Resume the subprocess after [`suspend`].
+    ///
+    /// On Unix-like systems, this sends the `SIGCONT` signal.  On
+    /// Windows, it resumes the threads of the process with
+    /// `NtResumeProcess`.
+    ///
+    /// If the subprocess is known to have finished, this does nothing.
+    ///
+    /// On Unix-like systems, resuming a subprocess started with
+    /// [`PopenConfig::start_suspended`] for the first time also waits
+    /// for it to execute the program.  Failure to execute it is
+    /// returned as an error that wraps the [`PopenError`] which
+    /// `Popen::create` would have returned, accessible with
+    /// `io::Error::get_ref`.
+    ///
+    /// [`suspend`]: struct.Popen.html#method.suspend
+    /// [`PopenConfig::start_suspended`]: struct.PopenConfig.html#structfield.start_suspended
+    /// [`PopenError`]: enum.PopenError.html
+    pub fn resume(&mut self) -> io::Result<()> {
+        match self.child_state {
+            Preparing => panic!("child_state == Preparing"),
+            Running { .. } => os::resume(self)?,
+            Finished(..) => return Ok(()),
         }
-        fn reuse_stream(
-            dest: &mut Option<Rc<File>>,
-            src: &mut Option<Rc<File>>,
-            src_id: StandardStream,
-        ) -> io::Result<()> {
-            // For Redirection::Merge, make stdout and stderr refer to
-            // the same File.  If the file is unavailable, use the
-            // appropriate system output stream.
-            if src.is_none() {
-                *src = Some(get_standard_stream(src_id)?);
-            }
-            *dest = Some(Rc::clone(src.as_ref().unwrap()));
-            Ok(())
+        #[cfg(unix)]
+        if let Some(pending) = self.pending_exec.take() {
+            return pending.finish().map_err(|e| match e {
+                PopenError::IoError(e) => e,
+                e => io::Error::other(e),
+            });
         }
+        Ok(())
+    }
 
-        enum MergeKind {
-            ErrToOut, // 2>&1
-            OutToErr, // 1>&2
-            None,
+    /// Send a signal to the subprocess.
+    ///
+    /// On Unix-like systems, this sends the corresponding signal with
+    /// `kill()`.  Raw signal numbers are accepted as well, so this can
+    /// be called as `send_signal(libc::SIGUSR1)`.
+    ///
+    /// On Windows, `Signal::Interrupt` and `Signal::Break` are sent as
+    /// `CTRL_C_EVENT` and `CTRL_BREAK_EVENT` to the process group of
+    /// the subprocess, which requires it to have been started with
+    /// [`PopenConfig::setpgid`].  Note that Windows disables
+    /// `CTRL_C_EVENT` in new process groups, so only processes that
+    /// re-enable it will receive it.  Other signals terminate the
+    /// process with [`TerminateProcess`] and exit code 1, as with
+    /// [`terminate`].
+    ///
+    /// If the subprocess is known to have finished, this does nothing
+    /// and returns `Ok`.
+    ///
+    /// # Errors
+    ///
+    /// On Windows, returns an error of kind `InvalidInput` when sending
+    /// a console event to a subprocess not started in a new process
+    /// group.
+    ///
+    /// [`PopenConfig::setpgid`]: struct.PopenConfig.html#structfield.setpgid
+    /// [`TerminateProcess`]: https://msdn.microsoft.com/en-us/library/windows/desktop/ms686714(v=vs.85).aspx
+    /// [`terminate`]: struct.Popen.html#method.terminate
+    pub fn send_signal(&self, signal: impl Into<Signal>) -> io::Result<()> {
+        if let Finished(..) = self.child_state {
+            return Ok(());
         }
-        let mut merge: MergeKind = MergeKind::None;
+        os::send_signal(self, signal.into())
+    }
 
-        let (mut child_stdin, mut child_stdout, mut child_stderr) = (None, None, None);
+    /// Connect the subprocess's standard input and output to a socket.
+    ///
+    /// Data read from `socket` is copied to the subprocess's standard
+    /// input, and its standard output is copied to `socket`, each
+    /// direction in a separate thread.  End of input on the socket
+    /// closes the subprocess's standard input, and end of its output
+    /// shuts down the writing half of the socket.  This returns when
+    /// both directions are finished, or when `timeout`, if given,
+    /// elapses, in which case the socket is shut down and an error of
+    /// kind `TimedOut` is returned.
+    ///
+    /// Only the streams redirected to `Redirection::Pipe` take part in
+    /// the transfer; they are consumed by this call.  Standard error is
+    /// left alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if neither standard input nor standard output
+    /// is a pipe, if the copying fails in either direction, or on
+    /// timeout.
+    pub fn bridge<S: BridgeSocket>(
+        &mut self,
+        socket: S,
+        timeout: Option<Duration>,
+    ) -> io::Result<BridgeStats> {
+        if self.stdin.is_none() && self.stdout.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "neither stdin nor stdout is redirected to a pipe",
+            ));
+        }
+        bridge::bridge(self.stdin.take(), self.stdout.take(), socket, timeout)
+    }
 
-        match stdin {
-            Redirection::Pipe => prepare_pipe(true, &mut self.stdin, &mut child_stdin)?,
-            Redirection::File(file) => prepare_file(file, &mut child_stdin)?,
-            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdin)?,
-            Redirection::Merge => {
-                return Err(PopenError::LogicError(
-                    "Redirection::Merge not valid for stdin",
-                ));
-            }
-            Redirection::None => (),
-        };
-        match stdout {
-            Redirection::Pipe => prepare_pipe(false, &mut self.stdout, &mut child_stdout)?,
-            Redirection::File(file) => prepare_file(file, &mut child_stdout)?,
-            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stdout)?,
-            Redirection::Merge => merge = MergeKind::OutToErr,
-            Redirection::None => (),
-        };
-        match stderr {
-            Redirection::Pipe => prepare_pipe(false, &mut self.stderr, &mut child_stderr)?,
-            Redirection::File(file) => prepare_file(file, &mut child_stderr)?,
-            Redirection::RcFile(file) => prepare_rc_file(file, &mut child_stderr)?,
-            Redirection::Merge => merge = MergeKind::ErrToOut,
-            Redirection::None => (),
-        };
+    /// Terminate the process group of the subprocess.
+    ///
+    /// This requires the subprocess to have been started in a new
+    /// process group using [`PopenConfig::setpgid`], or placed in an
+    /// existing one with `PopenConfig::pgid`, and terminates
+    /// all processes in the group, including grandchildren started by
+    /// a shell.  The group is signaled even after the subprocess
+    /// itself has finished, as long as other processes remain in it.
+    ///
+    /// On Unix-like systems, this sends `SIGTERM` to the process group.
+    /// On Windows, it sends `CTRL_BREAK_EVENT` to the group, which
+    /// only reaches console processes sharing the console of this
+    /// process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the subprocess was
+    /// not started in a new process group.
+    ///
+    /// [`PopenConfig::setpgid`]: struct.PopenConfig.html#structfield.setpgid
+    pub fn terminate_group(&mut self) -> io::Result<()> {
+        let pgid = self.group_id()?;
+        os::terminate_group(pgid)
+    }
 
-        // Handle Redirection::Merge after creating the output child
-        // streams.  Merge by cloning the child stream, or the
-        // appropriate standard stream if we don't have a child stream
-        // requested using Redirection::Pipe or Redirection::File.  In
-        // other words, 2>&1 (ErrToOut) is implemented by making
-        // child_stderr point to a dup of child_stdout, or of the OS's
-        // stdout stream.
-        match merge {
-            MergeKind::ErrToOut => {
-                reuse_stream(&mut child_stderr, &mut child_stdout, StandardStream::Output)?
-            }
-            MergeKind::OutToErr => {
-                reuse_stream(&mut child_stdout, &mut child_stderr, StandardStream::Error)?
-            }
-            MergeKind::None => (),
+    /// Kill the process group of the subprocess.
+    ///
+    /// Like [`terminate_group`], but sends `SIGKILL` on Unix-like
+    /// systems.  Windows has no way to kill a process group, so there
+    /// this kills only the subprocess, as with [`kill`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the subprocess was
+    /// not started in a new process group.
+    ///
+    /// [`terminate_group`]: struct.Popen.html#method.terminate_group
+    /// [`kill`]: struct.Popen.html#method.kill
+    pub fn kill_group(&mut self) -> io::Result<()> {
+        let pgid = self.group_id()?;
+        #[cfg(unix)]
+        {
+            os::kill_group(pgid)
+        }
+        #[cfg(windows)]
+        {
+            let _ = pgid;
+            self.kill()
         }
-
-        Ok((child_stdin, child_stdout, child_stderr))
     }
 
-    /// Mark the process as detached.
+    /// Kill the subprocess along with all of its descendants.
     ///
-    /// This method has no effect on the OS level, it simply tells
-    /// `Popen` not to wait for the subprocess to finish when going
-    /// out of scope.  If the child process has already finished, or
-    /// if it is guaranteed to finish before `Popen` goes out of
-    /// scope, calling `detach` has no effect.
-    pub fn detach(&mut self) {
-        self.detached = true;
-    }
-
-    /// Return the PID of the subprocess, if it is known to be still running.
+    /// This is useful when the descendants don't share the process
+    /// group of the subprocess, so [`kill_group`] can't reach them.
+    /// The descendants are found by walking the process tree: through
+    /// `/proc` on Linux, `proc_listchildpids` on macOS, and a Toolhelp
+    /// snapshot on Windows.  On Unix, the processes are first stopped
+    /// with `SIGSTOP` so that they can't start new ones while the tree
+    /// is walked, and then killed bottom-up.  On Windows, which records
+    /// only the parent PID, processes created before their recorded
+    /// parent are skipped, as their parent PID has been reused.
+    /// Descendants whose parent has already exited are no longer part
+    /// of the tree and are not found.
     ///
-    /// Note that this method won't actually *check* whether the child
-    /// process is still running, it will only return the information
-    /// last set using one of `create`, `wait`, `wait_timeout`, or
-    /// `poll`.  For a newly created `Popen`, `pid()` always returns
-    /// `Some`.
-    pub fn pid(&self) -> Option<u32> {
+    /// If the subprocess is known to have finished, this does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process tree can't be listed, including
+    /// on Unix systems other than Linux and macOS, or if a descendant
+    /// can't be killed.  The other descendants are still killed, those
+    /// that survive are resumed, and the subprocess itself is killed
+    /// in any case.
+    ///
+    /// [`kill_group`]: struct.Popen.html#method.kill_group
+    pub fn kill_tree(&mut self) -> io::Result<()> {
         match self.child_state {
-            Running { pid, .. } => Some(pid),
-            _ => None,
+            Preparing => panic!("child_state == Preparing"),
+            Running { pid, .. } => {
+                let result = os::kill_tree(pid);
+                self.kill()?;
+                result
+            }
+            Finished(..) => Ok(()),
         }
     }
 
-    /// Return the exit status of the subprocess, if it is known to have finished.
+    /// Return a handle that can wait for the subprocess and kill it.
     ///
-    /// Note that this method won't actually *check* whether the child
-    /// process has finished, it only returns the previously available
-    /// information.  To check or wait for the process to finish, call
-    /// `wait`, `wait_timeout`, or `poll`.
-    pub fn exit_status(&self) -> Option<ExitStatus> {
-        match self.child_state {
-            Finished(exit_status) => Some(exit_status),
-            _ => None,
-        }
+    /// The returned [`ProcessHandle`] is independent of this `Popen`
+    /// and can be sent to another thread, or its underlying pidfd or
+    /// process handle passed to another process.  Waiting on it doesn't
+    /// collect the exit status, which is still obtained from this
+    /// `Popen`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the subprocess has
+    /// already been waited for.  On Unix, process handles are backed by
+    /// pidfds, so an error is returned on systems other than Linux, and
+    /// on Linux kernels older than 5.3.
+    ///
+    /// [`ProcessHandle`]: struct.ProcessHandle.html
+    pub fn try_clone_handle(&self) -> io::Result<ProcessHandle> {
+        ProcessHandle::from_popen(self, false)
     }
 
-    /// Prepare to communicate with the subprocess.
+    /// Return a cloneable handle that can wait for the subprocess and
+    /// kill it from multiple threads.
     ///
-    /// Communicating refers to unattended data exchange with the subprocess.
-    /// During communication the given `input_data` is written to the
-    /// subprocess's standard input which is then closed, while simultaneously
-    /// its standard output and error streams are read until end-of-file is
-    /// reached.
+    /// This is like [`try_clone_handle`], except that where pidfds are
+    /// not available, on Unix systems other than Linux and on old Linux
+    /// kernels, the handle refers to the subprocess by its PID and start
+    /// time and polls for its exit.  Such a handle sees the subprocess
+    /// as running until it is reaped by this `Popen` on systems other
+    /// than Linux and macOS, where a zombie process can't be told apart
+    /// from a running one.
     ///
-    /// The difference between this and simply writing input data to
-    /// `self.stdin` and then reading output from `self.stdout` and
-    /// `self.stderr` is that the reading and the writing are performed
-    /// simultaneously.  A naive implementation that writes and then reads has
-    /// an issue when the subprocess responds to part of the input by
-    /// providing output.  The output must be read for the subprocess to
-    /// accept further input, but the parent process is still blocked on
-    /// writing the rest of the input daata.  Since neither process can
-    /// proceed, a deadlock occurs.  This is why a correct implementation must
-    /// write and read at the same time.
+    /// # Errors
     ///
-    /// This method does not perform the actual communication, it just sets it
-    /// up and returns a [`Communicator`].  Call the [`read`] or
-    /// [`read_string`] method on the `Communicator` to exchange data with the
-    /// subprocess.
+    /// Returns an error of kind `InvalidInput` if the subprocess has
+    /// already been waited for.
     ///
-    /// Compared to `communicate()` and `communicate_bytes()`, the
-    /// `Communicator` provides more control, such as timeout, read size
-    /// limit, and the ability to retrieve captured output in case of read
-    /// error.
+    /// [`try_clone_handle`]: struct.Popen.html#method.try_clone_handle
+    pub fn handle(&self) -> io::Result<ProcessHandle> {
+        ProcessHandle::from_popen(self, true)
+    }
+
+    /// Split the `Popen` into a handle to the process and its streams.
     ///
-    /// [`Communicator`]: struct.Communicator.html
-    /// [`read`]: struct.Communicator.html#method.read
-    /// [`read_string`]: struct.Communicator.html#method.read_string
-    pub fn communicate_start(&mut self, input_data: Option<Vec<u8>>) -> Communicator {
-        communicate::communicate(
-            self.stdin.take(),
-            self.stdout.take(),
-            self.stderr.take(),
-            input_data,
-        )
+    /// The returned [`ChildHandle`] can wait for the subprocess and
+    /// kill it, and can be shared between threads, so that one thread
+    /// can block waiting for the subprocess while another reads and
+    /// writes the [`ChildStreams`].
+    ///
+    /// [`ChildHandle`]: struct.ChildHandle.html
+    /// [`ChildStreams`]: struct.ChildStreams.html
+    pub fn split(mut self) -> (ChildHandle, ChildStreams) {
+        let streams = ChildStreams {
+            stdin: self.stdin.take(),
+            stdout: self.stdout.take(),
+            stderr: self.stderr.take(),
+        };
+        (ChildHandle::new(self), streams)
     }
 
-    /// Feed the subprocess with input data and capture its output.
+    /// Borrow the file descriptor that becomes readable when the
+    /// subprocess exits.
     ///
-    /// This will write the provided `input_data` to the subprocess's standard
-    /// input, and simultaneously read its standard output and error.  The
-    /// output and error contents are returned as a pair of `Option<Vec<u8>>`.
-    /// The `None` options correspond to streams not specified as
-    /// `Redirection::Pipe` when creating the subprocess.
+    /// The descriptor can be registered with an external event loop,
+    /// such as one based on `epoll`, to be notified of the exit without
+    /// blocking in `wait`, after which the exit status can be obtained
+    /// with `poll`.  It is a pidfd on Linux 5.3 and later, and a kqueue
+    /// on macOS and FreeBSD with the `async` feature.  Returns `None` on
+    /// other systems, and once the subprocess has been waited for, which
+    /// is why `Popen` doesn't implement `AsFd`.
+    #[cfg(unix)]
+    pub fn waitable_fd(&self) -> Option<BorrowedFd<'_>> {
+        os::waitable_fd(self)
+    }
+
+    /// Borrow the process handle, which is signaled when the subprocess
+    /// exits.
     ///
-    /// This implementation reads and writes simultaneously, avoiding deadlock
-    /// in case the subprocess starts writing output before reading the whole
-    /// input - see [`communicate_start()`] for details.
+    /// The handle can be waited on by an external event loop, e.g. with
+    /// `RegisterWaitForSingleObject`, to be notified of the exit without
+    /// blocking in `wait`, after which the exit status can be obtained
+    /// with `poll`.  Returns `None` once the subprocess has been waited
+    /// for, which is why `Popen` doesn't implement `AsHandle`.
+    #[cfg(windows)]
+    pub fn waitable_handle(&self) -> Option<BorrowedHandle<'_>> {
+        self.process_handle().map(AsHandle::as_handle)
+    }
+
+    /// Return the process group ID of the subprocess, if it was started
+    /// in a new process group, or placed in an existing one with
+    /// [`PopenConfig::pgid`].
     ///
-    /// Note that this method does not wait for the subprocess to finish, only
-    /// to close its output/error streams.  It is rare but possible for the
-    /// program to continue running after having closed the streams, in which
-    /// case `Popen::Drop` will wait for it to finish.  If such a wait is
-    /// undesirable, it can be prevented by waiting explicitly using `wait()`,
-    /// by detaching the process using `detach()`, or by terminating it with
-    /// `terminate()`.
+    /// On Windows, this is the ID of the new process group created by
+    /// [`PopenConfig::setpgid`], which is the PID of the subprocess.
     ///
-    /// For additional control over communication, such as timeout and size
-    /// limit, call [`communicate_start()`].
+    /// [`PopenConfig::pgid`]: struct.PopenConfig.html#structfield.pgid
+    /// [`PopenConfig::setpgid`]: struct.PopenConfig.html#structfield.setpgid
+    pub fn pgid(&self) -> Option<u32> {
+        self.pgid
+    }
+
+    fn group_id(&self) -> io::Result<u32> {
+        self.pgid.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "process was not started in a new process group",
+            )
+        })
+    }
+
+    /// Terminate the subprocess, killing it if it doesn't exit in time.
     ///
-    /// # Panics
+    /// This terminates the process as with [`terminate`] and waits up
+    /// to `grace` for it to exit.  If it is still running after that,
+    /// it is killed as with [`kill`] and waited for.  On Windows,
+    /// where terminating is equivalent to killing, the second step is
+    /// normally not needed.
     ///
-    /// If `input_data` is provided and `stdin` was not redirected to a pipe.
-    /// Also, if `input_data` is not provided and `stdin` was redirected to a
-    /// pipe.
+    /// Returns the exit status of the process.  If the process has
+    /// already finished, its exit status is returned without sending
+    /// any signals.
     ///
-    /// # Errors
+    /// [`terminate`]: struct.Popen.html#method.terminate
+    /// [`kill`]: struct.Popen.html#method.kill
+    pub fn terminate_with_timeout(&mut self, grace: Duration) -> Result<ExitStatus> {
+        if let Some(status) = self.poll() {
+            return Ok(status);
+        }
+        self.terminate()?;
+        if let Some(status) = self.wait_timeout(grace)? {
+            return Ok(status);
+        }
+        self.kill()?;
+        self.wait()
+    }
+
+    /// Set the action used by [`shutdown`] to politely ask the
+    /// subprocess to exit.
     ///
-    /// * `Err(::std::io::Error)` if a system call fails
+    /// Many programs have a protocol-level way to request an orderly
+    /// exit, such as a `quit` command read from standard input, which
+    /// is preferable to a signal.  Setting a new action replaces the
+    /// previous one.
     ///
-    /// [`communicate_start()`]: struct.Popen.html#method.communicate_start
-    pub fn communicate_bytes(
-        &mut self,
-        input_data: Option<&[u8]>,
-    ) -> io::Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
-        self.communicate_start(input_data.map(|i| i.to_vec()))
-            .read()
-            .map_err(|e| e.error)
+    /// [`shutdown`]: struct.Popen.html#method.shutdown
+    pub fn set_stop_action(&mut self, action: StopAction) {
+        self.stop_action = Some(action);
     }
 
-    /// Feed the subprocess with data and capture its output as string.
+    /// Stop the subprocess, escalating until it exits.
     ///
-    /// This is a convenience method equivalent to [`communicate_bytes`], but
-    /// with input as `&str` and output as `String`.  Invalid UTF-8 sequences,
-    /// if found, are replaced with the the `U+FFFD` Unicode replacement
-    /// character.
+    /// If a stop action was registered with [`set_stop_action`], it is
+    /// performed first and the process is given `grace` time to exit.
+    /// If it is still running after that, it is stopped with
+    /// [`terminate_with_timeout`], again allowing `grace` time before
+    /// killing it.  Failure of the stop action is not an
+    /// error, it only causes the escalation to proceed immediately.
     ///
-    /// # Panics
+    /// Returns the exit status of the process.  If the process has
+    /// already finished, its exit status is returned immediately.
     ///
-    /// The same as with `communicate_bytes`.
+    /// [`set_stop_action`]: struct.Popen.html#method.set_stop_action
+    /// [`terminate_with_timeout`]: struct.Popen.html#method.terminate_with_timeout
+    pub fn shutdown(&mut self, grace: Duration) -> Result<ExitStatus> {
+        if let Some(status) = self.poll() {
+            return Ok(status);
+        }
+        if let Some(mut action) = self.stop_action.take() {
+            let performed = action.perform(self);
+            self.stop_action = Some(action);
+            if performed.is_ok() {
+                if let Some(status) = self.wait_timeout(grace)? {
+                    return Ok(status);
+                }
+            }
+        }
+        self.terminate_with_timeout(grace)
+    }
+}
+
+/// What to do with a running subprocess when its `Popen` is dropped.
+///
+/// Set with [`PopenConfig::drop_policy`] or [`Popen::set_drop_policy`].
+/// Since drop also runs while unwinding from a panic, a policy that
+/// stops the subprocess ensures that a panicking parent doesn't leave
+/// it running.  Errors encountered while dropping are ignored.
+///
+/// [`PopenConfig::drop_policy`]: struct.PopenConfig.html#structfield.drop_policy
+/// [`Popen::set_drop_policy`]: struct.Popen.html#method.set_drop_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum DropPolicy {
+    /// Wait for the subprocess to finish.  This is the default.
+    #[default]
+    Wait,
+
+    /// Leave the subprocess running, as if the `Popen` were detached.
+    Detach,
+
+    /// Leave the subprocess running, and reap it in a background
+    /// thread once it exits.
     ///
-    /// # Errors
+    /// Unlike with `Detach`, a Unix subprocess doesn't remain a zombie
+    /// until the parent exits.  The thread is started when first
+    /// needed, and checks the subprocesses handed to it periodically.
+    /// On Windows, where nothing is left behind by exited processes,
+    /// this is the same as `Detach`.
+    Reap,
+
+    /// Terminate the subprocess without waiting for it.
+    Terminate,
+
+    /// Kill the subprocess without waiting for it.
+    Kill,
+
+    /// Kill the subprocess and wait for it to exit.
+    KillAndWait,
+
+    /// Terminate the subprocess and wait for it to exit, killing it if
+    /// it doesn't exit within the given grace period, as with
+    /// [`Popen::terminate_with_timeout`].
     ///
-    /// * `Err(::std::io::Error)` if a system call fails
+    /// [`Popen::terminate_with_timeout`]: struct.Popen.html#method.terminate_with_timeout
+    TerminateAndWait(Duration),
+}
+
+/// How a subprocess that reaches its timeout is stopped.
+///
+/// Set with [`PopenConfig::timeout`] and [`PopenConfig::on_timeout`].
+///
+/// [`PopenConfig::timeout`]: struct.PopenConfig.html#structfield.timeout
+/// [`PopenConfig::on_timeout`]: struct.PopenConfig.html#structfield.on_timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum TimeoutAction {
+    /// Terminate the subprocess, as with [`Popen::terminate`].  This is
+    /// the default.
     ///
-    /// [`communicate_bytes`]: struct.Popen.html#method.communicate_bytes
-    pub fn communicate(
-        &mut self,
-        input_data: Option<&str>,
-    ) -> io::Result<(Option<String>, Option<String>)> {
-        self.communicate_start(input_data.map(|s| s.as_bytes().to_vec()))
-            .read_string()
-            .map_err(|e| e.error)
+    /// [`Popen::terminate`]: struct.Popen.html#method.terminate
+    #[default]
+    Terminate,
+
+    /// Kill the subprocess, as with [`Popen::kill`].
+    ///
+    /// [`Popen::kill`]: struct.Popen.html#method.kill
+    Kill,
+}
+
+/// Where to look for a program given without a directory.
+///
+/// Set with [`PopenConfig::executable_search`].
+///
+/// [`PopenConfig::executable_search`]: struct.PopenConfig.html#structfield.executable_search
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ExecutableSearch {
+    /// Let the child search the `PATH` of the current process while
+    /// executing the program, like `execvp()`.  This is the default.
+    #[default]
+    Inherited,
+
+    /// Search the `PATH` of the environment given by
+    /// [`PopenConfig::env`], or of the current process if `env` isn't
+    /// set, before starting the child.
+    ///
+    /// [`PopenConfig::env`]: struct.PopenConfig.html#structfield.env
+    ChildEnv,
+
+    /// Search the given directories, in the format of `PATH`, before
+    /// starting the child.
+    Path(OsString),
+}
+
+impl ExecutableSearch {
+    // The directories to search in the parent, None if the child
+    // searches on its own.
+    fn search_path(&self, env: Option<&[(OsString, OsString)]>) -> Option<OsString> {
+        match *self {
+            ExecutableSearch::Inherited => None,
+            ExecutableSearch::ChildEnv => Some(match env {
+                // the last definition wins, as in the child
+                Some(env) => env
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| is_path_var(name))
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default(),
+                None => env::var_os("PATH").unwrap_or_default(),
+            }),
+            ExecutableSearch::Path(ref path) => Some(path.clone()),
+        }
+    }
+}
+
+fn is_path_var(name: &OsStr) -> bool {
+    if cfg!(windows) {
+        name.to_str()
+            .is_some_and(|name| name.eq_ignore_ascii_case("PATH"))
+    } else {
+        name == "PATH"
+    }
+}
+
+// The program that will be executed for argv[0], as reported to the
+// hooks.
+pub(crate) fn resolve_program(program: &OsStr, config: &PopenConfig) -> OsString {
+    os::resolve_program(program, config)
+}
+
+// Find the program in the directories of search_path, as the child
+// would, but before starting it, so that a missing program can be
+// reported together with the directories searched.  A name with a
+// directory is used as it is.  With a root, the directories and cwd are
+// inside it, as the child sees them after chroot().
+fn find_executable(
+    name: &OsStr,
+    search_path: &OsStr,
+    cwd: Option<&OsStr>,
+    root: Option<&OsStr>,
+) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return Ok(path.to_owned());
+    }
+    let mut searched = vec![];
+    for dir in env::split_paths(search_path) {
+        // an empty entry stands for the current directory
+        let dir = if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir
+        };
+        let mut candidate = dir.join(name);
+        if cfg!(windows) {
+            if candidate.extension().is_none() {
+                candidate.set_extension(env::consts::EXE_EXTENSION);
+            }
+            // CreateProcess resolves a relative program in the current
+            // directory of the parent, so make it absolute
+            if candidate.is_relative() {
+                candidate = env::current_dir()?.join(candidate);
+            }
+        }
+        // on Unix the child changes to cwd before executing the
+        // program, so a relative candidate is relative to cwd
+        let checked = match cwd {
+            Some(cwd) if cfg!(unix) => Path::new(cwd).join(&candidate),
+            _ => candidate.clone(),
+        };
+        let checked = match root {
+            // the current directory defaults to the root
+            Some(root) => Path::new(root).join(checked.strip_prefix("/").unwrap_or(&checked)),
+            None => checked,
+        };
+        if is_executable(&checked) {
+            return Ok(candidate);
+        }
+        searched.push(dir);
+    }
+    Err(PopenError::ExecutableNotFound {
+        name: name.to_owned(),
+        searched,
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(windows)]
+    {
+        metadata.is_file()
+    }
+}
+
+/// Resource whose use by a subprocess can be limited.
+///
+/// Used with [`PopenConfig::rlimits`].  See `setrlimit(2)` for the
+/// exact meaning of each limit.
+///
+/// [`PopenConfig::rlimits`]: ../struct.PopenConfig.html#structfield.rlimits
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Rlimit {
+    /// CPU time in seconds, `RLIMIT_CPU`.
+    Cpu,
+    /// Size of the virtual address space in bytes, `RLIMIT_AS`.
+    AddressSpace,
+    /// Number of open file descriptors, `RLIMIT_NOFILE`.
+    OpenFiles,
+    /// Size of core dumps in bytes, `RLIMIT_CORE`.
+    CoreSize,
+    /// Size of files created in bytes, `RLIMIT_FSIZE`.
+    FileSize,
+    /// Size of the data segment in bytes, `RLIMIT_DATA`.
+    DataSize,
+    /// Size of the stack in bytes, `RLIMIT_STACK`.
+    StackSize,
+    /// Number of processes of the user, `RLIMIT_NPROC`.
+    Processes,
+}
+
+#[cfg(unix)]
+impl Rlimit {
+    pub(crate) fn to_raw(self) -> i32 {
+        (match self {
+            Rlimit::Cpu => libc::RLIMIT_CPU,
+            Rlimit::AddressSpace => libc::RLIMIT_AS,
+            Rlimit::OpenFiles => libc::RLIMIT_NOFILE,
+            Rlimit::CoreSize => libc::RLIMIT_CORE,
+            Rlimit::FileSize => libc::RLIMIT_FSIZE,
+            Rlimit::DataSize => libc::RLIMIT_DATA,
+            Rlimit::StackSize => libc::RLIMIT_STACK,
+            Rlimit::Processes => libc::RLIMIT_NPROC,
+        }) as i32
+    }
+}
+
+/// A function run in the child process between `fork` and `exec`.
+///
+/// Used with [`PopenConfig::pre_exec`].  Cloning a `PreExec` refers to
+/// the same function, which each subprocess calls on its own copy of
+/// the memory of the parent.
+///
+/// [`PopenConfig::pre_exec`]: ../struct.PopenConfig.html#structfield.pre_exec
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct PreExec(Arc<PreExecCell>);
+
+#[cfg(unix)]
+struct PreExecCell(UnsafeCell<Box<dyn FnMut() -> io::Result<()> + Send + Sync>>);
+
+// The function is only called in the child, which has no other
+// threads, so it is never accessed concurrently.
+#[cfg(unix)]
+unsafe impl Sync for PreExecCell {}
+
+#[cfg(unix)]
+impl PreExec {
+    /// Wrap a function to be run in the child process.
+    ///
+    /// # Safety
+    ///
+    /// The function runs in a forked copy of the process, in which
+    /// only the thread that called `fork` exists.  It must only do what
+    /// is safe in that state: locks held by other threads at the time
+    /// of the fork are never released, so it must not allocate memory
+    /// or otherwise take locks, and it should mostly perform
+    /// async-signal-safe system calls.  An error it returns makes the
+    /// spawn fail with `ChildSetupStep::PreExec` and the `errno` of the
+    /// error.
+    pub unsafe fn new(f: impl FnMut() -> io::Result<()> + Send + Sync + 'static) -> PreExec {
+        PreExec(Arc::new(PreExecCell(UnsafeCell::new(Box::new(f)))))
+    }
+
+    // Called in the child only.
+    unsafe fn call(&self) -> io::Result<()> {
+        let PreExecCell(ref f) = *self.0;
+        (*f.get())()
+    }
+}
+
+#[cfg(unix)]
+impl fmt::Debug for PreExec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PreExec(..)")
+    }
+}
+
+/// Convert a socket to a handle that a subprocess can inherit.
+///
+/// The handle can be passed to [`PopenConfig::inherit_handles`] or, as
+/// done by [`Redirection::from_socket`], used as a standard stream.
+/// Unlike the socket itself, it refers to the socket of the base
+/// service provider, which is what the subprocess needs when layered
+/// service providers, such as some firewalls and proxies, are
+/// installed.  Takes over the ownership of the socket.
+///
+/// [`PopenConfig::inherit_handles`]: ../struct.PopenConfig.html#structfield.inherit_handles
+/// [`Redirection::from_socket`]: ../enum.Redirection.html#method.from_socket
+#[cfg(windows)]
+pub fn socket_handle(socket: impl Into<OwnedSocket>) -> io::Result<OwnedHandle> {
+    win32::SocketHandle(socket.into())
+}
+
+/// Credentials for starting a subprocess as another Windows user.
+///
+/// Used with [`PopenConfig::logon`].
+///
+/// [`PopenConfig::logon`]: ../struct.PopenConfig.html#structfield.logon
+#[cfg(windows)]
+#[derive(Clone, Default)]
+pub struct Logon {
+    /// Name of the user.
+    pub user: OsString,
+    /// Domain of the user, or `None` for a user principal name such as
+    /// `user@example.com` or a local account.
+    pub domain: Option<OsString>,
+    /// Password of the user.
+    pub password: OsString,
+    /// Whether to load the profile of the user, making its registry
+    /// hive available to the subprocess.
+    pub load_profile: bool,
+}
+
+#[cfg(windows)]
+impl fmt::Debug for Logon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logon")
+            .field("user", &self.user)
+            .field("domain", &self.domain)
+            .field("password", &"<hidden>")
+            .field("load_profile", &self.load_profile)
+            .finish()
+    }
+}
+
+/// Windows priority class of a subprocess.
+///
+/// Used with [`PopenConfig::priority_class`].
+///
+/// [`PopenConfig::priority_class`]: ../struct.PopenConfig.html#structfield.priority_class
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PriorityClass {
+    /// Runs only when the system is idle, `IDLE_PRIORITY_CLASS`.
+    Idle,
+    /// `BELOW_NORMAL_PRIORITY_CLASS`.
+    BelowNormal,
+    /// The default, `NORMAL_PRIORITY_CLASS`.
+    Normal,
+    /// `ABOVE_NORMAL_PRIORITY_CLASS`.
+    AboveNormal,
+    /// For time-critical tasks, `HIGH_PRIORITY_CLASS`.
+    High,
+    /// Preempts all other processes, including operating system
+    /// processes, `REALTIME_PRIORITY_CLASS`.  Without the
+    /// `SeIncreaseBasePriorityPrivilege` privilege, `High` is used
+    /// instead.
+    Realtime,
+}
+
+#[cfg(windows)]
+impl PriorityClass {
+    pub(crate) fn to_raw(self) -> u32 {
+        match self {
+            PriorityClass::Idle => win32::IDLE_PRIORITY_CLASS,
+            PriorityClass::BelowNormal => win32::BELOW_NORMAL_PRIORITY_CLASS,
+            PriorityClass::Normal => win32::NORMAL_PRIORITY_CLASS,
+            PriorityClass::AboveNormal => win32::ABOVE_NORMAL_PRIORITY_CLASS,
+            PriorityClass::High => win32::HIGH_PRIORITY_CLASS,
+            PriorityClass::Realtime => win32::REALTIME_PRIORITY_CLASS,
+        }
     }
+}
 
-    /// Check whether the process is still running, without blocking or errors.
-    ///
-    /// This checks whether the process is still running and if it
-    /// is still running, `None` is returned, otherwise
-    /// `Some(exit_status)`.  This method is guaranteed not to block
-    /// and is exactly equivalent to
-    /// `wait_timeout(Duration::from_secs(0)).unwrap_or(None)`.
-    pub fn poll(&mut self) -> Option<ExitStatus> {
-        self.wait_timeout(Duration::from_secs(0)).unwrap_or(None)
+/// Linux capability, see `capabilities(7)`.
+///
+/// Used with [`PopenConfig::keep_capabilities`].
+///
+/// [`PopenConfig::keep_capabilities`]: ../struct.PopenConfig.html#structfield.keep_capabilities
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Capability {
+    /// Change file owners, `CAP_CHOWN`.
+    Chown = 0,
+    /// Bypass file permission checks, `CAP_DAC_OVERRIDE`.
+    DacOverride = 1,
+    /// Bypass read and search permission checks, `CAP_DAC_READ_SEARCH`.
+    DacReadSearch = 2,
+    /// Bypass checks requiring file ownership, `CAP_FOWNER`.
+    Fowner = 3,
+    /// Keep set-user-ID bits when modifying files, `CAP_FSETID`.
+    Fsetid = 4,
+    /// Send signals to any process, `CAP_KILL`.
+    Kill = 5,
+    /// Change group IDs, `CAP_SETGID`.
+    Setgid = 6,
+    /// Change user IDs, `CAP_SETUID`.
+    Setuid = 7,
+    /// Modify capability sets, `CAP_SETPCAP`.
+    Setpcap = 8,
+    /// Set immutable and append-only file flags, `CAP_LINUX_IMMUTABLE`.
+    LinuxImmutable = 9,
+    /// Bind to ports below 1024, `CAP_NET_BIND_SERVICE`.
+    NetBindService = 10,
+    /// Make socket broadcasts, `CAP_NET_BROADCAST`.
+    NetBroadcast = 11,
+    /// Configure network interfaces and firewalls, `CAP_NET_ADMIN`.
+    NetAdmin = 12,
+    /// Use raw and packet sockets, `CAP_NET_RAW`.
+    NetRaw = 13,
+    /// Lock memory, `CAP_IPC_LOCK`.
+    IpcLock = 14,
+    /// Bypass permission checks on IPC objects, `CAP_IPC_OWNER`.
+    IpcOwner = 15,
+    /// Load and unload kernel modules, `CAP_SYS_MODULE`.
+    SysModule = 16,
+    /// Perform raw I/O on ports and devices, `CAP_SYS_RAWIO`.
+    SysRawio = 17,
+    /// Call `chroot()`, `CAP_SYS_CHROOT`.
+    SysChroot = 18,
+    /// Trace any process, `CAP_SYS_PTRACE`.
+    SysPtrace = 19,
+    /// Configure process accounting, `CAP_SYS_PACCT`.
+    SysPacct = 20,
+    /// Perform a range of administration tasks, `CAP_SYS_ADMIN`.
+    SysAdmin = 21,
+    /// Reboot the system, `CAP_SYS_BOOT`.
+    SysBoot = 22,
+    /// Raise priorities and scheduling policies, `CAP_SYS_NICE`.
+    SysNice = 23,
+    /// Override resource limits, `CAP_SYS_RESOURCE`.
+    SysResource = 24,
+    /// Set the system clock, `CAP_SYS_TIME`.
+    SysTime = 25,
+    /// Configure terminals, `CAP_SYS_TTY_CONFIG`.
+    SysTtyConfig = 26,
+    /// Create device files, `CAP_MKNOD`.
+    Mknod = 27,
+    /// Take file leases, `CAP_LEASE`.
+    Lease = 28,
+    /// Write to the audit log, `CAP_AUDIT_WRITE`.
+    AuditWrite = 29,
+    /// Configure auditing, `CAP_AUDIT_CONTROL`.
+    AuditControl = 30,
+    /// Set file capabilities, `CAP_SETFCAP`.
+    Setfcap = 31,
+    /// Override mandatory access control, `CAP_MAC_OVERRIDE`.
+    MacOverride = 32,
+    /// Configure mandatory access control, `CAP_MAC_ADMIN`.
+    MacAdmin = 33,
+    /// Perform privileged `syslog()` operations, `CAP_SYSLOG`.
+    Syslog = 34,
+    /// Trigger wakeups of the system, `CAP_WAKE_ALARM`.
+    WakeAlarm = 35,
+    /// Block system suspend, `CAP_BLOCK_SUSPEND`.
+    BlockSuspend = 36,
+    /// Read the audit log, `CAP_AUDIT_READ`.
+    AuditRead = 37,
+    /// Use performance monitoring, `CAP_PERFMON`.
+    Perfmon = 38,
+    /// Use privileged BPF operations, `CAP_BPF`.
+    Bpf = 39,
+    /// Checkpoint and restore processes, `CAP_CHECKPOINT_RESTORE`.
+    CheckpointRestore = 40,
+}
+
+/// Linux namespace in which a subprocess can be isolated.
+///
+/// Used with [`PopenConfig::unshare`].  See `namespaces(7)`.
+///
+/// [`PopenConfig::unshare`]: ../struct.PopenConfig.html#structfield.unshare
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Namespace {
+    /// User and group IDs, `CLONE_NEWUSER`.  Doesn't require
+    /// privileges, and gives the subprocess the capabilities needed to
+    /// create the other namespaces.
+    User,
+    /// Mount points, `CLONE_NEWNS`.
+    Mount,
+    /// Process IDs, `CLONE_NEWPID`.
+    Pid,
+    /// Network devices, addresses and ports, `CLONE_NEWNET`.
+    Network,
+    /// Host name and domain name, `CLONE_NEWUTS`.
+    Uts,
+    /// System V IPC objects and POSIX message queues, `CLONE_NEWIPC`.
+    Ipc,
+}
+
+#[cfg(target_os = "linux")]
+impl Namespace {
+    pub(crate) fn to_raw(self) -> i32 {
+        match self {
+            Namespace::User => libc::CLONE_NEWUSER,
+            Namespace::Mount => libc::CLONE_NEWNS,
+            Namespace::Pid => libc::CLONE_NEWPID,
+            Namespace::Network => libc::CLONE_NEWNET,
+            Namespace::Uts => libc::CLONE_NEWUTS,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+        }
     }
+}
 
-    /// Wait for the process to finish, and return its exit status.
-    ///
-    /// If the process has already finished, it will exit immediately,
-    /// returning the exit status.  Calling `wait` after that will
-    /// return the cached exit status without executing any system
-    /// calls.
-    ///
-    /// # Errors
-    ///
-    /// Returns an `Err` if a system call fails in an unpredicted way.
-    /// This should not happen in normal usage.
-    pub fn wait(&mut self) -> Result<ExitStatus> {
-        self.os_wait()
+/// Linux scheduling policy of a subprocess.
+///
+/// Set with [`PopenConfig::sched_policy`].  See `sched(7)` for the
+/// meaning of the policies.
+///
+/// [`PopenConfig::sched_policy`]: ../struct.PopenConfig.html#structfield.sched_policy
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SchedPolicy {
+    /// The standard time-sharing policy, `SCHED_OTHER`.
+    Other,
+
+    /// `SCHED_BATCH`, for CPU-intensive non-interactive work, which is
+    /// scheduled with a slight penalty.
+    Batch,
+
+    /// `SCHED_IDLE`, for work that runs only when the system is
+    /// otherwise idle.
+    Idle,
+
+    /// `SCHED_FIFO`, a real-time policy with the given priority from 1
+    /// to 99.  Requires privileges.
+    Fifo(u8),
+
+    /// `SCHED_RR`, a round-robin real-time policy with the given
+    /// priority from 1 to 99.  Requires privileges.
+    RoundRobin(u8),
+}
+
+#[cfg(target_os = "linux")]
+impl SchedPolicy {
+    // The policy and priority as passed to sched_setscheduler().
+    pub(crate) fn to_raw(self) -> (i32, i32) {
+        match self {
+            SchedPolicy::Other => (libc::SCHED_OTHER, 0),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
+            SchedPolicy::Fifo(priority) => (libc::SCHED_FIFO, priority.into()),
+            SchedPolicy::RoundRobin(priority) => (libc::SCHED_RR, priority.into()),
+        }
     }
+}
 
-    /// Wait for the process to finish, timing out after the specified duration.
-    ///
-    /// This function behaves like `wait()`, except that the caller
-    /// will be blocked for roughly no longer than `dur`.  It returns
-    /// `Ok(None)` if the timeout is known to have elapsed.
+/// Action that politely asks a subprocess to exit.
+///
+/// Used by [`Popen::shutdown`] before escalating to terminating and
+/// killing the process.  Register it with [`Popen::set_stop_action`].
+///
+/// [`Popen::shutdown`]: struct.Popen.html#method.shutdown
+/// [`Popen::set_stop_action`]: struct.Popen.html#method.set_stop_action
+#[non_exhaustive]
+pub enum StopAction {
+    /// Write the data to the subprocess's standard input.
     ///
-    /// On Unix-like systems, timeout is implemented by calling
-    /// `waitpid(..., WNOHANG)` in a loop with adaptive sleep
-    /// intervals between iterations.
-    pub fn wait_timeout(&mut self, dur: Duration) -> Result<Option<ExitStatus>> {
-        self.os_wait_timeout(dur)
-    }
+    /// Requires standard input to have been redirected to
+    /// `Redirection::Pipe`.
+    WriteStdin(Vec<u8>),
 
-    /// Terminate the subprocess.
+    /// Close the subprocess's standard input, so that it reads
+    /// end-of-file.
+    CloseStdin,
+
+    /// Create a sentinel file at the given path.
     ///
-    /// On Unix-like systems, this sends the `SIGTERM` signal to the
-    /// child process, which can be caught by the child in order to
-    /// perform cleanup before exiting.  On Windows, it is equivalent
-    /// to `kill()`.
-    pub fn terminate(&mut self) -> io::Result<()> {
-        self.os_terminate()
+    /// The file is created if it doesn't exist, and left unchanged if
+    /// it does.
+    TouchFile(PathBuf),
+
+    /// Send the given signal to the subprocess.
+    #[cfg(unix)]
+    Signal(i32),
+
+    /// Invoke the function with the `Popen` of the subprocess.
+    Custom(Box<dyn FnMut(&mut Popen) -> io::Result<()> + Send>),
+}
+
+impl StopAction {
+    fn perform(&mut self, popen: &mut Popen) -> io::Result<()> {
+        match self {
+            StopAction::WriteStdin(data) => match popen.stdin {
+                Some(ref mut stdin) => {
+                    stdin.write_all(data)?;
+                    stdin.flush()
+                }
+                None => Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "stdin is not redirected to a pipe",
+                )),
+            },
+            StopAction::CloseStdin => {
+                popen.stdin.take();
+                Ok(())
+            }
+            StopAction::TouchFile(path) => {
+                OpenOptions::new().create(true).append(true).open(path)?;
+                Ok(())
+            }
+            #[cfg(unix)]
+            StopAction::Signal(signal) => popen.send_signal(*signal),
+            StopAction::Custom(f) => f(popen),
+        }
     }
+}
 
-    /// Kill the subprocess.
-    ///
-    /// On Unix-like systems, this sends the `SIGKILL` signal to the
-    /// child process, which cannot be caught.
-    ///
-    /// On Windows, it invokes [`TerminateProcess`] on the process
-    /// handle with equivalent semantics.
-    ///
-    /// [`TerminateProcess`]: https://msdn.microsoft.com/en-us/library/windows/desktop/ms686714(v=vs.85).aspx
-    pub fn kill(&mut self) -> io::Result<()> {
-        self.os_kill()
+impl fmt::Debug for StopAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopAction::WriteStdin(data) => f.debug_tuple("WriteStdin").field(data).finish(),
+            StopAction::CloseStdin => f.write_str("CloseStdin"),
+            StopAction::TouchFile(path) => f.debug_tuple("TouchFile").field(path).finish(),
+            #[cfg(unix)]
+            StopAction::Signal(signal) => f.debug_tuple("Signal").field(signal).finish(),
+            StopAction::Custom(_) => f.write_str("Custom(..)"),
+        }
     }
 }
 
@@ -684,84 +4188,384 @@ mod os {
     use super::*;
 
     use crate::posix;
+    use std::cell::Cell;
     use std::collections::HashSet;
-    use std::ffi::OsString;
+    #[cfg(target_os = "openbsd")]
+    use std::ffi::CString;
+    use std::ffi::{CStr, OsString};
     use std::fs::File;
     use std::io::{self, Read, Write};
+    use std::mem;
     use std::os::unix::io::AsRawFd;
     use std::time::{Duration, Instant};
 
     use crate::os_common::ExitStatus;
     use crate::unix::PopenExt;
 
-    pub type ExtChildState = ();
+    // File descriptor that becomes readable when the running child exits,
+    // where supported: a pidfd on Linux, a kqueue on macOS and FreeBSD.
+    pub type ExtChildState = Option<File>;
+
+    // Step code sent over the exec pipe when executing the program
+    // itself has failed.
+    const EXEC_STEP_CODE: u8 = 0xff;
+
+    // Tell the parent which step of the child setup has failed, and
+    // with what error, over the exec pipe.
+    fn report_exec_failure(pipe: &File, step: Option<ChildSetupStep>, error_code: u32) {
+        let step_code = match step {
+            Some(step) => step as u8,
+            None => EXEC_STEP_CODE,
+        };
+        let mut pipe = pipe;
+        pipe.write_all(&[
+            step_code,
+            error_code as u8,
+            (error_code >> 8) as u8,
+            (error_code >> 16) as u8,
+            (error_code >> 24) as u8,
+        ])
+        .ok();
+    }
+
+    // The read end of the exec pipe, with what is needed to report a
+    // failure to execute the program.  Kept in the Popen of a child
+    // started suspended until it is resumed.
+    #[derive(Debug)]
+    pub struct PendingExec {
+        pipe: File,
+        argv: Vec<OsString>,
+        program: OsString,
+        cwd: Option<OsString>,
+        elsewhere: bool,
+    }
+
+    impl PendingExec {
+        // Wait for the child to execute the program, or to report
+        // failure to set up or execute it.
+        pub fn finish(mut self) -> Result<()> {
+            let mut error_buf = [0u8; 5];
+            let read_cnt = self.pipe.read(&mut error_buf)?;
+            if read_cnt == 0 {
+                Ok(())
+            } else if read_cnt == 5 {
+                let error_code: u32 = error_buf[1] as u32
+                    | (error_buf[2] as u32) << 8
+                    | (error_buf[3] as u32) << 16
+                    | (error_buf[4] as u32) << 24;
+                if error_buf[0] == EXEC_STEP_CODE {
+                    return Err(exec_error(
+                        io::Error::from_raw_os_error(error_code as i32),
+                        &self.argv,
+                        &self.program,
+                        self.cwd.as_deref(),
+                        self.elsewhere,
+                    ));
+                }
+                match ChildSetupStep::from_code(error_buf[0]) {
+                    Some(step) => Err(PopenError::ChildSetupFailed {
+                        step,
+                        errno: error_code as i32,
+                    }),
+                    None => Err(PopenError::LogicError("invalid step from exec pipe")),
+                }
+            } else {
+                Err(PopenError::LogicError("invalid read_count from exec pipe"))
+            }
+        }
+    }
+
+    // Armed while the child sets itself up.  If the setup panics, e.g.
+    // in a pre_exec closure, the child reports it and exits while
+    // unwinding, rather than unwinding into the code of the parent.
+    struct ExitOnUnwind<'a> {
+        pipe: &'a File,
+        step: &'a Cell<Option<ChildSetupStep>>,
+    }
+
+    impl Drop for ExitOnUnwind<'_> {
+        fn drop(&mut self) {
+            report_exec_failure(self.pipe, self.step.get(), 0);
+            posix::_exit(127);
+        }
+    }
 
     impl super::PopenOs for Popen {
-        fn os_start(&mut self, argv: Vec<OsString>, config: PopenConfig) -> Result<()> {
+        fn os_start(&mut self, argv: Vec<OsString>, mut config: PopenConfig) -> Result<()> {
+            // The slave side of the pseudo terminal becomes the
+            // standard streams and the controlling terminal of the
+            // child, in a new session.
+            let pty = match config.pty {
+                Some(size) => {
+                    let (pty, slave) = Pty::open(size, config.pty_raw, config.pty_echo)?;
+                    config.stdin = Redirection::File(slave.try_clone()?);
+                    config.stdout = Redirection::File(slave.try_clone()?);
+                    config.stderr = Redirection::File(slave.try_clone()?);
+                    config.setsid = true;
+                    config.controlling_tty = Some(slave);
+                    Some(pty)
+                }
+                None => None,
+            };
+            // Socket activation passes the sockets as descriptors 3 and
+            // up, and tells the child how many there are, and that they
+            // are meant for it.
+            let listen_pid = if config.listen_fds.is_empty() {
+                None
+            } else {
+                let count = config.listen_fds.len();
+                for (i, fd) in config.listen_fds.drain(..).enumerate() {
+                    config.fd_map.push((3 + i as RawFd, fd));
+                }
+                let env = config.env.get_or_insert_with(|| env::vars_os().collect());
+                env.retain(|(name, _)| name != "LISTEN_PID" && name != "LISTEN_FDNAMES");
+                env.push(("LISTEN_FDS".into(), count.to_string().into()));
+                Some("LISTEN_PID")
+            };
+            let notifications = if config.notify_socket {
+                let notifications = Notifications::open()?;
+                let env = config.env.get_or_insert_with(|| env::vars_os().collect());
+                env.push(("NOTIFY_SOCKET".into(), notifications.path().into()));
+                Some(notifications)
+            } else {
+                None
+            };
             let mut exec_fail_pipe = posix::pipe()?;
-            set_inheritable(&exec_fail_pipe.0, false)?;
-            set_inheritable(&exec_fail_pipe.1, false)?;
+            // Move the descriptors to pass, and the end of the exec pipe
+            // still used by the child after passing them, above those
+            // they are mapped to, so that dup2() in the child can't
+            // overwrite any of them.
+            let fd_map = match config.fd_map.iter().map(|&(child_fd, _)| child_fd).max() {
+                Some(max_fd) => {
+                    exec_fail_pipe.1 = posix::dup_above(&exec_fail_pipe.1, max_fd + 1)?.into();
+                    // the child gets the duplicates, so keep it from
+                    // inheriting the originals as well
+                    for (_, fd) in &config.fd_map {
+                        posix::set_cloexec(fd.as_raw_fd(), true)?;
+                    }
+                    config
+                        .fd_map
+                        .iter()
+                        .map(|(child_fd, fd)| Ok((*child_fd, posix::dup_above(fd, max_fd + 1)?)))
+                        .collect::<io::Result<Vec<_>>>()?
+                }
+                None => vec![],
+            };
+            // Pipe over which the intermediate process reports the PID
+            // of the daemon.
+            let daemon_pipe = if config.daemonize {
+                Some(posix::pipe()?)
+            } else {
+                None
+            };
+            #[cfg(target_os = "linux")]
+            let resize_pipes = [
+                matches!(config.stdout, Redirection::Pipe),
+                matches!(config.stderr, Redirection::Pipe),
+            ];
             {
                 let child_ends = self.setup_streams(config.stdin, config.stdout, config.stderr)?;
+                #[cfg(target_os = "linux")]
+                if let Some(size) = config.pipe_size {
+                    let pipes = [&self.stdout, &self.stderr];
+                    for (pipe, &resize) in pipes.iter().zip(&resize_pipes) {
+                        match pipe {
+                            Some(pipe) if resize => {
+                                match posix::set_pipe_size(pipe.as_raw_fd(), size) {
+                                    // over the pipe buffer limit of
+                                    // unprivileged users, the pipe keeps
+                                    // its size
+                                    Err(ref e) if e.raw_os_error() == Some(posix::EPERM) => (),
+                                    result => {
+                                        result?;
+                                    }
+                                }
+                            }
+                            _ => (),
+                        }
+                    }
+                }
                 let child_env = config.env.as_deref().map(format_env);
                 let cmd_to_exec = config.executable.as_ref().unwrap_or(&argv[0]);
-                let just_exec = posix::prep_exec(cmd_to_exec, &argv, child_env.as_deref())?;
-                unsafe {
+                let mut just_exec =
+                    posix::prep_exec(cmd_to_exec, &argv, child_env.as_deref(), listen_pid)?;
+                // converted here, as the child must not allocate
+                let cwd = match config.cwd {
+                    Some(ref path) => Some(posix::os_to_cstring(path)?),
+                    None => None,
+                };
+                let chroot = match config.chroot {
+                    Some(ref path) => Some(posix::os_to_cstring(path)?),
+                    None => None,
+                };
+                #[cfg(target_os = "linux")]
+                let (uid_map, gid_map) = (
+                    config.uid_map.as_deref().map(format_id_map),
+                    config.gid_map.as_deref().map(format_id_map),
+                );
+                #[cfg(target_os = "linux")]
+                let cgroup_procs = match config.cgroup {
+                    Some(ref path) => {
+                        let procs = std::path::Path::new(path).join("cgroup.procs");
+                        Some(posix::os_to_cstring(procs.as_os_str())?)
+                    }
+                    None => None,
+                };
+                #[cfg(target_os = "linux")]
+                let landlock_ruleset = match config.landlock {
+                    Some(ref rules) => rules.create_ruleset()?,
+                    None => None,
+                };
+                #[cfg(target_os = "openbsd")]
+                let pledge = match config.pledge {
+                    Some(ref promises) => Some(posix::os_to_cstring(OsStr::new(promises))?),
+                    None => None,
+                };
+                #[cfg(target_os = "openbsd")]
+                let unveil = config
+                    .unveil
+                    .iter()
+                    .map(|(path, permissions)| -> io::Result<_> {
+                        Ok((
+                            posix::os_to_cstring(path)?,
+                            posix::os_to_cstring(OsStr::new(permissions))?,
+                        ))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                let options = ChildOptions {
+                    cwd: cwd.as_deref(),
+                    cwd_fd: config.cwd_fd.as_ref().map(OwnedFd::as_fd),
+                    chroot: chroot.as_deref(),
+                    setuid: config.setuid,
+                    setgid: config.setgid,
+                    setgroups: config.setgroups.as_deref(),
+                    rlimits: &config.rlimits,
+                    setpgid: config.setpgid,
+                    pgid: config.pgid,
+                    setsid: config.setsid,
+                    controlling_tty: config.controlling_tty.as_ref(),
+                    umask: config.umask,
+                    pre_exec: &config.pre_exec,
+                    fd_map: &fd_map,
+                    nice: config.nice,
+                    #[cfg(target_os = "linux")]
+                    sched_policy: config.sched_policy,
+                    #[cfg(target_os = "linux")]
+                    cpu_affinity: config.cpu_affinity.as_deref(),
+                    #[cfg(target_os = "linux")]
+                    oom_score_adj: config.oom_score_adj,
+                    #[cfg(target_os = "openbsd")]
+                    pledge: pledge.as_deref(),
+                    #[cfg(target_os = "openbsd")]
+                    unveil: &unveil,
+                    #[cfg(target_os = "linux")]
+                    keep_capabilities: config
+                        .keep_capabilities
+                        .as_ref()
+                        .map(|caps| caps.iter().fold(0, |mask, &cap| mask | 1 << cap as u32)),
+                    #[cfg(target_os = "linux")]
+                    cgroup_procs: cgroup_procs.as_deref(),
+                    #[cfg(target_os = "linux")]
+                    seccomp: config.seccomp.as_ref().map(|f| f.program()),
+                    #[cfg(target_os = "linux")]
+                    landlock_ruleset: landlock_ruleset.as_ref().map(|fd| fd.as_raw_fd()),
+                    #[cfg(target_os = "linux")]
+                    unshare: config
+                        .unshare
+                        .iter()
+                        .fold(0, |flags, ns| flags | ns.to_raw()),
+                    #[cfg(target_os = "linux")]
+                    uid_map: uid_map.as_deref(),
+                    #[cfg(target_os = "linux")]
+                    gid_map: gid_map.as_deref(),
+                    start_suspended: config.start_suspended,
+                    daemon_pipe: daemon_pipe.as_ref().map(|pipe| &pipe.1),
+                };
+                let reaper_guard = crate::reaper::spawn_guard();
+                // Start the program with posix_spawn() when it can
+                // apply the options, which avoids copying the page
+                // tables of a large parent.
+                let os_cwd = config.cwd.as_deref();
+                let spawned = match options.spawn_options(&child_ends) {
+                    Some(spawn_options) => just_exec
+                        .spawn(&spawn_options)
+                        .map_err(|e| spawn_error(e, &argv, cmd_to_exec, os_cwd, options.cwd_fd))?,
+                    None => None,
+                };
+                // Set up and execute the program in the child.  The
+                // child only borrows just_exec, so that after
+                // clone_vfork() it doesn't free the memory it shares with
+                // the parent, which drops just_exec as usual.
+                let mut run_child = || {
+                    // The step being performed, None once all
+                    // setup is done and the program is executed.
+                    let step = Cell::new(None);
+                    let guard = ExitOnUnwind {
+                        pipe: &exec_fail_pipe.1,
+                        step: &step,
+                    };
+                    let result = Popen::do_exec(|| just_exec.exec(), &child_ends, &options, &step);
+                    mem::forget(guard);
+                    // If we are here, it means that setup or exec has
+                    // failed.  Notify the parent before exiting.
+                    let error_code = match result {
+                        Ok(()) => unreachable!(),
+                        Err(e) => e.raw_os_error().unwrap_or(-1),
+                    } as u32;
+                    report_exec_failure(&exec_fail_pipe.1, step.get(), error_code);
+                };
+                let child_pid = match spawned {
+                    Some(child_pid) => child_pid,
+                    // unsafe for the same reasons as fork(), and because
+                    // the child shares the memory of the parent
+                    #[cfg(target_os = "linux")]
+                    None if config.vfork => unsafe { posix::clone_vfork(&mut run_child)? },
                     // unsafe because after the call to fork() the
                     // child is not allowed to allocate
-                    match posix::fork()? {
-                        Some(child_pid) => {
-                            self.child_state = Running {
-                                pid: child_pid,
-                                ext: (),
-                            };
-                        }
+                    None => match unsafe { posix::fork()? } {
+                        Some(child_pid) => child_pid,
                         None => {
-                            drop(exec_fail_pipe.0);
-                            let result = Popen::do_exec(
-                                just_exec,
-                                child_ends,
-                                config.cwd.as_deref(),
-                                config.setuid,
-                                config.setgid,
-                                config.setpgid,
-                            );
-                            // If we are here, it means that exec has failed.  Notify
-                            // the parent and exit.
-                            let error_code = match result {
-                                Ok(()) => unreachable!(),
-                                Err(e) => e.raw_os_error().unwrap_or(-1),
-                            } as u32;
-                            exec_fail_pipe
-                                .1
-                                .write_all(&[
-                                    error_code as u8,
-                                    (error_code >> 8) as u8,
-                                    (error_code >> 16) as u8,
-                                    (error_code >> 24) as u8,
-                                ])
-                                .ok();
+                            run_child();
                             posix::_exit(127);
                         }
-                    }
+                    },
+                };
+                if let Some(reaper_guard) = reaper_guard {
+                    reaper_guard.track(child_pid);
                 }
+                self.child_state = Running {
+                    pid: child_pid,
+                    ext: open_exit_fd(child_pid),
+                };
             }
             drop(exec_fail_pipe.1);
-            let mut error_buf = [0u8; 4];
-            let read_cnt = exec_fail_pipe.0.read(&mut error_buf)?;
-            if read_cnt == 0 {
-                Ok(())
-            } else if read_cnt == 4 {
-                let error_code: u32 = error_buf[0] as u32
-                    | (error_buf[1] as u32) << 8
-                    | (error_buf[2] as u32) << 16
-                    | (error_buf[3] as u32) << 24;
-                Err(PopenError::from(io::Error::from_raw_os_error(
-                    error_code as i32,
-                )))
-            } else {
-                Err(PopenError::LogicError("invalid read_count from exec pipe"))
+            // Close the parent's slave side, so that reading the master
+            // side ends when the child closes the terminal.
+            drop(config.controlling_tty.take());
+            if let Some(pty) = pty {
+                self.stdin = Some(pty.master()?);
+                self.stdout = Some(pty.master()?);
+                self.pty = Some(pty);
+            }
+            self.notifications = notifications;
+            if let Some((mut reader, writer)) = daemon_pipe {
+                drop(writer);
+                self.finish_daemonize(&mut reader);
             }
+            let pending = PendingExec {
+                pipe: exec_fail_pipe.0,
+                program: config.executable.unwrap_or_else(|| argv[0].clone()),
+                argv,
+                cwd: config.cwd,
+                elsewhere: config.chroot.is_some() || config.cwd_fd.is_some(),
+            };
+            if config.start_suspended && posix::wait_stop_nowait(self.pid().unwrap())? {
+                // The child stopped just before executing the program,
+                // so the exec pipe won't be closed until it is resumed.
+                self.pending_exec = Some(pending);
+                return Ok(());
+            }
+            pending.finish()
         }
 
         fn os_wait(&mut self) -> Result<ExitStatus> {
@@ -783,6 +4587,8 @@ mod os {
             let mut delay = Duration::from_millis(1);
 
             loop {
+                #[cfg(feature = "sigchld")]
+                let generation = crate::sigchld::generation();
                 self.waitpid(false)?;
                 if let Finished(exit_status) = self.child_state {
                     return Ok(Some(exit_status));
@@ -792,6 +4598,25 @@ mod os {
                     return Ok(None);
                 }
                 let remaining = deadline.duration_since(now);
+                #[cfg(feature = "sigchld")]
+                {
+                    if crate::sigchld::is_enabled() {
+                        // Sleep until some child exits, then check ours.
+                        crate::sigchld::wait_change(generation, Some(deadline));
+                        continue;
+                    }
+                }
+                if let Running {
+                    ext: Some(ref exit_fd),
+                    ..
+                } = self.child_state
+                {
+                    // The fd becomes readable when the child exits, so we
+                    // can sleep exactly until then.
+                    let mut fds = [posix::PollFd::new(Some(exit_fd), posix::POLLIN)];
+                    posix::poll(&mut fds, Some(remaining))?;
+                    continue;
+                }
                 ::std::thread::sleep(min(delay, remaining));
                 delay = min(delay * 2, Duration::from_millis(100));
             }
@@ -806,6 +4631,215 @@ mod os {
         }
     }
 
+    pub fn send_signal(popen: &Popen, signal: Signal) -> io::Result<()> {
+        PopenExt::send_signal(popen, signal.number())
+    }
+
+    pub fn suspend(popen: &Popen) -> io::Result<()> {
+        PopenExt::send_signal(popen, posix::SIGSTOP)
+    }
+
+    pub fn peek_status(popen: &Popen) -> io::Result<Option<ExitStatus>> {
+        let pid = popen.pid().unwrap();
+        match posix::peek_exit_status(pid) {
+            Err(ref e) if e.raw_os_error() == Some(posix::ECHILD) => {
+                // Not our child, or already reaped by someone else.
+                if popen.adopted && popen.adopted_running(pid)? {
+                    Ok(None)
+                } else {
+                    Ok(Some(ExitStatus::Undetermined))
+                }
+            }
+            result => result,
+        }
+    }
+
+    pub fn sample_usage(popen: &Popen) -> io::Result<UsageSample> {
+        posix::sample_usage(popen.pid().unwrap())
+    }
+
+    pub fn resume(popen: &Popen) -> io::Result<()> {
+        PopenExt::send_signal(popen, posix::SIGCONT)
+    }
+
+    pub fn terminate_group(pgid: u32) -> io::Result<()> {
+        signal_group(pgid, posix::SIGTERM)
+    }
+
+    pub fn kill_group(pgid: u32) -> io::Result<()> {
+        signal_group(pgid, posix::SIGKILL)
+    }
+
+    // Kill the descendants of the process, which is left stopped.
+    pub fn kill_tree(root: u32) -> io::Result<()> {
+        fn ignore_gone(result: io::Result<()>) -> io::Result<()> {
+            match result {
+                Err(ref e) if e.raw_os_error() == Some(posix::ESRCH) => Ok(()),
+                result => result,
+            }
+        }
+        // Stop the processes top-down, collecting them in the order
+        // in which they were found.
+        let mut tree = vec![];
+        let mut stop_tree = || -> io::Result<()> {
+            ignore_gone(posix::kill(root, posix::SIGSTOP))?;
+            tree.push(root);
+            let mut i = 0;
+            while i < tree.len() {
+                for child in posix::child_pids(tree[i])? {
+                    if !tree.contains(&child) {
+                        ignore_gone(posix::kill(child, posix::SIGSTOP))?;
+                        tree.push(child);
+                    }
+                }
+                i += 1;
+            }
+            Ok(())
+        };
+        if let Err(e) = stop_tree() {
+            for &pid in &tree {
+                posix::kill(pid, posix::SIGCONT).ok();
+            }
+            return Err(e);
+        }
+        // Try every process, and resume those that can't be killed
+        // rather than leave them stopped.
+        let mut result = Ok(());
+        for &pid in tree[1..].iter().rev() {
+            if let Err(e) = ignore_gone(posix::kill(pid, posix::SIGKILL)) {
+                posix::kill(pid, posix::SIGCONT).ok();
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    fn signal_group(pgid: u32, signal: i32) -> io::Result<()> {
+        match posix::killpg(pgid, signal) {
+            // the group no longer has any members
+            Err(ref e) if e.raw_os_error() == Some(posix::ESRCH) => Ok(()),
+            result => result,
+        }
+    }
+
+    impl Popen {
+        // Reap the intermediate process started by daemonize and switch
+        // to tracking the daemon, whose PID it has sent.  If it has
+        // failed before starting the daemon, the error is reported over
+        // the exec pipe.
+        fn finish_daemonize(&mut self, daemon_pipe: &mut File) {
+            let intermediate = self.pid().unwrap();
+            let mut pid_buf = [0u8; 4];
+            let daemon_pid = daemon_pipe
+                .read_exact(&mut pid_buf)
+                .ok()
+                .map(|_| u32::from_ne_bytes(pid_buf));
+            let status = posix::waitpid(intermediate, 0)
+                .map(|(_, status)| status)
+                .unwrap_or(ExitStatus::Undetermined);
+            crate::reaper::untrack(intermediate);
+            self.child_state = match daemon_pid {
+                Some(pid) => Running { pid, ext: None },
+                None => Finished(status),
+            };
+        }
+
+        // The fd that becomes readable when the running child exits.
+        #[cfg(any(feature = "async", target_os = "linux"))]
+        pub(crate) fn exit_fd(&self) -> Option<&File> {
+            match self.child_state {
+                Running {
+                    ext: Some(ref exit_fd),
+                    ..
+                } => Some(exit_fd),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(any(feature = "async", target_os = "linux"))]
+    pub fn waitable_fd(popen: &Popen) -> Option<BorrowedFd<'_>> {
+        popen.exit_fd().map(AsFd::as_fd)
+    }
+
+    #[cfg(not(any(feature = "async", target_os = "linux")))]
+    pub fn waitable_fd(_popen: &Popen) -> Option<BorrowedFd<'_>> {
+        None
+    }
+
+    pub fn wait_any(
+        children: &mut [&mut Popen],
+        deadline: Option<Instant>,
+    ) -> Result<Option<(usize, ExitStatus)>> {
+        use std::cmp::min;
+
+        // double delay at every iteration, maxing at 100ms
+        let mut delay = Duration::from_millis(1);
+
+        loop {
+            #[cfg(feature = "sigchld")]
+            let generation = crate::sigchld::generation();
+            for (i, child) in children.iter_mut().enumerate() {
+                child.waitpid(false)?;
+                if let Finished(exit_status) = child.child_state {
+                    return Ok(Some((i, exit_status)));
+                }
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    Some(deadline.duration_since(now))
+                }
+                None => None,
+            };
+            #[cfg(feature = "sigchld")]
+            {
+                if crate::sigchld::is_enabled() {
+                    crate::sigchld::wait_change(generation, deadline);
+                    continue;
+                }
+            }
+            // All the children are running, so if each has an exit fd,
+            // we can sleep until one of them exits.
+            let exit_fds: Option<Vec<_>> = children
+                .iter()
+                .map(|child| match child.child_state {
+                    Running {
+                        ext: Some(ref exit_fd),
+                        ..
+                    } => Some(posix::PollFd::new(Some(exit_fd), posix::POLLIN)),
+                    _ => None,
+                })
+                .collect();
+            if let Some(mut fds) = exit_fds {
+                posix::poll(&mut fds, remaining)?;
+                continue;
+            }
+            ::std::thread::sleep(match remaining {
+                Some(remaining) => min(delay, remaining),
+                None => delay,
+            });
+            delay = min(delay * 2, Duration::from_millis(100));
+        }
+    }
+
+    // Format UID or GID mappings as written to /proc/PID/uid_map.
+    #[cfg(target_os = "linux")]
+    fn format_id_map(map: &[(u32, u32, u32)]) -> Vec<u8> {
+        map.iter()
+            .map(|(inside, outside, count)| format!("{} {} {}\n", inside, outside, count))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn c_str(bytes: &'static [u8]) -> &'static CStr {
+        CStr::from_bytes_with_nul(bytes).unwrap()
+    }
+
     fn format_env(env: &[(OsString, OsString)]) -> Vec<OsString> {
         // Convert Vec of (key, val) pairs to Vec of key=val, as required by
         // execvpe.  Eliminate dups, in favor of later-appearing entries.
@@ -825,14 +4859,264 @@ mod os {
         formatted
     }
 
+    // Options applied in the child between fork and exec.
+    struct ChildOptions<'a> {
+        cwd: Option<&'a CStr>,
+        cwd_fd: Option<BorrowedFd<'a>>,
+        chroot: Option<&'a CStr>,
+        setuid: Option<u32>,
+        setgid: Option<u32>,
+        setgroups: Option<&'a [u32]>,
+        rlimits: &'a [(Rlimit, u64, u64)],
+        setpgid: bool,
+        pgid: Option<u32>,
+        setsid: bool,
+        controlling_tty: Option<&'a File>,
+        umask: Option<u32>,
+        pre_exec: &'a [PreExec],
+        fd_map: &'a [(RawFd, OwnedFd)],
+        nice: Option<i32>,
+        #[cfg(target_os = "linux")]
+        sched_policy: Option<SchedPolicy>,
+        #[cfg(target_os = "linux")]
+        cpu_affinity: Option<&'a [usize]>,
+        #[cfg(target_os = "linux")]
+        oom_score_adj: Option<i32>,
+        #[cfg(target_os = "openbsd")]
+        pledge: Option<&'a CStr>,
+        #[cfg(target_os = "openbsd")]
+        unveil: &'a [(CString, CString)],
+        // bit mask of capability numbers
+        #[cfg(target_os = "linux")]
+        keep_capabilities: Option<u64>,
+        #[cfg(target_os = "linux")]
+        cgroup_procs: Option<&'a CStr>,
+        #[cfg(target_os = "linux")]
+        seccomp: Option<&'a [BpfInstruction]>,
+        #[cfg(target_os = "linux")]
+        landlock_ruleset: Option<i32>,
+        // CLONE_NEW* flags
+        #[cfg(target_os = "linux")]
+        unshare: i32,
+        #[cfg(target_os = "linux")]
+        uid_map: Option<&'a [u8]>,
+        #[cfg(target_os = "linux")]
+        gid_map: Option<&'a [u8]>,
+        start_suspended: bool,
+        daemon_pipe: Option<&'a File>,
+    }
+
+    impl ChildOptions<'_> {
+        // Return the options the way posix_spawn() applies them, or None
+        // if some of them need code to run between fork and exec.
+        fn spawn_options(&self, child_ends: &ChildEnds) -> Option<posix::SpawnOptions> {
+            // no `..`, so that new options must be considered here
+            let ChildOptions {
+                cwd,
+                cwd_fd,
+                chroot,
+                setuid,
+                setgid,
+                setgroups,
+                rlimits,
+                setpgid,
+                pgid,
+                setsid,
+                controlling_tty,
+                umask,
+                pre_exec,
+                fd_map,
+                nice,
+                #[cfg(target_os = "linux")]
+                sched_policy,
+                #[cfg(target_os = "linux")]
+                cpu_affinity,
+                #[cfg(target_os = "linux")]
+                oom_score_adj,
+                #[cfg(target_os = "openbsd")]
+                pledge,
+                #[cfg(target_os = "openbsd")]
+                unveil,
+                #[cfg(target_os = "linux")]
+                keep_capabilities,
+                #[cfg(target_os = "linux")]
+                cgroup_procs,
+                #[cfg(target_os = "linux")]
+                seccomp,
+                #[cfg(target_os = "linux")]
+                landlock_ruleset,
+                #[cfg(target_os = "linux")]
+                unshare,
+                #[cfg(target_os = "linux")]
+                uid_map,
+                #[cfg(target_os = "linux")]
+                gid_map,
+                start_suspended,
+                daemon_pipe,
+            } = self;
+            let fork_only = chroot.is_some()
+                || setuid.is_some()
+                || setgid.is_some()
+                || setgroups.is_some()
+                || !rlimits.is_empty()
+                || *setsid
+                || controlling_tty.is_some()
+                || umask.is_some()
+                || !pre_exec.is_empty()
+                || nice.is_some()
+                || *start_suspended
+                || daemon_pipe.is_some();
+            #[cfg(target_os = "linux")]
+            let fork_only = fork_only
+                || sched_policy.is_some()
+                || cpu_affinity.is_some()
+                || oom_score_adj.is_some()
+                || keep_capabilities.is_some()
+                || cgroup_procs.is_some()
+                || seccomp.is_some()
+                || landlock_ruleset.is_some()
+                || *unshare != 0
+                || uid_map.is_some()
+                || gid_map.is_some();
+            #[cfg(target_os = "openbsd")]
+            let fork_only = fork_only || pledge.is_some() || !unveil.is_empty();
+            if fork_only {
+                return None;
+            }
+            let cwd = cwd.map(CStr::to_owned);
+            // the same as done by do_exec()
+            let fds = stream_fds(child_ends);
+            let mut file_actions = vec![];
+            for (target, &fd) in fds.iter().enumerate() {
+                match fd {
+                    Some(fd) if fd != target as RawFd => {
+                        file_actions.push(posix::FileAction::Dup2(fd, target as RawFd))
+                    }
+                    // a file action can't clear FD_CLOEXEC portably, so
+                    // leave a close-on-exec descriptor that is already in
+                    // place to the fork path
+                    Some(fd) if posix::is_cloexec(fd).ok()? => return None,
+                    _ => (),
+                }
+            }
+            for (i, &fd) in fds.iter().enumerate() {
+                if close_after_dup(&fds, i) {
+                    file_actions.push(posix::FileAction::Close(fd.unwrap()));
+                }
+            }
+            file_actions.extend(
+                fd_map
+                    .iter()
+                    .map(|(child_fd, fd)| posix::FileAction::Dup2(fd.as_raw_fd(), *child_fd)),
+            );
+            let pgid = if *setpgid { Some(0) } else { *pgid };
+            Some(posix::SpawnOptions {
+                cwd,
+                cwd_fd: cwd_fd.map(|fd| fd.as_raw_fd()),
+                file_actions,
+                pgid,
+            })
+        }
+    }
+
+    // The files the parent gives the child as its standard streams.
+    type ChildEnds = (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>);
+
+    fn stream_fds(child_ends: &ChildEnds) -> [Option<RawFd>; 3] {
+        let (stdin, stdout, stderr) = child_ends;
+        [stdin, stdout, stderr].map(|end| end.as_ref().map(|end| end.as_raw_fd()))
+    }
+
+    // Whether the child closes fds[i] once the streams are duplicated,
+    // so that the program doesn't inherit it: each original descriptor
+    // is closed once, except the standard streams of the parent.
+    fn close_after_dup(fds: &[Option<RawFd>; 3], i: usize) -> bool {
+        match fds[i] {
+            Some(fd) => fd > 2 && !fds[..i].contains(&Some(fd)),
+            None => false,
+        }
+    }
+
+    // posix_spawn() fails the same way whether changing to the directory
+    // or executing the program has failed, so tell them apart by
+    // checking the directory, to report what the fork path would.
+    fn spawn_error(
+        error: io::Error,
+        argv: &[OsString],
+        program: &OsStr,
+        cwd: Option<&OsStr>,
+        cwd_fd: Option<BorrowedFd<'_>>,
+    ) -> PopenError {
+        let metadata = match (cwd, cwd_fd) {
+            (Some(cwd), _) => Some(fs::metadata(cwd)),
+            (None, Some(fd)) => Some(
+                fd.try_clone_to_owned()
+                    .and_then(|fd| File::from(fd).metadata()),
+            ),
+            (None, None) => None,
+        };
+        let chdir_errno = match metadata {
+            Some(Err(e)) => e.raw_os_error(),
+            Some(Ok(metadata)) if !metadata.is_dir() => Some(libc::ENOTDIR),
+            _ => None,
+        };
+        match chdir_errno {
+            Some(errno) => PopenError::ChildSetupFailed {
+                step: ChildSetupStep::Chdir,
+                errno,
+            },
+            None => exec_error(error, argv, program, cwd, cwd_fd.is_some()),
+        }
+    }
+
+    // The kernel reports a missing #! interpreter like a missing
+    // program, so tell them apart by looking for the program, unless it
+    // is looked up in another root, or in a directory known only by its
+    // descriptor.
+    fn exec_error(
+        error: io::Error,
+        argv: &[OsString],
+        program: &OsStr,
+        cwd: Option<&OsStr>,
+        elsewhere: bool,
+    ) -> PopenError {
+        let mut cause = ExecFailure::of(&error);
+        if cause == ExecFailure::NotFound && !elsewhere {
+            let search_path = env::var_os("PATH").unwrap_or_default();
+            let found = find_executable(program, &search_path, cwd, None).ok();
+            let found = found.map(|path| match cwd {
+                Some(cwd) => Path::new(cwd).join(path),
+                None => path,
+            });
+            if found.is_some_and(|path| is_executable(&path)) {
+                cause = ExecFailure::InterpreterNotFound;
+            }
+        }
+        exec_failed(error, cause, argv, program, cwd)
+    }
+
+    // The program the child will execute, looked up in the PATH as
+    // execvp() would, or the name as given if it can't be found from the
+    // parent.
+    pub fn resolve_program(program: &OsStr, config: &PopenConfig) -> OsString {
+        let program = config.executable.as_deref().unwrap_or(program);
+        // a program in another root can't be looked up from here
+        if config.chroot.is_some() || config.cwd_fd.is_some() {
+            return program.to_owned();
+        }
+        let search_path = env::var_os("PATH").unwrap_or_default();
+        match find_executable(program, &search_path, config.cwd.as_deref(), None) {
+            Ok(path) => path.into_os_string(),
+            Err(_) => program.to_owned(),
+        }
+    }
+
     trait PopenOsImpl: super::PopenOs {
         fn do_exec(
             just_exec: impl FnOnce() -> io::Result<()>,
-            child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
-            setuid: Option<u32>,
-            setgid: Option<u32>,
-            setpgid: bool,
+            child_ends: &ChildEnds,
+            options: &ChildOptions<'_>,
+            step: &Cell<Option<ChildSetupStep>>,
         ) -> io::Result<()>;
         fn waitpid(&mut self, block: bool) -> io::Result<()>;
     }
@@ -840,43 +5124,183 @@ mod os {
     impl PopenOsImpl for Popen {
         fn do_exec(
             just_exec: impl FnOnce() -> io::Result<()>,
-            child_ends: (Option<Rc<File>>, Option<Rc<File>>, Option<Rc<File>>),
-            cwd: Option<&OsStr>,
-            setuid: Option<u32>,
-            setgid: Option<u32>,
-            setpgid: bool,
+            child_ends: &ChildEnds,
+            options: &ChildOptions<'_>,
+            step: &Cell<Option<ChildSetupStep>>,
         ) -> io::Result<()> {
-            if let Some(cwd) = cwd {
-                env::set_current_dir(cwd)?;
+            if let Some(mut daemon_pipe) = options.daemon_pipe {
+                step.set(Some(ChildSetupStep::Daemonize));
+                posix::setsid()?;
+                // unsafe for the same reason as the first fork()
+                if let Some(daemon_pid) = unsafe { posix::fork()? } {
+                    daemon_pipe.write_all(&daemon_pid.to_ne_bytes()).ok();
+                    posix::_exit(0);
+                }
             }
-
-            let (stdin, stdout, stderr) = child_ends;
-            if let Some(stdin) = stdin {
-                if stdin.as_raw_fd() != 0 {
-                    posix::dup2(stdin.as_raw_fd(), 0)?;
+            #[cfg(target_os = "linux")]
+            {
+                if options.unshare != 0 {
+                    step.set(Some(ChildSetupStep::Unshare));
+                    posix::unshare(options.unshare)?;
+                    if options.unshare & libc::CLONE_NEWNS != 0 {
+                        posix::make_mounts_private()?;
+                    }
+                }
+                if let Some(map) = options.uid_map {
+                    step.set(Some(ChildSetupStep::WriteIdMap));
+                    posix::write_proc_file(c_str(b"/proc/self/uid_map\0"), map)?;
                 }
+                if let Some(map) = options.gid_map {
+                    step.set(Some(ChildSetupStep::WriteIdMap));
+                    posix::write_proc_file(c_str(b"/proc/self/setgroups\0"), b"deny")?;
+                    posix::write_proc_file(c_str(b"/proc/self/gid_map\0"), map)?;
+                }
+            }
+            if let Some(root) = options.chroot {
+                step.set(Some(ChildSetupStep::Chroot));
+                posix::chroot(root)?;
             }
-            if let Some(stdout) = stdout {
-                if stdout.as_raw_fd() != 1 {
-                    posix::dup2(stdout.as_raw_fd(), 1)?;
+            if let Some(cwd) = options.cwd {
+                step.set(Some(ChildSetupStep::Chdir));
+                posix::chdir(cwd)?;
+            }
+            if let Some(fd) = options.cwd_fd {
+                step.set(Some(ChildSetupStep::Chdir));
+                posix::fchdir(fd.as_raw_fd())?;
+            }
+
+            step.set(Some(ChildSetupStep::RedirectStreams));
+            let fds = stream_fds(child_ends);
+            for (target, &fd) in fds.iter().enumerate() {
+                if let Some(fd) = fd {
+                    if fd != target as RawFd {
+                        posix::dup2(fd, target as RawFd)?;
+                    } else {
+                        // dup2() would clear FD_CLOEXEC, but is a no-op
+                        // for the same descriptor
+                        posix::set_cloexec(fd, false)?;
+                    }
                 }
             }
-            if let Some(stderr) = stderr {
-                if stderr.as_raw_fd() != 2 {
-                    posix::dup2(stderr.as_raw_fd(), 2)?;
+            // Closed rather than dropped, as the files belong to the
+            // parent, whose memory the child shares with vfork.
+            for (i, &fd) in fds.iter().enumerate() {
+                if close_after_dup(&fds, i) {
+                    posix::close(fd.unwrap())?;
                 }
             }
+            step.set(Some(ChildSetupStep::ResetSigpipe));
             posix::reset_sigpipe()?;
 
-            if let Some(uid) = setuid {
-                posix::setuid(uid)?;
+            // before setuid, which loses the privilege to raise the hard
+            // limits and the priority, or use real-time policies
+            for &(resource, soft, hard) in options.rlimits {
+                step.set(Some(ChildSetupStep::SetRlimit));
+                posix::setrlimit(resource.to_raw(), soft, hard)?;
+            }
+            if let Some(nice) = options.nice {
+                step.set(Some(ChildSetupStep::SetPriority));
+                posix::setpriority(nice)?;
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(policy) = options.sched_policy {
+                    step.set(Some(ChildSetupStep::SetScheduler));
+                    let (policy, priority) = policy.to_raw();
+                    posix::sched_setscheduler(policy, priority)?;
+                }
+                if let Some(cpus) = options.cpu_affinity {
+                    step.set(Some(ChildSetupStep::SetAffinity));
+                    posix::sched_setaffinity(cpus)?;
+                }
+                if let Some(procs) = options.cgroup_procs {
+                    step.set(Some(ChildSetupStep::JoinCgroup));
+                    posix::write_proc_file(procs, b"0")?;
+                }
+                if let Some(adj) = options.oom_score_adj {
+                    step.set(Some(ChildSetupStep::SetOomScoreAdj));
+                    posix::set_oom_score_adj(adj)?;
+                }
+                if let Some(keep) = options.keep_capabilities {
+                    step.set(Some(ChildSetupStep::DropCapabilities));
+                    posix::drop_capabilities(keep)?;
+                }
+            }
+            if let Some(groups) = options.setgroups {
+                step.set(Some(ChildSetupStep::SetGroups));
+                posix::setgroups(groups)?;
             }
-            if let Some(gid) = setgid {
+            if let Some(gid) = options.setgid {
+                step.set(Some(ChildSetupStep::SetGid));
                 posix::setgid(gid)?;
             }
-            if setpgid {
+            if let Some(uid) = options.setuid {
+                step.set(Some(ChildSetupStep::SetUid));
+                posix::setuid(uid)?;
+            }
+            if options.setsid {
+                step.set(Some(ChildSetupStep::SetSid));
+                posix::setsid()?;
+                if let Some(tty) = options.controlling_tty {
+                    step.set(Some(ChildSetupStep::ControllingTty));
+                    posix::set_controlling_tty(tty.as_raw_fd())?;
+                }
+            } else if options.setpgid {
+                // a session leader already leads its own group
+                step.set(Some(ChildSetupStep::SetPgid));
                 posix::setpgid(0, 0)?;
+            } else if let Some(pgid) = options.pgid {
+                step.set(Some(ChildSetupStep::SetPgid));
+                posix::setpgid(0, pgid)?;
+            }
+            if let Some(mask) = options.umask {
+                posix::umask(mask);
+            }
+            if !options.pre_exec.is_empty() {
+                step.set(Some(ChildSetupStep::PreExec));
+                for hook in options.pre_exec {
+                    // safety is promised by the caller of PreExec::new
+                    unsafe { hook.call()? };
+                }
             }
+            #[cfg(target_os = "linux")]
+            if let Some(ruleset) = options.landlock_ruleset {
+                step.set(Some(ChildSetupStep::Landlock));
+                posix::landlock_restrict_self(ruleset)?;
+            }
+            if !options.fd_map.is_empty() {
+                step.set(Some(ChildSetupStep::MapFds));
+                for (child_fd, fd) in options.fd_map {
+                    // the duplicate doesn't have FD_CLOEXEC, so the
+                    // program inherits it
+                    posix::dup2(fd.as_raw_fd(), *child_fd)?;
+                }
+            }
+            #[cfg(target_os = "openbsd")]
+            {
+                if !options.unveil.is_empty() {
+                    step.set(Some(ChildSetupStep::Unveil));
+                    for (path, permissions) in options.unveil {
+                        posix::unveil(Some(path.as_c_str()), Some(permissions.as_c_str()))?;
+                    }
+                    posix::unveil(None, None)?;
+                }
+                if let Some(promises) = options.pledge {
+                    step.set(Some(ChildSetupStep::Pledge));
+                    posix::pledge_exec(promises)?;
+                }
+            }
+            if options.start_suspended {
+                posix::raise(posix::SIGSTOP)?;
+            }
+            // last, as the filter may not allow the system calls of the
+            // setup, including stopping
+            #[cfg(target_os = "linux")]
+            if let Some(program) = options.seccomp {
+                step.set(Some(ChildSetupStep::Seccomp));
+                posix::install_seccomp(program)?;
+            }
+            step.set(None);
             just_exec()?;
             unreachable!();
         }
@@ -885,23 +5309,38 @@ mod os {
             match self.child_state {
                 Preparing => panic!("child_state == Preparing"),
                 Running { pid, .. } => {
-                    match posix::waitpid(pid, if block { 0 } else { posix::WNOHANG }) {
+                    match posix::wait4(pid, if block { 0 } else { posix::WNOHANG }) {
                         Err(e) => {
                             if let Some(errno) = e.raw_os_error() {
                                 if errno == posix::ECHILD {
+                                    // An adopted process may simply not be
+                                    // our child, in which case its exit can
+                                    // only be observed.
+                                    if self.adopted && self.adopted_running(pid)? {
+                                        if block {
+                                            self.wait_adopted(pid)?;
+                                        }
+                                        return Ok(());
+                                    }
                                     // Someone else has waited for the child
                                     // (another thread, a signal handler...).
-                                    // The PID no longer exists and we cannot
-                                    // find its exit status.
-                                    self.child_state = Finished(ExitStatus::Undetermined);
+                                    // Unless it was the init reaper, the PID
+                                    // no longer exists and we cannot find its
+                                    // exit status.
+                                    let exit_status = crate::reaper::untrack(pid)
+                                        .unwrap_or(ExitStatus::Undetermined);
+                                    self.set_finished(exit_status);
                                     return Ok(());
                                 }
                             }
                             return Err(e);
                         }
-                        Ok((pid_out, exit_status)) => {
+                        Ok((pid_out, exit_status, core_dumped, usage)) => {
                             if pid_out == pid {
-                                self.child_state = Finished(exit_status);
+                                crate::reaper::untrack(pid);
+                                self.resource_usage = Some(usage);
+                                self.core_dumped = core_dumped;
+                                self.set_finished(exit_status);
                             }
                         }
                     }
@@ -912,22 +5351,88 @@ mod os {
         }
     }
 
+    impl Popen {
+        // Whether an adopted process that isn't our child is running.
+        fn adopted_running(&self, pid: u32) -> io::Result<bool> {
+            if let Running {
+                ext: Some(ref exit_fd),
+                ..
+            } = self.child_state
+            {
+                let mut fds = [posix::PollFd::new(Some(exit_fd), posix::POLLIN)];
+                return Ok(posix::poll(&mut fds, Some(Duration::from_secs(0)))? == 0);
+            }
+            match posix::kill(pid, 0) {
+                Ok(()) => Ok(true),
+                Err(ref e) if e.raw_os_error() == Some(posix::ESRCH) => Ok(false),
+                // exists, but belongs to another user
+                Err(ref e) if e.raw_os_error() == Some(posix::EPERM) => Ok(true),
+                Err(e) => Err(e),
+            }
+        }
+
+        // Block until an adopted process that isn't our child exits.
+        fn wait_adopted(&self, pid: u32) -> io::Result<()> {
+            while self.adopted_running(pid)? {
+                if let Running {
+                    ext: Some(ref exit_fd),
+                    ..
+                } = self.child_state
+                {
+                    let mut fds = [posix::PollFd::new(Some(exit_fd), posix::POLLIN)];
+                    posix::poll(&mut fds, None)?;
+                } else {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub fn adopted_state(pid: u32) -> ChildState {
+        Running {
+            pid,
+            ext: open_exit_fd(pid),
+        }
+    }
+
+    // Failure to obtain the fd, e.g. due to the kernel not supporting
+    // pidfds, results in falling back to polling waitpid() in
+    // wait_timeout().
+
+    #[cfg(target_os = "linux")]
+    fn open_exit_fd(pid: u32) -> Option<File> {
+        posix::pidfd_open(pid).ok()
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    fn open_exit_fd(pid: u32) -> Option<File> {
+        posix::kqueue_proc_exit(pid).ok()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+    fn open_exit_fd(_pid: u32) -> Option<File> {
+        None
+    }
+
     pub fn set_inheritable(f: &File, inheritable: bool) -> io::Result<()> {
         if inheritable {
-            // Unix pipes are inheritable by default.
+            // The child gets the descriptors through dup2(), which
+            // clears FD_CLOEXEC, so they are left close-on-exec until
+            // then, and not leaked to other processes.
         } else {
-            let fd = f.as_raw_fd();
-            let old = posix::fcntl(fd, posix::F_GETFD, None)?;
-            posix::fcntl(fd, posix::F_SETFD, Some(old | posix::FD_CLOEXEC))?;
+            posix::set_cloexec(f.as_raw_fd(), true)?;
         }
         Ok(())
     }
 
     /// Create a pipe.
     ///
-    /// This is a safe wrapper over `libc::pipe` or
+    /// This is a safe wrapper over `libc::pipe2` or
     /// `winapi::um::namedpipeapi::CreatePipe`, depending on the operating
-    /// system.
+    /// system.  Both ends are created close-on-exec (non-inheritable on
+    /// Windows), and are passed to a subprocess only when given to it as
+    /// a redirection.
     pub fn make_pipe() -> io::Result<(File, File)> {
         posix::pipe()
     }
@@ -937,6 +5442,7 @@ mod os {
         use crate::popen::Popen;
         use crate::posix;
         use std::io;
+        use std::os::unix::io::{AsRawFd, RawFd};
 
         /// Unix-specific extension methods for `Popen`
         pub trait PopenExt {
@@ -953,6 +5459,18 @@ mod os {
             /// [`wait`]: ../struct.Popen.html#method.wait
             /// [`libc`]: https://docs.rs/libc/
             fn send_signal(&self, signal: i32) -> io::Result<()>;
+
+            /// Return the pidfd referring to the child process, if any.
+            ///
+            /// On Linux 5.3 and later, a pidfd is obtained for each
+            /// started child.  It becomes readable when the child exits,
+            /// and can be registered with an external event loop to be
+            /// notified of the exit, after which the status can be
+            /// obtained with [`poll`].  Returns `None` on other systems,
+            /// or after the child has been waited for.
+            ///
+            /// [`poll`]: ../struct.Popen.html#method.poll
+            fn pidfd(&self) -> Option<RawFd>;
         }
         impl PopenExt for Popen {
             fn send_signal(&self, signal: i32) -> io::Result<()> {
@@ -962,6 +5480,35 @@ mod os {
                     Finished(..) => Ok(()),
                 }
             }
+
+            #[cfg(target_os = "linux")]
+            fn pidfd(&self) -> Option<RawFd> {
+                match self.child_state {
+                    Running {
+                        ext: Some(ref pidfd),
+                        ..
+                    } => Some(pidfd.as_raw_fd()),
+                    _ => None,
+                }
+            }
+
+            #[cfg(not(target_os = "linux"))]
+            fn pidfd(&self) -> Option<RawFd> {
+                None
+            }
+        }
+
+        /// Return the group IDs of a user, including `gid`.
+        ///
+        /// These are the groups that `initgroups()` would set for the
+        /// user: `gid`, normally the user's primary group, and the groups
+        /// that list the user as a member.  The result can be passed to
+        /// [`PopenConfig::setgroups`] to start a subprocess with the
+        /// groups of the user it runs as.
+        ///
+        /// [`PopenConfig::setgroups`]: ../struct.PopenConfig.html#structfield.setgroups
+        pub fn user_groups(user: impl AsRef<std::ffi::OsStr>, gid: u32) -> io::Result<Vec<u32>> {
+            posix::getgrouplist(user.as_ref(), gid)
         }
     }
 }
@@ -976,9 +5523,10 @@ mod os {
     use std::fs::{self, File};
     use std::io;
     use std::os::windows::ffi::{OsStrExt, OsStringExt};
-    use std::os::windows::io::{AsRawHandle, RawHandle};
-    use std::time::Duration;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+    use std::time::{Duration, Instant};
 
+    use crate::cmdline;
     use crate::os_common::{ExitStatus, StandardStream};
     use crate::win32;
 
@@ -990,28 +5538,179 @@ mod os {
             fn raw(opt: &Option<Rc<File>>) -> Option<RawHandle> {
                 opt.as_ref().map(|f| f.as_raw_handle())
             }
-            let (mut child_stdin, mut child_stdout, mut child_stderr) =
-                self.setup_streams(config.stdin, config.stdout, config.stderr)?;
-            ensure_child_stream(&mut child_stdin, StandardStream::Input)?;
-            ensure_child_stream(&mut child_stdout, StandardStream::Output)?;
-            ensure_child_stream(&mut child_stderr, StandardStream::Error)?;
-            let cmdline = assemble_cmdline(argv)?;
+            // The pseudo console provides the standard streams of the
+            // child, which mustn't be given any.
+            let (child_stdin, child_stdout, child_stderr, pty) = match config.pty {
+                Some(size) => {
+                    let (pty, input, output) = Pty::open(size)?;
+                    self.stdin = Some(input);
+                    self.stdout = Some(output);
+                    (None, None, None, Some(pty))
+                }
+                None => {
+                    let (mut child_stdin, mut child_stdout, mut child_stderr) =
+                        self.setup_streams(config.stdin, config.stdout, config.stderr)?;
+                    ensure_child_stream(&mut child_stdin, StandardStream::Input)?;
+                    ensure_child_stream(&mut child_stdout, StandardStream::Output)?;
+                    ensure_child_stream(&mut child_stderr, StandardStream::Error)?;
+                    (child_stdin, child_stdout, child_stderr, None)
+                }
+            };
+            let sinfo_flags = if pty.is_some() {
+                0
+            } else {
+                win32::STARTF_USESTDHANDLES
+            };
             let env_block = config.env.map(|env| format_env_block(&env));
             // CreateProcess doesn't search for appname in the PATH.
-            // We do it ourselves to match the Unix behavior.
-            let executable = config.executable.map(locate_in_path);
+            // We do it ourselves to match the Unix behavior.  The program
+            // given by argv[0] is also passed as appname, rather than
+            // left for CreateProcess to parse out of the command line,
+            // where C:\Program Files\... could be taken to mean
+            // C:\Program.exe.
+            let executable = match config.executable {
+                Some(executable) => Some(locate_in_path(executable)),
+                None => find_program(&argv[0]),
+            };
+            let program = executable.as_ref().unwrap_or(&argv[0]);
+            // Batch files are run by cmd.exe, and only its own quoting
+            // keeps the arguments from being interpreted.  Newlines
+            // end the command whatever the quoting.
+            let quoting = if config.raw_cmdline.is_some() {
+                config.arg_quoting
+            } else if is_batch_file(program) && !config.allow_batch {
+                let newline = |c: u16| c == '\r' as u16 || c == '\n' as u16;
+                if let Some(arg) = argv.iter().find(|arg| arg.encode_wide().any(newline)) {
+                    return Err(PopenError::UnsafeBatchArgument {
+                        program: program.clone(),
+                        arg: arg.clone(),
+                    });
+                }
+                ArgQuoting::Cmd
+            } else {
+                config.arg_quoting
+            };
+            let cmdline = match config.raw_cmdline {
+                Some(ref cmdline) if cmdline.encode_wide().any(|c| c == 0) => {
+                    return Err(
+                        io::Error::from_raw_os_error(win32::ERROR_BAD_PATHNAME as i32).into(),
+                    );
+                }
+                Some(ref cmdline) => cmdline.clone(),
+                None => assemble_cmdline(&argv, quoting)?,
+            };
+            // Paths longer than MAX_PATH are only accepted in the \\?\
+            // form.  A long program that wasn't found is still passed as
+            // appname, because CreateProcess wouldn't find it either.
+            let appname = match executable {
+                Some(ref executable) => Some(win32::long_path(executable, win32::MAX_PATH)?),
+                None if argv[0].len() >= win32::MAX_PATH => {
+                    Some(win32::long_path(&argv[0], win32::MAX_PATH)?)
+                }
+                None => None,
+            };
+            let cwd = match config.cwd {
+                Some(ref cwd) => Some(win32::long_path(cwd, win32::MAX_DIR_PATH)?),
+                None => None,
+            };
+            let mut creation_flags = config.creation_flags;
+            if config.daemonize {
+                creation_flags |= win32::DETACHED_PROCESS | win32::CREATE_NEW_PROCESS_GROUP;
+            } else if config.setpgid {
+                creation_flags |= win32::CREATE_NEW_PROCESS_GROUP;
+            }
+            let affinity_mask = match config.cpu_affinity {
+                Some(ref cpus) => Some(affinity_mask(cpus)?),
+                None => None,
+            };
+            // The affinity and the job are set before the process runs.
+            let set_up_suspended = affinity_mask.is_some() || config.job.is_some();
+            if config.start_suspended || set_up_suspended {
+                creation_flags |= win32::CREATE_SUSPENDED;
+            }
+            if let Some(nice) = config.nice {
+                creation_flags |= priority_class(nice);
+            }
+            if let Some(class) = config.priority_class {
+                creation_flags |= class.to_raw();
+            }
+            let restricted_token = if config.restricted_token {
+                Some(win32::CreateRestrictedToken()?)
+            } else {
+                None
+            };
+            let credentials = if let Some(ref logon) = config.logon {
+                win32::Credentials::Logon {
+                    user: &logon.user,
+                    domain: logon.domain.as_deref(),
+                    password: &logon.password,
+                    load_profile: logon.load_profile,
+                }
+            } else if let Some(ref token) = config.user_token {
+                win32::Credentials::Token(token.as_handle())
+            } else if let Some(ref token) = restricted_token {
+                win32::Credentials::Token(token.as_handle())
+            } else {
+                win32::Credentials::Current
+            };
+            // Another user gets its own environment, like a new logon.
+            let env_block = match (env_block, &config.user_token) {
+                (None, Some(token)) => Some(win32::UserEnvironmentBlock(token.as_handle())?),
+                (env_block, _) => env_block,
+            };
+            let app_container = match config.app_container {
+                Some(ref name) => Some(win32::AppContainerSid(name)?),
+                None => None,
+            };
+            let extra_handles: Vec<_> = config
+                .inherit_handles
+                .iter()
+                .map(|handle| handle.as_raw_handle())
+                .collect();
+            let console = pty.as_ref().map(Pty::lock_console);
             let (handle, pid) = win32::CreateProcess(
-                executable.as_ref().map(OsString::as_ref),
+                appname.as_deref(),
                 &cmdline,
                 &env_block,
-                &config.cwd.as_deref(),
+                &cwd.as_deref(),
                 true,
-                0,
+                creation_flags,
                 raw(&child_stdin),
                 raw(&child_stdout),
                 raw(&child_stderr),
-                win32::STARTF_USESTDHANDLES,
-            )?;
+                sinfo_flags,
+                config.show_window,
+                credentials,
+                app_container.as_ref(),
+                &extra_handles,
+                console.as_ref().and_then(|console| console.as_ref()),
+            )
+            .map_err(|e| {
+                let program = executable.as_ref().unwrap_or(&argv[0]);
+                let cause = ExecFailure::of(&e);
+                exec_failed(e, cause, &argv, program, config.cwd.as_deref())
+            })?;
+            drop(console);
+            let set_up = || -> io::Result<()> {
+                if let Some(mask) = affinity_mask {
+                    win32::SetProcessAffinityMask(&handle, mask)?;
+                }
+                if let Some(ref job) = config.job {
+                    job.assign(&handle)?;
+                }
+                Ok(())
+            };
+            if let Err(e) = set_up() {
+                let _ = win32::TerminateProcess(&handle, 1);
+                return Err(e.into());
+            }
+            if set_up_suspended && !config.start_suspended {
+                win32::NtResumeProcess(&handle)?;
+            }
+            if let Some(ref pty) = pty {
+                pty.close_on_exit(win32::DuplicateProcessHandle(&handle)?)?;
+            }
+            self.pty = pty;
             self.child_state = Running {
                 pid: pid as u32,
                 ext: ExtChildState(handle),
@@ -1042,7 +5741,7 @@ mod os {
         }
 
         fn os_terminate(&mut self) -> io::Result<()> {
-            let mut new_child_state = None;
+            let mut exit_status = None;
             if let Running {
                 ext: ExtChildState(ref handle),
                 ..
@@ -1057,13 +5756,13 @@ mod os {
                         if rc == win32::STILL_ACTIVE {
                             return Err(err);
                         }
-                        new_child_state = Some(Finished(ExitStatus::Exited(rc)));
+                        exit_status = Some(ExitStatus::Exited(rc));
                     }
                     Ok(_) => (),
                 }
             }
-            if let Some(new_child_state) = new_child_state {
-                self.child_state = new_child_state;
+            if let Some(exit_status) = exit_status {
+                self.set_finished(exit_status);
             }
             Ok(())
         }
@@ -1073,6 +5772,165 @@ mod os {
         }
     }
 
+    // Kill the descendants of the process.
+    pub fn kill_tree(root: u32) -> io::Result<()> {
+        fn open(pid: u32) -> io::Result<(win32::Handle, u64)> {
+            let handle = win32::OpenProcess(
+                win32::PROCESS_TERMINATE | win32::PROCESS_QUERY_LIMITED_INFORMATION,
+                pid,
+            )?;
+            let created = win32::GetProcessCreationTime(&handle)?;
+            Ok((handle, created))
+        }
+        let processes = win32::list_processes()?;
+        // The handles keep the PIDs from being reused until the
+        // processes are terminated.
+        let (root_handle, root_created) = open(root)?;
+        let mut tree = vec![(root, root_handle, root_created)];
+        let mut i = 0;
+        while i < tree.len() {
+            let (parent, parent_created) = (tree[i].0, tree[i].2);
+            for &(pid, ppid) in &processes {
+                if ppid != parent || tree.iter().any(|&(seen, ..)| seen == pid) {
+                    continue;
+                }
+                // The process may have exited in the meantime, or may
+                // not be ours to open.
+                if let Ok((handle, created)) = open(pid) {
+                    // A process created before its recorded parent is
+                    // a child of an earlier process with the parent's
+                    // PID, which has since been reused.
+                    if created > parent_created {
+                        tree.push((pid, handle, created));
+                    }
+                }
+            }
+            i += 1;
+        }
+        for (_, handle, _) in tree[1..].iter().rev() {
+            win32::TerminateProcess(handle, 1).ok();
+        }
+        Ok(())
+    }
+
+    pub fn send_signal(popen: &Popen, signal: Signal) -> io::Result<()> {
+        let ctrl_event = match signal {
+            Signal::Interrupt => win32::CTRL_C_EVENT,
+            Signal::Break => win32::CTRL_BREAK_EVENT,
+            _ => {
+                let handle = match popen.process_handle() {
+                    Some(handle) => handle,
+                    None => return Ok(()),
+                };
+                return match win32::TerminateProcess(handle, 1) {
+                    // Access is denied once the process has exited.
+                    Err(err) if err.raw_os_error() == Some(win32::ERROR_ACCESS_DENIED as i32) => {
+                        if win32::GetExitCodeProcess(handle)? == win32::STILL_ACTIVE {
+                            return Err(err);
+                        }
+                        Ok(())
+                    }
+                    result => result,
+                };
+            }
+        };
+        win32::GenerateConsoleCtrlEvent(ctrl_event, popen.group_id()?)
+    }
+
+    pub fn terminate_group(pgid: u32) -> io::Result<()> {
+        win32::GenerateConsoleCtrlEvent(win32::CTRL_BREAK_EVENT, pgid)
+    }
+
+    pub fn suspend(popen: &Popen) -> io::Result<()> {
+        win32::NtSuspendProcess(popen.process_handle().unwrap())
+    }
+
+    pub fn peek_status(popen: &Popen) -> io::Result<Option<ExitStatus>> {
+        let handle = popen.process_handle().unwrap();
+        let event = win32::WaitForSingleObject(handle, Some(Duration::from_secs(0)))?;
+        if let win32::WaitEvent::OBJECT_0 = event {
+            return Ok(Some(ExitStatus::Exited(win32::GetExitCodeProcess(handle)?)));
+        }
+        Ok(None)
+    }
+
+    pub fn resume(popen: &Popen) -> io::Result<()> {
+        win32::NtResumeProcess(popen.process_handle().unwrap())
+    }
+
+    pub unsafe fn adopted_state(handle: RawHandle) -> ChildState {
+        let handle = win32::Handle::from_raw_handle(handle);
+        Running {
+            pid: win32::GetProcessId(&handle),
+            ext: ExtChildState(handle),
+        }
+    }
+
+    impl Popen {
+        // The handle of the running child.
+        pub(crate) fn process_handle(&self) -> Option<&win32::Handle> {
+            match self.child_state {
+                Running {
+                    ext: ExtChildState(ref handle),
+                    ..
+                } => Some(handle),
+                _ => None,
+            }
+        }
+    }
+
+    // Maximum number of handles accepted by WaitForMultipleObjects.
+    const MAXIMUM_WAIT_OBJECTS: usize = 64;
+
+    pub fn wait_any(
+        children: &mut [&mut Popen],
+        deadline: Option<Instant>,
+    ) -> Result<Option<(usize, ExitStatus)>> {
+        use std::cmp::min;
+
+        // double delay at every iteration, maxing at 100ms
+        let mut delay = Duration::from_millis(1);
+
+        loop {
+            for (i, child) in children.iter_mut().enumerate() {
+                if let Some(exit_status) = child.wait_handle(Some(Duration::from_secs(0)))? {
+                    return Ok(Some((i, exit_status)));
+                }
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            if children.len() <= MAXIMUM_WAIT_OBJECTS {
+                let handles: Vec<_> = children
+                    .iter()
+                    .filter_map(|child| match child.child_state {
+                        Running {
+                            ext: ExtChildState(ref handle),
+                            ..
+                        } => Some(handle),
+                        _ => None,
+                    })
+                    .collect();
+                // All children are running, since none was found
+                // finished above.
+                win32::WaitForMultipleObjects(&handles, remaining)?;
+                continue;
+            }
+            ::std::thread::sleep(match remaining {
+                Some(remaining) => min(delay, remaining),
+                None => delay,
+            });
+            delay = min(delay * 2, Duration::from_millis(100));
+        }
+    }
+
     fn format_env_block(env: &[(OsString, OsString)]) -> Vec<u16> {
         fn to_uppercase(s: &OsStr) -> OsString {
             OsString::from_wide(
@@ -1112,7 +5970,7 @@ mod os {
 
     impl PopenOsImpl for Popen {
         fn wait_handle(&mut self, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
-            let mut new_child_state = None;
+            let mut exit_status = None;
             if let Running {
                 ext: ExtChildState(ref handle),
                 ..
@@ -1121,16 +5979,40 @@ mod os {
                 let event = win32::WaitForSingleObject(handle, timeout)?;
                 if let win32::WaitEvent::OBJECT_0 = event {
                     let exit_code = win32::GetExitCodeProcess(handle)?;
-                    new_child_state = Some(Finished(ExitStatus::Exited(exit_code)));
+                    exit_status = Some(ExitStatus::Exited(exit_code));
+                    self.resource_usage = resource_usage(handle).ok();
                 }
             }
-            if let Some(new_child_state) = new_child_state {
-                self.child_state = new_child_state;
+            if let Some(exit_status) = exit_status {
+                self.set_finished(exit_status);
             }
             Ok(self.exit_status())
         }
     }
 
+    fn resource_usage(handle: &win32::Handle) -> io::Result<ResourceUsage> {
+        let (user_time, system_time) = win32::GetProcessTimes(handle)?;
+        let counters = win32::GetProcessMemoryInfo(handle)?;
+        Ok(ResourceUsage {
+            user_time,
+            system_time,
+            max_rss: counters.PeakWorkingSetSize as u64,
+            minor_faults: counters.PageFaultCount as u64,
+            major_faults: 0,
+        })
+    }
+
+    pub fn sample_usage(popen: &Popen) -> io::Result<UsageSample> {
+        let handle = popen.process_handle().unwrap();
+        let (user_time, system_time) = win32::GetProcessTimes(handle)?;
+        let counters = win32::GetProcessMemoryInfo(handle)?;
+        Ok(UsageSample {
+            rss: counters.WorkingSetSize as u64,
+            user_time,
+            system_time,
+        })
+    }
+
     fn ensure_child_stream(stream: &mut Option<Rc<File>>, which: StandardStream) -> io::Result<()> {
         // If no stream is sent to CreateProcess, the child doesn't
         // get a valid stream.  This results in e.g.
@@ -1144,24 +6026,58 @@ mod os {
     }
 
     pub fn set_inheritable(f: &File, inheritable: bool) -> io::Result<()> {
-        win32::SetHandleInformation(
-            f,
-            win32::HANDLE_FLAG_INHERIT,
-            if inheritable { 1 } else { 0 },
-        )?;
+        if inheritable {
+            // CreateProcess makes the handles passed to the child
+            // inheritable only for the duration of the call, so they
+            // are left non-inheritable until then, and not leaked to
+            // other processes.
+        } else {
+            win32::SetHandleInformation(f, win32::HANDLE_FLAG_INHERIT, 0)?;
+        }
         Ok(())
     }
 
     /// Create a pipe.
     ///
-    /// This is a safe wrapper over `libc::pipe` or
+    /// This is a safe wrapper over `libc::pipe2` or
     /// `winapi::um::namedpipeapi::CreatePipe`, depending on the operating
-    /// system.
+    /// system.  Both ends are created close-on-exec (non-inheritable on
+    /// Windows), and are passed to a subprocess only when given to it as
+    /// a redirection.
     pub fn make_pipe() -> io::Result<(File, File)> {
-        win32::CreatePipe(true)
+        win32::CreatePipe(false)
+    }
+
+    fn affinity_mask(cpus: &[usize]) -> io::Result<usize> {
+        let mut mask = 0usize;
+        for &cpu in cpus {
+            if cpu >= usize::BITS as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "CPU number out of range for the affinity mask",
+                ));
+            }
+            mask |= 1 << cpu;
+        }
+        Ok(mask)
+    }
+
+    // Map a Unix niceness to the closest priority class.
+    fn priority_class(nice: i32) -> u32 {
+        match nice {
+            15..=i32::MAX => win32::IDLE_PRIORITY_CLASS,
+            5..=14 => win32::BELOW_NORMAL_PRIORITY_CLASS,
+            -4..=4 => win32::NORMAL_PRIORITY_CLASS,
+            -14..=-5 => win32::ABOVE_NORMAL_PRIORITY_CLASS,
+            i32::MIN..=-15 => win32::HIGH_PRIORITY_CLASS,
+        }
     }
 
     fn locate_in_path(executable: OsString) -> OsString {
+        // already resolved, e.g. by PopenConfig::executable_search
+        if Path::new(&executable).is_absolute() && fs::metadata(&executable).is_ok() {
+            return executable;
+        }
         if let Some(path) = env::var_os("PATH") {
             for path in env::split_paths(&path) {
                 let path = path
@@ -1175,81 +6091,126 @@ mod os {
         executable
     }
 
-    fn assemble_cmdline(argv: Vec<OsString>) -> io::Result<OsString> {
-        let mut cmdline = vec![];
-        let mut is_first = true;
-        for arg in argv {
-            if !is_first {
-                cmdline.push(' ' as u16);
-            } else {
-                is_first = false;
-            }
-            if arg.encode_wide().any(|c| c == 0) {
-                return Err(io::Error::from_raw_os_error(
-                    win32::ERROR_BAD_PATHNAME as i32,
-                ));
-            }
-            append_quoted(&arg, &mut cmdline);
+    // Find the program CreateProcess would run for the first token of
+    // the command line, looking in the same places in the same order:
+    // the directory of the current executable, the current directory,
+    // the system directories and the PATH.  Returns None if it isn't
+    // found, leaving the search and the error to CreateProcess.
+    fn find_program(program: &OsStr) -> Option<OsString> {
+        let mut name = PathBuf::from(program);
+        // like CreateProcess, only add .exe to a name without extension
+        if name.extension().is_none() {
+            name.set_extension(env::consts::EXE_EXTENSION);
         }
-        Ok(OsString::from_wide(&cmdline))
+        if name.components().count() > 1 {
+            return Some(name.into_os_string()).filter(|name| is_executable(Path::new(name)));
+        }
+        let mut dirs = vec![];
+        dirs.extend(
+            env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(PathBuf::from)),
+        );
+        dirs.extend(env::current_dir().ok());
+        dirs.extend(win32::GetSystemDirectory().ok().map(PathBuf::from));
+        dirs.extend(win32::GetWindowsDirectory().ok().map(PathBuf::from));
+        if let Some(path) = env::var_os("PATH") {
+            dirs.extend(env::split_paths(&path));
+        }
+        dirs.into_iter()
+            .map(|dir| dir.join(&name))
+            .find(|candidate| is_executable(candidate))
+            .map(PathBuf::into_os_string)
     }
 
-    // Translated from ArgvQuote at http://tinyurl.com/zmgtnls
-    fn append_quoted(arg: &OsStr, cmdline: &mut Vec<u16>) {
-        if !arg.is_empty()
-            && !arg.encode_wide().any(|c| {
-                c == ' ' as u16
-                    || c == '\t' as u16
-                    || c == '\n' as u16
-                    || c == '\x0b' as u16
-                    || c == '\"' as u16
-            })
-        {
-            cmdline.extend(arg.encode_wide());
-            return;
-        }
-        cmdline.push('"' as u16);
+    // The program CreateProcess will run, or the name as given if it
+    // can't be found.
+    pub fn resolve_program(program: &OsStr, config: &PopenConfig) -> OsString {
+        let found = match config.executable {
+            Some(ref executable) => Some(locate_in_path(executable.clone())),
+            None => find_program(program),
+        };
+        found.unwrap_or_else(|| program.to_owned())
+    }
 
-        let arg: Vec<_> = arg.encode_wide().collect();
-        let mut i = 0;
-        while i < arg.len() {
-            let mut num_backslashes = 0;
-            while i < arg.len() && arg[i] == '\\' as u16 {
-                i += 1;
-                num_backslashes += 1;
-            }
+    fn is_batch_file(program: &OsStr) -> bool {
+        Path::new(program)
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bat") || ext.eq_ignore_ascii_case("cmd"))
+    }
 
-            if i == arg.len() {
-                for _ in 0..num_backslashes * 2 {
-                    cmdline.push('\\' as u16);
-                }
-                break;
-            } else if arg[i] == b'"' as u16 {
-                for _ in 0..num_backslashes * 2 + 1 {
-                    cmdline.push('\\' as u16);
-                }
-                cmdline.push(arg[i]);
-            } else {
-                for _ in 0..num_backslashes {
-                    cmdline.push('\\' as u16);
-                }
-                cmdline.push(arg[i]);
-            }
-            i += 1;
+    fn assemble_cmdline(argv: &[OsString], quoting: ArgQuoting) -> io::Result<OsString> {
+        let argv: Vec<Vec<u16>> = argv.iter().map(|arg| arg.encode_wide().collect()).collect();
+        if argv.iter().any(|arg| arg.contains(&0)) {
+            return Err(io::Error::from_raw_os_error(
+                win32::ERROR_BAD_PATHNAME as i32,
+            ));
         }
-        cmdline.push('"' as u16);
+        let cmdline = cmdline::assemble(argv.iter().map(Vec::as_slice), quoting)?;
+        Ok(OsString::from_wide(&cmdline))
     }
 
     pub mod ext {}
 }
 
+// Redirect the standard streams that are not redirected to the null
+// device, so that a daemon doesn't keep those of the parent open.
+fn redirect_to_null(config: &mut PopenConfig) -> io::Result<()> {
+    use crate::builder::os::NULL_DEVICE;
+    if let Redirection::None = config.stdin {
+        let null = OpenOptions::new().read(true).open(NULL_DEVICE)?;
+        config.stdin = Redirection::File(null);
+    }
+    for stream in &mut [&mut config.stdout, &mut config.stderr] {
+        if let Redirection::None = **stream {
+            let null = OpenOptions::new().write(true).open(NULL_DEVICE)?;
+            **stream = Redirection::File(null);
+        }
+    }
+    Ok(())
+}
+
 impl Drop for Popen {
     // Wait for the process to exit.  To avoid the wait, call
     // detach().
     fn drop(&mut self) {
         if let (false, &Running { .. }) = (self.detached, &self.child_state) {
             // Should we log error if one occurs during drop()?
-            self.wait().ok();
+            match self.drop_policy {
+                DropPolicy::Wait => {
+                    self.wait().ok();
+                }
+                DropPolicy::Detach => (),
+                DropPolicy::Reap =>
+                {
+                    #[cfg(unix)]
+                    if let Running { pid, .. } = self.child_state {
+                        crate::reaper::reap_in_background(pid);
+                    }
+                }
+                DropPolicy::Terminate => {
+                    self.terminate().ok();
+                }
+                DropPolicy::Kill => {
+                    self.kill().ok();
+                }
+                DropPolicy::KillAndWait => {
+                    if self.kill().is_ok() {
+                        self.wait().ok();
+                    }
+                }
+                DropPolicy::TerminateAndWait(grace) => {
+                    self.terminate_with_timeout(grace).ok();
+                }
+            }
+        }
+        if let Running { pid, .. } = self.child_state {
+            #[cfg(unix)]
+            crate::reaper::untrack(pid);
+            crate::registry::unregister(pid);
+        } else {
+            crate::registry::unregister_exited();
         }
     }
 }
@@ -1266,7 +6227,7 @@ use crate::win32::make_standard_stream;
 fn get_standard_stream(which: StandardStream) -> io::Result<Rc<File>> {
     STREAMS.with(|streams| {
         if let Some(ref stream) = streams.borrow()[which as usize] {
-            return Ok(Rc::clone(&stream));
+            return Ok(Rc::clone(stream));
         }
         let stream = make_standard_stream(which)?;
         streams.borrow_mut()[which as usize] = Some(Rc::clone(&stream));
@@ -1285,6 +6246,290 @@ pub enum PopenError {
     IoError(io::Error),
     /// A logical error was made, e.g. invalid arguments detected at run-time.
     LogicError(&'static str),
+    /// The program was not found in the directories searched as
+    /// requested by `PopenConfig::executable_search`.
+    ExecutableNotFound {
+        /// The name of the program.
+        name: OsString,
+        /// The directories that were searched, in order.
+        searched: Vec<PathBuf>,
+    },
+    /// The program could not be executed.
+    ///
+    /// Reported by `Popen::create` instead of the bare OS error, with
+    /// the command and directory that were used, so that the message
+    /// tells which program failed and why.
+    ExecFailed {
+        /// The program that was executed, the `executable` of the
+        /// `PopenConfig` if specified, otherwise the first element of
+        /// `argv`.
+        program: OsString,
+        /// The arguments following the program name in `argv`.
+        args: Vec<OsString>,
+        /// The directory the program was started in, if `cwd` was
+        /// specified.
+        cwd: Option<PathBuf>,
+        /// What kind of failure it was.
+        cause: ExecFailure,
+        /// The error reported by the OS.
+        error: io::Error,
+    },
+    /// Setting up the child process failed after it was forked, before
+    /// it could execute the program.
+    ///
+    /// Versions before 0.3 reported these failures, such as a `cwd`
+    /// that doesn't exist, as `IoError`.
+    ChildSetupFailed {
+        /// The setup step that failed.
+        step: ChildSetupStep,
+        /// The OS error code reported by the step, or 0 if the step
+        /// panicked.
+        errno: i32,
+    },
+    /// An argument can't be passed safely to a batch file.
+    ///
+    /// Reported on Windows when the program is a `.bat` or `.cmd` file
+    /// and an argument contains a character, such as a newline, that
+    /// `cmd.exe` can't be prevented from interpreting.  See
+    /// `PopenConfig::allow_batch`.
+    UnsafeBatchArgument {
+        /// The batch file.
+        program: OsString,
+        /// The argument that was refused.
+        arg: OsString,
+    },
+}
+
+/// Why a program could not be executed.
+///
+/// Reported by [`PopenError::ExecFailed`].
+///
+/// [`PopenError::ExecFailed`]: enum.PopenError.html#variant.ExecFailed
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum ExecFailure {
+    /// The program doesn't exist (`ENOENT`, or `ERROR_FILE_NOT_FOUND`
+    /// and `ERROR_PATH_NOT_FOUND` on Windows).
+    NotFound,
+    /// The program is a script whose `#!` line names an interpreter
+    /// that doesn't exist, which the OS reports as `ENOENT`.
+    InterpreterNotFound,
+    /// The program, or a directory leading to it, may not be accessed,
+    /// or the program isn't marked executable (`EACCES` or `EPERM`, or
+    /// `ERROR_ACCESS_DENIED` on Windows).
+    PermissionDenied,
+    /// The program isn't in a format the OS can execute (`ENOEXEC`, or
+    /// `ERROR_BAD_EXE_FORMAT` on Windows).
+    NotExecutable,
+    /// Another error, given by the `error` of the `ExecFailed`.
+    Other,
+}
+
+impl ExecFailure {
+    fn of(error: &io::Error) -> ExecFailure {
+        #[cfg(unix)]
+        const BAD_EXE_FORMAT: i32 = libc::ENOEXEC;
+        #[cfg(windows)]
+        const BAD_EXE_FORMAT: i32 = crate::win32::ERROR_BAD_EXE_FORMAT as i32;
+        match error.kind() {
+            io::ErrorKind::NotFound => ExecFailure::NotFound,
+            io::ErrorKind::PermissionDenied => ExecFailure::PermissionDenied,
+            _ if error.raw_os_error() == Some(BAD_EXE_FORMAT) => ExecFailure::NotExecutable,
+            _ => ExecFailure::Other,
+        }
+    }
+}
+
+impl fmt::Display for ExecFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExecFailure::NotFound => "program not found",
+            ExecFailure::InterpreterNotFound => "interpreter of the script not found",
+            ExecFailure::PermissionDenied => "permission denied",
+            ExecFailure::NotExecutable => "not an executable format",
+            ExecFailure::Other => "execution failed",
+        })
+    }
+}
+
+fn exec_failed(
+    error: io::Error,
+    cause: ExecFailure,
+    argv: &[OsString],
+    program: &OsStr,
+    cwd: Option<&OsStr>,
+) -> PopenError {
+    PopenError::ExecFailed {
+        program: program.to_owned(),
+        args: argv[1..].to_vec(),
+        cwd: cwd.map(PathBuf::from),
+        cause,
+        error,
+    }
+}
+
+// A few arguments, shortened, to recognize the command in a message.
+fn summarize_args(args: &[OsString]) -> String {
+    const MAX_ARGS: usize = 3;
+    const MAX_CHARS: usize = 40;
+    let mut summary: Vec<String> = args
+        .iter()
+        .take(MAX_ARGS)
+        .map(|arg| {
+            let arg = format!("{:?}", arg);
+            if arg.chars().count() <= MAX_CHARS {
+                return arg;
+            }
+            let mut short: String = arg.chars().take(MAX_CHARS - 4).collect();
+            short.push_str("...\"");
+            short
+        })
+        .collect();
+    if args.len() > MAX_ARGS {
+        summary.push(format!("and {} more", args.len() - MAX_ARGS));
+    }
+    summary.join(" ")
+}
+
+/// Step of setting up the child process which can fail before the
+/// program is executed.
+///
+/// Reported by [`PopenError::ChildSetupFailed`].
+///
+/// [`PopenError::ChildSetupFailed`]: enum.PopenError.html#variant.ChildSetupFailed
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum ChildSetupStep {
+    /// Changing the current directory to `PopenConfig::cwd` or
+    /// `PopenConfig::cwd_fd`.
+    Chdir,
+    /// Redirecting the standard streams.
+    RedirectStreams,
+    /// Restoring the default `SIGPIPE` disposition.
+    ResetSigpipe,
+    /// Setting the user ID to `PopenConfig::setuid`.
+    SetUid,
+    /// Setting the group ID to `PopenConfig::setgid`.
+    SetGid,
+    /// Creating a new process group as requested by `PopenConfig::setpgid`,
+    /// or joining `PopenConfig::pgid`.
+    SetPgid,
+    /// Starting a new session and forking again, as requested by
+    /// `PopenConfig::daemonize`.
+    Daemonize,
+    /// Setting the supplementary groups to `PopenConfig::setgroups`.
+    SetGroups,
+    /// Changing the root directory to `PopenConfig::chroot`.
+    Chroot,
+    /// Starting a new session as requested by `PopenConfig::setsid`.
+    SetSid,
+    /// Acquiring `PopenConfig::controlling_tty`.
+    ControllingTty,
+    /// Setting the priority to `PopenConfig::nice`.
+    SetPriority,
+    /// Setting the scheduling policy to `PopenConfig::sched_policy`.
+    SetScheduler,
+    /// Setting the CPU affinity to `PopenConfig::cpu_affinity`.
+    SetAffinity,
+    /// Setting one of `PopenConfig::rlimits`.
+    SetRlimit,
+    /// Setting `PopenConfig::oom_score_adj`.
+    SetOomScoreAdj,
+    /// Moving to `PopenConfig::cgroup`.
+    JoinCgroup,
+    /// Creating the namespaces in `PopenConfig::unshare`.
+    Unshare,
+    /// Writing `PopenConfig::uid_map` or `PopenConfig::gid_map`.
+    WriteIdMap,
+    /// Installing `PopenConfig::seccomp`.
+    Seccomp,
+    /// Restricting the process with `PopenConfig::landlock`.
+    Landlock,
+    /// Dropping the capabilities not in
+    /// `PopenConfig::keep_capabilities`.
+    DropCapabilities,
+    /// Calling `unveil()` for `PopenConfig::unveil`.
+    Unveil,
+    /// Calling `pledge()` for `PopenConfig::pledge`.
+    Pledge,
+    /// Running the functions in `PopenConfig::pre_exec`.
+    PreExec,
+    /// Installing the descriptors in `PopenConfig::fd_map`.
+    MapFds,
+}
+
+impl ChildSetupStep {
+    #[cfg_attr(windows, allow(dead_code))]
+    const ALL: &'static [ChildSetupStep] = &[
+        ChildSetupStep::Chdir,
+        ChildSetupStep::RedirectStreams,
+        ChildSetupStep::ResetSigpipe,
+        ChildSetupStep::SetUid,
+        ChildSetupStep::SetGid,
+        ChildSetupStep::SetPgid,
+        ChildSetupStep::Daemonize,
+        ChildSetupStep::SetGroups,
+        ChildSetupStep::Chroot,
+        ChildSetupStep::SetSid,
+        ChildSetupStep::ControllingTty,
+        ChildSetupStep::SetPriority,
+        ChildSetupStep::SetScheduler,
+        ChildSetupStep::SetAffinity,
+        ChildSetupStep::SetRlimit,
+        ChildSetupStep::SetOomScoreAdj,
+        ChildSetupStep::JoinCgroup,
+        ChildSetupStep::Unshare,
+        ChildSetupStep::WriteIdMap,
+        ChildSetupStep::Seccomp,
+        ChildSetupStep::Landlock,
+        ChildSetupStep::DropCapabilities,
+        ChildSetupStep::Unveil,
+        ChildSetupStep::Pledge,
+        ChildSetupStep::PreExec,
+        ChildSetupStep::MapFds,
+    ];
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn from_code(code: u8) -> Option<ChildSetupStep> {
+        ChildSetupStep::ALL.get(code as usize).copied()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ChildSetupStep::Chdir => "chdir",
+            ChildSetupStep::RedirectStreams => "redirecting standard streams",
+            ChildSetupStep::ResetSigpipe => "resetting SIGPIPE",
+            ChildSetupStep::SetUid => "setuid",
+            ChildSetupStep::SetGid => "setgid",
+            ChildSetupStep::SetPgid => "setpgid",
+            ChildSetupStep::Daemonize => "daemonizing",
+            ChildSetupStep::SetGroups => "setgroups",
+            ChildSetupStep::Chroot => "chroot",
+            ChildSetupStep::SetSid => "setsid",
+            ChildSetupStep::ControllingTty => "acquiring the controlling terminal",
+            ChildSetupStep::SetPriority => "setpriority",
+            ChildSetupStep::SetScheduler => "sched_setscheduler",
+            ChildSetupStep::SetAffinity => "sched_setaffinity",
+            ChildSetupStep::SetRlimit => "setrlimit",
+            ChildSetupStep::SetOomScoreAdj => "writing oom_score_adj",
+            ChildSetupStep::JoinCgroup => "joining the cgroup",
+            ChildSetupStep::Unshare => "unshare",
+            ChildSetupStep::WriteIdMap => "writing the ID map",
+            ChildSetupStep::Seccomp => "installing the seccomp filter",
+            ChildSetupStep::Landlock => "applying the Landlock rules",
+            ChildSetupStep::DropCapabilities => "dropping capabilities",
+            ChildSetupStep::Unveil => "unveil",
+            ChildSetupStep::Pledge => "pledge",
+            ChildSetupStep::PreExec => "running a pre_exec function",
+            ChildSetupStep::MapFds => "mapping file descriptors",
+        }
+    }
+}
+
+impl fmt::Display for ChildSetupStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 impl From<io::Error> for PopenError {
@@ -1304,6 +6549,10 @@ impl Error for PopenError {
         match *self {
             PopenError::IoError(ref err) => Some(err),
             PopenError::LogicError(_msg) => None,
+            PopenError::ExecutableNotFound { .. } => None,
+            PopenError::ExecFailed { ref error, .. } => Some(error),
+            PopenError::ChildSetupFailed { .. } => None,
+            PopenError::UnsafeBatchArgument { .. } => None,
         }
     }
 }
@@ -1313,6 +6562,56 @@ impl fmt::Display for PopenError {
         match *self {
             PopenError::IoError(ref err) => fmt::Display::fmt(err, f),
             PopenError::LogicError(desc) => f.write_str(desc),
+            PopenError::ExecutableNotFound {
+                ref name,
+                ref searched,
+            } => {
+                write!(f, "executable {:?} not found", name)?;
+                if !searched.is_empty() {
+                    let dirs: Vec<_> = searched
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect();
+                    write!(f, " in {}", dirs.join(", "))?;
+                }
+                Ok(())
+            }
+            PopenError::ExecFailed {
+                ref program,
+                ref args,
+                ref cwd,
+                cause,
+                ref error,
+            } => {
+                write!(f, "failed to execute {:?}", program)?;
+                if !args.is_empty() {
+                    write!(f, " with arguments {}", summarize_args(args))?;
+                }
+                if let Some(cwd) = cwd {
+                    write!(f, " in {:?}", cwd)?;
+                }
+                match cause {
+                    ExecFailure::Other => write!(f, ": {}", error),
+                    cause => write!(f, ": {}: {}", cause, error),
+                }
+            }
+            PopenError::ChildSetupFailed { step, errno: 0 } => {
+                write!(f, "child setup failed: {} panicked", step)
+            }
+            PopenError::ChildSetupFailed { step, errno } => write!(
+                f,
+                "child setup failed: {}: {}",
+                step,
+                io::Error::from_raw_os_error(errno)
+            ),
+            PopenError::UnsafeBatchArgument {
+                ref program,
+                ref arg,
+            } => write!(
+                f,
+                "argument {:?} can't be passed safely to batch file {:?}",
+                arg, program
+            ),
         }
     }
 }
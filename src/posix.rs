@@ -1,21 +1,21 @@
 use std::env;
-use std::ffi::{CString, OsStr, OsString};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fs::File;
 use std::io::{Error, Result};
 use std::iter;
 use std::marker::PhantomData;
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::ptr;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 use libc::{c_char, c_int};
 
-use crate::os_common::{ExitStatus, StandardStream};
+use crate::os_common::{ExitStatus, ResourceUsage, StandardStream, UsageSample};
 
-pub use libc::{ECHILD, ENOSPC};
+pub use libc::{ECHILD, EPERM, ESRCH};
 
 fn check_err<T: Ord + Default>(num: T) -> Result<T> {
     if num < T::default() {
@@ -24,10 +24,42 @@ fn check_err<T: Ord + Default>(num: T) -> Result<T> {
     Ok(num)
 }
 
+// Create a pipe with FD_CLOEXEC set on both ends.  Where pipe2() is
+// available the flag is set atomically, so that a child forked by
+// another thread in the meantime can't inherit the descriptors.
 pub fn pipe() -> Result<(File, File)> {
     let mut fds = [0 as c_int; 2];
-    check_err(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
-    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+        target_os = "solaris"
+    ))]
+    {
+        check_err(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+        Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+        target_os = "solaris"
+    )))]
+    {
+        check_err(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let pipe = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        set_cloexec(fds[0], true)?;
+        set_cloexec(fds[1], true)?;
+        Ok(pipe)
+    }
 }
 
 // marked unsafe because the child must not allocate before exec-ing
@@ -40,6 +72,74 @@ pub unsafe fn fork() -> Result<Option<u32>> {
     }
 }
 
+/// Run `child` in a new process sharing the memory of the current one,
+/// like `vfork()`, and return its PID once it has executed a program
+/// or exited.  If `child` returns, the process exits with status 127.
+///
+/// The child runs on a stack of its own, with all signals blocked and
+/// the signal handlers reset to the default, so that no handler of the
+/// current process runs in it.  Ignored signals stay ignored.
+///
+/// Marked unsafe because in addition to not allocating, as after
+/// `fork()`, the child must not change memory the current process
+/// uses after the call.
+#[cfg(target_os = "linux")]
+pub unsafe fn clone_vfork(child: &mut dyn FnMut()) -> Result<u32> {
+    const STACK_SIZE: usize = 256 * 1024;
+
+    extern "C" fn run(arg: *mut libc::c_void) -> c_int {
+        unsafe {
+            for signal in 1..=libc::SIGRTMAX() {
+                let mut action: libc::sigaction = mem::zeroed();
+                if libc::sigaction(signal, ptr::null(), &mut action) == 0
+                    && action.sa_sigaction != libc::SIG_DFL
+                    && action.sa_sigaction != libc::SIG_IGN
+                {
+                    let action: libc::sigaction = mem::zeroed(); // SIG_DFL
+                    libc::sigaction(signal, &action, ptr::null_mut());
+                }
+            }
+            let child = &mut *(arg as *mut &mut dyn FnMut());
+            child();
+            libc::_exit(127)
+        }
+    }
+
+    let stack = libc::mmap(
+        ptr::null_mut(),
+        STACK_SIZE,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK,
+        -1,
+        0,
+    );
+    if stack == libc::MAP_FAILED {
+        return Err(Error::last_os_error());
+    }
+    // the stack grows down, towards a guard page
+    check_err(libc::mprotect(
+        stack,
+        libc::sysconf(libc::_SC_PAGESIZE) as usize,
+        libc::PROT_NONE,
+    ))
+    .ok();
+    let mut all: libc::sigset_t = mem::zeroed();
+    let mut old: libc::sigset_t = mem::zeroed();
+    libc::sigfillset(&mut all);
+    libc::pthread_sigmask(libc::SIG_SETMASK, &all, &mut old);
+    let mut child = child;
+    let pid = check_err(libc::clone(
+        run,
+        (stack as *mut u8).add(STACK_SIZE) as *mut libc::c_void,
+        libc::CLONE_VM | libc::CLONE_VFORK | libc::SIGCHLD,
+        &mut child as *mut &mut dyn FnMut() as *mut libc::c_void,
+    ));
+    // the child has executed the program or exited by now
+    libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut());
+    libc::munmap(stack, STACK_SIZE);
+    Ok(pid? as u32)
+}
+
 pub fn setuid(uid: u32) -> Result<()> {
     check_err(unsafe { libc::setuid(uid as libc::uid_t) })?;
     Ok(())
@@ -50,12 +150,378 @@ pub fn setgid(gid: u32) -> Result<()> {
     Ok(())
 }
 
+pub fn setgroups(groups: &[u32]) -> Result<()> {
+    check_err(unsafe {
+        libc::setgroups(groups.len() as _, groups.as_ptr() as *const libc::gid_t)
+    })?;
+    Ok(())
+}
+
+// Return the groups of the user, as initgroups() would set them.
+pub fn getgrouplist(user: &OsStr, gid: u32) -> Result<Vec<u32>> {
+    let user = os_to_cstring(user)?;
+    let mut groups = vec![0u32; 64];
+    loop {
+        let mut ngroups = groups.len() as c_int;
+        let rc = unsafe {
+            libc::getgrouplist(
+                user.as_ptr(),
+                gid as _,
+                groups.as_mut_ptr() as *mut _,
+                &mut ngroups,
+            )
+        };
+        if rc >= 0 {
+            groups.truncate(ngroups as usize);
+            return Ok(groups);
+        }
+        // ngroups is updated to the required size on Linux only
+        let len = (ngroups as usize).max(groups.len() * 2);
+        groups.resize(len, 0);
+    }
+}
+
+pub fn setpriority(nice: i32) -> Result<()> {
+    check_err(unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn sched_setscheduler(policy: i32, priority: i32) -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    check_err(unsafe { libc::sched_setscheduler(0, policy, &param) })?;
+    Ok(())
+}
+
+// Set the CPU affinity of the calling process.  Doesn't allocate, for
+// use between fork and exec.
+#[cfg(target_os = "linux")]
+pub fn sched_setaffinity(cpus: &[usize]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(Error::from_raw_os_error(libc::EINVAL));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    check_err(unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) })?;
+    Ok(())
+}
+
+pub fn setrlimit(resource: i32, soft: u64, hard: u64) -> Result<()> {
+    fn to_rlim(limit: u64) -> libc::rlim_t {
+        if limit == u64::MAX {
+            libc::RLIM_INFINITY
+        } else {
+            limit as libc::rlim_t
+        }
+    }
+    let rlim = libc::rlimit {
+        rlim_cur: to_rlim(soft),
+        rlim_max: to_rlim(hard),
+    };
+    check_err(unsafe { libc::setrlimit(resource as _, &rlim) })?;
+    Ok(())
+}
+
+// Write the OOM score adjustment of the calling process.  Doesn't
+// allocate, for use between fork and exec.
+#[cfg(target_os = "linux")]
+pub fn set_oom_score_adj(adj: i32) -> Result<()> {
+    // format the number backwards into a stack buffer
+    let mut buf = [0u8; 12];
+    let mut pos = buf.len();
+    let mut n = adj.unsigned_abs();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    if adj < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+    let path = unsafe { CStr::from_bytes_with_nul_unchecked(b"/proc/self/oom_score_adj\0") };
+    write_proc_file(path, &buf[pos..])
+}
+
+// Write data to a kernel interface file such as those in /proc or
+// /sys/fs/cgroup with a single write().  Doesn't allocate.
+#[cfg(target_os = "linux")]
+pub fn write_proc_file(path: &CStr, data: &[u8]) -> Result<()> {
+    let fd = check_err(unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) })?;
+    let written = unsafe { libc::write(fd, data.as_ptr() as *const _, data.len()) };
+    let result = if written < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+#[cfg(target_os = "linux")]
+pub fn unshare(flags: i32) -> Result<()> {
+    check_err(unsafe { libc::unshare(flags) })?;
+    Ok(())
+}
+
+// Make all mounts private, so that mounting in a new mount namespace
+// doesn't propagate to the parent namespace.
+#[cfg(target_os = "linux")]
+pub fn make_mounts_private() -> Result<()> {
+    check_err(unsafe {
+        libc::mount(
+            ptr::null(),
+            b"/\0".as_ptr() as *const c_char,
+            ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            ptr::null(),
+        )
+    })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn install_seccomp(program: &[crate::unix::BpfInstruction]) -> Result<()> {
+    let prog = libc::sock_fprog {
+        len: program.len() as u16,
+        // BpfInstruction has the layout of struct sock_filter
+        filter: program.as_ptr() as *mut libc::sock_filter,
+    };
+    check_err(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    check_err(unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0,
+            &prog as *const libc::sock_fprog,
+        )
+    })?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn landlock_restrict_self(ruleset: i32) -> Result<()> {
+    check_err(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    check_err(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32) })?;
+    Ok(())
+}
+
+// Drop the capabilities not in the `keep` mask from the bounding and
+// inheritable sets, and clear the ambient set.
+#[cfg(target_os = "linux")]
+pub fn drop_capabilities(keep: u64) -> Result<()> {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    for cap in 0..64 {
+        // EINVAL past the last capability known to the kernel
+        let present = unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap, 0, 0, 0) };
+        if present < 0 {
+            break;
+        }
+        if present == 1 && keep & (1 << cap) == 0 {
+            check_err(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) })?;
+        }
+    }
+
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData {
+        effective: 0,
+        permitted: 0,
+        inheritable: 0,
+    }; 2];
+    check_err(unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) })?;
+    data[0].inheritable &= keep as u32;
+    data[1].inheritable &= (keep >> 32) as u32;
+    check_err(unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) })?;
+
+    check_err(unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+#[cfg(target_os = "openbsd")]
+pub fn unveil(path: Option<&CStr>, permissions: Option<&CStr>) -> Result<()> {
+    let path = path.map_or(ptr::null(), |p| p.as_ptr());
+    let permissions = permissions.map_or(ptr::null(), |p| p.as_ptr());
+    check_err(unsafe { libc::unveil(path, permissions) })?;
+    Ok(())
+}
+
+// Restrict the program to be executed, leaving the current process
+// unrestricted.
+#[cfg(target_os = "openbsd")]
+pub fn pledge_exec(execpromises: &CStr) -> Result<()> {
+    check_err(unsafe { libc::pledge(ptr::null(), execpromises.as_ptr()) })?;
+    Ok(())
+}
+
+pub fn umask(mask: u32) -> u32 {
+    unsafe { libc::umask(mask as libc::mode_t) as u32 }
+}
+
+pub fn chdir(path: &CStr) -> Result<()> {
+    check_err(unsafe { libc::chdir(path.as_ptr()) })?;
+    Ok(())
+}
+
+pub fn fchdir(fd: RawFd) -> Result<()> {
+    check_err(unsafe { libc::fchdir(fd) })?;
+    Ok(())
+}
+
+// Return the device and inode numbers that identify the open file.
+pub fn file_id(fd: RawFd) -> Result<(u64, u64)> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    check_err(unsafe { libc::fstat(fd, &mut st) })?;
+    Ok((st.st_dev as u64, st.st_ino as u64))
+}
+
+// Change the root directory, and move into it so that the old root
+// isn't reachable through the current directory.
+pub fn chroot(path: &CStr) -> Result<()> {
+    check_err(unsafe { libc::chroot(path.as_ptr()) })?;
+    check_err(unsafe { libc::chdir(b"/\0".as_ptr() as *const c_char) })?;
+    Ok(())
+}
+
+pub fn setsid() -> Result<()> {
+    check_err(unsafe { libc::setsid() })?;
+    Ok(())
+}
+
+pub fn set_controlling_tty(fd: RawFd) -> Result<()> {
+    check_err(unsafe { libc::ioctl(fd, libc::TIOCSCTTY as _, 0) })?;
+    Ok(())
+}
+
+// Open a pseudo terminal with FD_CLOEXEC set on both sides.  On Linux
+// the flag is set atomically, as in pipe().  Elsewhere it is set after
+// openpty() returns, leaving a window in which a child forked by
+// another thread can inherit the descriptors.
+#[cfg(target_os = "linux")]
+pub fn openpty(rows: u16, cols: u16) -> Result<(File, File)> {
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let master = unsafe { File::from_raw_fd(check_err(libc::posix_openpt(flags))?) };
+    check_err(unsafe { libc::grantpt(master.as_raw_fd()) })?;
+    check_err(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+    let mut name = [0 as c_char; 128];
+    let err = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+    if err != 0 {
+        return Err(Error::from_raw_os_error(err));
+    }
+    let slave = unsafe { File::from_raw_fd(check_err(libc::open(name.as_ptr(), flags))?) };
+    set_winsize(slave.as_raw_fd(), rows, cols)?;
+    Ok((master, slave))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn openpty(rows: u16, cols: u16) -> Result<(File, File)> {
+    let (mut master, mut slave) = (0, 0);
+    // the parameters are const on some platforms and mut on others
+    let mut size = winsize(rows, cols);
+    check_err(unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut size as *mut libc::winsize,
+        )
+    })?;
+    let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+    // the child gets the slave side through dup2, which clears the flag
+    for fd in &[master.as_raw_fd(), slave.as_raw_fd()] {
+        set_cloexec(*fd, true)?;
+    }
+    Ok((master, slave))
+}
+
+fn winsize(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+pub fn set_winsize(fd: RawFd, rows: u16, cols: u16) -> Result<()> {
+    let size = winsize(rows, cols);
+    check_err(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ as _, &size) })?;
+    Ok(())
+}
+
+pub fn get_winsize(fd: RawFd) -> Result<(u16, u16)> {
+    let mut size = winsize(0, 0);
+    check_err(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ as _, &mut size) })?;
+    Ok((size.ws_row, size.ws_col))
+}
+
+pub fn set_terminal_mode(fd: RawFd, raw: bool, echo: bool) -> Result<()> {
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    check_err(unsafe { libc::tcgetattr(fd, &mut termios) })?;
+    if raw {
+        unsafe { libc::cfmakeraw(&mut termios) };
+    }
+    if !echo {
+        termios.c_lflag &= !(libc::ECHO | libc::ECHOE | libc::ECHOK | libc::ECHONL);
+    }
+    check_err(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) })?;
+    Ok(())
+}
+
 pub fn setpgid(pid: u32, pgid: u32) -> Result<()> {
     check_err(unsafe { libc::setpgid(pid as _, pgid as _) })?;
     Ok(())
 }
 
-fn os_to_cstring(s: &OsStr) -> Result<CString> {
+pub fn mkfifo(path: &OsStr, mode: u32) -> Result<()> {
+    let path = os_to_cstring(path)?;
+    check_err(unsafe { libc::mkfifo(path.as_ptr(), mode as libc::mode_t) })?;
+    Ok(())
+}
+
+pub fn set_nonblocking(fd: RawFd, nonblocking: bool) -> Result<()> {
+    let old = fcntl(fd, libc::F_GETFL, None)?;
+    let new = if nonblocking {
+        old | libc::O_NONBLOCK
+    } else {
+        old & !libc::O_NONBLOCK
+    };
+    fcntl(fd, libc::F_SETFL, Some(new))?;
+    Ok(())
+}
+
+pub fn os_to_cstring(s: &OsStr) -> Result<CString> {
     // Like CString::new, but returns an io::Result for consistency with
     // everything else.
     CString::new(s.as_bytes()).map_err(|_| Error::from_raw_os_error(libc::EINVAL))
@@ -65,6 +531,7 @@ fn os_to_cstring(s: &OsStr) -> Result<CString> {
 struct CVec {
     // Individual C strings.  Each element self.ptrs[i] points to the
     // data of self.strings[i].as_bytes_with_nul().as_ptr().
+    #[allow(dead_code)]
     strings: Vec<CString>,
 
     // nullptr-terminated vector of pointers to data inside
@@ -112,49 +579,25 @@ fn split_path(mut path: &OsStr) -> impl Iterator<Item = &OsStr> {
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::split_path;
-    use std;
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-
-    fn s(s: &str) -> Vec<&str> {
-        split_path(OsStr::new(s))
-            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
-            .collect()
-    }
-
-    #[test]
-    fn test_split_path() {
-        let empty = Vec::<&OsStr>::new();
-
-        assert_eq!(s("a:b"), vec!["a", "b"]);
-        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
-        assert_eq!(s("a:"), vec!["a"]);
-        assert_eq!(s(""), empty);
-        assert_eq!(s(":"), empty);
-        assert_eq!(s("::"), empty);
-        assert_eq!(s(":::"), empty);
-        assert_eq!(s("a::b"), vec!["a", "b"]);
-        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
-    }
-}
-
-struct PrepExec {
+pub struct PrepExec {
     cmd: OsString,
     argvec: CVec,
     envvec: Option<CVec>,
     search_path: Option<OsString>,
     prealloc_exe: Vec<u8>,
+    // "NAME=" followed by room for the PID and the terminating NUL,
+    // and the length of "NAME=".  Already in envvec, the PID is filled
+    // in by exec.
+    pid_var: Option<(Vec<u8>, usize)>,
 }
 
 impl PrepExec {
     fn new(
         cmd: OsString,
         argvec: CVec,
-        envvec: Option<CVec>,
+        mut envvec: Option<CVec>,
         search_path: Option<OsString>,
+        pid_var: Option<&str>,
     ) -> PrepExec {
         // Avoid allocation after fork() by pre-allocating the buffer
         // that will be used for constructing the executable C string.
@@ -168,28 +611,56 @@ impl PrepExec {
             max_exe_len += 1 + split_path(search_path).map(OsStr::len).max().unwrap_or(0);
         }
 
+        // The variable is added before the terminating null pointer;
+        // moving the Vec doesn't move the data it points to.
+        let pid_var = pid_var.map(|name| {
+            let mut var = format!("{}=", name).into_bytes();
+            let prefix_len = var.len();
+            var.resize(prefix_len + 11, 0);
+            let envvec = envvec.as_mut().expect("pid_var requires an environment");
+            let last = envvec.ptrs.len() - 1;
+            envvec.ptrs.insert(last, var.as_ptr() as _);
+            (var, prefix_len)
+        });
+
         PrepExec {
             cmd,
             argvec,
             envvec,
             search_path,
             prealloc_exe: Vec::with_capacity(max_exe_len),
+            pid_var,
         }
     }
 
-    fn exec(mut self) -> Result<()> {
-        // Invoked after fork() - no heap allocation allowed
-        let mut exe = std::mem::take(&mut self.prealloc_exe);
+    pub fn exec(&mut self) -> Result<()> {
+        // Invoked after fork() - no heap allocation allowed.  After
+        // clone_vfork() the child shares the memory of the parent, so
+        // nothing may be freed either, and self is only borrowed.
+        let PrepExec {
+            ref cmd,
+            ref argvec,
+            ref envvec,
+            ref search_path,
+            prealloc_exe: ref mut exe,
+            ref mut pid_var,
+        } = *self;
+
+        if let Some((ref mut var, prefix_len)) = *pid_var {
+            let pid = unsafe { libc::getpid() } as u32;
+            write_decimal(&mut var[prefix_len..], pid);
+        }
 
-        if let Some(ref search_path) = self.search_path {
+        if let Some(ref search_path) = *search_path {
             let mut err = Ok(());
             // POSIX requires execvp and execve, but not execvpe (although
             // glibc provides one), so we have to iterate over PATH ourselves
             for dir in split_path(search_path.as_os_str()) {
-                err = self.libc_exec(PrepExec::assemble_exe(
-                    &mut exe,
-                    &[dir.as_bytes(), b"/", self.cmd.as_bytes()],
-                ));
+                err = PrepExec::libc_exec(
+                    PrepExec::assemble_exe(exe, &[dir.as_bytes(), b"/", cmd.as_bytes()]),
+                    argvec,
+                    envvec.as_ref(),
+                );
                 // if exec succeeds, we won't run anymore; if we're here, it failed
                 assert!(err.is_err());
             }
@@ -198,12 +669,104 @@ impl PrepExec {
             return err;
         }
 
-        self.libc_exec(PrepExec::assemble_exe(&mut exe, &[self.cmd.as_bytes()]))?;
+        PrepExec::libc_exec(
+            PrepExec::assemble_exe(exe, &[cmd.as_bytes()]),
+            argvec,
+            envvec.as_ref(),
+        )?;
 
         // failed exec can only return Err(..)
         unreachable!();
     }
 
+    /// Start the program with `posix_spawn()` instead of `fork()` and
+    /// `exec()`, applying `options` in the child.
+    ///
+    /// Returns `None` if `posix_spawn()` can't be used, because the
+    /// platform doesn't provide everything needed, such as changing the
+    /// directory of the child with older C libraries, or because the
+    /// child must set its PID in the environment.  A failure to execute the
+    /// program is reported by the returned error.
+    #[cfg(any(
+        all(target_os = "linux", any(target_env = "gnu", target_env = "musl")),
+        target_os = "macos"
+    ))]
+    pub fn spawn(&self, options: &SpawnOptions) -> Result<Option<u32>> {
+        if self.pid_var.is_some() {
+            return Ok(None);
+        }
+        let exe = os_to_cstring(&self.cmd)?;
+        let mut actions = SpawnFileActions::new()?;
+        if let Some(ref cwd) = options.cwd {
+            let addchdir = match spawn::addchdir_np() {
+                Some(addchdir) => addchdir,
+                None => return Ok(None),
+            };
+            check_spawn(unsafe { addchdir(&mut actions.0, cwd.as_ptr()) })?;
+        }
+        if let Some(cwd_fd) = options.cwd_fd {
+            let addfchdir = match spawn::addfchdir_np() {
+                Some(addfchdir) => addfchdir,
+                None => return Ok(None),
+            };
+            check_spawn(unsafe { addfchdir(&mut actions.0, cwd_fd) })?;
+        }
+        for action in &options.file_actions {
+            check_spawn(unsafe {
+                match *action {
+                    FileAction::Dup2(fd, target) => {
+                        libc::posix_spawn_file_actions_adddup2(&mut actions.0, fd, target)
+                    }
+                    FileAction::Close(fd) => {
+                        libc::posix_spawn_file_actions_addclose(&mut actions.0, fd)
+                    }
+                }
+            })?;
+        }
+        let mut attr = SpawnAttr::new()?;
+        // reset the signal mask and SIGPIPE, like reset_sigpipe()
+        let mut flags = libc::POSIX_SPAWN_SETSIGMASK | libc::POSIX_SPAWN_SETSIGDEF;
+        unsafe {
+            let mut set: mem::MaybeUninit<libc::sigset_t> = mem::MaybeUninit::uninit();
+            check_err(libc::sigemptyset(set.as_mut_ptr()))?;
+            check_spawn(libc::posix_spawnattr_setsigmask(&mut attr.0, set.as_ptr()))?;
+            check_err(libc::sigaddset(set.as_mut_ptr(), libc::SIGPIPE))?;
+            check_spawn(libc::posix_spawnattr_setsigdefault(
+                &mut attr.0,
+                set.as_ptr(),
+            ))?;
+            if let Some(pgid) = options.pgid {
+                flags |= libc::POSIX_SPAWN_SETPGROUP;
+                check_spawn(libc::posix_spawnattr_setpgroup(&mut attr.0, pgid as _))?;
+            }
+            check_spawn(libc::posix_spawnattr_setflags(&mut attr.0, flags as _))?;
+        }
+        let argv = self.argvec.as_c_vec() as *const *mut c_char;
+        let envp = match self.envvec {
+            Some(ref envvec) => envvec.as_c_vec(),
+            None => unsafe { environ() },
+        } as *const *mut c_char;
+        let mut pid: libc::pid_t = 0;
+        // posix_spawnp() searches the PATH of the current process, like
+        // exec() does when search_path is set
+        check_spawn(unsafe {
+            if self.search_path.is_some() {
+                libc::posix_spawnp(&mut pid, exe.as_ptr(), &actions.0, &attr.0, argv, envp)
+            } else {
+                libc::posix_spawn(&mut pid, exe.as_ptr(), &actions.0, &attr.0, argv, envp)
+            }
+        })?;
+        Ok(Some(pid as u32))
+    }
+
+    #[cfg(not(any(
+        all(target_os = "linux", any(target_env = "gnu", target_env = "musl")),
+        target_os = "macos"
+    )))]
+    pub fn spawn(&self, _options: &SpawnOptions) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
     fn assemble_exe<'a>(storage: &'a mut Vec<u8>, components: &[&[u8]]) -> &'a [u8] {
         storage.truncate(0);
         for comp in components {
@@ -214,30 +777,185 @@ impl PrepExec {
         storage.as_slice()
     }
 
-    fn libc_exec(&self, exe: &[u8]) -> Result<()> {
+    fn libc_exec(exe: &[u8], argvec: &CVec, envvec: Option<&CVec>) -> Result<()> {
         unsafe {
-            match self.envvec.as_ref() {
+            match envvec {
                 Some(envvec) => {
-                    libc::execve(exe.as_ptr() as _, self.argvec.as_c_vec(), envvec.as_c_vec())
+                    libc::execve(exe.as_ptr() as _, argvec.as_c_vec(), envvec.as_c_vec())
                 }
-                None => libc::execv(exe.as_ptr() as _, self.argvec.as_c_vec()),
+                None => libc::execv(exe.as_ptr() as _, argvec.as_c_vec()),
             }
         };
         Err(Error::last_os_error())
     }
 }
 
+/// The part of the child setup that `posix_spawn()` can apply, see
+/// `PrepExec::spawn`.
+#[derive(Debug, Default)]
+pub struct SpawnOptions {
+    /// Directory to change to.
+    pub cwd: Option<CString>,
+    /// Open directory to change to.
+    pub cwd_fd: Option<RawFd>,
+    /// Descriptors to duplicate and close, in order.
+    pub file_actions: Vec<FileAction>,
+    /// Process group to join, 0 for a new one led by the child.
+    pub pgid: Option<u32>,
+}
+
+/// A change to the descriptors of a child started by `PrepExec::spawn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAction {
+    /// `dup2(fd, target)`
+    Dup2(RawFd, RawFd),
+    /// `close(fd)`
+    Close(RawFd),
+}
+
+#[cfg(any(
+    all(target_os = "linux", any(target_env = "gnu", target_env = "musl")),
+    target_os = "macos"
+))]
+use self::spawn::{check_spawn, environ, SpawnAttr, SpawnFileActions};
+
+#[cfg(any(
+    all(target_os = "linux", any(target_env = "gnu", target_env = "musl")),
+    target_os = "macos"
+))]
+mod spawn {
+    use libc::{c_char, c_int};
+    use std::ffi::CStr;
+    use std::io::{Error, Result};
+    use std::mem;
+
+    // The posix_spawn functions return the error number instead of
+    // setting errno.
+    pub fn check_spawn(rc: libc::c_int) -> Result<()> {
+        if rc != 0 {
+            return Err(Error::from_raw_os_error(rc));
+        }
+        Ok(())
+    }
+
+    pub struct SpawnFileActions(pub libc::posix_spawn_file_actions_t);
+
+    impl SpawnFileActions {
+        pub fn new() -> Result<SpawnFileActions> {
+            let mut actions = mem::MaybeUninit::uninit();
+            unsafe {
+                check_spawn(libc::posix_spawn_file_actions_init(actions.as_mut_ptr()))?;
+                Ok(SpawnFileActions(actions.assume_init()))
+            }
+        }
+    }
+
+    impl Drop for SpawnFileActions {
+        fn drop(&mut self) {
+            unsafe {
+                libc::posix_spawn_file_actions_destroy(&mut self.0);
+            }
+        }
+    }
+
+    pub struct SpawnAttr(pub libc::posix_spawnattr_t);
+
+    impl SpawnAttr {
+        pub fn new() -> Result<SpawnAttr> {
+            let mut attr = mem::MaybeUninit::uninit();
+            unsafe {
+                check_spawn(libc::posix_spawnattr_init(attr.as_mut_ptr()))?;
+                Ok(SpawnAttr(attr.assume_init()))
+            }
+        }
+    }
+
+    impl Drop for SpawnAttr {
+        fn drop(&mut self) {
+            unsafe {
+                libc::posix_spawnattr_destroy(&mut self.0);
+            }
+        }
+    }
+
+    // The environment of the current process, passed on when no
+    // environment is given.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn environ() -> *const *const c_char {
+        extern "C" {
+            static environ: *const *const c_char;
+        }
+        environ
+    }
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn environ() -> *const *const c_char {
+        *libc::_NSGetEnviron() as *const *const c_char
+    }
+
+    type AddChdir =
+        unsafe extern "C" fn(*mut libc::posix_spawn_file_actions_t, *const c_char) -> c_int;
+    type AddFchdir = unsafe extern "C" fn(*mut libc::posix_spawn_file_actions_t, c_int) -> c_int;
+
+    // Look up a function of the C library at run time, so that the caller
+    // can fall back to another way if the library is too old to provide
+    // it.
+    fn lookup(name: &CStr) -> Option<*mut libc::c_void> {
+        let addr = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+        if addr.is_null() {
+            None
+        } else {
+            Some(addr)
+        }
+    }
+
+    // posix_spawn_file_actions_addchdir_np(), available since glibc 2.29
+    // and macOS 10.15.
+    pub fn addchdir_np() -> Option<AddChdir> {
+        let name = CStr::from_bytes_with_nul(b"posix_spawn_file_actions_addchdir_np\0").unwrap();
+        lookup(name).map(|addr| unsafe { mem::transmute::<*mut libc::c_void, AddChdir>(addr) })
+    }
+
+    // posix_spawn_file_actions_addfchdir_np(), likewise.
+    pub fn addfchdir_np() -> Option<AddFchdir> {
+        let name = CStr::from_bytes_with_nul(b"posix_spawn_file_actions_addfchdir_np\0").unwrap();
+        lookup(name).map(|addr| unsafe { mem::transmute::<*mut libc::c_void, AddFchdir>(addr) })
+    }
+}
+
+// Write n in decimal followed by NUL to buf, without allocating.
+fn write_decimal(buf: &mut [u8], mut n: u32) {
+    let mut digits = [0u8; 10];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    let len = digits.len() - start;
+    buf[..len].copy_from_slice(&digits[start..]);
+    buf[len] = 0;
+}
+
 /// Prepare everything needed to `exec()` the provided `cmd` after `fork()`.
 ///
 /// Since code executed in the child after a `fork()` is not allowed to
 /// allocate (because the lock might be held), this allocates everything
-/// beforehand.
-
+/// beforehand.  If `pid_var` is given, the environment variable of that
+/// name is added to `env`, which must be given, and set to the PID of
+/// the process that executes `cmd`.
+///
+/// The program can also be started with `PrepExec::spawn`, which
+/// doesn't fork the current process.
 pub fn prep_exec(
     cmd: impl AsRef<OsStr>,
     args: &[impl AsRef<OsStr>],
     env: Option<&[impl AsRef<OsStr>]>,
-) -> Result<impl FnOnce() -> Result<()>> {
+    pid_var: Option<&str>,
+) -> Result<PrepExec> {
     let cmd = cmd.as_ref().to_owned();
     let argvec = CVec::new(args)?;
     let envvec = if let Some(env) = env {
@@ -246,17 +964,16 @@ pub fn prep_exec(
         None
     };
 
-    let search_path = if !cmd.as_bytes().iter().any(|&b| b == b'/') {
+    let search_path = if !cmd.as_bytes().contains(&b'/') {
         env::var_os("PATH")
             // treat empty path as non-existent
-            .and_then(|p| if p.len() == 0 { None } else { Some(p) })
+            .and_then(|p| if p.is_empty() { None } else { Some(p) })
     } else {
         None
     };
 
-    // Allocate now and return a closure that just does the exec.
-    let prep = PrepExec::new(cmd, argvec, envvec, search_path);
-    Ok(move || prep.exec())
+    // Allocate now, so that exec() just does the exec.
+    Ok(PrepExec::new(cmd, argvec, envvec, search_path, pid_var))
 }
 
 pub fn _exit(status: u8) -> ! {
@@ -277,7 +994,108 @@ pub fn waitpid(pid: u32, flags: i32) -> Result<(u32, ExitStatus)> {
     Ok((pid as u32, decode_exit_status(status)))
 }
 
-fn decode_exit_status(status: i32) -> ExitStatus {
+// Like waitpid(), but also return whether the child dumped core, and
+// the resources it used.
+pub fn wait4(pid: u32, flags: i32) -> Result<(u32, ExitStatus, bool, ResourceUsage)> {
+    let mut status = 0 as c_int;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    let pid = check_err(unsafe {
+        libc::wait4(
+            pid as libc::pid_t,
+            &mut status as *mut c_int,
+            flags as c_int,
+            &mut rusage,
+        )
+    })?;
+    fn duration(tv: libc::timeval) -> Duration {
+        Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+    }
+    // ru_maxrss is in kilobytes, except on macOS.
+    let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    let usage = ResourceUsage {
+        user_time: duration(rusage.ru_utime),
+        system_time: duration(rusage.ru_stime),
+        max_rss: rusage.ru_maxrss as u64 * rss_unit,
+        minor_faults: rusage.ru_minflt as u64,
+        major_faults: rusage.ru_majflt as u64,
+    };
+    let core_dumped = libc::WIFSIGNALED(status) && libc::WCOREDUMP(status);
+    Ok((pid as u32, decode_exit_status(status), core_dumped, usage))
+}
+
+// Wait for any child process, returning (0, Undetermined) with WNOHANG
+// if none has exited.
+pub fn waitpid_any(flags: i32) -> Result<(u32, ExitStatus)> {
+    let mut status = 0 as c_int;
+    let pid = check_err(unsafe { libc::waitpid(-1, &mut status as *mut c_int, flags as c_int) })?;
+    if pid == 0 {
+        return Ok((0, ExitStatus::Undetermined));
+    }
+    Ok((pid as u32, decode_exit_status(status)))
+}
+
+// Block until the process, or any child if pid is None, exits, leaving
+// it waitable for waitpid().
+pub fn wait_exit_nowait(pid: Option<u32>) -> Result<()> {
+    let (idtype, id) = match pid {
+        Some(pid) => (libc::P_PID, pid as libc::id_t),
+        None => (libc::P_ALL, 0),
+    };
+    loop {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let rc = unsafe { libc::waitid(idtype, id, &mut info, libc::WEXITED | libc::WNOWAIT) };
+        match check_err(rc) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => continue,
+            result => return result.map(|_| ()),
+        }
+    }
+}
+
+// Block until the child stops or exits, leaving it waitable, and return
+// whether it has stopped.
+pub fn wait_stop_nowait(pid: u32) -> Result<bool> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let rc = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WSTOPPED | libc::WNOWAIT,
+            )
+        };
+        match check_err(rc) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => continue,
+            result => return result.map(|_| info.si_code == libc::CLD_STOPPED),
+        }
+    }
+}
+
+// Return the exit status of the child if it has exited, without
+// reaping it.
+pub fn peek_exit_status(pid: u32) -> Result<Option<ExitStatus>> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    check_err(unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    })?;
+    // With WNOHANG, si_pid is left zero if the child is still running.
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+    let status = unsafe { info.si_status() };
+    Ok(Some(match info.si_code {
+        libc::CLD_EXITED => ExitStatus::Exited(status as u32),
+        libc::CLD_KILLED | libc::CLD_DUMPED => ExitStatus::Signaled(status as u8),
+        _ => ExitStatus::Other(status),
+    }))
+}
+
+pub fn decode_exit_status(status: i32) -> ExitStatus {
     if libc::WIFEXITED(status) {
         ExitStatus::Exited(libc::WEXITSTATUS(status) as u32)
     } else if libc::WIFSIGNALED(status) {
@@ -287,7 +1105,286 @@ fn decode_exit_status(status: i32) -> ExitStatus {
     }
 }
 
-pub use libc::{SIGKILL, SIGTERM};
+// Obtain a file descriptor referring to the process, which becomes readable
+// when the process exits.  Fails with ENOSYS on kernels older than 5.3.
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: u32) -> Result<File> {
+    let fd = check_err(unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) })?;
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+#[cfg(target_os = "linux")]
+pub fn pidfd_send_signal(pidfd: &File, signal: i32) -> Result<()> {
+    check_err(unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+// Make orphaned descendants be re-parented to the calling process
+// instead of init.
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper() -> Result<()> {
+    check_err(unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) })?;
+    Ok(())
+}
+
+// Obtain the PID of the process referred to by the pidfd.
+#[cfg(target_os = "linux")]
+pub fn pidfd_pid(pidfd: &File) -> Result<u32> {
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()))?;
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Pid:"))
+        .and_then(|pid| pid.trim().parse::<i64>().ok())
+        .filter(|&pid| pid > 0)
+        .map(|pid| pid as u32)
+        .ok_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "not a pidfd of a running process",
+            )
+        })
+}
+
+// Create a kqueue that becomes readable when the process exits.
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub fn kqueue_proc_exit(pid: u32) -> Result<File> {
+    let kq = unsafe { File::from_raw_fd(check_err(libc::kqueue())?) };
+    set_cloexec(kq.as_raw_fd(), true)?;
+    let mut event: libc::kevent = unsafe { mem::zeroed() };
+    event.ident = pid as _;
+    event.filter = libc::EVFILT_PROC as _;
+    event.flags = (libc::EV_ADD | libc::EV_ONESHOT) as _;
+    event.fflags = libc::NOTE_EXIT as _;
+    check_err(unsafe { libc::kevent(kq.as_raw_fd(), &event, 1, ptr::null_mut(), 0, ptr::null()) })?;
+    Ok(kq)
+}
+
+pub use libc::{SIGCONT, SIGKILL, SIGSTOP, SIGTERM};
+
+// Return the PIDs of the children of the process.
+#[cfg(target_os = "linux")]
+pub fn child_pids(pid: u32) -> Result<Vec<u32>> {
+    let mut children = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let child: u32 = match entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(child) => child,
+            None => continue,
+        };
+        // The process may have exited since listing the directory.
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The command name in parentheses can contain spaces and
+        // parentheses, so the fields are looked up after the last ')'.
+        let ppid = stat
+            .rfind(')')
+            .and_then(|pos| stat[pos + 1..].split_whitespace().nth(1))
+            .and_then(|ppid| ppid.parse::<u32>().ok());
+        if ppid == Some(pid) {
+            children.push(child);
+        }
+    }
+    Ok(children)
+}
+
+#[cfg(target_os = "macos")]
+pub fn child_pids(pid: u32) -> Result<Vec<u32>> {
+    let mut buf: Vec<libc::pid_t> = vec![0; 64];
+    loop {
+        let size = (buf.len() * mem::size_of::<libc::pid_t>()) as c_int;
+        let count = check_err(unsafe {
+            libc::proc_listchildpids(pid as libc::pid_t, buf.as_mut_ptr() as *mut _, size)
+        })? as usize;
+        // A full buffer means that there might be more children.
+        if count < buf.len() {
+            return Ok(buf[..count].iter().map(|&pid| pid as u32).collect());
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn child_pids(_pid: u32) -> Result<Vec<u32>> {
+    Err(Error::new(
+        std::io::ErrorKind::Other,
+        "listing child processes is not supported on this platform",
+    ))
+}
+
+// Read the fields of /proc/<pid>/stat following the command name,
+// starting with the state, which is field 3 in proc(5).
+#[cfg(target_os = "linux")]
+fn proc_stat(pid: u32) -> Result<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    Ok(match stat.rfind(')') {
+        Some(pos) => stat[pos + 1..]
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect(),
+        None => vec![],
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn proc_stat_field(fields: &[String], n: usize) -> Result<u64> {
+    fields
+        .get(n - 3)
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| Error::from_raw_os_error(libc::EINVAL))
+}
+
+// Return the current memory and CPU use of the process.
+#[cfg(target_os = "linux")]
+pub fn sample_usage(pid: u32) -> Result<UsageSample> {
+    let fields = proc_stat(pid)?;
+    let field = |n: usize| proc_stat_field(&fields, n);
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let from_ticks = |t: u64| Duration::from_nanos(t * 1_000_000_000 / ticks);
+    Ok(UsageSample {
+        rss: field(24)? * page_size,
+        user_time: from_ticks(field(14)?),
+        system_time: from_ticks(field(15)?),
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn sample_usage(pid: u32) -> Result<UsageSample> {
+    let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_taskinfo>() as c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as c_int,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        return Err(Error::last_os_error());
+    }
+    Ok(UsageSample {
+        rss: info.pti_resident_size,
+        user_time: from_mach_time(info.pti_total_user),
+        system_time: from_mach_time(info.pti_total_system),
+    })
+}
+
+// Convert CPU times reported by proc_pidinfo, which are in mach absolute
+// time units rather than nanoseconds on Apple silicon.
+#[cfg(target_os = "macos")]
+fn from_mach_time(time: u64) -> Duration {
+    #[repr(C)]
+    struct TimebaseInfo {
+        numer: u32,
+        denom: u32,
+    }
+    extern "C" {
+        fn mach_timebase_info(info: *mut TimebaseInfo) -> c_int;
+    }
+    let mut timebase = TimebaseInfo { numer: 1, denom: 1 };
+    if unsafe { mach_timebase_info(&mut timebase) } != 0 || timebase.denom == 0 {
+        return Duration::from_nanos(time);
+    }
+    let nanos = time as u128 * timebase.numer as u128 / timebase.denom as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn sample_usage(_pid: u32) -> Result<UsageSample> {
+    Err(Error::new(
+        std::io::ErrorKind::Other,
+        "sampling resource usage is not supported on this platform",
+    ))
+}
+
+// What is known about a process that exists, possibly as a zombie.
+pub struct ProcessState {
+    // False once the process has exited, if that can be told.
+    pub running: bool,
+    // Start time in platform-specific units, where available, used to
+    // tell the process from a later one with the same PID.
+    pub start_time: Option<u64>,
+}
+
+// Return the state of the process, or None if it doesn't exist.
+#[cfg(target_os = "linux")]
+pub fn process_state(pid: u32) -> Result<Option<ProcessState>> {
+    let fields = match proc_stat(pid) {
+        Ok(fields) => fields,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let state = fields.first().map(String::as_str);
+    Ok(Some(ProcessState {
+        running: !matches!(state, Some("Z") | Some("X")),
+        start_time: Some(proc_stat_field(&fields, 22)?),
+    }))
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_state(pid: u32) -> Result<Option<ProcessState>> {
+    // from <sys/proc.h>
+    const SZOMB: u32 = 5;
+    let mut info: libc::proc_bsdinfo = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::proc_bsdinfo>() as c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid as c_int,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if written != size {
+        let err = Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    Ok(Some(ProcessState {
+        running: info.pbi_status != SZOMB,
+        start_time: Some(info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec),
+    }))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn process_state(pid: u32) -> Result<Option<ProcessState>> {
+    match kill(pid, 0) {
+        // exists, possibly owned by another user
+        Ok(()) => {}
+        Err(ref e) if e.raw_os_error() == Some(libc::EPERM) => {}
+        Err(ref e) if e.raw_os_error() == Some(libc::ESRCH) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    Ok(Some(ProcessState {
+        running: true,
+        start_time: None,
+    }))
+}
+
+pub fn killpg(pgid: u32, signal: i32) -> Result<()> {
+    check_err(unsafe { libc::killpg(pgid as libc::pid_t, signal) })?;
+    Ok(())
+}
+
+pub fn raise(signal: i32) -> Result<()> {
+    check_err(unsafe { libc::raise(signal) })?;
+    Ok(())
+}
 
 pub fn kill(pid: u32, signal: i32) -> Result<()> {
     check_err(unsafe { libc::kill(pid as c_int, signal) })?;
@@ -307,6 +1404,53 @@ pub fn fcntl(fd: i32, cmd: i32, arg1: Option<i32>) -> Result<i32> {
     })
 }
 
+// Set the capacity of a pipe, capped at the maximum allowed to
+// unprivileged processes, and return the capacity set, which the
+// kernel rounds up to a power of two number of pages.
+#[cfg(target_os = "linux")]
+pub fn set_pipe_size(fd: RawFd, size: usize) -> Result<usize> {
+    let max_size = std::fs::read_to_string("/proc/sys/fs/pipe-max-size")
+        .ok()
+        .and_then(|max_size| max_size.trim().parse().ok())
+        .unwrap_or(c_int::MAX as usize);
+    let size = size.min(max_size).min(c_int::MAX as usize);
+    Ok(fcntl(fd, libc::F_SETPIPE_SZ, Some(size as c_int))? as usize)
+}
+
+#[cfg(target_os = "linux")]
+pub fn pipe_size(fd: RawFd) -> Result<usize> {
+    Ok(fcntl(fd, libc::F_GETPIPE_SZ, None)? as usize)
+}
+
+pub fn is_cloexec(fd: RawFd) -> Result<bool> {
+    Ok(fcntl(fd, F_GETFD, None)? & FD_CLOEXEC != 0)
+}
+
+pub fn set_cloexec(fd: RawFd, cloexec: bool) -> Result<()> {
+    let old = fcntl(fd, F_GETFD, None)?;
+    let new = if cloexec {
+        old | FD_CLOEXEC
+    } else {
+        old & !FD_CLOEXEC
+    };
+    if new != old {
+        fcntl(fd, F_SETFD, Some(new))?;
+    }
+    Ok(())
+}
+
+// Duplicate fd to the lowest free descriptor not below min, with
+// FD_CLOEXEC set.
+pub fn dup_above(fd: &impl AsRawFd, min: RawFd) -> Result<OwnedFd> {
+    let new_fd = fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, Some(min))?;
+    Ok(unsafe { OwnedFd::from_raw_fd(new_fd) })
+}
+
+pub fn close(fd: i32) -> Result<()> {
+    check_err(unsafe { libc::close(fd) })?;
+    Ok(())
+}
+
 pub fn dup2(oldfd: i32, newfd: i32) -> Result<()> {
     check_err(unsafe { libc::dup2(oldfd, newfd) })?;
     Ok(())
@@ -371,7 +1515,7 @@ impl PollFd<'_> {
     }
 }
 
-pub use libc::{POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI};
+pub use libc::{POLLHUP, POLLIN, POLLOUT};
 
 pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usize> {
     let deadline = timeout.map(|timeout| Instant::now() + timeout);
@@ -382,20 +1526,30 @@ pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usi
         let (timeout_ms, overflow) = timeout
             .map(|timeout| {
                 let timeout = timeout.as_millis();
-                if timeout <= i32::max_value() as u128 {
+                if timeout <= i32::MAX as u128 {
                     (timeout as i32, false)
                 } else {
-                    (i32::max_value(), true)
+                    (i32::MAX, true)
                 }
             })
             .unwrap_or((-1, false));
         let fds_ptr = fds.as_ptr() as *mut libc::pollfd;
-        let cnt = unsafe { check_err(libc::poll(fds_ptr, fds.len() as libc::nfds_t, timeout_ms))? };
-        if cnt != 0 || !overflow {
+        let cnt = unsafe { libc::poll(fds_ptr, fds.len() as libc::nfds_t, timeout_ms) };
+        if cnt < 0 {
+            let err = Error::last_os_error();
+            // poll() is not restarted after a signal handler runs, even
+            // with SA_RESTART, e.g. when SIGCHLD is being handled.
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(err);
+            }
+        }
+        if cnt > 0 || (cnt == 0 && !overflow) {
             return Ok(cnt as usize);
         }
-
-        let deadline = deadline.unwrap();
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => continue,
+        };
         let now = Instant::now();
         if now >= deadline {
             return Ok(0);
@@ -403,3 +1557,31 @@ pub fn poll(fds: &mut [PollFd<'_>], mut timeout: Option<Duration>) -> Result<usi
         timeout = Some(deadline - now);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::split_path;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn s(s: &str) -> Vec<&str> {
+        split_path(OsStr::new(s))
+            .map(|osstr| std::str::from_utf8(osstr.as_bytes()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_path() {
+        let empty = Vec::<&OsStr>::new();
+
+        assert_eq!(s("a:b"), vec!["a", "b"]);
+        assert_eq!(s("one:twothree"), vec!["one", "twothree"]);
+        assert_eq!(s("a:"), vec!["a"]);
+        assert_eq!(s(""), empty);
+        assert_eq!(s(":"), empty);
+        assert_eq!(s("::"), empty);
+        assert_eq!(s(":::"), empty);
+        assert_eq!(s("a::b"), vec!["a", "b"]);
+        assert_eq!(s(":a::::b:"), vec!["a", "b"]);
+    }
+}
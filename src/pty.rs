@@ -0,0 +1,273 @@
+// Pseudo terminals attached to subprocesses, see PopenConfig::pty.
+
+use std::io;
+use std::sync::Arc;
+
+#[cfg(all(unix, feature = "sigwinch"))]
+pub use self::relay::ResizeRelay;
+
+/// The size of a pseudo terminal, in character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PtySize {
+    /// Number of rows.
+    pub rows: u16,
+    /// Number of columns.
+    pub cols: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> PtySize {
+        PtySize { rows: 24, cols: 80 }
+    }
+}
+
+/// A pseudo terminal connected to a subprocess.
+///
+/// Created when the subprocess is started with [`PopenConfig::pty`],
+/// and available through [`Popen::pty`].  The subprocess sees the
+/// terminal as its console, reading from the `stdin` of the `Popen` and
+/// writing to its `stdout`, so that programs which only behave
+/// interactively on a terminal can be driven and captured.
+///
+/// On Unix, the terminal is allocated with `openpty()`, and the `stdin`
+/// and `stdout` of the `Popen` are both connected to its master side,
+/// which is also available through `AsRawFd`, for example to change the
+/// terminal attributes.  The terminal converts line endings in the
+/// output to `\r\n` and, unless raw, echoes the input.  Closing `stdin`
+/// doesn't end the input, which is done by writing the end-of-file
+/// character, normally `\x04`.  Once the subprocess and its descendants
+/// have closed the terminal, reading the master side fails with `EIO`,
+/// which `communicate` treats as the end of the output.
+///
+/// On Windows, the terminal is a pseudo console (ConPTY), which
+/// requires Windows 10 1809 or later.  The output contains the escape
+/// sequences the pseudo console uses to render the screen.  The pseudo
+/// console is closed when the subprocess exits, which ends the output.
+///
+/// [`PopenConfig::pty`]: struct.PopenConfig.html#structfield.pty
+/// [`Popen::pty`]: struct.Popen.html#method.pty
+#[derive(Debug, Clone)]
+pub struct Pty {
+    #[cfg(unix)]
+    master: Arc<std::fs::File>,
+    #[cfg(windows)]
+    console: Arc<std::sync::Mutex<Option<crate::win32::PseudoConsole>>>,
+}
+
+impl Pty {
+    /// Change the size of the terminal.
+    ///
+    /// On Unix, the foreground process group of the terminal receives
+    /// `SIGWINCH`.  On Windows, this fails once the subprocess has
+    /// exited and the pseudo console has been closed.
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.os_resize(size)
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::{Pty, PtySize};
+    use crate::posix;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+    use std::sync::Arc;
+
+    impl PtySize {
+        /// Return the size of a terminal, such as the standard input of
+        /// the current process when it runs in one.
+        pub fn of_terminal(terminal: impl AsFd) -> io::Result<PtySize> {
+            let (rows, cols) = posix::get_winsize(terminal.as_fd().as_raw_fd())?;
+            Ok(PtySize { rows, cols })
+        }
+    }
+
+    impl Pty {
+        // Allocate a pseudo terminal, returning it together with the
+        // slave side for the child.
+        pub(crate) fn open(size: PtySize, raw: bool, echo: bool) -> io::Result<(Pty, File)> {
+            let (master, slave) = posix::openpty(size.rows, size.cols)?;
+            posix::set_terminal_mode(slave.as_raw_fd(), raw, echo)?;
+            let pty = Pty {
+                master: Arc::new(master),
+            };
+            Ok((pty, slave))
+        }
+
+        // Return a new handle to the master side, for the streams of
+        // the Popen.
+        pub(crate) fn master(&self) -> io::Result<File> {
+            self.master.try_clone()
+        }
+
+        pub(super) fn os_resize(&self, size: PtySize) -> io::Result<()> {
+            posix::set_winsize(self.master.as_raw_fd(), size.rows, size.cols)
+        }
+    }
+
+    impl AsFd for Pty {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.master.as_fd()
+        }
+    }
+
+    impl AsRawFd for Pty {
+        fn as_raw_fd(&self) -> RawFd {
+            self.master.as_raw_fd()
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "sigwinch"))]
+mod relay {
+    use super::{Pty, PtySize};
+    use signal_hook::consts::SIGWINCH;
+    use signal_hook::SigId;
+    use std::io::{self, Read, Write};
+    use std::os::unix::io::AsFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    impl Pty {
+        /// Mirror the size of a terminal to the pseudo terminal.
+        ///
+        /// Sets the size of the pseudo terminal to that of
+        /// `terminal`, typically `std::io::stdin()` of an
+        /// interactive program, and again whenever the current
+        /// process receives `SIGWINCH`, so that full-screen programs
+        /// in the subprocess follow the size of the real terminal.
+        /// This stops when the returned `ResizeRelay` is dropped.
+        ///
+        /// The `SIGWINCH` handler is installed using the
+        /// `signal-hook` crate, which cooperates with previously
+        /// installed handlers.
+        ///
+        /// Only available with the `sigwinch` feature.
+        pub fn relay_resize(
+            &self,
+            terminal: impl AsFd + Send + 'static,
+        ) -> io::Result<ResizeRelay> {
+            self.resize(PtySize::of_terminal(&terminal)?)?;
+            let (mut reader, writer) = UnixStream::pair()?;
+            let waker = writer.try_clone()?;
+            let signal = signal_hook::low_level::pipe::register(SIGWINCH, writer)?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread_stop = Arc::clone(&stop);
+            let pty = self.clone();
+            let spawned = thread::Builder::new()
+                .name("subprocess-sigwinch".into())
+                .spawn(move || {
+                    let mut buf = [0u8; 64];
+                    loop {
+                        match reader.read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(_) => (),
+                            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                            Err(_) => break,
+                        }
+                        if thread_stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        // keep relaying if the terminal can't be
+                        // queried for a moment
+                        if let Ok(size) = PtySize::of_terminal(&terminal) {
+                            let _ = pty.resize(size);
+                        }
+                    }
+                });
+            if let Err(e) = spawned {
+                signal_hook::low_level::unregister(signal);
+                return Err(e);
+            }
+            Ok(ResizeRelay {
+                signal,
+                stop,
+                waker,
+            })
+        }
+    }
+
+    /// Relay of terminal size changes to a pseudo terminal, created
+    /// by [`Pty::relay_resize`].
+    ///
+    /// Dropping it stops the relay.
+    ///
+    /// Only available with the `sigwinch` feature.
+    ///
+    /// [`Pty::relay_resize`]: ../struct.Pty.html#method.relay_resize
+    #[derive(Debug)]
+    pub struct ResizeRelay {
+        signal: SigId,
+        stop: Arc<AtomicBool>,
+        waker: UnixStream,
+    }
+
+    impl Drop for ResizeRelay {
+        fn drop(&mut self) {
+            signal_hook::low_level::unregister(self.signal);
+            self.stop.store(true, Ordering::SeqCst);
+            let _ = self.waker.write(&[0]);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::{Pty, PtySize};
+    use crate::win32::{self, Handle};
+    use std::fs::File;
+    use std::io;
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::thread;
+
+    impl Pty {
+        // Create a pseudo console and the pipes connected to it,
+        // returning the parent's ends: the input writer and the output
+        // reader.
+        pub(crate) fn open(size: PtySize) -> io::Result<(Pty, File, File)> {
+            let (input_read, input_write) = win32::CreatePipe(false)?;
+            let (output_read, output_write) = win32::CreatePipe(false)?;
+            // the pseudo console keeps its own references to the pipe
+            // ends it was given, which are closed here
+            let console =
+                win32::CreatePseudoConsole(size.rows, size.cols, &input_read, &output_write)?;
+            let pty = Pty {
+                console: Arc::new(Mutex::new(Some(console))),
+            };
+            Ok((pty, input_write, output_read))
+        }
+
+        pub(super) fn os_resize(&self, size: PtySize) -> io::Result<()> {
+            match *self.lock_console() {
+                Some(ref console) => console.resize(size.rows, size.cols),
+                None => Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "the pseudo console is closed",
+                )),
+            }
+        }
+
+        pub(crate) fn lock_console(&self) -> MutexGuard<'_, Option<win32::PseudoConsole>> {
+            self.console.lock().unwrap()
+        }
+
+        // The output pipe only reaches EOF once the pseudo console is
+        // closed, so close it when the process exits.
+        pub(crate) fn close_on_exit(&self, process: Handle) -> io::Result<()> {
+            let console = Arc::clone(&self.console);
+            thread::Builder::new()
+                .name("subprocess-pty".into())
+                .spawn(move || {
+                    let _ = win32::WaitForSingleObject(&process, None);
+                    let console = console.lock().unwrap().take();
+                    // closing can block until the output is drained, so
+                    // do it without holding the lock
+                    drop(console);
+                })?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Threads copying data between the pipes of a subprocess and the
+// destinations of its redirections, see Redirection::Tee,
+// Redirection::Writer and Redirection::Reader.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::thread::{self, JoinHandle};
+
+// Copy what the child writes to `source` both to `target` and to
+// `dest`, the pipe read by the parent.  A destination that fails, for
+// example because the parent has closed its end, is dropped, and the
+// copy stops when both have failed, so that the child then sees its
+// pipe closed, or when the child closes its end.
+pub(crate) fn tee(mut source: File, target: File, dest: File) -> io::Result<()> {
+    thread::Builder::new()
+        .name("subprocess-tee".into())
+        .spawn(move || {
+            let mut buf = vec![0u8; 65536];
+            let mut outputs = [Some(target), Some(dest)];
+            while outputs.iter().any(Option::is_some) {
+                let n = match source.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                for output in &mut outputs {
+                    if let Some(ref mut f) = *output {
+                        if f.write_all(&buf[..n]).is_err() {
+                            *output = None;
+                        }
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// Copy what the child writes to `source` to `sink`, flushing it once
+// the child closes its end.  If writing fails, the copy stops, and the
+// child then sees its pipe closed.
+pub(crate) fn to_writer(
+    mut source: File,
+    mut sink: Box<dyn Write + Send>,
+) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("subprocess-writer".into())
+        .spawn(move || {
+            if io::copy(&mut source, &mut sink).is_ok() {
+                let _ = sink.flush();
+            }
+        })
+}
+
+// Copy `source` to `dest`, the pipe read by the child, closing it at
+// the end of the input so that the child sees end-of-file.  The copy
+// stops early if the child closes its end.
+pub(crate) fn from_reader(mut source: Box<dyn Read + Send>, mut dest: File) -> io::Result<()> {
+    thread::Builder::new()
+        .name("subprocess-reader".into())
+        .spawn(move || {
+            let _ = io::copy(&mut source, &mut dest);
+        })?;
+    Ok(())
+}
@@ -0,0 +1,212 @@
+// Reaping of orphaned processes, for programs running as PID 1.
+//
+// A background thread sleeps until some child has exited and then
+// collects all exited children with waitpid(-1).  Children started by
+// Popen are tracked so that their exit status, if collected by the
+// reaper, is kept for the Popen to find when its own waitpid() fails
+// with ECHILD.  The exit status of other processes, typically orphans
+// re-parented to init or to a subreaper, is sent to the subscribed
+// channel, if any, and otherwise discarded.
+//
+// The lock on the tracked children is held by the reaper while it
+// collects exit statuses, and by Popen across fork(), so that a child
+// can't be collected before it is tracked, nor looked up before its
+// status is recorded.
+//
+// Separately, children of Popens dropped with DropPolicy::Reap are
+// handed to another thread, which reaps just those once they exit.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, MutexGuard, Once};
+use std::thread;
+use std::time::Duration;
+
+use crate::os_common::ExitStatus;
+use crate::posix;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+// PIDs of tracked children, along with their exit status once reaped.
+static TRACKED: Mutex<Vec<(u32, Option<ExitStatus>)>> = Mutex::new(Vec::new());
+// Receives the exit status of reaped processes that aren't tracked.
+#[cfg(target_os = "linux")]
+static ORPHANS: Mutex<Option<mpsc::Sender<(u32, ExitStatus)>>> = Mutex::new(None);
+
+/// Reap orphaned processes, as init does.
+///
+/// When a process exits, its children are re-parented to PID 1, which
+/// is expected to wait for them once they exit.  A program running as
+/// PID 1, as is common in containers, must therefore collect the exit
+/// status of processes it never started, or they remain as zombies.
+///
+/// This starts a thread that waits for any child process as soon as it
+/// exits.  Subprocesses started with `Popen` after the call are
+/// unaffected: if the thread collects one of them, its exit status is
+/// kept and returned by `Popen::wait` as usual.  Children started
+/// otherwise, e.g. with `std::process::Command`, can't be waited for by
+/// other means once the thread is running.  Calling this function again
+/// has no effect.
+///
+/// In a process other than PID 1, orphans are not re-parented to it
+/// (unless it is a subreaper), so this only reaps the process's own
+/// children.
+pub fn start_init_reaper() -> io::Result<()> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let spawned = thread::Builder::new()
+        .name("subprocess-reaper".into())
+        .spawn(|| loop {
+            match posix::wait_exit_nowait(None) {
+                Ok(()) => reap_exited(),
+                // No children yet; orphans may be adopted at any time.
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        });
+    if let Err(e) = spawned {
+        RUNNING.store(false, Ordering::SeqCst);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Make orphaned descendants be re-parented to the current process.
+///
+/// Marks the current process as a child subreaper with
+/// `prctl(PR_SET_CHILD_SUBREAPER)`, so that descendants orphaned by
+/// the exit of their parent, such as commands started in the background
+/// by an intermediate shell, are re-parented to it rather than to init.
+/// The process must then reap them to avoid leaving zombies, e.g. with
+/// [`start_subreaper`].
+///
+/// This function is only available on Linux.
+///
+/// [`start_subreaper`]: fn.start_subreaper.html
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper() -> io::Result<()> {
+    posix::set_child_subreaper()
+}
+
+/// Become a subreaper and reap orphaned descendants.
+///
+/// Marks the current process as a child subreaper, as with
+/// [`set_child_subreaper`], and starts the reaping thread described in [`start_init_reaper`],
+/// and returns a channel that receives the PID and exit status of each
+/// reaped process not started with `Popen`.
+///
+/// Calling this function again returns a new channel, which replaces
+/// the previous one.
+///
+/// This function is only available on Linux.
+///
+/// [`set_child_subreaper`]: fn.set_child_subreaper.html
+/// [`start_init_reaper`]: fn.start_init_reaper.html
+#[cfg(target_os = "linux")]
+pub fn start_subreaper() -> io::Result<mpsc::Receiver<(u32, ExitStatus)>> {
+    set_child_subreaper()?;
+    let orphans = subscribe();
+    start_init_reaper()?;
+    Ok(orphans)
+}
+
+// Send the exit status of reaped processes that aren't tracked to the
+// returned channel.
+#[cfg(target_os = "linux")]
+pub fn subscribe() -> mpsc::Receiver<(u32, ExitStatus)> {
+    let (tx, rx) = mpsc::channel();
+    *ORPHANS.lock().unwrap() = Some(tx);
+    rx
+}
+
+// Collect all exited children, recording the status of tracked ones.
+fn reap_exited() {
+    let mut tracked = TRACKED.lock().unwrap();
+    while let Ok((pid, status)) = posix::waitpid_any(posix::WNOHANG) {
+        if pid == 0 {
+            break;
+        }
+        record(&mut tracked, pid, status);
+    }
+}
+
+fn record(tracked: &mut [(u32, Option<ExitStatus>)], pid: u32, status: ExitStatus) {
+    if let Some(entry) = tracked.iter_mut().find(|entry| entry.0 == pid) {
+        entry.1 = Some(status);
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let mut orphans = ORPHANS.lock().unwrap();
+        if let Some(ref tx) = *orphans {
+            if tx.send((pid, status)).is_err() {
+                // the receiver is gone
+                *orphans = None;
+            }
+        }
+    }
+}
+
+// Children of dropped Popens, to be reaped in the background once they
+// exit.
+static ABANDONED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static ABANDONED_ADDED: Condvar = Condvar::new();
+
+// Reap the child in a background thread once it exits.
+pub fn reap_in_background(pid: u32) {
+    static START: Once = Once::new();
+    ABANDONED.lock().unwrap().push(pid);
+    ABANDONED_ADDED.notify_one();
+    START.call_once(|| {
+        // If the thread can't be started, the children remain zombies,
+        // as with Popen::detach().
+        thread::Builder::new()
+            .name("subprocess-zombie-reaper".into())
+            .spawn(reap_abandoned)
+            .ok();
+    });
+}
+
+fn reap_abandoned() {
+    loop {
+        let mut abandoned = ABANDONED.lock().unwrap();
+        while abandoned.is_empty() {
+            abandoned = ABANDONED_ADDED.wait(abandoned).unwrap();
+        }
+        // Keep the children that are still running.  An error means that
+        // the child has been reaped by someone else.
+        abandoned.retain(|&pid| matches!(posix::waitpid(pid, posix::WNOHANG), Ok((0, _))));
+        drop(abandoned);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Lock held across fork(), to be passed the PID of the new child.
+pub struct SpawnGuard(MutexGuard<'static, Vec<(u32, Option<ExitStatus>)>>);
+
+impl SpawnGuard {
+    pub fn track(mut self, pid: u32) {
+        self.0.push((pid, None));
+    }
+}
+
+// Return the guard to hold while starting a child, if the reaper is
+// running.
+pub fn spawn_guard() -> Option<SpawnGuard> {
+    if !RUNNING.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(SpawnGuard(TRACKED.lock().unwrap()))
+}
+
+// Stop tracking the child, returning its exit status if the reaper has
+// collected it.
+pub fn untrack(pid: u32) -> Option<ExitStatus> {
+    if !RUNNING.load(Ordering::SeqCst) {
+        return None;
+    }
+    let mut tracked = TRACKED.lock().unwrap();
+    let pos = tracked.iter().position(|entry| entry.0 == pid)?;
+    tracked.swap_remove(pos).1
+}
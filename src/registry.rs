@@ -0,0 +1,119 @@
+// Opt-in registry of the running subprocesses started with Popen, so
+// that they can all be stopped with one call, and when the program
+// exits.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+
+use crate::handle::ProcessHandle;
+use crate::popen::Popen;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTERED: Mutex<Vec<ProcessHandle>> = Mutex::new(Vec::new());
+
+/// Grace period given to subprocesses stopped when the program exits.
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Keep track of the subprocesses started from now on.
+///
+/// Once enabled, each subprocess started with `Popen` (including by
+/// `Exec` and `Pipeline`) is registered until its `Popen` is dropped,
+/// and all the registered subprocesses that are still running can be
+/// stopped with [`shutdown_all`].  They are also stopped, on a best-effort
+/// basis, when the program exits normally, i.e. by returning from `main`
+/// or calling `std::process::exit`, allowing each a grace period of one
+/// second.  Nothing is done if the program is killed or aborts.
+///
+/// Calling this function again has no effect.
+///
+/// [`shutdown_all`]: fn.shutdown_all.html
+pub fn enable_registry() {
+    static AT_EXIT: Once = Once::new();
+    ENABLED.store(true, Ordering::SeqCst);
+    AT_EXIT.call_once(|| unsafe {
+        libc::atexit(shutdown_at_exit);
+    });
+}
+
+/// Stop all the registered subprocesses that are still running.
+///
+/// Each subprocess is terminated, and those that don't exit within
+/// `grace` are killed.  Returns the number of subprocesses that were
+/// running.  The exit status of the subprocesses is still collected by
+/// their `Popen`s.
+///
+/// Subprocesses are only registered after a call to
+/// [`enable_registry`].
+///
+/// [`enable_registry`]: fn.enable_registry.html
+pub fn shutdown_all(grace: Duration) -> usize {
+    shutdown_where(|_| true, grace)
+}
+
+// Stop the registered subprocesses whose PID matches `pred`.
+pub(crate) fn shutdown_where(pred: impl Fn(u32) -> bool, grace: Duration) -> usize {
+    let running: Vec<ProcessHandle> = REGISTERED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|handle| pred(handle.pid()) && !handle.has_exited().unwrap_or(true))
+        .cloned()
+        .collect();
+    for handle in &running {
+        handle.terminate().ok();
+    }
+    let deadline = Instant::now() + grace;
+    for handle in &running {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !handle.wait_timeout(remaining).unwrap_or(false) {
+            handle.kill().ok();
+        }
+    }
+    running.len()
+}
+
+extern "C" fn shutdown_at_exit() {
+    shutdown_all(EXIT_GRACE);
+}
+
+// Register the newly started subprocess, if the registry is enabled.
+pub(crate) fn register(popen: &Popen) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Ok(handle) = popen.handle() {
+        REGISTERED.lock().unwrap().push(handle);
+    }
+}
+
+// Unregister the subprocesses that have exited, for use when a Popen
+// that no longer knows its PID is dropped.
+pub(crate) fn unregister_exited() {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    REGISTERED
+        .lock()
+        .unwrap()
+        .retain(|handle| !handle.has_exited().unwrap_or(true));
+}
+
+pub(crate) fn unregister(pid: u32) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    REGISTERED
+        .lock()
+        .unwrap()
+        .retain(|handle| handle.pid() != pid);
+}
+
+#[cfg(test)]
+pub(crate) fn is_registered(pid: u32) -> bool {
+    REGISTERED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|handle| handle.pid() == pid)
+}
@@ -0,0 +1,150 @@
+// Seccomp filters installed by the child before exec, see
+// PopenConfig::seccomp.
+
+/// A classic BPF instruction, laid out as `struct sock_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct BpfInstruction {
+    /// The opcode.
+    pub code: u16,
+    /// Jump offset if the condition is true.
+    pub jt: u8,
+    /// Jump offset if the condition is false.
+    pub jf: u8,
+    /// The generic operand.
+    pub k: u32,
+}
+
+/// Action taken by a seccomp filter for a system call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SeccompAction {
+    /// Allow the system call.
+    Allow,
+    /// Fail the system call with the given errno.
+    Errno(u16),
+    /// Kill the process with `SIGSYS`.
+    KillProcess,
+    /// Allow the system call, logging it to the audit log.
+    Log,
+}
+
+impl SeccompAction {
+    fn to_raw(self) -> u32 {
+        match self {
+            SeccompAction::Allow => SECCOMP_RET_ALLOW,
+            SeccompAction::Errno(errno) => SECCOMP_RET_ERRNO | errno as u32,
+            SeccompAction::KillProcess => SECCOMP_RET_KILL_PROCESS,
+            SeccompAction::Log => SECCOMP_RET_LOG,
+        }
+    }
+}
+
+/// A seccomp-BPF program restricting the system calls of a subprocess.
+///
+/// The filter is installed by passing it to [`PopenConfig::seccomp`].
+/// It can be a precompiled program, for example one exported by
+/// libseccomp, or a simple allowlist built with [`allowlist`].
+///
+/// [`PopenConfig::seccomp`]: ../struct.PopenConfig.html#structfield.seccomp
+/// [`allowlist`]: #method.allowlist
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeccompFilter {
+    program: Vec<BpfInstruction>,
+}
+
+// Constants from linux/seccomp.h, linux/filter.h and linux/audit.h.
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+// offsets in struct seccomp_data
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64"
+)))]
+const AUDIT_ARCH: Option<u32> = None;
+
+// x32 system calls have the same audit arch as x86_64, and are told
+// apart by this bit in the system call number.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+fn stmt(code: u16, k: u32) -> BpfInstruction {
+    BpfInstruction {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> BpfInstruction {
+    BpfInstruction { code, jt, jf, k }
+}
+
+impl SeccompFilter {
+    /// Create a filter from a precompiled BPF program.
+    ///
+    /// The program is checked by the kernel when the filter is
+    /// installed; an invalid one makes the spawn fail with `EINVAL`.
+    pub fn from_program(program: Vec<BpfInstruction>) -> SeccompFilter {
+        SeccompFilter { program }
+    }
+
+    /// Create a filter allowing only the given system calls, such as
+    /// `libc::SYS_read`, and taking `default` for all others.
+    ///
+    /// System calls made for a different architecture than the one this
+    /// crate is compiled for, for example 32-bit calls on x86_64, kill
+    /// the process.  The list must include `execve` for the subprocess
+    /// to start.
+    pub fn allowlist(syscalls: &[libc::c_long], default: SeccompAction) -> SeccompFilter {
+        let mut program = vec![];
+        if let Some(arch) = AUDIT_ARCH {
+            program.push(stmt(BPF_LD_W_ABS, ARCH_OFFSET));
+            program.push(jump(BPF_JMP_JEQ_K, arch, 1, 0));
+            program.push(stmt(BPF_RET_K, SECCOMP_RET_KILL_PROCESS));
+        }
+        program.push(stmt(BPF_LD_W_ABS, NR_OFFSET));
+        if let Some(bit) = X32_SYSCALL_BIT {
+            program.push(jump(BPF_JMP_JGE_K, bit, 0, 1));
+            program.push(stmt(BPF_RET_K, default.to_raw()));
+        }
+        for &nr in syscalls {
+            program.push(jump(BPF_JMP_JEQ_K, nr as u32, 0, 1));
+            program.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+        }
+        program.push(stmt(BPF_RET_K, default.to_raw()));
+        SeccompFilter { program }
+    }
+
+    /// Return the instructions of the BPF program.
+    pub fn program(&self) -> &[BpfInstruction] {
+        &self.program
+    }
+}
@@ -0,0 +1,84 @@
+// Shared notification of child exits via SIGCHLD.
+//
+// A single SIGCHLD handler, installed with signal-hook, writes to a pipe
+// drained by a helper thread, which increments a generation counter and
+// wakes up all threads waiting for a change.  This allows any number of
+// `Popen::wait_timeout` calls to sleep until some child has exited, instead
+// of polling with exponential backoff.
+
+use std::io::{self, Read};
+use std::os::unix::net::UnixStream;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use signal_hook::consts::SIGCHLD;
+
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+static GENERATION: Mutex<u64> = Mutex::new(0);
+static CHANGED: Condvar = Condvar::new();
+
+/// Wake up `wait_timeout` calls as soon as a child process exits.
+///
+/// This installs a `SIGCHLD` handler using the `signal-hook` crate, which
+/// cooperates with previously installed handlers.  Once installed,
+/// `Popen::wait_timeout` sleeps until a child exits instead of
+/// repeatedly checking the child's state, and all `Popen` instances share
+/// the same handler.  Calling this function again has no effect.
+///
+/// Only available with the `sigchld` feature.
+pub fn enable_sigchld_wakeup() -> io::Result<()> {
+    let mut installed = INSTALLED.lock().unwrap();
+    if *installed {
+        return Ok(());
+    }
+    let (mut reader, writer) = UnixStream::pair()?;
+    signal_hook::low_level::pipe::register(SIGCHLD, writer)?;
+    thread::Builder::new()
+        .name("subprocess-sigchld".into())
+        .spawn(move || {
+            let mut buf = [0u8; 64];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => (),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+                *GENERATION.lock().unwrap() += 1;
+                CHANGED.notify_all();
+            }
+        })?;
+    *installed = true;
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    *INSTALLED.lock().unwrap()
+}
+
+// Returns the current generation, to be passed to wait_change() after
+// checking the state of the child.
+pub fn generation() -> u64 {
+    *GENERATION.lock().unwrap()
+}
+
+// Wait until a SIGCHLD arrives after `generation` was obtained, or until
+// the deadline, if any.
+pub fn wait_change(generation: u64, deadline: Option<Instant>) {
+    let mut current = GENERATION.lock().unwrap();
+    while *current == generation {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                current = CHANGED.wait(current).unwrap();
+                continue;
+            }
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        current = CHANGED.wait_timeout(current, deadline - now).unwrap().0;
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::communicate::{self, Communicator, InputSource};
+use crate::handle::ProcessHandle;
+use crate::os_common::ExitStatus;
+use crate::popen::{Popen, Result};
+
+// How long the lock is held at a time while waiting without a process
+// handle to block on.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// The process half of a [`Popen`] split with [`Popen::split`].
+///
+/// `ChildHandle` can wait for the subprocess and kill it, and is `Send`
+/// and `Sync`, so it can be shared between threads, e.g. in an `Arc`.
+/// While one thread is blocked in `wait`, another can kill the
+/// subprocess, which is not possible with a `Popen` in a `Mutex`.
+///
+/// Where available, waiting blocks on a pidfd or process handle without
+/// holding the internal lock.  Elsewhere, it polls for the exit,
+/// releasing the lock between short waits.
+///
+/// [`Popen`]: struct.Popen.html
+/// [`Popen::split`]: struct.Popen.html#method.split
+#[derive(Debug)]
+pub struct ChildHandle {
+    pid: Option<u32>,
+    popen: Mutex<Popen>,
+    handle: Option<ProcessHandle>,
+}
+
+impl ChildHandle {
+    pub(crate) fn new(popen: Popen) -> ChildHandle {
+        ChildHandle {
+            pid: popen.pid(),
+            handle: popen.try_clone_handle().ok(),
+            popen: Mutex::new(popen),
+        }
+    }
+
+    /// Return the PID of the subprocess, as returned by [`Popen::pid`]
+    /// at the time of the split.
+    ///
+    /// [`Popen::pid`]: struct.Popen.html#method.pid
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Return the exit status of the subprocess, if it is known to have
+    /// finished.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.popen.lock().unwrap().exit_status()
+    }
+
+    /// Check whether the subprocess has finished, without blocking.
+    pub fn poll(&self) -> Option<ExitStatus> {
+        self.popen.lock().unwrap().poll()
+    }
+
+    /// Wait for the subprocess to finish, and return its exit status.
+    pub fn wait(&self) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.wait_timeout(Duration::from_secs(3600))? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Wait for the subprocess to finish, timing out after the
+    /// specified duration.
+    pub fn wait_timeout(&self, dur: Duration) -> Result<Option<ExitStatus>> {
+        if let Some(ref handle) = self.handle {
+            if !handle.wait_timeout(dur)? {
+                return Ok(None);
+            }
+            return self
+                .popen
+                .lock()
+                .unwrap()
+                .wait_timeout(Duration::from_secs(0));
+        }
+        let deadline = Instant::now() + dur;
+        loop {
+            let now = Instant::now();
+            let slice = WAIT_SLICE.min(deadline.saturating_duration_since(now));
+            let status = self.popen.lock().unwrap().wait_timeout(slice)?;
+            if status.is_some() || Instant::now() >= deadline {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Terminate the subprocess.
+    ///
+    /// See [`Popen::terminate`] for details.
+    ///
+    /// [`Popen::terminate`]: struct.Popen.html#method.terminate
+    pub fn terminate(&self) -> io::Result<()> {
+        self.popen.lock().unwrap().terminate()
+    }
+
+    /// Kill the subprocess.
+    ///
+    /// See [`Popen::kill`] for details.
+    ///
+    /// [`Popen::kill`]: struct.Popen.html#method.kill
+    pub fn kill(&self) -> io::Result<()> {
+        self.popen.lock().unwrap().kill()
+    }
+
+    /// Return the `Popen`, without its streams.
+    pub fn into_inner(self) -> Popen {
+        self.popen.into_inner().unwrap()
+    }
+}
+
+/// The stream half of a [`Popen`] split with [`Popen::split`].
+///
+/// The fields hold the parent's ends of the pipes, as taken from the
+/// corresponding fields of the `Popen`.
+///
+/// [`Popen`]: struct.Popen.html
+/// [`Popen::split`]: struct.Popen.html#method.split
+#[derive(Debug)]
+pub struct ChildStreams {
+    /// The standard input of the subprocess, if redirected to a pipe.
+    pub stdin: Option<File>,
+    /// The standard output of the subprocess, if redirected to a pipe.
+    pub stdout: Option<File>,
+    /// The standard error of the subprocess, if redirected to a pipe.
+    pub stderr: Option<File>,
+}
+
+impl ChildStreams {
+    /// Prepare to communicate with the subprocess.
+    ///
+    /// See [`Popen::communicate_start`] for details.
+    ///
+    /// [`Popen::communicate_start`]: struct.Popen.html#method.communicate_start
+    pub fn communicate_start(self, input_data: Option<Vec<u8>>) -> Communicator {
+        communicate::communicate(
+            self.stdin,
+            self.stdout,
+            self.stderr,
+            input_data.map(|data| vec![InputSource::Bytes(data)]),
+        )
+    }
+}
@@ -6,7 +6,7 @@ use std::sync::Mutex;
 use std::io::prelude::*;
 use std::sync::MutexGuard;
 
-use crate::{Exec, ExitStatus, NullFile, Redirection};
+use crate::{Exec, ExitStatus, InputSource, NullFile, Redirection};
 
 use lazy_static::lazy_static;
 use tempdir::TempDir;
@@ -88,12 +88,260 @@ fn capture_err() {
     assert_eq!(c.stderr_str(), "foo");
 }
 
+#[test]
+fn capture_timeout() {
+    use std::time::Duration;
+
+    let (c, timed_out) = Exec::cmd("printf")
+        .arg("foo")
+        .capture_timeout(Duration::from_secs(100))
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    assert!(c.success() && !timed_out);
+
+    // the output read before the timeout is kept
+    let (c, timed_out) = Exec::cmd("sh")
+        .args(&["-c", "printf foo; exec sleep 1000"])
+        .capture_timeout(Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    assert!(!c.success() && timed_out);
+
+    // the process is stopped even after it has closed its output
+    let (c, timed_out) = Exec::cmd("sh")
+        .args(&["-c", "exec >/dev/null; exec sleep 1000"])
+        .capture_timeout(Duration::from_millis(100))
+        .unwrap();
+    assert!(!c.success() && timed_out);
+}
+
+#[test]
+fn capture_tee() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let (out_path, err_path) = (tmpdir.path().join("out"), tmpdir.path().join("err"));
+    let c = Exec::cmd("sh")
+        .arg("-c")
+        .arg("printf foo; printf bar >&2")
+        .stdout(Redirection::Tee(File::create(&out_path).unwrap()))
+        .stderr(Redirection::Tee(File::create(&err_path).unwrap()))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foo");
+    assert_eq!(c.stderr_str(), "bar");
+    // the copy is complete once the captured output is
+    assert_eq!(read_whole_file(File::open(&out_path).unwrap()), "foo");
+    assert_eq!(read_whole_file(File::open(&err_path).unwrap()), "bar");
+
+    let err = crate::Popen::create(
+        &["true"],
+        crate::PopenConfig {
+            stdin: Redirection::Tee(File::create(&out_path).unwrap()),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(err, Err(crate::PopenError::LogicError(_))));
+}
+
+#[test]
+fn output_to_writer() {
+    use std::sync::Arc;
+
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (out, err) = (Arc::default(), Arc::default());
+    let status = Exec::cmd("sh")
+        .arg("-c")
+        .arg("printf foo; printf bar >&2")
+        .stdout(Redirection::Writer(Box::new(SharedSink(Arc::clone(&out)))))
+        .stderr(Redirection::Writer(Box::new(SharedSink(Arc::clone(&err)))))
+        .join()
+        .unwrap();
+    assert!(status.success());
+    // join() returns once the output has been copied
+    assert_eq!(*out.lock().unwrap(), b"foo");
+    assert_eq!(*err.lock().unwrap(), b"bar");
+}
+
+#[test]
+fn input_from_reader() {
+    // more than fits in the pipes, which would deadlock without
+    // reading and writing concurrently
+    let input = std::io::repeat(b'x').take(1_000_000);
+    let c = Exec::cmd("cat")
+        .stdin(Redirection::Reader(Box::new(input)))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout.len(), 1_000_000);
+
+    // the copy of an endless input stops when the child exits
+    let c = Exec::cmd("head")
+        .args(&["-c", "3"])
+        .stdin(Redirection::Reader(Box::new(std::io::repeat(b'x'))))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "xxx");
+}
+
+#[test]
+fn output_to_path() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let log = tmpdir.path().join("log");
+    for word in &["foo", "bar"] {
+        Exec::cmd("printf")
+            .arg(word)
+            .stdout(Redirection::append(&log))
+            .join()
+            .unwrap();
+    }
+    assert_eq!(read_whole_file(File::open(&log).unwrap()), "foobar");
+
+    let c = Exec::cmd("cat")
+        .stdin(Redirection::open(
+            &log,
+            std::fs::OpenOptions::new().read(true),
+        ))
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foobar");
+
+    // the file is opened when the process is started
+    let create_new = Redirection::open(
+        &log,
+        std::fs::OpenOptions::new().write(true).create_new(true),
+    );
+    match Exec::cmd("true").stdout(create_new).join() {
+        Err(crate::PopenError::IoError(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists)
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
 #[test]
 fn capture_out_with_input_data1() {
     let c = Exec::cmd("cat").stdin("foo").capture().unwrap();
     assert_eq!(c.stdout_str(), "foo");
 }
 
+#[test]
+fn capture_out_with_input_sources() {
+    let c = Exec::cmd("cat")
+        .stdin(vec![
+            InputSource::from("foo"),
+            InputSource::from(&b"bar"[..]),
+        ])
+        .capture()
+        .unwrap();
+    assert_eq!(c.stdout_str(), "foobar");
+}
+
+#[test]
+fn clone_shares_input_reader() {
+    let reader = std::io::Cursor::new(b"foobar".to_vec());
+    let exec = Exec::cmd("cat").stdin(vec![
+        InputSource::from("x"),
+        InputSource::Reader(Box::new(reader)),
+    ]);
+    let exec2 = exec.clone();
+    assert_eq!(exec.capture().unwrap().stdout_str(), "xfoobar");
+    assert_eq!(exec2.capture().unwrap().stdout_str(), "x");
+}
+
+#[test]
+fn communicate_stdout_reader() {
+    let mut reader = Exec::cmd("sh")
+        .args(&["-c", "cat; echo err >&2"])
+        .stdin("foo\nbar\n")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .communicate()
+        .unwrap()
+        .stdout_reader();
+    let lines: Vec<String> = (&mut reader).lines().map(|l| l.unwrap()).collect();
+    assert_eq!(lines, vec!["foo", "bar"]);
+    assert_eq!(reader.stderr(), b"err\n");
+}
+
+#[test]
+fn capture_out_with_shared_input() {
+    let data: std::sync::Arc<[u8]> = b"shared"[..].into();
+    for _ in 0..2 {
+        let c = Exec::cmd("cat")
+            .stdin(std::sync::Arc::clone(&data))
+            .capture()
+            .unwrap();
+        assert_eq!(c.stdout_str(), "shared");
+    }
+}
+
+#[test]
+fn run_report() {
+    use crate::RunLimits;
+    use std::time::Duration;
+
+    let report = Exec::shell("printf foo; printf bar >&2")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .cwd("/")
+        .run_report(RunLimits::default())
+        .unwrap();
+    assert_eq!(report.cmdline, "sh -c 'printf foo; printf bar >&2'");
+    assert_eq!(report.cwd.as_deref(), Some(std::path::Path::new("/")));
+    assert_eq!(report.stdout_str(), "foo");
+    assert_eq!(report.stderr_str(), "bar");
+    assert!(report.success() && !report.truncated && !report.timed_out);
+
+    let report = Exec::cmd("yes")
+        .run_report(RunLimits {
+            max_output: Some(10),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(report.stdout, b"y\ny\ny\ny\ny\n");
+    assert!(report.truncated && !report.timed_out);
+
+    let report = Exec::cmd("sleep")
+        .arg("10")
+        .run_report(RunLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(report.timed_out && !report.success());
+    assert!(report.duration < Duration::from_secs(5));
+
+    // stdout overflows, so stderr is reported in full
+    let report = Exec::shell("printf foo >&2; sleep 0.1; exec yes")
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .run_report(RunLimits {
+            max_output: Some(5),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(report.stderr_str(), "foo");
+    assert_eq!(report.stdout_str(), "y\n");
+    assert!(report.truncated && !report.timed_out);
+
+    // the timeout also covers a process that outlives its output
+    let report = Exec::shell("exec sleep 10 >/dev/null")
+        .run_report(RunLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(report.timed_out && !report.success());
+    assert!(report.duration < Duration::from_secs(5));
+}
+
 #[test]
 fn capture_out_with_input_data2() {
     let c = Exec::cmd("cat").stdin(b"foo".to_vec()).capture().unwrap();
@@ -306,6 +554,17 @@ fn env_extend() {
         .success());
 }
 
+#[test]
+fn env_secret() {
+    let exec = Exec::cmd("sh")
+        .args(&["-c", "echo $TOKEN"])
+        .env_secret("TOKEN", "hunter2");
+    let shown = format!("{:?}", exec);
+    assert!(shown.contains("TOKEN=***"));
+    assert!(!shown.contains("hunter2"));
+    assert_eq!(exec.capture().unwrap().stdout_str(), "hunter2\n");
+}
+
 lazy_static! {
     static ref MUTATE_ENV: Mutex<()> = Mutex::new(());
 }
@@ -423,3 +682,147 @@ fn pipeline_to_string() {
         "Pipeline { 'command with space' arg | wc -l }"
     )
 }
+
+#[test]
+fn exec_context_handlers() {
+    use crate::{ExecContext, OutcomeClass, PopenError};
+    use std::io;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let seen = Arc::new(Mutex::new(vec![]));
+    let mut ctx = ExecContext::new();
+    for &class in &[
+        OutcomeClass::Success,
+        OutcomeClass::NonzeroExit,
+        OutcomeClass::Signaled,
+        OutcomeClass::Timeout,
+        OutcomeClass::SpawnError,
+    ] {
+        let seen = Arc::clone(&seen);
+        ctx = ctx.on(class, move |cmd, outcome| {
+            assert_eq!(outcome.class(), class);
+            seen.lock().unwrap().push((class, cmd.to_owned()));
+        });
+    }
+    ctx.join(Exec::cmd("true")).unwrap();
+    ctx.capture(Exec::cmd("false")).unwrap();
+    ctx.join(Exec::shell("kill -9 $$")).unwrap();
+    ctx.join_timeout(Exec::cmd("sleep").arg("10"), Duration::from_millis(50))
+        .unwrap();
+    ctx.join(Exec::cmd("nosuchcommand")).unwrap_err();
+    let err = ctx
+        .timeout(Duration::from_millis(50))
+        .capture(Exec::cmd("sleep").arg("10"))
+        .unwrap_err();
+    assert!(matches!(err, PopenError::IoError(ref e) if e.kind() == io::ErrorKind::TimedOut));
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            (OutcomeClass::Success, "true".to_owned()),
+            (OutcomeClass::NonzeroExit, "false".to_owned()),
+            (OutcomeClass::Signaled, "sh -c 'kill -9 $$'".to_owned()),
+            (OutcomeClass::Timeout, "sleep 10".to_owned()),
+            (OutcomeClass::SpawnError, "nosuchcommand".to_owned()),
+            (OutcomeClass::Timeout, "sleep 10".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn deterministic_env() {
+    let out = Exec::shell("echo $LC_ALL $TZ $LANG; umask")
+        .env("LANG", "hr_HR.UTF-8")
+        .deterministic()
+        .stdout(Redirection::Pipe)
+        .capture()
+        .unwrap()
+        .stdout_str();
+    assert_eq!(out, "C UTC\n0022\n");
+}
+
+#[test]
+fn env_filter() {
+    let out = Exec::cmd("sh")
+        .args(&["-c", "echo ${A-unset} ${B-unset} ${C-unset}"])
+        .env("A", "1")
+        .env("B", "2")
+        .env("B", "3")
+        .env("C", "4")
+        .env_filter(|k, v| k != "A" && v != "3")
+        .capture()
+        .unwrap()
+        .stdout_str();
+    // removing the last definition of B doesn't resurrect the earlier one
+    assert_eq!(out, "unset unset 4\n");
+
+    let config = crate::PopenConfig::builder()
+        .env_clear()
+        .env_set("X", "1")
+        .env_set("Y", "2")
+        .env_remove("Y")
+        .build()
+        .unwrap();
+    assert_eq!(config.env, Some(vec![("X".into(), "1".into())]));
+    let config = crate::PopenConfig::builder()
+        .env_filter(|k, _| k == "PATH")
+        .build()
+        .unwrap();
+    assert!(config.env.unwrap().iter().all(|(k, _)| k == "PATH"));
+}
+
+#[test]
+fn spec_hash() {
+    let a = Exec::cmd("true").env_clear().env("A", "1").env("B", "2");
+    let b = Exec::cmd("true").env_clear().env("B", "2").env("A", "1");
+    assert_eq!(a.spec_hash(), b.spec_hash());
+    assert_ne!(a.spec_hash(), a.clone().arg("x").spec_hash());
+    assert_ne!(a.spec_hash(), a.clone().stdin("data").spec_hash());
+    assert_ne!(a.spec_hash(), a.clone().nice(10).spec_hash());
+    let null_out = a.clone().stdout(NullFile).spec_hash();
+    assert_ne!(a.spec_hash(), null_out);
+    assert_ne!(null_out, a.clone().stderr(NullFile).spec_hash());
+    assert_ne!(null_out, a.clone().stdout(Redirection::Pipe).spec_hash());
+}
+
+#[test]
+fn coprocess_restart() {
+    use crate::Coprocess;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let starts = Arc::new(AtomicUsize::new(0));
+    let starts_hook = Arc::clone(&starts);
+    // serves a single request per run
+    let mut coproc = Coprocess::new(Exec::cmd("head").arg("-n1")).on_start(move |_io| {
+        starts_hook.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    });
+    assert_eq!(coproc.call(b"foo\n").unwrap(), b"foo\n");
+    assert_eq!(coproc.call(b"bar\n").unwrap(), b"bar\n");
+    assert_eq!(starts.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn capture_data_serde() {
+    let c = Exec::cmd("printf").arg("foo").capture().unwrap();
+    let json = serde_json::to_string(&c).unwrap();
+    let c2: crate::CaptureData = serde_json::from_str(&json).unwrap();
+    assert_eq!(c2.stdout, b"foo");
+    assert_eq!(c2.exit_status, ExitStatus::Exited(0));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn run_report_serde() {
+    let report = Exec::cmd("printf")
+        .arg("foo")
+        .run_report(crate::RunLimits::default())
+        .unwrap();
+    let json = serde_json::to_string(&report).unwrap();
+    let report2: crate::RunReport = serde_json::from_str(&json).unwrap();
+    assert_eq!(report2.stdout, b"foo");
+    assert_eq!(report2.exit, ExitStatus::Exited(0));
+    assert_eq!(report2.started_at, report.started_at);
+}
@@ -6,7 +6,10 @@ use std::io::Write;
 use std::io::{self, Read};
 use std::time::Duration;
 
-use crate::{ExitStatus, Popen, PopenConfig, PopenError, Redirection};
+use crate::{
+    CommunicateEvent, ExitStatus, InputSource, Popen, PopenConfig, PopenError, Redirection,
+    StopAction, Stream,
+};
 
 pub fn read_whole_file<T: Read>(mut f: T) -> String {
     let mut content = String::new();
@@ -31,7 +34,7 @@ fn reject_empty_argv() {
     let test = Popen::create(&[""; 0], PopenConfig::default());
     if let Err(PopenError::LogicError(..)) = test {
     } else {
-        assert!(false, "didn't get LogicError for empty argv");
+        panic!("didn't get LogicError for empty argv");
     }
 }
 
@@ -59,6 +62,69 @@ fn terminate_twice() {
     p.terminate().unwrap();
 }
 
+#[test]
+fn shutdown_stop_action() {
+    let mut p = Popen::create(
+        &["sh", "-c", "read cmd; test \"$cmd\" = quit && exit 7"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.set_stop_action(StopAction::WriteStdin(b"quit\n".to_vec()));
+    let status = p.shutdown(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, ExitStatus::Exited(7));
+}
+
+#[test]
+fn shutdown_escalates() {
+    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    // sleep ignores its stdin, so the stop action has no effect
+    p.set_stop_action(StopAction::CloseStdin);
+    let status = p.shutdown(Duration::from_millis(100)).unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn timeout_watchdog() {
+    use crate::TimeoutAction;
+
+    // the watchdog stops the process, though it is only waited for
+    let mut p = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            timeout: Some(Duration::from_millis(100)),
+            on_timeout: TimeoutAction::Kill,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(!p.wait().unwrap().success());
+    assert!(p.timed_out());
+    assert!(p.runtime().unwrap() < Duration::from_secs(100));
+
+    // also while the output is read to the end
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", "echo foo; exec sleep 1000"])
+        .timeout(Duration::from_millis(100), TimeoutAction::Terminate)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "foo\n");
+    assert!(!out.exit_status.success());
+
+    let mut p = Popen::create(
+        &["true"],
+        PopenConfig {
+            timeout: Some(Duration::from_secs(100)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.wait().unwrap().success());
+    assert!(!p.timed_out());
+}
+
 #[test]
 fn read_from_stdout() {
     let mut p = Popen::create(
@@ -169,7 +235,7 @@ fn communicate_input() {
     .unwrap();
     if let (None, None) = p.communicate_bytes(Some(b"hello world")).unwrap() {
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
     assert_eq!(
@@ -193,7 +259,7 @@ fn communicate_output() {
         assert_eq!(out, b"foo\n");
         assert_eq!(err, b"bar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -214,7 +280,7 @@ fn communicate_input_output() {
         assert_eq!(out, b"hello world");
         assert_eq!(err, b"foo\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -236,8 +302,118 @@ fn communicate_input_output_long() {
         assert_eq!(&out[..], &input[..]);
         assert_eq!(&err[..], &[32u8; 100_000][..]);
     } else {
-        assert!(false);
+        panic!();
+    }
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn communicate_input_sources() {
+    let tmpdir = TempDir::new("test").unwrap();
+    let tmpname = tmpdir.path().join("input");
+    File::create(&tmpname).unwrap().write_all(b"file,").unwrap();
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let sources = vec![
+        InputSource::from("bytes,"),
+        InputSource::File(File::open(&tmpname).unwrap()),
+        InputSource::Reader(Box::new(io::repeat(b'x').take(100_000))),
+    ];
+    let (out, err) = p.communicate_start_sources(sources).read().unwrap();
+    let out = out.unwrap();
+    assert_eq!(&out[..11], b"bytes,file,");
+    assert_eq!(&out[11..], &[b'x'; 100_000][..]);
+    assert!(err.is_none());
+    assert!(p.wait().unwrap().success());
+}
+
+// Reader returning the chunks received over a channel, failing with EINTR
+// before each of them.
+struct ChannelReader {
+    rx: std::sync::mpsc::Receiver<Vec<u8>>,
+    interrupted: bool,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupted = !self.interrupted;
+        if self.interrupted {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        match self.rx.recv() {
+            Ok(chunk) => {
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(chunk.len())
+            }
+            Err(_) => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn communicate_input_reader_blocked() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let reader = ChannelReader {
+        rx,
+        interrupted: false,
+    };
+    tx.send(b"foo".to_vec()).unwrap();
+    let mut comm = p
+        .communicate_start_sources(vec![InputSource::Reader(Box::new(reader))])
+        .limit_time(Duration::from_millis(200));
+    let err = comm.read().unwrap_err();
+    assert_eq!(err.error.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(comm.progress().input_written, 3);
+    assert!(!comm.progress().stdin_closed);
+    tx.send(b"bar".to_vec()).unwrap();
+    drop(tx);
+    let (out, _) = comm.limit_time(Duration::from_secs(5)).read().unwrap();
+    let mut out = out.unwrap();
+    if let Some(early) = err.capture.0 {
+        out.splice(..0, early);
     }
+    assert_eq!(out, b"foobar");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn communicate_split() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (mut writer, mut reader) = p.communicate_start(Some(b"foo".to_vec())).split();
+    let feeder = std::thread::spawn(move || {
+        writer.write_all(&[b'x'; 100_000])?;
+        writer.finish()
+    });
+    let (out, err) = reader.read().unwrap();
+    feeder.join().unwrap().unwrap();
+    let out = out.unwrap();
+    assert_eq!(&out[..3], b"foo");
+    assert_eq!(&out[3..], &[b'x'; 100_000][..]);
+    assert!(err.is_none());
     assert!(p.wait().unwrap().success());
 }
 
@@ -266,6 +442,121 @@ fn communicate_timeout() {
     p.kill().unwrap();
 }
 
+#[test]
+fn communicate_progress() {
+    let mut p = Popen::create(
+        &["sh", "-c", "head -c 3; exec >&-; sleep 1"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p
+        .communicate_start(Some(b"foo".to_vec()))
+        .limit_time(Duration::from_millis(200));
+    let progress = comm.progress();
+    assert_eq!(progress.input_written, 0);
+    assert!(!progress.stdin_closed && !progress.stdout_eof && !progress.stderr_eof);
+    match comm.read() {
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let progress = comm.progress();
+    assert_eq!(progress.input_written, 3);
+    assert!(progress.stdin_closed && progress.stdout_eof && !progress.stderr_eof);
+    p.kill().unwrap();
+}
+
+#[test]
+fn communicate_iter_chunks() {
+    let mut p = Popen::create(
+        &["sh", "-c", "printf '%5s' a; printf bb >&2"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p.communicate_start(None).limit_size(2);
+    let (mut out, mut err) = (vec![], vec![]);
+    for chunk in comm.iter_chunks() {
+        let (stream, data) = chunk.unwrap();
+        assert!(!data.is_empty() && data.len() <= 2);
+        match stream {
+            Stream::Stdout => out.extend(data),
+            Stream::Stderr => err.extend(data),
+            Stream::Stdin => unreachable!(),
+        }
+    }
+    assert_eq!(out, b"    a");
+    assert_eq!(err, b"bb");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn communicate_read_step() {
+    let mut p = Popen::create(
+        &["sh", "-c", "cat; printf err >&2"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut comm = p.communicate_start(Some(b"hello".to_vec()));
+    let (mut written, mut out, mut err, mut eofs) = (0, vec![], vec![], vec![]);
+    while let Some(event) = comm.read_step().unwrap() {
+        match event {
+            CommunicateEvent::WroteInput(n) => written += n,
+            CommunicateEvent::Stdout(data) => out.extend(data),
+            CommunicateEvent::Stderr(data) => err.extend(data),
+            CommunicateEvent::Eof(stream) => eofs.push(stream),
+            CommunicateEvent::Idle => panic!("unexpected idle"),
+        }
+    }
+    assert_eq!(written, 5);
+    assert_eq!(out, b"hello");
+    assert_eq!(err, b"err");
+    eofs.sort_by_key(|&s| s as u8);
+    assert_eq!(eofs, vec![Stream::Stdin, Stream::Stdout, Stream::Stderr]);
+    assert!(p.wait().unwrap().success());
+}
+
+#[cfg(feature = "poll-trace")]
+#[test]
+fn communicate_trace_hook() {
+    use std::sync::{Arc, Mutex};
+
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let traces = Arc::new(Mutex::new(vec![]));
+    let traces2 = Arc::clone(&traces);
+    let (out, _) = p
+        .communicate_start(Some(b"foo".to_vec()))
+        .trace_hook(move |trace| traces2.lock().unwrap().push(trace.clone()))
+        .read()
+        .unwrap();
+    assert_eq!(out.unwrap(), b"foo");
+    let traces = traces.lock().unwrap();
+    let total = |i: usize| traces.iter().map(|t| t.streams[i].bytes).sum::<usize>();
+    assert_eq!(total(0), 3);
+    assert_eq!(total(1), 3);
+    assert!(traces.iter().all(|t| t.streams[2].fd.is_none()));
+}
+
 #[test]
 fn communicate_size_limit_small() {
     let mut p = Popen::create(
@@ -280,7 +571,7 @@ fn communicate_size_limit_small() {
     let mut comm = p.communicate_start(None).limit_size(2);
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![32; 2]), Some(vec![])));
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
@@ -310,7 +601,7 @@ fn communicate_size_limit_large() {
     check_vec(out, 10_000, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     p.kill().unwrap();
 }
 
@@ -347,7 +638,7 @@ fn communicate_size_limit_different_sizes() {
     check_vec(out, 8_900, 32);
     assert_eq!(err, Some(vec![]));
 
-    assert_eq!(comm.read().unwrap(), (Some(vec!['a' as u8]), Some(vec![])));
+    assert_eq!(comm.read().unwrap(), (Some(vec![b'a']), Some(vec![])));
     assert_eq!(comm.read().unwrap(), (Some(vec![]), Some(vec![])));
     p.kill().unwrap();
 }
@@ -372,7 +663,7 @@ fn merge_err_to_out_pipe() {
     if let (Some(out), None) = p.communicate_bytes(None).unwrap() {
         assert_eq!(out, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -391,7 +682,7 @@ fn merge_out_to_err_pipe() {
     if let (None, Some(err)) = p.communicate_bytes(None).unwrap() {
         assert_eq!(err, b"foo\nbar\n");
     } else {
-        assert!(false);
+        panic!();
     }
     assert!(p.wait().unwrap().success());
 }
@@ -413,6 +704,63 @@ fn merge_err_to_out_file() {
     assert_eq!(read_whole_file(File::open(&tmpname).unwrap()), "foobar");
 }
 
+#[test]
+fn merge_invalid() {
+    let config = PopenConfig {
+        stdout: Redirection::Merge,
+        stderr: Redirection::Merge,
+        ..Default::default()
+    };
+    match Popen::create(&["true"], config) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    match PopenConfig::builder().stdin(Redirection::Merge).build() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let config = PopenConfig {
+        stdin: Redirection::Merge,
+        ..Default::default()
+    };
+    match Popen::create(&["true"], config) {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn config_input_data() {
+    match PopenConfig::builder().input_data("foo").build() {
+        Err(PopenError::LogicError(_)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let config = PopenConfig::builder()
+        .stdin(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .input_data("foo")
+        .build()
+        .unwrap();
+    let mut p = Popen::create(&["cat"], config).unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "foo");
+}
+
+#[test]
+fn config_builder() {
+    let config = PopenConfig::builder()
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .env(vec![("FOO", "bar")])
+        .build()
+        .unwrap();
+    let mut p = Popen::create(&["sh", "-c", "printf $FOO; printf baz >&2"], config).unwrap();
+    let (out, err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "barbaz");
+    assert!(err.is_none());
+    assert!(p.wait().unwrap().success());
+}
+
 #[test]
 fn simple_pipe() {
     let mut c1 = Popen::create(
@@ -517,7 +865,6 @@ fn cwd() {
 
 #[test]
 fn failed_cwd() {
-    use crate::popen::PopenError::IoError;
     let ret = Popen::create(
         &["anything"],
         PopenConfig {
@@ -526,9 +873,336 @@ fn failed_cwd() {
             ..Default::default()
         },
     );
-    let err_num = match ret {
-        Err(IoError(e)) => e.raw_os_error().unwrap_or(-1),
-        _ => panic!("expected error return"),
+    #[cfg(unix)]
+    match ret {
+        Err(PopenError::ChildSetupFailed {
+            step: crate::ChildSetupStep::Chdir,
+            errno,
+        }) => assert_eq!(errno, libc::ENOENT),
+        other => panic!("unexpected result {:?}", other),
+    }
+    #[cfg(windows)]
+    match ret {
+        Err(PopenError::ExecFailed { cwd, .. }) => {
+            assert_eq!(cwd, Some(std::path::PathBuf::from("/nosuchdir")))
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn bridge_tcp() {
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"hello").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        read_whole_file(stream)
+    });
+    let (socket, _) = listener.accept().unwrap();
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let stats = p.bridge(socket, Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(stats.to_child, 5);
+    assert_eq!(stats.from_child, 5);
+    assert_eq!(client.join().unwrap(), "hello");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn wait_any() {
+    let mut slow = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let mut fast =
+        Popen::create(&["sh", "-c", "sleep 0.1; exit 3"], PopenConfig::default()).unwrap();
+    {
+        let mut children = [&mut slow, &mut fast];
+        assert!(
+            Popen::wait_any(&mut children, Some(Duration::from_millis(10)))
+                .unwrap()
+                .is_none()
+        );
+        let (i, status) = Popen::wait_any(&mut children, Some(Duration::from_secs(5)))
+            .unwrap()
+            .unwrap();
+        assert_eq!((i, status), (1, ExitStatus::Exited(3)));
+    }
+    slow.kill().unwrap();
+    slow.wait().unwrap();
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn wait_async() {
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut p = Popen::create(&["sh", "-c", "sleep 0.1; exit 5"], PopenConfig::default()).unwrap();
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = Box::pin(p.wait_async());
+    let status = loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(status) => break status.unwrap(),
+            Poll::Pending => thread::park(),
+        }
     };
-    assert_eq!(err_num, libc::ENOENT);
+    assert_eq!(status, ExitStatus::Exited(5));
+}
+
+#[test]
+fn arg_quoting() {
+    use crate::ArgQuoting;
+
+    let args = ["prog", "a b", r#"say "hi""#, r"dir\", "100%", ""];
+    assert_eq!(
+        ArgQuoting::Standard.join(&args).unwrap(),
+        r#"prog "a b" "say \"hi\"" dir\ 100% """#
+    );
+    assert_eq!(
+        ArgQuoting::Cmd.join(&args).unwrap(),
+        r#"prog "a b" "say ""hi""" dir\ "100%%cd:~,%" """#
+    );
+    assert_eq!(
+        ArgQuoting::MsvcLegacy.join(&args).unwrap(),
+        r#""prog" "a b" "say ""hi""" "dir\" "100%" """#
+    );
+    assert_eq!(ArgQuoting::Standard.join(&[r"a b\"]).unwrap(), r#""a b\\""#);
+    assert!(ArgQuoting::Cmd.join(&["a\nb"]).is_err());
+    assert!(ArgQuoting::Standard.join(&["a\0b"]).is_err());
+}
+
+#[test]
+fn child_guard() {
+    let mut guard =
+        crate::ChildGuard::new(Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap());
+    assert!(guard.poll().is_none());
+    drop(guard);
+    let guard = crate::ChildGuard::new(Popen::create(&["true"], PopenConfig::default()).unwrap());
+    let mut p = guard.into_inner();
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn runtime() {
+    let mut p = Popen::create(&["sleep", "0.2"], PopenConfig::default()).unwrap();
+    assert!(p.start_time() <= std::time::Instant::now());
+    assert!(p.runtime().is_none());
+    p.wait().unwrap();
+    let runtime = p.runtime().unwrap();
+    assert!(runtime >= Duration::from_millis(200));
+    assert_eq!(p.elapsed(), runtime);
+}
+
+#[test]
+fn take_streams() {
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut stdin = p.take_stdin().unwrap();
+    let mut stdout = p.take_stdout().unwrap();
+    assert!(p.take_stdin().is_none());
+    assert!(p.take_stderr().is_none());
+    stdin.write_all(b"foo").unwrap();
+    drop(stdin);
+    let mut output = String::new();
+    stdout.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "foo");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+#[should_panic]
+fn communicate_after_take() {
+    let mut p = Popen::create(
+        &["true"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    p.take_stdout();
+    p.communicate(None).ok();
+}
+
+#[test]
+fn communicate_after_take_none() {
+    let mut p = Popen::create(
+        &["echo", "foo"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.take_stdin().is_none());
+    assert!(p.take_stderr().is_none());
+    let (out, err) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "foo\n");
+    assert!(err.is_none());
+}
+
+#[test]
+fn split() {
+    use std::sync::Arc;
+
+    let p = Popen::create(
+        &["sh", "-c", "echo foo; exec sleep 1000"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (handle, mut streams) = p.split();
+    let handle = Arc::new(handle);
+    let waiter = {
+        let handle = Arc::clone(&handle);
+        std::thread::spawn(move || handle.wait().unwrap())
+    };
+    let mut line = [0u8; 4];
+    streams
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_exact(&mut line)
+        .unwrap();
+    assert_eq!(&line, b"foo\n");
+    assert!(handle.poll().is_none());
+    handle.kill().unwrap();
+    let status = waiter.join().unwrap();
+    assert!(!status.success());
+    assert_eq!(handle.exit_status(), Some(status));
+}
+
+#[test]
+fn registry_shutdown() {
+    crate::enable_registry();
+    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let pid = p.pid().unwrap();
+    assert!(crate::registry::is_registered(pid));
+    // only stop our own subprocess, not those of concurrent tests
+    let stopped = crate::registry::shutdown_where(|p| p == pid, Duration::from_secs(5));
+    assert_eq!(stopped, 1);
+    assert!(!p.wait().unwrap().success());
+    drop(p);
+    assert!(!crate::registry::is_registered(pid));
+}
+
+#[test]
+fn spawn_and_exit_hooks() {
+    use std::sync::{Arc, Mutex};
+    // the hooks are global, so only act on the subprocesses of this test
+    let marker = OsStr::new("spawn_and_exit_hooks");
+    let spawns = Arc::new(Mutex::new(vec![]));
+    let hook_spawns = Arc::clone(&spawns);
+    crate::set_spawn_hook(move |info| {
+        if info.argv.last().map(OsString::as_os_str) == Some(OsStr::new("vetoed")) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "vetoed"));
+        }
+        if info.argv.iter().any(|arg| arg == marker) {
+            hook_spawns.lock().unwrap().push(info.clone());
+        }
+        Ok(())
+    });
+    let exits = Arc::new(Mutex::new(vec![]));
+    let hook_exits = Arc::clone(&exits);
+    crate::set_exit_hook(move |info| {
+        if info.argv.iter().any(|arg| arg == marker) {
+            hook_exits.lock().unwrap().push(info.clone());
+        }
+    });
+    let result = Popen::create(&["true", "vetoed"], PopenConfig::default());
+    match result {
+        Err(PopenError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+        other => panic!("unexpected result {:?}", other),
+    }
+    let config = PopenConfig::builder()
+        .env_set("TOKEN", "hunter2")
+        .secret_env("TOKEN")
+        .build()
+        .unwrap();
+    let mut p = Popen::create(&["sh", "-c", "exit 3", "spawn_and_exit_hooks"], config).unwrap();
+    let pid = p.pid().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+    p.wait().unwrap();
+    crate::clear_hooks();
+    let spawns = spawns.lock().unwrap();
+    assert_eq!(spawns.len(), 1);
+    // the program is reported as found in the PATH
+    let program = std::path::Path::new(&spawns[0].argv[0]);
+    assert!(program.is_absolute());
+    assert_eq!(program.file_name(), Some(OsStr::new("sh")));
+    let token = spawns[0].env.iter().find(|(k, _)| k == "TOKEN").unwrap();
+    assert_eq!(token.1, "***");
+    let exits = exits.lock().unwrap();
+    assert_eq!(exits.len(), 1);
+    assert_eq!(exits[0].argv, spawns[0].argv);
+    assert_eq!(exits[0].pid, pid);
+    assert_eq!(exits[0].exit_status, ExitStatus::Exited(3));
+    assert_eq!(Some(exits[0].runtime), p.runtime());
+}
+
+#[test]
+fn executable_not_found() {
+    use crate::ExecutableSearch;
+    use std::path::PathBuf;
+
+    let dirs = vec![PathBuf::from("nosuchdir1"), PathBuf::from("nosuchdir2")];
+    let search_path = std::env::join_paths(&dirs).unwrap();
+    let result = Popen::create(
+        &["true"],
+        PopenConfig {
+            executable_search: ExecutableSearch::Path(search_path),
+            ..Default::default()
+        },
+    );
+    match result {
+        Err(PopenError::ExecutableNotFound { name, searched }) => {
+            assert_eq!(name, "true");
+            assert_eq!(searched, dirs);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn secret_env_debug() {
+    let config = PopenConfig::builder()
+        .env_set("TOKEN", "hunter2")
+        .env_set("USER_NAME", "joe")
+        .secret_env("TOKEN")
+        .build()
+        .unwrap();
+    let shown = format!("{:?}", config);
+    assert!(!shown.contains("hunter2"));
+    assert!(shown.contains("\"***\""));
+    assert!(shown.contains("\"joe\""));
 }
@@ -3,8 +3,6 @@ use std::ffi::OsString;
 use crate::unix::PopenExt;
 use crate::{ExitStatus, Popen, PopenConfig, Redirection};
 
-use libc;
-
 #[test]
 fn err_terminate() {
     let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
@@ -31,6 +29,77 @@ fn send_signal() {
     assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGUSR1 as u8));
 }
 
+#[cfg(target_os = "linux")]
+#[test]
+fn pidfd_wait_timeout() {
+    use std::time::{Duration, Instant};
+
+    let mut p = Popen::create(&["sleep", "0.2"], PopenConfig::default()).unwrap();
+    assert!(p.pidfd().is_some());
+    let start = Instant::now();
+    let status = p.wait_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, Some(ExitStatus::Exited(0)));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(p.pidfd().is_none());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn waitable_fd() {
+    use std::os::unix::io::AsRawFd;
+
+    let mut p = Popen::create(&["sleep", "0.1"], PopenConfig::default()).unwrap();
+    let fd = p.waitable_fd().unwrap().as_raw_fd();
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 5000) }, 1);
+    assert_eq!(p.poll(), Some(ExitStatus::Exited(0)));
+    assert!(p.waitable_fd().is_none());
+}
+
+#[test]
+fn peek_status() {
+    use std::time::{Duration, Instant};
+
+    let p = Popen::create(&["sh", "-c", "sleep 0.1; exit 3"], PopenConfig::default()).unwrap();
+    assert_eq!(p.peek_status().unwrap(), None);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = p.peek_status().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(status, ExitStatus::Exited(3));
+    // the child is still a zombie, waitable by anyone
+    let mut raw_status = 0;
+    let pid = p.pid().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut raw_status, 0) }, pid);
+    assert!(libc::WIFEXITED(raw_status));
+    assert_eq!(libc::WEXITSTATUS(raw_status), 3);
+}
+
+#[cfg(feature = "sigchld")]
+#[test]
+fn sigchld_wait_timeout() {
+    use std::time::{Duration, Instant};
+
+    crate::unix::enable_sigchld_wakeup().unwrap();
+    let mut procs: Vec<_> = (0..3)
+        .map(|_| Popen::create(&["sleep", "0.1"], PopenConfig::default()).unwrap())
+        .collect();
+    let start = Instant::now();
+    for p in &mut procs {
+        let status = p.wait_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status, Some(ExitStatus::Exited(0)));
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
 #[test]
 fn env_set_all_1() {
     let mut p = Popen::create(
@@ -60,3 +129,1569 @@ fn env_set_all_2() {
     let (out, _err) = p.communicate(None).unwrap();
     assert_eq!(out.unwrap().trim_end(), "FOO=bar");
 }
+
+#[test]
+fn bridge_timeout() {
+    use std::io::ErrorKind;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let mut p = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (socket, _peer) = UnixStream::pair().unwrap();
+    let err = p
+        .bridge(socket, Some(Duration::from_millis(100)))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn terminate_with_timeout() {
+    use std::time::Duration;
+
+    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let status = p.terminate_with_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGTERM as u8));
+
+    let mut p = Popen::create(
+        &["sh", "-c", "trap '' TERM; while :; do sleep 0.1; done"],
+        PopenConfig::default(),
+    )
+    .unwrap();
+    // give the shell time to install the trap
+    std::thread::sleep(Duration::from_millis(100));
+    let status = p
+        .terminate_with_timeout(Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGKILL as u8));
+}
+
+#[test]
+fn kill_group() {
+    use std::time::Duration;
+
+    // the shell's child sleeps in the same group, holding stdout open
+    let mut p = Popen::create(
+        &["sh", "-c", "sleep 1000; true"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            setpgid: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    p.kill_group().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGKILL as u8));
+    // EOF means that the grandchild is gone as well
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "");
+    p.terminate_group().unwrap();
+}
+
+#[test]
+fn join_group() {
+    let mut first = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            setpgid: true,
+            drop_policy: crate::DropPolicy::KillAndWait,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let pgid = first.pgid().unwrap();
+    assert_eq!(Some(pgid), first.pid());
+    let mut second = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig::builder()
+            .pgid(pgid)
+            .drop_policy(crate::DropPolicy::KillAndWait)
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(second.pgid(), Some(pgid));
+    assert_eq!(
+        unsafe { libc::getpgid(second.pid().unwrap() as libc::pid_t) },
+        pgid as libc::pid_t
+    );
+    // killing the group through the second kills the first as well
+    second.kill_group().unwrap();
+    let killed = ExitStatus::Signaled(libc::SIGKILL as u8);
+    assert_eq!(first.wait().unwrap(), killed);
+    assert_eq!(second.wait().unwrap(), killed);
+
+    let config = PopenConfig::builder().pgid(pgid).setpgid(true).build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn kill_group_requires_setpgid() {
+    let mut p = Popen::create(&["true"], PopenConfig::default()).unwrap();
+    let err = p.kill_group().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    p.wait().unwrap();
+}
+
+#[test]
+fn kill_tree() {
+    // the grandchild runs in its own process group, out of reach of
+    // kill_group, and keeps stdout open until it is killed
+    let mut p = Popen::create(
+        &["sh", "-c", "set -m; sleep 1000 & wait"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    p.kill_tree().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGKILL as u8));
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn try_clone_handle() {
+    use std::time::Duration;
+    let mut p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    let handle = match p.try_clone_handle() {
+        Ok(handle) => handle,
+        // no pidfd support in the kernel
+        Err(_) if p.pidfd().is_none() => return,
+        Err(e) => panic!("{}", e),
+    };
+    assert_eq!(handle.pid(), p.pid().unwrap());
+    assert!(handle.pidfd().is_some());
+    let polled = crate::ProcessHandle::pid_based_for_test(&p);
+    assert!(polled.pidfd().is_none());
+    assert!(!handle.has_exited().unwrap());
+    let t = std::thread::spawn(move || {
+        handle.kill().unwrap();
+        handle.wait_timeout(Duration::from_secs(5)).unwrap()
+    });
+    assert!(t.join().unwrap());
+    // the exit status is still available to the Popen
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGKILL as u8));
+    assert!(p.try_clone_handle().is_err());
+}
+
+#[test]
+fn shared_handle() {
+    use std::time::Duration;
+    let make_handles: [fn(&Popen) -> crate::ProcessHandle; 2] = [
+        |p| p.handle().unwrap(),
+        crate::ProcessHandle::pid_based_for_test,
+    ];
+    for make_handle in &make_handles {
+        let mut p = Popen::create(
+            &["sleep", "1000"],
+            PopenConfig {
+                drop_policy: crate::DropPolicy::KillAndWait,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let handle = make_handle(&p);
+        let waiter = {
+            let handle = handle.clone();
+            std::thread::spawn(move || handle.wait_timeout(Duration::from_secs(5)).unwrap())
+        };
+        assert!(!handle.has_exited().unwrap());
+        handle.terminate().unwrap();
+        assert!(waiter.join().unwrap());
+        assert!(handle.has_exited().unwrap());
+        assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+    }
+}
+
+#[test]
+fn send_portable_signal() {
+    use crate::Signal;
+    let p = Popen::create(&["sleep", "1000"], PopenConfig::default()).unwrap();
+    // not SIGINT, which is ignored by background jobs of some shells
+    p.send_signal(Signal::Terminate).unwrap();
+    let mut p = p;
+    assert_eq!(p.wait().unwrap(), ExitStatus::Signaled(libc::SIGTERM as u8));
+    // finished, so nothing happens
+    p.send_signal(Signal::Kill).unwrap();
+    assert_eq!(Signal::from(libc::SIGINT), Signal::Interrupt);
+    assert_eq!(Signal::from(libc::SIGUSR1).number(), libc::SIGUSR1);
+}
+
+#[test]
+fn suspend_resume() {
+    use std::time::Duration;
+    let mut p = Popen::create(&["sleep", "0.2"], PopenConfig::default()).unwrap();
+    p.suspend().unwrap();
+    // a stopped process doesn't finish sleeping
+    assert!(p
+        .wait_timeout(Duration::from_millis(500))
+        .unwrap()
+        .is_none());
+    p.resume().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+    p.suspend().unwrap();
+}
+
+#[test]
+fn start_suspended() {
+    use std::time::Duration;
+    let mut p = Popen::create(
+        &["sh", "-c", "exit 7"],
+        PopenConfig {
+            start_suspended: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    // the child is stopped before running the program
+    assert!(p
+        .wait_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+    p.resume().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(7));
+
+    // failure to execute the program is reported on resume
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let program = tmpdir.path().join("program");
+    std::fs::write(&program, "").unwrap();
+    let mut p = Popen::create(
+        &[&program],
+        PopenConfig {
+            start_suspended: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let err = p.resume().unwrap_err();
+    match err.get_ref().and_then(|e| e.downcast_ref()) {
+        Some(crate::PopenError::ExecFailed { error, .. }) => {
+            assert_eq!(error.raw_os_error(), Some(libc::EACCES));
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(127));
+
+    let config = PopenConfig::builder()
+        .start_suspended(true)
+        .daemonize(true)
+        .build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn nice() {
+    let out = crate::Exec::cmd("nice").nice(7).capture().unwrap();
+    assert_eq!(out.stdout_str(), "7\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sched_policy() {
+    use crate::unix::{ExecExt, SchedPolicy};
+
+    // the policy is field 41 of /proc/PID/stat
+    let out = crate::Exec::cmd("cut")
+        .args(&["-d", " ", "-f", "41", "/proc/self/stat"])
+        .sched_policy(SchedPolicy::Batch)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str().trim(), libc::SCHED_BATCH.to_string());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cpu_affinity() {
+    let out = crate::Exec::cmd("grep")
+        .args(&["Cpus_allowed_list", "/proc/self/status"])
+        .cpu_affinity(&[0])
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "Cpus_allowed_list:\t0\n");
+
+    let err = crate::Exec::cmd("true").cpu_affinity(&[100_000]).join();
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::SetAffinity);
+            assert_eq!(errno, libc::EINVAL);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn rlimits() {
+    use crate::unix::{ExecExt, Rlimit};
+
+    let out = crate::Exec::shell("ulimit -n; ulimit -Hn; ulimit -c")
+        .rlimit(Rlimit::OpenFiles, 64, 128)
+        .rlimit(Rlimit::CoreSize, 0, 0)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "64\n128\n0\n");
+
+    // the soft limit can't exceed the hard one
+    let err = crate::Exec::cmd("true")
+        .rlimit(Rlimit::OpenFiles, 128, 64)
+        .join();
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::SetRlimit);
+            assert_eq!(errno, libc::EINVAL);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn oom_score_adj() {
+    let out = crate::Exec::cmd("cat")
+        .arg("/proc/self/oom_score_adj")
+        .oom_score_adj(500)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "500\n");
+
+    let err = crate::Exec::cmd("true").oom_score_adj(-2000).join();
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::SetOomScoreAdj);
+            assert_eq!(errno, libc::EINVAL);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cgroup() {
+    use crate::unix::Cgroup;
+    use std::time::Duration;
+
+    // the limits are written to the control files
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    std::fs::write(tmpdir.path().join("memory.max"), "").unwrap();
+    std::fs::write(tmpdir.path().join("cpu.max"), "").unwrap();
+    let fake = Cgroup::open(tmpdir.path());
+    fake.set_memory_max(Some(1 << 20)).unwrap();
+    fake.set_cpu_max(Some(Duration::from_millis(50)), Duration::from_millis(100))
+        .unwrap();
+    let read = |file| std::fs::read_to_string(tmpdir.path().join(file)).unwrap();
+    assert_eq!(read("memory.max"), "1048576");
+    assert_eq!(read("cpu.max"), "50000 100000");
+
+    // a directory that isn't a cgroup has no cgroup.procs
+    let err = crate::Exec::cmd("true").cgroup(tmpdir.path()).join();
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, .. }) => {
+            assert_eq!(step, crate::ChildSetupStep::JoinCgroup)
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    // actually starting in a cgroup requires a writable cgroup2 mount
+    let root = ["/sys/fs/cgroup/unified", "/sys/fs/cgroup"]
+        .iter()
+        .map(std::path::Path::new)
+        .find(|root| root.join("cgroup.procs").exists());
+    let name = format!("subprocess-test-{}", std::process::id());
+    let cgroup = match root.map(|root| Cgroup::create(root.join(&name))) {
+        Some(Ok(cgroup)) => cgroup,
+        _ => return,
+    };
+    let out = crate::Exec::cmd("cat")
+        .arg("/proc/self/cgroup")
+        .cgroup(&cgroup)
+        .capture();
+    cgroup.remove().unwrap();
+    let line = format!("0::/{}\n", name);
+    assert!(out.unwrap().stdout_str().ends_with(&line));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn unshare() {
+    use crate::unix::Namespace;
+
+    // unprivileged user namespaces may be disabled
+    let out =
+        match crate::Exec::shell("id -u; cat /proc/self/uid_map; hostname sandbox && hostname")
+            .unshare(Namespace::User)
+            .unshare(Namespace::Uts)
+            .uid_map(&[(0, unsafe { libc::geteuid() }, 1)])
+            .gid_map(&[(0, unsafe { libc::getegid() }, 1)])
+            .capture()
+        {
+            Err(crate::PopenError::ChildSetupFailed { errno, .. }) if errno == libc::EPERM => {
+                return
+            }
+            result => result.unwrap(),
+        };
+    let lines: Vec<_> = out
+        .stdout_str()
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    assert_eq!(lines[0], "0");
+    assert_eq!(lines[1], format!("0 {} 1", unsafe { libc::geteuid() }));
+    assert_eq!(lines[2], "sandbox");
+    // the host name of the parent is unchanged
+    let host = crate::Exec::cmd("hostname").capture().unwrap().stdout_str();
+    assert_ne!(host.trim(), "sandbox");
+
+    let config = PopenConfig::builder().uid_map(&[(0, 0, 1)]).build();
+    assert!(config.is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn seccomp() {
+    use crate::unix::{SeccompAction, SeccompFilter};
+
+    // everything except uname is allowed
+    let allowed: Vec<libc::c_long> = (0..1024).filter(|&nr| nr != libc::SYS_uname).collect();
+    let out = crate::Exec::cmd("uname")
+        .seccomp(SeccompFilter::allowlist(
+            &allowed,
+            SeccompAction::Errno(libc::EPERM as u16),
+        ))
+        .stderr(Redirection::Pipe)
+        .stdout(Redirection::Pipe)
+        .capture()
+        .unwrap();
+    assert!(!out.success());
+    assert!(out.stdout.is_empty());
+
+    // an empty program is rejected by the kernel
+    let err = crate::Exec::cmd("true")
+        .seccomp(SeccompFilter::from_program(vec![]))
+        .join()
+        .unwrap_err();
+    match err {
+        crate::PopenError::ChildSetupFailed { errno, .. } => assert_eq!(errno, libc::EINVAL),
+        err => panic!("unexpected error {}", err),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn landlock() {
+    use crate::unix::Landlock;
+
+    if Landlock::abi_version().is_none() {
+        let out = crate::Exec::cmd("true")
+            .landlock(Landlock::new().best_effort(true))
+            .join()
+            .unwrap();
+        assert!(out.success());
+        return;
+    }
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let inside = tmpdir.path().join("inside");
+    std::fs::create_dir(&inside).unwrap();
+    let rules = Landlock::new().allow_read("/").allow_write(&inside);
+    let status = crate::Exec::cmd("touch")
+        .arg(inside.join("file"))
+        .landlock(rules.clone())
+        .join()
+        .unwrap();
+    assert!(status.success());
+    let status = crate::Exec::cmd("touch")
+        .arg(tmpdir.path().join("file"))
+        .landlock(rules)
+        .stderr(crate::NullFile)
+        .join()
+        .unwrap();
+    assert!(!status.success());
+    assert!(inside.join("file").exists());
+    assert!(!tmpdir.path().join("file").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn keep_capabilities() {
+    use crate::unix::Capability;
+
+    // dropping from the bounding set requires CAP_SETPCAP
+    let out = match crate::Exec::cmd("grep")
+        .args(&["^Cap\\(Inh\\|Bnd\\|Amb\\)", "/proc/self/status"])
+        .keep_capabilities(&[Capability::NetBindService, Capability::Kill])
+        .capture()
+    {
+        Err(crate::PopenError::ChildSetupFailed { errno, .. }) if errno == libc::EPERM => return,
+        result => result.unwrap(),
+    };
+    let caps: Vec<_> = out
+        .stdout_str()
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    assert_eq!(
+        caps,
+        [
+            "CapInh: 0000000000000000",
+            "CapBnd: 0000000000000420",
+            "CapAmb: 0000000000000000"
+        ]
+    );
+}
+
+#[test]
+fn daemonize() {
+    let mut p = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            daemonize: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let pid = p.pid().unwrap();
+    // the daemon runs in a session of its own
+    assert_ne!(unsafe { libc::getsid(pid as i32) }, unsafe {
+        libc::getsid(0)
+    });
+    p.kill().unwrap();
+    assert_eq!(p.wait().unwrap(), ExitStatus::Undetermined);
+}
+
+#[test]
+fn drop_policy_kill_and_wait() {
+    let p = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            drop_policy: crate::DropPolicy::KillAndWait,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let pid = p.pid().unwrap();
+    drop(p);
+    // the child has been killed and reaped
+    let err = crate::posix::kill(pid, 0).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ESRCH));
+}
+
+#[test]
+fn drop_policy_reap() {
+    let p = Popen::create(
+        &["sleep", "0.1"],
+        PopenConfig {
+            drop_policy: crate::DropPolicy::Reap,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let pid = p.pid().unwrap();
+    drop(p);
+    // the child is reaped in the background once it exits
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while crate::posix::kill(pid, 0).is_ok() {
+        assert!(std::time::Instant::now() < deadline);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn scoped_stops_on_panic() {
+    let mut pid = None;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        Popen::scoped(&["sleep", "1000"], PopenConfig::default(), |p| {
+            pid = p.pid();
+            panic!("scope failed");
+        })
+    }));
+    assert!(result.is_err());
+    let err = crate::posix::kill(pid.unwrap(), 0).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ESRCH));
+}
+
+#[test]
+fn resource_usage() {
+    let mut p = Popen::create(
+        &[
+            "sh",
+            "-c",
+            "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done",
+        ],
+        PopenConfig::default(),
+    )
+    .unwrap();
+    assert!(p.resource_usage().is_none());
+    assert!(p.wait().unwrap().success());
+    let usage = p.resource_usage().unwrap();
+    assert!(usage.user_time + usage.system_time > std::time::Duration::from_secs(0));
+    assert!(usage.max_rss > 0);
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn sample_usage() {
+    let mut p = Popen::create(
+        &["sleep", "1000"],
+        PopenConfig {
+            drop_policy: crate::DropPolicy::KillAndWait,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    // let the child get past exec
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let sample = p.sample_usage().unwrap();
+    assert!(sample.rss > 0);
+    p.kill().unwrap();
+    p.wait().unwrap();
+    assert!(p.sample_usage().is_err());
+}
+
+#[test]
+fn exit_status_display() {
+    let mut p = Popen::create(&["sleep", "5"], PopenConfig::default()).unwrap();
+    p.terminate().unwrap();
+    let status = p.wait().unwrap();
+    assert_eq!(status.signal_name(), Some("SIGTERM"));
+    assert!(!p.core_dumped());
+    assert_eq!(status.to_string(), "killed by SIGTERM");
+    let status = ExitStatus::Signaled(libc::SIGSEGV as u8);
+    assert!(status.is_killed_by(libc::SIGSEGV));
+    assert_eq!(status.to_string(), "killed by SIGSEGV");
+    assert_eq!(ExitStatus::Exited(3).to_string(), "exited with status 3");
+    assert_eq!(ExitStatus::Exited(3).signal_name(), None);
+}
+
+#[test]
+fn core_dump_keeps_signal_number() {
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let mut p = Popen::create(
+        &["sh", "-c", "ulimit -c unlimited 2>/dev/null; kill -SEGV $$"],
+        PopenConfig {
+            cwd: Some(tmpdir.path().as_os_str().to_owned()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let status = p.wait().unwrap();
+    // Whether a core is actually written depends on the system
+    // configuration, but the status must not depend on it.
+    assert_eq!(status, ExitStatus::Signaled(libc::SIGSEGV as u8));
+    assert_eq!(status.signal(), Some(libc::SIGSEGV as u8));
+    assert_eq!(status.to_string(), "killed by SIGSEGV");
+    let core_written = std::fs::read_dir(tmpdir.path())
+        .unwrap()
+        .any(|e| e.unwrap().file_name().to_string_lossy().starts_with("core"));
+    if core_written {
+        assert!(p.core_dumped());
+    }
+}
+
+#[test]
+fn std_exit_status_conversion() {
+    use std::convert::TryFrom;
+    let std_status = std::process::Command::new("sh")
+        .args(["-c", "exit 3"])
+        .status()
+        .unwrap();
+    assert_eq!(ExitStatus::from(std_status), ExitStatus::Exited(3));
+    for &status in &[
+        ExitStatus::Exited(0),
+        ExitStatus::Exited(3),
+        ExitStatus::Signaled(libc::SIGTERM as u8),
+        ExitStatus::Signaled(libc::SIGSEGV as u8),
+    ] {
+        let std_status = std::process::ExitStatus::try_from(status).unwrap();
+        assert_eq!(ExitStatus::from(std_status), status);
+    }
+    assert!(std::process::ExitStatus::try_from(ExitStatus::Undetermined).is_err());
+    assert!(std::process::ExitStatus::try_from(ExitStatus::Exited(256)).is_err());
+}
+
+#[test]
+fn redirect_to_owned_fd() {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    let mut p = Popen::create(
+        &["echo", "foo"],
+        PopenConfig {
+            stdout: Redirection::from_fd(theirs),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.wait().unwrap().success());
+    let mut output = String::new();
+    (&ours).read_to_string(&mut output).unwrap();
+    assert_eq!(output, "foo\n");
+}
+
+#[test]
+fn from_pid() {
+    // reaped by the adopting Popen
+    #[allow(clippy::zombie_processes)]
+    let child = std::process::Command::new("sh")
+        .args(["-c", "exit 3"])
+        .spawn()
+        .unwrap();
+    let mut p = Popen::from_pid(child.id());
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(3));
+
+    // not our child: the exit is observed, but the status is unknown
+    let out = crate::Exec::shell("sleep 0.3 >/dev/null & echo $!")
+        .capture()
+        .unwrap();
+    let pid = out.stdout_str().trim().parse().unwrap();
+    let mut p = Popen::from_pid(pid);
+    assert!(p.poll().is_none());
+    assert_eq!(p.wait().unwrap(), ExitStatus::Undetermined);
+}
+
+#[test]
+fn setgroups() {
+    use crate::unix::ExecExt;
+
+    assert!(crate::unix::user_groups("root", 0).unwrap().contains(&0));
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let out = crate::Exec::cmd("id")
+        .arg("-G")
+        .setgroups(vec![1, 2])
+        .setgid(3)
+        .setuid(4)
+        .capture()
+        .unwrap();
+    let mut groups: Vec<u32> = out
+        .stdout_str()
+        .split_whitespace()
+        .map(|gid| gid.parse().unwrap())
+        .collect();
+    groups.sort_unstable();
+    assert_eq!(groups, vec![1, 2, 3]);
+}
+
+#[test]
+fn umask() {
+    use crate::unix::ExecExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let file = tmpdir.path().join("created");
+    crate::Exec::cmd("touch")
+        .arg(&file)
+        .umask(0o077)
+        .join()
+        .unwrap();
+    let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn chroot() {
+    use crate::unix::ExecExt;
+
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    // cwd is interpreted inside the new root
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", "pwd"])
+        .chroot("/")
+        .cwd("/tmp")
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "/tmp\n");
+    // the program is looked up inside the new root
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let err = crate::Exec::cmd("sh").chroot(tmpdir.path()).join();
+    match err {
+        Err(crate::PopenError::ExecFailed { cause, .. }) => {
+            assert_eq!(cause, crate::ExecFailure::NotFound)
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn chroot_executable_search() {
+    use crate::ExecutableSearch;
+    use std::os::unix::fs::PermissionsExt;
+
+    // the search happens inside the new root, before anything requiring
+    // privileges is attempted
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let config = PopenConfig {
+        chroot: Some(tmpdir.path().into()),
+        executable_search: ExecutableSearch::Path("/bin".into()),
+        ..Default::default()
+    };
+    match Popen::create(&["sh"], config) {
+        Err(crate::PopenError::ExecutableNotFound { name, .. }) => assert_eq!(name, "sh"),
+        other => panic!("unexpected result {:?}", other),
+    }
+    // whether or not chroot() is then allowed, the program is found
+    let bin = tmpdir.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(bin.join("sh"), "").unwrap();
+    std::fs::set_permissions(bin.join("sh"), PermissionsExt::from_mode(0o755)).unwrap();
+    let config = PopenConfig {
+        chroot: Some(tmpdir.path().into()),
+        executable_search: ExecutableSearch::Path("/bin".into()),
+        ..Default::default()
+    };
+    match Popen::create(&["sh"], config) {
+        Err(crate::PopenError::ExecutableNotFound { .. }) => panic!("sh not found in the root"),
+        Ok(mut p) => {
+            p.wait().unwrap();
+        }
+        Err(_) => (),
+    }
+}
+
+#[test]
+fn cwd_fd() {
+    use crate::unix::ExecExt;
+    use std::fs::{self, File};
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let dir = tmpdir.path().canonicalize().unwrap();
+    fs::create_dir(dir.join("old")).unwrap();
+    let handle = File::open(dir.join("old")).unwrap();
+    // the child starts in the opened directory, under its new name
+    fs::rename(dir.join("old"), dir.join("new")).unwrap();
+    let expected = format!("{}\n", dir.join("new").display());
+    for &fork in &[false, true] {
+        let mut exec = crate::Exec::cmd("sh")
+            .args(&["-c", "pwd -P"])
+            .cwd_fd(handle.try_clone().unwrap());
+        if fork {
+            // umask keeps posix_spawn() from being used
+            exec = exec.umask(0o022);
+        }
+        let out = exec.capture().unwrap();
+        assert_eq!(out.stdout_str(), expected);
+    }
+
+    let file = File::create(dir.join("file")).unwrap();
+    match crate::Exec::cmd("true").cwd_fd(file).join() {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::Chdir);
+            assert_eq!(errno, libc::ENOTDIR);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let a = crate::Exec::cmd("true");
+    let hash = a.clone().cwd_fd(handle.try_clone().unwrap()).spec_hash();
+    assert_ne!(hash, a.spec_hash());
+    assert_eq!(
+        hash,
+        a.clone().cwd_fd(handle.try_clone().unwrap()).spec_hash()
+    );
+    let other = File::open(&dir).unwrap();
+    assert_ne!(hash, a.clone().cwd_fd(other).spec_hash());
+    let err = crate::Exec::cmd("true").cwd_fd(handle).cwd("/").join();
+    assert!(matches!(err, Err(crate::PopenError::LogicError(_))));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn setsid_controlling_tty() {
+    use crate::unix::ExecExt;
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    // the session ID, field 6 of /proc/PID/stat, is the PID of the leader
+    let script = "read pid comm state ppid pgrp sid rest < /proc/$$/stat; \
+                  test $sid = $$ && echo leader; : < /dev/tty && echo tty";
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", script])
+        .setsid()
+        .stdout(Redirection::Pipe)
+        .stderr(crate::NullFile)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "leader\n");
+
+    let tty = unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0);
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+        let name = std::ffi::CStr::from_ptr(libc::ptsname(master));
+        let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+        assert!(slave >= 0);
+        (File::from_raw_fd(master), File::from_raw_fd(slave))
+    };
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", script])
+        .controlling_tty(tty.1)
+        .stdout(Redirection::Pipe)
+        .stderr(crate::NullFile)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "leader\ntty\n");
+
+    let config = PopenConfig::builder().controlling_tty(tty.0).build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn pty() {
+    use crate::unix::ExecExt;
+    use crate::PtySize;
+
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", "test -t 0 && test -t 2 && stty size"])
+        .pty(PtySize {
+            rows: 30,
+            cols: 100,
+        })
+        .capture()
+        .unwrap();
+    assert!(out.exit_status.success());
+    assert_eq!(out.stdout_str(), "30 100\r\n");
+
+    let out = crate::Exec::cmd("sh")
+        .args(&["-c", "read x; echo got $x"])
+        .pty(PtySize::default())
+        .pty_echo(false)
+        .stdin("hello\n")
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "got hello\r\n");
+
+    let a = crate::Exec::cmd("true");
+    let hashes = [
+        a.spec_hash(),
+        a.clone().pty(PtySize::default()).spec_hash(),
+        a.clone().pty(PtySize { rows: 30, cols: 80 }).spec_hash(),
+        a.clone()
+            .pty(PtySize::default())
+            .pty_echo(false)
+            .spec_hash(),
+    ];
+    for (i, x) in hashes.iter().enumerate() {
+        assert!(!hashes[i + 1..].contains(x), "{}", i);
+    }
+
+    let config = PopenConfig::builder()
+        .pty(PtySize::default())
+        .stderr(Redirection::Pipe)
+        .build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn pty_resize() {
+    use crate::PtySize;
+    use std::io::{Read, Write};
+
+    let mut p = Popen::create(
+        &["sh", "-c", "read x; stty size"],
+        PopenConfig {
+            pty: Some(PtySize::default()),
+            pty_raw: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let size = PtySize {
+        rows: 40,
+        cols: 120,
+    };
+    p.pty().unwrap().resize(size).unwrap();
+    p.stdin.as_ref().unwrap().write_all(b"\n").unwrap();
+    let mut out = vec![];
+    let err = p
+        .stdout
+        .as_ref()
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EIO));
+    // raw mode doesn't translate the newline
+    assert_eq!(out, b"40 120\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[cfg(feature = "sigwinch")]
+#[test]
+fn pty_relay_resize() {
+    use crate::PtySize;
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    // a pseudo terminal standing in for the terminal of this process
+    let (terminal, outer) = crate::posix::openpty(50, 132).unwrap();
+    let mut p = Popen::create(
+        &["sleep", "5"],
+        PopenConfig {
+            pty: Some(PtySize::default()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let pty = p.pty().unwrap().clone();
+    let relay = pty.relay_resize(outer).unwrap();
+    let size = |rows, cols| PtySize { rows, cols };
+    assert_eq!(PtySize::of_terminal(&pty).unwrap(), size(50, 132));
+
+    crate::posix::set_winsize(terminal.as_raw_fd(), 60, 200).unwrap();
+    unsafe { libc::raise(libc::SIGWINCH) };
+    let start = Instant::now();
+    while PtySize::of_terminal(&pty).unwrap() != size(60, 200) {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    drop(relay);
+    crate::posix::set_winsize(terminal.as_raw_fd(), 10, 10).unwrap();
+    unsafe { libc::raise(libc::SIGWINCH) };
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(PtySize::of_terminal(&pty).unwrap(), size(60, 200));
+    p.kill().unwrap();
+    p.wait().unwrap();
+}
+
+#[test]
+fn pre_exec() {
+    use crate::unix::ExecExt;
+
+    // the hook runs after the standard streams are redirected
+    let out = unsafe {
+        crate::Exec::cmd("echo")
+            .arg("main")
+            .pre_exec(|| {
+                libc::write(1, b"hook\n".as_ptr() as *const libc::c_void, 5);
+                Ok(())
+            })
+            .capture()
+            .unwrap()
+    };
+    assert_eq!(out.stdout_str(), "hook\nmain\n");
+
+    let err = unsafe {
+        crate::Exec::cmd("true")
+            .pre_exec(|| Err(std::io::Error::from_raw_os_error(libc::EPERM)))
+            .join()
+    };
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::PreExec);
+            assert_eq!(errno, libc::EPERM);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    // a panic is reported without unwinding in the child
+    let err = unsafe { crate::Exec::cmd("true").pre_exec(|| panic!("boom")).join() };
+    match err {
+        Err(crate::PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::PreExec);
+            assert_eq!(errno, 0);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn fd_map() {
+    use crate::unix::ExecExt;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+
+    // swap the numbers of the write ends of two pipes in the child
+    let (mut read_a, write_a) = crate::posix::pipe().unwrap();
+    let (mut read_b, write_b) = crate::posix::pipe().unwrap();
+    let (fd_a, fd_b) = (write_a.as_raw_fd(), write_b.as_raw_fd());
+    let script = format!("echo a >&{}; echo b >&{}", fd_b, fd_a);
+    crate::Exec::cmd("sh")
+        .args(&["-c", &script])
+        .map_fd(fd_b, write_a)
+        .map_fd(fd_a, write_b)
+        .join()
+        .unwrap();
+    let mut out = String::new();
+    read_a.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "a\n");
+    out.clear();
+    read_b.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "b\n");
+
+    // the original descriptor isn't inherited, even without FD_CLOEXEC
+    let (_read, write) = crate::posix::pipe().unwrap();
+    crate::posix::set_cloexec(write.as_raw_fd(), false).unwrap();
+    let fd = write.as_raw_fd();
+    let script = format!("! [ -e /dev/fd/{} ] && [ -e /dev/fd/{} ]", fd, fd + 10);
+    let status = crate::Exec::cmd("sh")
+        .args(&["-c", &script])
+        .map_fd(fd + 10, write)
+        .join()
+        .unwrap();
+    assert!(status.success());
+
+    let (_, write) = crate::posix::pipe().unwrap();
+    let err = crate::Exec::cmd("true").map_fd(1, write).join();
+    assert!(matches!(err, Err(crate::PopenError::LogicError(_))));
+}
+
+#[test]
+fn pipes_close_on_exec() {
+    use std::os::unix::io::AsRawFd;
+
+    let cloexec = |fd: &dyn AsRawFd| crate::posix::is_cloexec(fd.as_raw_fd()).unwrap();
+    let (read, write) = crate::make_pipe().unwrap();
+    assert!(cloexec(&read) && cloexec(&write));
+    let (master, slave) = crate::posix::openpty(24, 80).unwrap();
+    assert!(cloexec(&master) && cloexec(&slave));
+
+    // the child still gets its ends of the pipes
+    let mut p = Popen::create(
+        &["cat"],
+        PopenConfig {
+            stdin: Redirection::Pipe,
+            stdout: Redirection::Pipe,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(cloexec(p.stdin.as_ref().unwrap()));
+    assert!(cloexec(p.stdout.as_ref().unwrap()));
+    let (out, _) = p.communicate(Some("foo")).unwrap();
+    assert_eq!(out.unwrap(), "foo");
+}
+
+#[test]
+fn fifo() {
+    use crate::unix::Fifo;
+    use std::io::{Read, Write};
+
+    let fifo = Fifo::temp().unwrap();
+    let path = fifo.path().to_owned();
+
+    let mut p = crate::Exec::cmd("sh")
+        .args(&["-c", "echo foo > \"$0\""])
+        .arg(&path)
+        .popen()
+        .unwrap();
+    let mut out = String::new();
+    fifo.open_read(&mut p)
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "foo\n");
+    assert!(p.wait().unwrap().success());
+
+    let mut p = crate::Exec::cmd("cat")
+        .arg(&path)
+        .stdout(Redirection::Pipe)
+        .popen()
+        .unwrap();
+    fifo.open_write(&mut p).unwrap().write_all(b"bar").unwrap();
+    assert_eq!(p.communicate(None).unwrap().0.unwrap(), "bar");
+
+    // a child that never opens the FIFO doesn't block the parent
+    let mut p = crate::Exec::cmd("true").popen().unwrap();
+    out.clear();
+    fifo.open_read(&mut p)
+        .unwrap()
+        .read_to_string(&mut out)
+        .unwrap();
+    assert_eq!(out, "");
+    let mut p = crate::Exec::cmd("true").popen().unwrap();
+    let err = fifo
+        .open_write(&mut p)
+        .unwrap()
+        .write_all(b"bar")
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+    let (mut read, write) = fifo.open_pair().unwrap();
+    crate::Exec::cmd("printf")
+        .arg("baz")
+        .stdout(write)
+        .join()
+        .unwrap();
+    out.clear();
+    read.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "baz");
+
+    drop(fifo);
+    assert!(!path.exists());
+    assert!(!path.parent().unwrap().exists());
+}
+
+#[test]
+fn socket_stdio() {
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+
+    // an inetd-style server reading and writing its connected socket
+    let (mut ours, theirs) = UnixStream::pair().unwrap();
+    let mut p = crate::Exec::cmd("cat")
+        .stdin(Redirection::from_fd(theirs.try_clone().unwrap()))
+        .stdout(Redirection::from_fd(theirs))
+        .popen()
+        .unwrap();
+    ours.write_all(b"foo\n").unwrap();
+    ours.shutdown(Shutdown::Write).unwrap();
+    let mut out = String::new();
+    ours.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "foo\n");
+    assert!(p.wait().unwrap().success());
+}
+
+#[test]
+fn listen_fds() {
+    use crate::unix::ExecExt;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let out = crate::Exec::cmd("sh")
+        .args(&[
+            "-c",
+            "test -S /dev/fd/3 && echo $LISTEN_FDS $(test $LISTEN_PID = $$ && echo ok)",
+        ])
+        .env("LISTEN_PID", "1")
+        .listen_fd(listener)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "1 ok\n");
+}
+
+#[test]
+fn notify_socket() {
+    use crate::unix::{ExecExt, NotifyMessage};
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    let mut p = crate::Exec::cmd("sh")
+        .args(&["-c", "echo $NOTIFY_SOCKET; sleep 5"])
+        .stdout(Redirection::Pipe)
+        .notify_socket(true)
+        .popen()
+        .unwrap();
+    let mut path = String::new();
+    BufReader::new(p.stdout.as_ref().unwrap())
+        .read_line(&mut path)
+        .unwrap();
+    let path = PathBuf::from(path.trim_end());
+    // send what sd_notify() would
+    let client = UnixDatagram::unbound().unwrap();
+    client.send_to(b"STATUS=starting", &path).unwrap();
+    client.send_to(b"READY=1\nWATCHDOG=1\n", &path).unwrap();
+    let notifications = p.notifications().unwrap();
+    assert_eq!(
+        notifications.recv_timeout(Duration::from_secs(5)),
+        Some(NotifyMessage::Status("starting".into()))
+    );
+    assert!(notifications.wait_ready(Duration::from_secs(5)));
+    assert_eq!(
+        notifications.recv_timeout(Duration::from_secs(5)),
+        Some(NotifyMessage::Watchdog)
+    );
+    assert_eq!(notifications.try_recv(), None);
+    p.kill().unwrap();
+    p.wait().unwrap();
+    drop(p);
+    assert!(!path.exists());
+}
+
+#[test]
+fn executable_search_child_env() {
+    use crate::ExecutableSearch;
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let script = tmpdir.path().join("subprocess-test-script");
+    std::fs::write(&script, "#!/bin/sh\necho found\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // only the PATH of the child has the script
+    let out = crate::Exec::cmd("subprocess-test-script")
+        .env("PATH", tmpdir.path())
+        .executable_search(ExecutableSearch::ChildEnv)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "found\n");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn signal_mask_reset() {
+    use std::{mem, ptr};
+
+    // with the signal blocked in this thread, both the posix_spawn() path
+    // and the fork path, which umask requires, must unblock it
+    let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+    let mut old: libc::sigset_t = unsafe { mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGUSR2);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, &mut old);
+    }
+    for umask in [None, Some(0o022)] {
+        let mut p = Popen::create(
+            &["grep", "SigBlk", "/proc/self/status"],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                umask,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (out, _) = p.communicate(None).unwrap();
+        assert_eq!(out.unwrap(), "SigBlk:\t0000000000000000\n");
+    }
+    unsafe {
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut());
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn vfork() {
+    use crate::PopenError;
+
+    // umask keeps posix_spawn() from being used
+    let config = || PopenConfig {
+        stdout: Redirection::Pipe,
+        umask: Some(0o027),
+        vfork: true,
+        ..Default::default()
+    };
+    let mut p = Popen::create(&["sh", "-c", "umask"], config()).unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "0027\n");
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(0));
+
+    let cwd_config = PopenConfig {
+        cwd: Some("/".into()),
+        ..config()
+    };
+    let mut p = Popen::create(&["pwd"], cwd_config).unwrap();
+    let (out, _) = p.communicate(None).unwrap();
+    assert_eq!(out.unwrap(), "/\n");
+
+    match Popen::create(&["nosuchcommand"], config()) {
+        Err(PopenError::ExecFailed { error, .. }) => {
+            assert_eq!(error.raw_os_error(), Some(libc::ENOENT))
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let config = PopenConfig {
+        cwd: Some("/nosuchdir".into()),
+        ..config()
+    };
+    match Popen::create(&["true"], config) {
+        Err(PopenError::ChildSetupFailed { step, errno }) => {
+            assert_eq!(step, crate::ChildSetupStep::Chdir);
+            assert_eq!(errno, libc::ENOENT);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let config = PopenConfig {
+        vfork: true,
+        setuid: Some(0),
+        ..Default::default()
+    };
+    assert!(matches!(
+        Popen::create(&["true"], config),
+        Err(PopenError::LogicError(_))
+    ));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn pipe_size() {
+    let max_size: usize = std::fs::read_to_string("/proc/sys/fs/pipe-max-size")
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let mut p = Popen::create(
+        &["head", "-c", "3000000", "/dev/zero"],
+        PopenConfig {
+            stdout: Redirection::Pipe,
+            pipe_size: Some(1 << 20),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let (out_size, err_size) = p.pipe_size();
+    assert!(out_size.unwrap() >= std::cmp::min(1 << 20, max_size));
+    assert_eq!(err_size, None);
+    let (out, _) = p.communicate_bytes(None).unwrap();
+    assert_eq!(out.unwrap().len(), 3_000_000);
+
+    // only the pipes are resized
+    let p = crate::Exec::cmd("true")
+        .stderr(Redirection::Pipe)
+        .pipe_size(1 << 20)
+        .popen()
+        .unwrap();
+    let (out_size, err_size) = p.pipe_size();
+    assert_eq!(out_size, None);
+    assert!(err_size.unwrap() >= std::cmp::min(1 << 20, max_size));
+}
+
+#[test]
+fn exec_failure_causes() {
+    use crate::{ExecFailure, PopenError};
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let write = |name: &str, content: &str, mode: u32| {
+        let path = tmpdir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path.into_os_string()
+    };
+    let cases = [
+        (
+            write("no-interp", "#!/nosuchdir/sh\n", 0o755),
+            ExecFailure::InterpreterNotFound,
+        ),
+        (
+            write("not-exec", "#!/bin/sh\n", 0o644),
+            ExecFailure::PermissionDenied,
+        ),
+        (
+            write("garbage", "\x01\x02\x03\x04", 0o755),
+            ExecFailure::NotExecutable,
+        ),
+        ("nosuchcommand".into(), ExecFailure::NotFound),
+    ];
+    // umask keeps posix_spawn() from being used
+    for umask in [None, Some(0o022)] {
+        for (program, expected) in &cases {
+            let config = PopenConfig {
+                cwd: Some("/".into()),
+                umask,
+                ..Default::default()
+            };
+            let err = Popen::create(&[program.clone(), "arg".into()], config).unwrap_err();
+            let message = err.to_string();
+            match err {
+                PopenError::ExecFailed {
+                    program: failed,
+                    args,
+                    cwd,
+                    cause,
+                    ..
+                } => {
+                    assert_eq!(&failed, program);
+                    assert_eq!(args, ["arg"]);
+                    assert_eq!(cwd.as_deref(), Some(std::path::Path::new("/")));
+                    assert_eq!(cause, *expected);
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+            assert!(message.contains(&*program.to_string_lossy()), "{}", message);
+        }
+    }
+}
+
+#[test]
+fn spec_hash_unix() {
+    use crate::unix::ExecExt;
+    use std::fs::File;
+
+    let a = crate::Exec::cmd("true");
+    let hashes = [
+        a.spec_hash(),
+        a.clone().setgroups(vec![]).spec_hash(),
+        a.clone().setgroups(vec![0]).spec_hash(),
+        a.clone().chroot("").spec_hash(),
+        a.clone().chroot("/").spec_hash(),
+        a.clone().pgid(0).spec_hash(),
+        a.clone().pgid(1).spec_hash(),
+        a.clone()
+            .stdout(File::open("/dev/null").unwrap())
+            .spec_hash(),
+        a.clone().stdout(File::open("/").unwrap()).spec_hash(),
+        a.clone()
+            .controlling_tty(File::open("/dev/null").unwrap())
+            .spec_hash(),
+    ];
+    for (i, x) in hashes.iter().enumerate() {
+        assert!(!hashes[i + 1..].contains(x), "{}", i);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn spec_hash_linux() {
+    use crate::unix::{
+        Capability, ExecExt, Landlock, Namespace, Rlimit, SchedPolicy, SeccompAction, SeccompFilter,
+    };
+
+    let a = crate::Exec::cmd("true");
+    let user = a.clone().unshare(Namespace::User);
+    let hashes = [
+        a.spec_hash(),
+        a.clone().sched_policy(SchedPolicy::Batch).spec_hash(),
+        a.clone().sched_policy(SchedPolicy::Fifo(1)).spec_hash(),
+        a.clone().sched_policy(SchedPolicy::Fifo(2)).spec_hash(),
+        a.clone().cpu_affinity(&[0]).spec_hash(),
+        a.clone().cpu_affinity(&[0, 1]).spec_hash(),
+        a.clone().rlimit(Rlimit::Cpu, 1, 2).spec_hash(),
+        a.clone().rlimit(Rlimit::Cpu, 1, 3).spec_hash(),
+        a.clone().rlimit(Rlimit::OpenFiles, 1, 2).spec_hash(),
+        a.clone().oom_score_adj(100).spec_hash(),
+        a.clone().oom_score_adj(-100).spec_hash(),
+        a.clone().cgroup("/sys/fs/cgroup/a").spec_hash(),
+        a.clone().cgroup("/sys/fs/cgroup/b").spec_hash(),
+        user.spec_hash(),
+        user.clone().unshare(Namespace::Mount).spec_hash(),
+        user.clone().uid_map(&[(0, 1000, 1)]).spec_hash(),
+        user.clone().gid_map(&[(0, 1000, 1)]).spec_hash(),
+        user.clone().gid_map(&[(0, 1001, 1)]).spec_hash(),
+        a.clone()
+            .seccomp(SeccompFilter::allowlist(
+                &[libc::SYS_execve],
+                SeccompAction::Allow,
+            ))
+            .spec_hash(),
+        a.clone()
+            .seccomp(SeccompFilter::allowlist(
+                &[libc::SYS_execve],
+                SeccompAction::Log,
+            ))
+            .spec_hash(),
+        a.clone().landlock(Landlock::new()).spec_hash(),
+        a.clone()
+            .landlock(Landlock::new().allow_read("/"))
+            .spec_hash(),
+        a.clone()
+            .landlock(Landlock::new().allow_write("/"))
+            .spec_hash(),
+        a.clone()
+            .landlock(Landlock::new().best_effort(true))
+            .spec_hash(),
+        a.clone().keep_capabilities(&[]).spec_hash(),
+        a.clone()
+            .keep_capabilities(&[Capability::Chown])
+            .spec_hash(),
+    ];
+    for (i, x) in hashes.iter().enumerate() {
+        assert!(!hashes[i + 1..].contains(x), "{}", i);
+    }
+}
@@ -7,3 +7,224 @@ fn err_terminate() {
     p.terminate().unwrap();
     assert!(p.wait().unwrap() == ExitStatus::Exited(1));
 }
+
+#[test]
+fn long_path() {
+    use crate::win32::{self, long_path};
+    use std::ffi::OsStr;
+
+    let short = OsStr::new(r"C:\Windows\..\Windows");
+    assert_eq!(long_path(short, win32::MAX_PATH).unwrap(), short);
+
+    let name = "x".repeat(100);
+    let nested = format!(r"C:\{0}\{0}\..\{0}/{0}", name);
+    assert_eq!(
+        long_path(OsStr::new(&nested), win32::MAX_PATH).unwrap(),
+        OsStr::new(&format!(r"\\?\C:\{0}\{0}\{0}", name))
+    );
+    let unc = format!(r"\\server\share\{0}\{0}\{0}", name);
+    assert_eq!(
+        long_path(OsStr::new(&unc), win32::MAX_PATH).unwrap(),
+        OsStr::new(&format!(r"\\?\UNC\server\share\{0}\{0}\{0}", name))
+    );
+    let verbatim = format!(r"\\?\C:\{0}\{0}\{0}", name);
+    assert_eq!(
+        long_path(OsStr::new(&verbatim), win32::MAX_PATH).unwrap(),
+        OsStr::new(&verbatim)
+    );
+}
+
+#[test]
+fn long_cwd() {
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let mut dir = tmpdir.path().to_owned();
+    while dir.as_os_str().len() < 300 {
+        dir.push("x".repeat(50));
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    // too long to be passed to CreateProcess as it is
+    let status = crate::Exec::cmd("cmd")
+        .args(&["/c", "exit 0"])
+        .cwd(&dir)
+        .join()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn program_path_with_space() {
+    use std::fs;
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let dir = tmpdir.path().join("a b");
+    fs::create_dir(&dir).unwrap();
+    let cmd = std::path::Path::new(&crate::win32::GetSystemDirectory().unwrap()).join("cmd.exe");
+    fs::copy(cmd, dir.join("c.exe")).unwrap();
+    // the program the path could be taken for if split at the space
+    fs::write(tmpdir.path().join("a.exe"), "not a program").unwrap();
+    let status = crate::Exec::cmd(dir.join("c.exe"))
+        .args(&["/c", "exit 5"])
+        .join()
+        .unwrap();
+    assert_eq!(status, ExitStatus::Exited(5));
+}
+
+#[test]
+fn batch_file_quoting() {
+    use crate::{ArgQuoting, Exec, PopenError};
+
+    let tmpdir = tempdir::TempDir::new("test").unwrap();
+    let script = tmpdir.path().join("args.bat");
+    std::fs::write(&script, "@echo [%1]\r\n").unwrap();
+    // the argument stays within its quotes despite Standard quoting
+    let out = Exec::cmd(&script)
+        .arg("a\" & echo injected")
+        .arg_quoting(ArgQuoting::Standard)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "[\"a\"\" & echo injected\"]\r\n");
+
+    match Exec::cmd(&script).arg("a\nb").join() {
+        Err(PopenError::UnsafeBatchArgument { arg, .. }) => assert_eq!(arg, "a\nb"),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(Exec::cmd(&script)
+        .arg("a\nb")
+        .allow_batch(true)
+        .join()
+        .is_ok());
+}
+
+#[test]
+fn raw_cmdline() {
+    let out = crate::Exec::cmd("cmd")
+        .raw_cmdline(r#"cmd /c echo "a  b"&echo c"#)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str(), "\"a  b\"\r\nc\r\n");
+    let a = crate::Exec::cmd("cmd");
+    assert_ne!(
+        a.spec_hash(),
+        a.clone().raw_cmdline("cmd /c echo a").spec_hash()
+    );
+    assert_ne!(
+        a.clone().raw_cmdline("cmd /c echo a").spec_hash(),
+        a.clone().raw_cmdline("cmd /c echo b").spec_hash()
+    );
+}
+
+#[test]
+fn job_object() {
+    use crate::windows::JobObject;
+
+    let job = JobObject::new().unwrap();
+    job.set_kill_on_close(true).unwrap();
+    job.set_process_limit(Some(10)).unwrap();
+    let mut p = Popen::create(
+        &["cmd", "/c", "ping -n 100 127.0.0.1 > nul"],
+        PopenConfig {
+            job: Some(job.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(p.poll().is_none());
+    // terminates cmd together with the ping it started
+    job.terminate(7).unwrap();
+    assert!(p.wait().unwrap() == ExitStatus::Exited(7));
+}
+
+#[test]
+fn priority_class() {
+    use crate::windows::PriorityClass;
+
+    let status = crate::Exec::cmd("cmd")
+        .args(&["/c", "exit 0"])
+        .priority_class(PriorityClass::BelowNormal)
+        .join()
+        .unwrap();
+    assert!(status.success());
+    let config = PopenConfig::builder()
+        .nice(10)
+        .priority_class(PriorityClass::Idle)
+        .build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn no_window() {
+    use crate::windows::CREATE_NO_WINDOW;
+
+    let out = crate::Exec::cmd("cmd")
+        .args(&["/c", "echo foo"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .show_window(0)
+        .capture()
+        .unwrap();
+    assert_eq!(out.stdout_str().trim(), "foo");
+}
+
+#[test]
+fn restricted_token() {
+    let out = crate::Exec::cmd("whoami")
+        .arg("/priv")
+        .restricted_token()
+        .capture()
+        .unwrap();
+    let privileges = out.stdout_str();
+    assert!(privileges.contains("SeChangeNotifyPrivilege"));
+    assert!(!privileges.contains("SeShutdownPrivilege"));
+}
+
+#[test]
+fn logon_conflicts() {
+    use crate::windows::Logon;
+
+    let logon = Logon {
+        user: "nobody".into(),
+        password: "secret".into(),
+        ..Default::default()
+    };
+    assert!(!format!("{:?}", logon).contains("secret"));
+    let config = PopenConfig::builder()
+        .logon(logon)
+        .restricted_token(true)
+        .build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn pty() {
+    use crate::PtySize;
+
+    let out = crate::Exec::cmd("cmd")
+        .args(&["/c", "echo foo"])
+        .pty(PtySize::default())
+        .capture()
+        .unwrap();
+    assert!(out.stdout_str().contains("foo"));
+    let config = PopenConfig::builder()
+        .pty(PtySize::default())
+        .stderr(crate::Redirection::Pipe)
+        .build();
+    assert!(config.is_err());
+}
+
+#[test]
+fn socket_stdout() {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let status = crate::Exec::cmd("cmd")
+        .args(&["/c", "echo foo"])
+        .stdout(crate::Redirection::from_socket(server).unwrap())
+        .join()
+        .unwrap();
+    assert!(status.success());
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert_eq!(out.trim(), "foo");
+}
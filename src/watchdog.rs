@@ -0,0 +1,59 @@
+// Watchdog stopping a subprocess that runs for longer than allowed by
+// PopenConfig::timeout.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::handle::ProcessHandle;
+use crate::popen::TimeoutAction;
+
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    // Start a thread that waits for the process to exit for up to
+    // `timeout`, and stops it as given by `action` if it doesn't.  The
+    // thread ends as soon as the process exits, and refers to it by a
+    // handle, so it can't stop an unrelated process that got its PID.
+    pub(crate) fn arm(
+        handle: ProcessHandle,
+        timeout: Duration,
+        action: TimeoutAction,
+    ) -> io::Result<Watchdog> {
+        let fired = Arc::new(AtomicBool::new(false));
+        let thread_fired = Arc::clone(&fired);
+        let deadline = Instant::now() + timeout;
+        thread::Builder::new()
+            .name("subprocess-watchdog".into())
+            .spawn(move || {
+                let exited = loop {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match handle.wait_timeout(remaining) {
+                        // a signal interrupted polling the pidfd
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        result => break result,
+                    }
+                };
+                if let Ok(false) = exited {
+                    // set before stopping the process, so that whoever
+                    // sees it exit also sees why
+                    thread_fired.store(true, Ordering::SeqCst);
+                    let _ = match action {
+                        TimeoutAction::Terminate => handle.terminate(),
+                        TimeoutAction::Kill => handle.kill(),
+                    };
+                }
+            })?;
+        Ok(Watchdog { fired })
+    }
+
+    // Whether the timeout has elapsed while the process was running.
+    pub(crate) fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
@@ -3,25 +3,38 @@
 use std::fs::File;
 use std::io::{Error, Result};
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::iter;
 use std::mem;
-use std::os::windows::ffi::OsStrExt;
-use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{
+    AsHandle, AsRawHandle, AsRawSocket, BorrowedHandle, FromRawHandle, OwnedHandle, OwnedSocket,
+    RawHandle,
+};
 use std::ptr;
 use std::rc::Rc;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use winapi;
 use winapi::shared::minwindef::{BOOL, DWORD, LPVOID};
+use winapi::shared::ntdef::HRESULT;
 use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
 use winapi::um::minwinbase::{LPSECURITY_ATTRIBUTES, SECURITY_ATTRIBUTES};
-use winapi::um::processthreadsapi::{CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW};
-use winapi::um::winbase::CREATE_UNICODE_ENVIRONMENT;
-use winapi::um::winnt::PHANDLE;
-use winapi::um::{handleapi, namedpipeapi, processenv, processthreadsapi, synchapi};
+use winapi::um::processthreadsapi::{
+    CreateProcessAsUserW, CreateProcessW, LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION,
+    STARTUPINFOW,
+};
+use winapi::um::winbase::{
+    CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT, STARTUPINFOEXW,
+};
+use winapi::um::winnt::{PHANDLE, PSID, SECURITY_CAPABILITIES};
+use winapi::um::{
+    fileapi, handleapi, jobapi2, namedpipeapi, processenv, processthreadsapi, securitybaseapi,
+    synchapi, sysinfoapi, userenv, winbase,
+};
 
-pub use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_BAD_PATHNAME};
+pub use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_BAD_EXE_FORMAT, ERROR_BAD_PATHNAME};
 pub const STILL_ACTIVE: u32 = 259;
 
 use crate::os_common::StandardStream;
@@ -46,6 +59,12 @@ impl AsRawHandle for Handle {
     }
 }
 
+impl AsHandle for Handle {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        unsafe { BorrowedHandle::borrow_raw(self.0) }
+    }
+}
+
 impl FromRawHandle for Handle {
     unsafe fn from_raw_handle(handle: RawHandle) -> Handle {
         Handle(handle)
@@ -54,6 +73,22 @@ impl FromRawHandle for Handle {
 
 pub const HANDLE_FLAG_INHERIT: u32 = 1;
 pub const STARTF_USESTDHANDLES: DWORD = winapi::um::winbase::STARTF_USESTDHANDLES;
+const STARTF_USESHOWWINDOW: DWORD = winapi::um::winbase::STARTF_USESHOWWINDOW;
+/// Creation flag starting the process in a new process group.
+pub const CREATE_NEW_PROCESS_GROUP: DWORD = winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+/// Creation flag starting a console process without a console.
+pub const DETACHED_PROCESS: DWORD = winapi::um::winbase::DETACHED_PROCESS;
+/// Creation flag starting a console process with a new console window.
+pub const CREATE_NEW_CONSOLE: DWORD = winapi::um::winbase::CREATE_NEW_CONSOLE;
+/// Creation flag starting a console process with a console that has
+/// no window.
+pub const CREATE_NO_WINDOW: DWORD = winapi::um::winbase::CREATE_NO_WINDOW;
+pub const CREATE_SUSPENDED: DWORD = winapi::um::winbase::CREATE_SUSPENDED;
+pub use winapi::um::winbase::{
+    ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS,
+    IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, REALTIME_PRIORITY_CLASS,
+};
+pub use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
 
 fn check(status: BOOL) -> Result<()> {
     if status != 0 {
@@ -76,6 +111,91 @@ fn to_nullterm(s: &OsStr) -> Vec<u16> {
     s.encode_wide().chain(iter::once(0u16)).collect()
 }
 
+pub fn GetFullPathName(path: &OsStr) -> Result<OsString> {
+    let path = to_nullterm(path);
+    let mut buf = vec![0u16; 512];
+    loop {
+        let len = unsafe {
+            fileapi::GetFullPathNameW(
+                path.as_ptr(),
+                buf.len() as DWORD,
+                buf.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        } as usize;
+        if len == 0 {
+            return Err(Error::last_os_error());
+        }
+        // on success the length excludes the terminating zero, and when
+        // the buffer is too small it includes it
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(OsString::from_wide(&buf));
+        }
+        buf.resize(len, 0);
+    }
+}
+
+pub fn GetSystemDirectory() -> Result<OsString> {
+    get_directory(|buf, len| unsafe { sysinfoapi::GetSystemDirectoryW(buf, len) })
+}
+
+pub fn GetWindowsDirectory() -> Result<OsString> {
+    get_directory(|buf, len| unsafe { sysinfoapi::GetWindowsDirectoryW(buf, len) })
+}
+
+// Call a function that stores a directory in the buffer, and returns
+// its length, or the required size if the buffer is too small.
+fn get_directory(get: impl Fn(*mut u16, u32) -> u32) -> Result<OsString> {
+    let mut buf = vec![0u16; 260];
+    loop {
+        let len = get(buf.as_mut_ptr(), buf.len() as u32) as usize;
+        if len == 0 {
+            return Err(Error::last_os_error());
+        }
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(OsString::from_wide(&buf));
+        }
+        buf.resize(len, 0);
+    }
+}
+
+// The longest path CreateProcess accepts as a program or a current
+// directory without the \\?\ prefix.  The current directory must leave
+// room for an 8.3 file name.
+pub const MAX_PATH: usize = 260;
+pub const MAX_DIR_PATH: usize = MAX_PATH - 12;
+
+// Return `path` unchanged if it is shorter than `limit`, and otherwise
+// as a full path in the \\?\ form, which isn't subject to the limit.
+// \\server\share\... becomes \\?\UNC\server\share\...
+pub fn long_path(path: &OsStr, limit: usize) -> Result<OsString> {
+    let starts_with = |wide: &[u16], prefix: &str| {
+        let prefix: Vec<u16> = prefix.encode_utf16().collect();
+        wide.starts_with(&prefix)
+    };
+    let wide: Vec<u16> = path.encode_wide().collect();
+    // already verbatim, or a device path
+    if wide.len() < limit || starts_with(&wide, r"\\?\") || starts_with(&wide, r"\\.\") {
+        return Ok(path.to_owned());
+    }
+    // \\?\ paths aren't normalized, so resolve relative paths, . and
+    // .. and forward slashes first
+    let full: Vec<u16> = GetFullPathName(path)?.encode_wide().collect();
+    let long = if starts_with(&full, r"\\?\") || starts_with(&full, r"\\.\") {
+        full
+    } else if starts_with(&full, r"\\") {
+        r"\\?\UNC\"
+            .encode_utf16()
+            .chain(full[2..].iter().copied())
+            .collect()
+    } else {
+        r"\\?\".encode_utf16().chain(full).collect()
+    };
+    Ok(OsString::from_wide(&long))
+}
+
 pub fn CreatePipe(inherit_handle: bool) -> Result<(File, File)> {
     let mut attributes = SECURITY_ATTRIBUTES {
         nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
@@ -104,19 +224,98 @@ pub fn CreateProcess(
     cmdline: &OsStr,
     env_block: &Option<Vec<u16>>,
     cwd: &Option<&OsStr>,
-    inherit_handles: bool,
+    mut inherit_handles: bool,
     mut creation_flags: u32,
     stdin: Option<RawHandle>,
     stdout: Option<RawHandle>,
     stderr: Option<RawHandle>,
-    sinfo_flags: u32,
+    mut sinfo_flags: u32,
+    show_window: Option<u16>,
+    credentials: Credentials<'_>,
+    app_container: Option<&Sid>,
+    extra_handles: &[RawHandle],
+    pseudo_console: Option<&PseudoConsole>,
 ) -> Result<(Handle, u64)> {
-    let mut sinfo: STARTUPINFOW = unsafe { mem::zeroed() };
+    let mut sinfo_ex: STARTUPINFOEXW = unsafe { mem::zeroed() };
+    let sinfo = &mut sinfo_ex.StartupInfo;
     sinfo.cb = mem::size_of::<STARTUPINFOW>() as DWORD;
     sinfo.hStdInput = stdin.unwrap_or(ptr::null_mut());
     sinfo.hStdOutput = stdout.unwrap_or(ptr::null_mut());
     sinfo.hStdError = stderr.unwrap_or(ptr::null_mut());
+    if let Some(show_window) = show_window {
+        sinfo.wShowWindow = show_window;
+        sinfo_flags |= STARTF_USESHOWWINDOW;
+    }
     sinfo.dwFlags = sinfo_flags;
+    // Only the listed handles are inherited, rather than every
+    // inheritable handle, which could belong to a process spawned
+    // concurrently by another thread.  The list must only contain
+    // inheritable handles, so the handles are made inheritable for the
+    // duration of the call, and not leaked into processes spawned by
+    // code that doesn't pass a list.  The spawns are serialized because
+    // the same handle, such as our stdout, can be passed to several
+    // children.  CreateProcessWithLogon doesn't take the list, but only
+    // passes the standard handles.
+    let _spawn = SPAWN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut inheritable = InheritGuard(vec![]);
+    let mut handle_list = vec![];
+    if inherit_handles {
+        for &handle in [stdin, stdout, stderr].iter().flatten() {
+            // standard handles which can't be inherited, such as
+            // console pseudo-handles, are left out
+            inheritable.add(handle, &mut handle_list).ok();
+        }
+        let logon = matches!(credentials, Credentials::Logon { .. });
+        if !logon {
+            for &handle in extra_handles {
+                inheritable.add(handle, &mut handle_list)?;
+            }
+        }
+        if handle_list.is_empty() {
+            inherit_handles = false;
+        } else if logon {
+            handle_list.clear();
+        }
+    }
+    // The handle list, the pseudo console and the AppContainer are
+    // passed in the attribute list of an extended STARTUPINFO.  They
+    // must outlive the CreateProcess call.
+    let mut capabilities: SECURITY_CAPABILITIES = unsafe { mem::zeroed() };
+    let attribute_count = !handle_list.is_empty() as DWORD
+        + app_container.is_some() as DWORD
+        + pseudo_console.is_some() as DWORD;
+    let _attributes = if attribute_count != 0 {
+        let attributes = ProcThreadAttributeList::new(attribute_count)?;
+        if !handle_list.is_empty() {
+            attributes.update(
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+                handle_list.as_mut_ptr() as LPVOID,
+                handle_list.len() * mem::size_of::<RawHandle>(),
+            )?;
+        }
+        if let Some(console) = pseudo_console {
+            // the value is the console handle itself
+            attributes.update(
+                PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+                console.0 as LPVOID,
+                mem::size_of::<RawHandle>(),
+            )?;
+        }
+        if let Some(sid) = app_container {
+            capabilities.AppContainerSid = sid.0;
+            attributes.update(
+                PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES,
+                &mut capabilities as *mut SECURITY_CAPABILITIES as LPVOID,
+                mem::size_of::<SECURITY_CAPABILITIES>(),
+            )?;
+        }
+        sinfo_ex.StartupInfo.cb = mem::size_of::<STARTUPINFOEXW>() as DWORD;
+        sinfo_ex.lpAttributeList = attributes.as_ptr();
+        creation_flags |= EXTENDED_STARTUPINFO_PRESENT;
+        Some(attributes)
+    } else {
+        None
+    };
     let mut pinfo: PROCESS_INFORMATION = unsafe { mem::zeroed() };
     let mut cmdline = to_nullterm(cmdline);
     let wc_appname = appname.map(to_nullterm);
@@ -126,22 +325,66 @@ pub fn CreateProcess(
         .unwrap_or(ptr::null()) as LPVOID;
     let cwd = cwd.map(to_nullterm);
     creation_flags |= CREATE_UNICODE_ENVIRONMENT;
+    let wc_appname = wc_appname
+        .as_ref()
+        .map(|v| v.as_ptr())
+        .unwrap_or(ptr::null());
+    let cwd = cwd.as_ref().map(|v| v.as_ptr()).unwrap_or(ptr::null());
     check(unsafe {
-        CreateProcessW(
-            wc_appname
-                .as_ref()
-                .map(|v| v.as_ptr())
-                .unwrap_or(ptr::null()),
-            cmdline.as_mut_ptr(),
-            ptr::null_mut(),         // lpProcessAttributes
-            ptr::null_mut(),         // lpThreadAttributes
-            inherit_handles as BOOL, // bInheritHandles
-            creation_flags,          // dwCreationFlags
-            env_block_ptr,           // lpEnvironment
-            cwd.as_ref().map(|v| v.as_ptr()).unwrap_or(ptr::null()), // lpCurrentDirectory
-            &mut sinfo,
-            &mut pinfo,
-        )
+        match credentials {
+            Credentials::Logon {
+                user,
+                domain,
+                password,
+                load_profile,
+            } => {
+                let user = to_nullterm(user);
+                let domain = domain.map(to_nullterm);
+                let password = to_nullterm(password);
+                winbase::CreateProcessWithLogonW(
+                    user.as_ptr(),
+                    domain.as_ref().map(|v| v.as_ptr()).unwrap_or(ptr::null()),
+                    password.as_ptr(),
+                    if load_profile {
+                        winbase::LOGON_WITH_PROFILE
+                    } else {
+                        0
+                    },
+                    wc_appname,
+                    cmdline.as_mut_ptr(),
+                    creation_flags,
+                    env_block_ptr,
+                    cwd,
+                    &mut sinfo_ex.StartupInfo,
+                    &mut pinfo,
+                )
+            }
+            Credentials::Token(token) => CreateProcessAsUserW(
+                token.as_raw_handle(),
+                wc_appname,
+                cmdline.as_mut_ptr(),
+                ptr::null_mut(),         // lpProcessAttributes
+                ptr::null_mut(),         // lpThreadAttributes
+                inherit_handles as BOOL, // bInheritHandles
+                creation_flags,          // dwCreationFlags
+                env_block_ptr,           // lpEnvironment
+                cwd,                     // lpCurrentDirectory
+                &mut sinfo_ex.StartupInfo,
+                &mut pinfo,
+            ),
+            Credentials::Current => CreateProcessW(
+                wc_appname,
+                cmdline.as_mut_ptr(),
+                ptr::null_mut(),         // lpProcessAttributes
+                ptr::null_mut(),         // lpThreadAttributes
+                inherit_handles as BOOL, // bInheritHandles
+                creation_flags,          // dwCreationFlags
+                env_block_ptr,           // lpEnvironment
+                cwd,                     // lpCurrentDirectory
+                &mut sinfo_ex.StartupInfo,
+                &mut pinfo,
+            ),
+        }
     })?;
     unsafe {
         drop(Handle::from_raw_handle(pinfo.hThread));
@@ -152,6 +395,311 @@ pub fn CreateProcess(
     }
 }
 
+// The user as whom CreateProcess runs the process.
+pub enum Credentials<'a> {
+    // the user of the current process
+    Current,
+    // CreateProcessAsUser with a primary token
+    Token(BorrowedHandle<'a>),
+    // CreateProcessWithLogon, which doesn't support handle inheritance
+    // other than of the standard handles
+    Logon {
+        user: &'a OsStr,
+        domain: Option<&'a OsStr>,
+        password: &'a OsStr,
+        load_profile: bool,
+    },
+}
+
+// Return the environment block of the user of the token, without the
+// variables of the current process.
+pub fn UserEnvironmentBlock(token: BorrowedHandle<'_>) -> Result<Vec<u16>> {
+    let mut block = ptr::null_mut();
+    check(unsafe { userenv::CreateEnvironmentBlock(&mut block, token.as_raw_handle(), 0) })?;
+    // The block ends with an empty string, i.e. two consecutive NULs.
+    let mut env = vec![];
+    unsafe {
+        let mut p = block as *const u16;
+        loop {
+            env.push(*p);
+            if *p == 0 && (env.len() == 1 || env[env.len() - 2] == 0) {
+                break;
+            }
+            p = p.add(1);
+        }
+        userenv::DestroyEnvironmentBlock(block);
+    }
+    if env.len() == 1 {
+        // an empty block has a second NUL
+        env.push(0);
+    }
+    Ok(env)
+}
+
+// Attribute list of an extended STARTUPINFO.
+struct ProcThreadAttributeList {
+    // usize for the alignment of the list
+    buf: Vec<usize>,
+}
+
+impl ProcThreadAttributeList {
+    fn new(count: DWORD) -> Result<ProcThreadAttributeList> {
+        let mut size = 0;
+        // fails with ERROR_INSUFFICIENT_BUFFER, reporting the size
+        unsafe {
+            processthreadsapi::InitializeProcThreadAttributeList(
+                ptr::null_mut(),
+                count,
+                0,
+                &mut size,
+            )
+        };
+        let mut buf = vec![0usize; (size + mem::size_of::<usize>() - 1) / mem::size_of::<usize>()];
+        check(unsafe {
+            processthreadsapi::InitializeProcThreadAttributeList(
+                buf.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST,
+                count,
+                0,
+                &mut size,
+            )
+        })?;
+        Ok(ProcThreadAttributeList { buf })
+    }
+
+    fn as_ptr(&self) -> LPPROC_THREAD_ATTRIBUTE_LIST {
+        self.buf.as_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST
+    }
+
+    fn update(&self, attribute: usize, value: LPVOID, size: usize) -> Result<()> {
+        check(unsafe {
+            processthreadsapi::UpdateProcThreadAttribute(
+                self.as_ptr(),
+                0,
+                attribute,
+                value,
+                size,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        })
+    }
+}
+
+impl Drop for ProcThreadAttributeList {
+    fn drop(&mut self) {
+        unsafe { processthreadsapi::DeleteProcThreadAttributeList(self.as_ptr()) }
+    }
+}
+
+// PROC_THREAD_ATTRIBUTE_* from winbase.h.
+const PROC_THREAD_ATTRIBUTE_HANDLE_LIST: usize = 0x0002_0002;
+const PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES: usize = 0x0002_0009;
+const PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE: usize = 0x0002_0016;
+
+fn is_inheritable(handle: RawHandle) -> bool {
+    let mut flags = 0;
+    if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+        return false;
+    }
+    let ok = unsafe { handleapi::GetHandleInformation(handle, &mut flags) };
+    ok != 0 && flags & HANDLE_FLAG_INHERIT != 0
+}
+
+// Held while the handles passed to a child are inheritable.
+static SPAWN_LOCK: Mutex<()> = Mutex::new(());
+
+// The handles made inheritable for a CreateProcess call, which are
+// made non-inheritable again on drop.
+struct InheritGuard(Vec<RawHandle>);
+
+impl InheritGuard {
+    // Make the handle inheritable, unless it already is, and add it to
+    // the handle list, which must not contain duplicates.
+    fn add(&mut self, handle: RawHandle, handle_list: &mut Vec<RawHandle>) -> Result<()> {
+        if handle_list.contains(&handle) {
+            return Ok(());
+        }
+        if !is_inheritable(handle) {
+            check(unsafe {
+                handleapi::SetHandleInformation(handle, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT)
+            })?;
+            self.0.push(handle);
+        }
+        handle_list.push(handle);
+        Ok(())
+    }
+}
+
+impl Drop for InheritGuard {
+    fn drop(&mut self) {
+        for &handle in &self.0 {
+            unsafe {
+                handleapi::SetHandleInformation(handle, HANDLE_FLAG_INHERIT, 0);
+            }
+        }
+    }
+}
+
+// A security identifier allocated by the system.
+#[derive(Debug)]
+pub struct Sid(PSID);
+
+unsafe impl Send for Sid {}
+unsafe impl Sync for Sid {}
+
+impl Drop for Sid {
+    fn drop(&mut self) {
+        unsafe {
+            securitybaseapi::FreeSid(self.0);
+        }
+    }
+}
+
+// Return the SID of the AppContainer profile with the given name,
+// creating the profile if it doesn't exist.
+pub fn AppContainerSid(name: &OsStr) -> Result<Sid> {
+    use winapi::shared::winerror::{ERROR_ALREADY_EXISTS, HRESULT_FROM_WIN32};
+    let name = to_nullterm(name);
+    let mut sid = ptr::null_mut();
+    let mut hr = unsafe {
+        userenv::CreateAppContainerProfile(
+            name.as_ptr(),
+            name.as_ptr(),
+            name.as_ptr(),
+            ptr::null_mut(),
+            0,
+            &mut sid,
+        )
+    };
+    if hr == HRESULT_FROM_WIN32(ERROR_ALREADY_EXISTS) {
+        hr = unsafe { userenv::DeriveAppContainerSidFromAppContainerName(name.as_ptr(), &mut sid) };
+    }
+    check_hresult(hr)?;
+    Ok(Sid(sid))
+}
+
+fn check_hresult(hr: HRESULT) -> Result<()> {
+    if hr >= 0 {
+        return Ok(());
+    }
+    // unwrap errors from HRESULT_FROM_WIN32
+    let code = if hr as u32 & 0xffff_0000 == 0x8007_0000 {
+        hr & 0xffff
+    } else {
+        hr
+    };
+    Err(Error::from_raw_os_error(code))
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct COORD_ {
+    X: i16,
+    Y: i16,
+}
+
+// Pseudo console functions, missing from winapi.
+#[link(name = "kernel32")]
+extern "system" {
+    #[link_name = "CreatePseudoConsole"]
+    fn CreatePseudoConsole_(
+        size: COORD_,
+        input: RawHandle,
+        output: RawHandle,
+        flags: DWORD,
+        console: *mut RawHandle,
+    ) -> HRESULT;
+    #[link_name = "ResizePseudoConsole"]
+    fn ResizePseudoConsole_(console: RawHandle, size: COORD_) -> HRESULT;
+    #[link_name = "ClosePseudoConsole"]
+    fn ClosePseudoConsole_(console: RawHandle);
+}
+
+// A pseudo console (ConPTY), closed on drop.
+#[derive(Debug)]
+pub struct PseudoConsole(RawHandle);
+
+unsafe impl Send for PseudoConsole {}
+unsafe impl Sync for PseudoConsole {}
+
+impl Drop for PseudoConsole {
+    fn drop(&mut self) {
+        unsafe { ClosePseudoConsole_(self.0) }
+    }
+}
+
+// Create a pseudo console which reads its input from `input` and writes
+// the output of the attached processes to `output`.
+pub fn CreatePseudoConsole(
+    rows: u16,
+    cols: u16,
+    input: &File,
+    output: &File,
+) -> Result<PseudoConsole> {
+    let size = COORD_ {
+        X: cols as i16,
+        Y: rows as i16,
+    };
+    let mut console = ptr::null_mut();
+    check_hresult(unsafe {
+        CreatePseudoConsole_(
+            size,
+            input.as_raw_handle(),
+            output.as_raw_handle(),
+            0,
+            &mut console,
+        )
+    })?;
+    Ok(PseudoConsole(console))
+}
+
+impl PseudoConsole {
+    pub fn resize(&self, rows: u16, cols: u16) -> Result<()> {
+        let size = COORD_ {
+            X: cols as i16,
+            Y: rows as i16,
+        };
+        check_hresult(unsafe { ResizePseudoConsole_(self.0, size) })
+    }
+}
+
+// Return a restricted version of the primary token of the current
+// process, with all privileges except SeChangeNotifyPrivilege removed.
+pub fn CreateRestrictedToken() -> Result<Handle> {
+    use winapi::um::winnt::{
+        DISABLE_MAX_PRIVILEGE, TOKEN_ADJUST_DEFAULT, TOKEN_ADJUST_SESSIONID, TOKEN_ASSIGN_PRIMARY,
+        TOKEN_DUPLICATE, TOKEN_QUERY,
+    };
+    let mut token = ptr::null_mut();
+    check(unsafe {
+        processthreadsapi::OpenProcessToken(
+            processthreadsapi::GetCurrentProcess(),
+            TOKEN_DUPLICATE
+                | TOKEN_QUERY
+                | TOKEN_ASSIGN_PRIMARY
+                | TOKEN_ADJUST_DEFAULT
+                | TOKEN_ADJUST_SESSIONID,
+            &mut token,
+        )
+    })?;
+    let token = unsafe { Handle::from_raw_handle(token) };
+    let mut restricted = ptr::null_mut();
+    check(unsafe {
+        securitybaseapi::CreateRestrictedToken(
+            token.as_raw_handle(),
+            DISABLE_MAX_PRIVILEGE,
+            0,
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            0,
+            ptr::null_mut(),
+            &mut restricted,
+        )
+    })?;
+    Ok(unsafe { Handle::from_raw_handle(restricted) })
+}
+
 pub enum WaitEvent {
     OBJECT_0,
     ABANDONED,
@@ -202,6 +750,97 @@ pub fn WaitForSingleObject(handle: &Handle, mut timeout: Option<Duration>) -> Re
     }
 }
 
+// Wait for any of the handles to become signaled, returning the index of
+// the signaled handle, or None on timeout.
+pub fn WaitForMultipleObjects(
+    handles: &[&Handle],
+    mut timeout: Option<Duration>,
+) -> Result<Option<usize>> {
+    use winapi::shared::winerror::WAIT_TIMEOUT;
+    use winapi::um::winbase::{INFINITE, WAIT_ABANDONED_0, WAIT_FAILED, WAIT_OBJECT_0};
+    let raw_handles: Vec<RawHandle> = handles.iter().map(|h| h.as_raw_handle()).collect();
+    let count = raw_handles.len() as DWORD;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let result = loop {
+        // Clamp the timeout as in WaitForSingleObject.
+        let (timeout_ms, overflow) = timeout
+            .map(|timeout| {
+                let timeout = timeout.as_millis();
+                if timeout < INFINITE as u128 {
+                    (timeout as u32, false)
+                } else {
+                    (INFINITE - 1, true)
+                }
+            })
+            .unwrap_or((INFINITE, false));
+
+        let result = unsafe {
+            synchapi::WaitForMultipleObjects(count, raw_handles.as_ptr() as *const _, 0, timeout_ms)
+        };
+        if result != WAIT_TIMEOUT || !overflow {
+            break result;
+        }
+        let deadline = deadline.unwrap();
+        let now = Instant::now();
+        if now >= deadline {
+            break WAIT_TIMEOUT;
+        }
+        timeout = Some(deadline - now);
+    };
+
+    let signaled = |base: DWORD| result.checked_sub(base).filter(|&i| i < count);
+    if let Some(i) = signaled(WAIT_OBJECT_0).or_else(|| signaled(WAIT_ABANDONED_0)) {
+        Ok(Some(i as usize))
+    } else if result == WAIT_TIMEOUT {
+        Ok(None)
+    } else if result == WAIT_FAILED {
+        Err(Error::last_os_error())
+    } else {
+        panic!("WaitForMultipleObjects returned {}", result);
+    }
+}
+
+pub use winapi::shared::ntdef::{BOOLEAN, PVOID};
+use winapi::shared::ntdef::{NTSTATUS, ULONG};
+pub use winapi::um::winnt::WAITORTIMERCALLBACK;
+
+// Wait registered with the thread pool by RegisterWaitForSingleObject.
+#[derive(Debug)]
+pub struct RegisteredWait(RawHandle);
+
+impl RegisteredWait {
+    // Cancel the wait, blocking until a running callback completes.
+    pub fn unregister(&mut self) -> Result<()> {
+        check(unsafe {
+            winapi::um::threadpoollegacyapiset::UnregisterWaitEx(self.0, INVALID_HANDLE_VALUE)
+        })
+    }
+}
+
+// Invoke the callback once in a thread pool thread when the handle
+// becomes signaled.
+pub fn RegisterWaitForSingleObject(
+    handle: &Handle,
+    callback: WAITORTIMERCALLBACK,
+    context: PVOID,
+) -> Result<RegisteredWait> {
+    use winapi::um::winbase::INFINITE;
+    use winapi::um::winnt::WT_EXECUTEONLYONCE;
+    let mut wait = ptr::null_mut();
+    check(unsafe {
+        winapi::um::winbase::RegisterWaitForSingleObject(
+            &mut wait,
+            handle.as_raw_handle(),
+            callback,
+            context,
+            INFINITE,
+            WT_EXECUTEONLYONCE,
+        )
+    })?;
+    Ok(RegisteredWait(wait))
+}
+
 pub fn GetExitCodeProcess(handle: &Handle) -> Result<u32> {
     let mut exit_code = 0u32;
     check(unsafe {
@@ -210,10 +849,297 @@ pub fn GetExitCodeProcess(handle: &Handle) -> Result<u32> {
     Ok(exit_code)
 }
 
+pub fn SetProcessAffinityMask(handle: &Handle, mask: usize) -> Result<()> {
+    check(unsafe { winapi::um::winbase::SetProcessAffinityMask(handle.as_raw_handle(), mask) })
+}
+
+pub fn GenerateConsoleCtrlEvent(ctrl_event: DWORD, process_group_id: u32) -> Result<()> {
+    check(unsafe { winapi::um::wincon::GenerateConsoleCtrlEvent(ctrl_event, process_group_id) })
+}
+
+pub use winapi::um::winnt::{PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE};
+
+pub fn OpenProcess(desired_access: DWORD, pid: u32) -> Result<Handle> {
+    let handle = unsafe { processthreadsapi::OpenProcess(desired_access, 0, pid) };
+    if handle.is_null() {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { Handle::from_raw_handle(handle) })
+}
+
+// Duplicate a process handle with just the access needed to wait for
+// the process and to terminate it.
+pub fn DuplicateProcessHandle(handle: &Handle) -> Result<Handle> {
+    use winapi::um::winnt::SYNCHRONIZE;
+    let mut duplicate = ptr::null_mut();
+    check(unsafe {
+        handleapi::DuplicateHandle(
+            processthreadsapi::GetCurrentProcess(),
+            handle.as_raw_handle(),
+            processthreadsapi::GetCurrentProcess(),
+            &mut duplicate,
+            SYNCHRONIZE | PROCESS_TERMINATE | PROCESS_QUERY_LIMITED_INFORMATION,
+            0,
+            0,
+        )
+    })?;
+    Ok(unsafe { Handle::from_raw_handle(duplicate) })
+}
+
+// Winsock functions, from ws2_32 which winapi only covers with a
+// feature of its own.
+#[link(name = "ws2_32")]
+extern "system" {
+    #[link_name = "WSAIoctl"]
+    fn WSAIoctl_(
+        socket: usize,
+        control_code: DWORD,
+        in_buffer: LPVOID,
+        in_size: DWORD,
+        out_buffer: LPVOID,
+        out_size: DWORD,
+        bytes_returned: *mut DWORD,
+        overlapped: LPVOID,
+        completion_routine: LPVOID,
+    ) -> i32;
+    #[link_name = "WSAGetLastError"]
+    fn WSAGetLastError_() -> i32;
+}
+
+const SIO_BASE_HANDLE: DWORD = 0x4800_0022;
+
+// Convert a socket to a handle that a child process can inherit.
+//
+// With layered service providers installed, the socket is a handle of
+// the topmost provider, which is meaningless in another process, so
+// the handle of the base provider is duplicated instead.  The socket
+// itself is closed with closesocket(), leaving the connection open
+// through the duplicate.
+pub fn SocketHandle(socket: OwnedSocket) -> Result<OwnedHandle> {
+    let mut base: usize = 0;
+    let mut returned: DWORD = 0;
+    let rc = unsafe {
+        WSAIoctl_(
+            socket.as_raw_socket() as usize,
+            SIO_BASE_HANDLE,
+            ptr::null_mut(),
+            0,
+            &mut base as *mut usize as LPVOID,
+            mem::size_of::<usize>() as DWORD,
+            &mut returned,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if rc != 0 {
+        return Err(Error::from_raw_os_error(unsafe { WSAGetLastError_() }));
+    }
+    let mut duplicate = ptr::null_mut();
+    check(unsafe {
+        handleapi::DuplicateHandle(
+            processthreadsapi::GetCurrentProcess(),
+            base as RawHandle,
+            processthreadsapi::GetCurrentProcess(),
+            &mut duplicate,
+            0,
+            0,
+            winapi::um::winnt::DUPLICATE_SAME_ACCESS,
+        )
+    })?;
+    drop(socket);
+    Ok(unsafe { OwnedHandle::from_raw_handle(duplicate) })
+}
+
+pub fn GetProcessId(handle: &Handle) -> u32 {
+    unsafe { processthreadsapi::GetProcessId(handle.as_raw_handle()) }
+}
+
+// Return the (pid, parent pid) pairs of all processes in the system.
+pub fn list_processes() -> Result<Vec<(u32, u32)>> {
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    let snapshot = unsafe {
+        Handle::from_raw_handle(check_handle(CreateToolhelp32Snapshot(
+            TH32CS_SNAPPROCESS,
+            0,
+        ))?)
+    };
+    let mut entry: PROCESSENTRY32W = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+    let mut processes = vec![];
+    let mut more = unsafe { Process32FirstW(snapshot.as_raw_handle(), &mut entry) };
+    while more != 0 {
+        processes.push((entry.th32ProcessID, entry.th32ParentProcessID));
+        more = unsafe { Process32NextW(snapshot.as_raw_handle(), &mut entry) };
+    }
+    Ok(processes)
+}
+
+// Undocumented, but long-standing, functions exported by ntdll.
+#[link(name = "ntdll")]
+extern "system" {
+    #[link_name = "NtSuspendProcess"]
+    fn NtSuspendProcess_(handle: RawHandle) -> NTSTATUS;
+    #[link_name = "NtResumeProcess"]
+    fn NtResumeProcess_(handle: RawHandle) -> NTSTATUS;
+    fn RtlNtStatusToDosError(status: NTSTATUS) -> ULONG;
+}
+
+fn check_status(status: NTSTATUS) -> Result<()> {
+    if status < 0 {
+        let code = unsafe { RtlNtStatusToDosError(status) };
+        return Err(Error::from_raw_os_error(code as i32));
+    }
+    Ok(())
+}
+
+pub fn NtSuspendProcess(handle: &Handle) -> Result<()> {
+    check_status(unsafe { NtSuspendProcess_(handle.as_raw_handle()) })
+}
+
+pub fn NtResumeProcess(handle: &Handle) -> Result<()> {
+    check_status(unsafe { NtResumeProcess_(handle.as_raw_handle()) })
+}
+
+// Return the creation, exit, kernel and user times of the process, as
+// counts of 100-nanosecond intervals.
+fn process_times(handle: &Handle) -> Result<[u64; 4]> {
+    use winapi::shared::minwindef::FILETIME;
+    fn ticks(ft: FILETIME) -> u64 {
+        (ft.dwHighDateTime as u64) << 32 | ft.dwLowDateTime as u64
+    }
+    let (mut creation, mut exit, mut kernel, mut user): (FILETIME, FILETIME, FILETIME, FILETIME) =
+        unsafe { mem::zeroed() };
+    check(unsafe {
+        processthreadsapi::GetProcessTimes(
+            handle.as_raw_handle(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    })?;
+    Ok([ticks(creation), ticks(exit), ticks(kernel), ticks(user)])
+}
+
+// Return the user and kernel CPU time of the process.
+pub fn GetProcessTimes(handle: &Handle) -> Result<(Duration, Duration)> {
+    let [_, _, kernel, user] = process_times(handle)?;
+    Ok((
+        Duration::from_nanos(user * 100),
+        Duration::from_nanos(kernel * 100),
+    ))
+}
+
+// Return the creation time of the process, in 100-nanosecond intervals
+// since January 1, 1601.
+pub fn GetProcessCreationTime(handle: &Handle) -> Result<u64> {
+    let [creation, ..] = process_times(handle)?;
+    Ok(creation)
+}
+
+pub use winapi::um::psapi::PROCESS_MEMORY_COUNTERS;
+
+pub fn GetProcessMemoryInfo(handle: &Handle) -> Result<PROCESS_MEMORY_COUNTERS> {
+    let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { mem::zeroed() };
+    let size = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD;
+    check(unsafe {
+        winapi::um::psapi::GetProcessMemoryInfo(handle.as_raw_handle(), &mut counters, size)
+    })?;
+    Ok(counters)
+}
+
 pub fn TerminateProcess(handle: &Handle, exit_code: u32) -> Result<()> {
     check(unsafe { processthreadsapi::TerminateProcess(handle.as_raw_handle(), exit_code) })
 }
 
+pub use winapi::um::winnt::{
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_ACTIVE_PROCESS,
+    JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+
+// Information classes and CPU rate control flags from winnt.h.
+const JobObjectExtendedLimitInformation: DWORD = 9;
+const JobObjectCpuRateControlInformation: DWORD = 15;
+const JOB_OBJECT_CPU_RATE_CONTROL_ENABLE: DWORD = 0x1;
+const JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP: DWORD = 0x4;
+
+// JOBOBJECT_CPU_RATE_CONTROL_INFORMATION with the CpuRate member of
+// its union.
+#[repr(C)]
+struct JOBOBJECT_CPU_RATE_CONTROL_INFORMATION {
+    ControlFlags: DWORD,
+    CpuRate: DWORD,
+}
+
+pub fn CreateJobObject() -> Result<Handle> {
+    let handle = unsafe { jobapi2::CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+    if handle.is_null() {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { Handle::from_raw_handle(handle) })
+}
+
+pub fn AssignProcessToJobObject(job: &Handle, process: &Handle) -> Result<()> {
+    check(unsafe {
+        jobapi2::AssignProcessToJobObject(job.as_raw_handle(), process.as_raw_handle())
+    })
+}
+
+pub fn TerminateJobObject(job: &Handle, exit_code: u32) -> Result<()> {
+    check(unsafe { jobapi2::TerminateJobObject(job.as_raw_handle(), exit_code) })
+}
+
+pub fn QueryJobLimits(job: &Handle) -> Result<JOBOBJECT_EXTENDED_LIMIT_INFORMATION> {
+    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { mem::zeroed() };
+    check(unsafe {
+        jobapi2::QueryInformationJobObject(
+            job.as_raw_handle(),
+            JobObjectExtendedLimitInformation,
+            &mut info as *mut _ as LPVOID,
+            mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as DWORD,
+            ptr::null_mut(),
+        )
+    })?;
+    Ok(info)
+}
+
+pub fn SetJobLimits(job: &Handle, info: &JOBOBJECT_EXTENDED_LIMIT_INFORMATION) -> Result<()> {
+    check(unsafe {
+        jobapi2::SetInformationJobObject(
+            job.as_raw_handle(),
+            JobObjectExtendedLimitInformation,
+            info as *const _ as LPVOID,
+            mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as DWORD,
+        )
+    })
+}
+
+// Cap the CPU rate of the job to `rate` in 1/100 of a percent of all
+// CPUs, or remove the cap if None.
+pub fn SetJobCpuRate(job: &Handle, rate: Option<u32>) -> Result<()> {
+    let info = match rate {
+        Some(rate) => JOBOBJECT_CPU_RATE_CONTROL_INFORMATION {
+            ControlFlags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+            CpuRate: rate,
+        },
+        None => JOBOBJECT_CPU_RATE_CONTROL_INFORMATION {
+            ControlFlags: 0,
+            CpuRate: 0,
+        },
+    };
+    check(unsafe {
+        jobapi2::SetInformationJobObject(
+            job.as_raw_handle(),
+            JobObjectCpuRateControlInformation,
+            &info as *const _ as LPVOID,
+            mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as DWORD,
+        )
+    })
+}
+
 unsafe fn GetStdHandle(which: StandardStream) -> Result<RawHandle> {
     // private/unsafe because the raw handle it returns must be
     // duplicated or leaked before converting to an owned Handle.
@@ -0,0 +1,26 @@
+// The reaper thread collects every child of the process, so this test
+// runs in a process of its own.
+#![cfg(unix)]
+
+extern crate subprocess;
+
+use std::thread;
+use std::time::Duration;
+use subprocess::{ExitStatus, Popen, PopenConfig};
+
+#[test]
+fn init_reaper_keeps_status() {
+    subprocess::unix::start_init_reaper().unwrap();
+    let mut p = Popen::create(&["sh", "-c", "exit 7"], PopenConfig::default()).unwrap();
+    // let the reaper collect the child before the Popen does, at which
+    // point the PID no longer exists
+    let pid = p.pid().unwrap() as libc::pid_t;
+    for _ in 0..500 {
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+    assert_eq!(p.wait().unwrap(), ExitStatus::Exited(7));
+}
@@ -0,0 +1,23 @@
+// Becoming a subreaper affects the whole process, so this test runs in a
+// process of its own.
+#![cfg(target_os = "linux")]
+
+extern crate subprocess;
+
+use std::time::Duration;
+use subprocess::{Exec, ExitStatus};
+
+#[test]
+fn subreaper() {
+    let orphans = subprocess::unix::start_subreaper().unwrap();
+    let out = Exec::shell("sleep 0.1 >/dev/null & echo $!")
+        .capture()
+        .unwrap();
+    assert!(out.success());
+    let pid = out.stdout_str().trim().parse().unwrap();
+    // the orphaned grandchild is re-parented to us and reaped
+    assert_eq!(
+        orphans.recv_timeout(Duration::from_secs(5)),
+        Ok((pid, ExitStatus::Exited(0)))
+    );
+}